@@ -1,31 +1,16 @@
 use std::io;
-use std::fs::File as StdFile;
-use bytes::BytesMut;
 use ritsu::executor::Runtime;
-use ritsu::action::fs;
+use ritsu::action::{ fs, io as rio };
 
 
 fn main() -> io::Result<()> {
     let mut pool = Runtime::new()?;
 
-    let fd = StdFile::open("./Cargo.toml")?;
-    let stdout = StdFile::create("/dev/stdout")?;
-    let mut fd = fs::File::from_std(fd);
-    let mut stdout = fs::File::from_std(stdout);
-
     let fut = async move {
-        let mut pos = 0;
-
-        loop {
-            let buf = fd.read_at(pos, BytesMut::with_capacity(64)).await?;
-
-            if buf.is_empty() {
-                break
-            }
+        let mut fd = fs::File::open("./Cargo.toml").await?;
+        let mut stdout = rio::stdout();
 
-            pos += buf.len() as i64;
-            stdout.write_at(0, buf.freeze()).await?;
-        }
+        rio::copy(&mut fd, &mut stdout).await?;
 
         Ok(())
     };
@@ -0,0 +1,75 @@
+use std::{ io, net, thread };
+use std::sync::Arc;
+use bytes::{ Buf, Bytes, BytesMut };
+use rustls::{ ClientConfig, RootCertStore, ServerConfig };
+use rustls::pki_types::{ PrivateKeyDer, PrivatePkcs8KeyDer };
+use ritsu::executor::Runtime;
+use ritsu::action::tcp;
+use ritsu_tls::{ TlsAcceptor, TlsConnector };
+
+
+fn main() -> anyhow::Result<()> {
+    // a self-signed certificate, trusted by the client
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()])?;
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der()));
+    let cert = cert.cert.der().clone();
+
+    let server_config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert.clone()], key)?;
+    let mut roots = RootCertStore::empty();
+    roots.add(cert)?;
+    let client_config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    let listener = net::TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+
+    let server = thread::spawn(move || {
+        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+        let mut listener = tcp::TcpListener::from_std(listener);
+
+        Runtime::new()?.run_until(async move {
+            let (stream, _) = listener.accept().await?;
+            let mut stream = acceptor.accept(stream).await?;
+
+            // echo until the client closes the session
+            loop {
+                let buf = stream.read(BytesMut::with_capacity(1024)).await?;
+
+                if buf.is_empty() {
+                    break
+                }
+
+                let mut buf = buf.freeze();
+
+                while buf.has_remaining() {
+                    buf = stream.write(buf).await?;
+                }
+            }
+
+            stream.close().await
+        })
+    });
+
+    Runtime::new()?.run_until(async move {
+        let connector = TlsConnector::from(Arc::new(client_config));
+        let stream = tcp::TcpStream::connect(addr).await?;
+        let mut stream = connector.connect("localhost", stream).await?;
+
+        stream.write_all(Bytes::from_static(b"hello tls")).await?;
+        let buf = stream.read(BytesMut::with_capacity(1024)).await?;
+        println!("echo: {}", String::from_utf8_lossy(&buf));
+
+        stream.close().await?;
+        let buf = stream.read(BytesMut::with_capacity(1024)).await?;
+        assert!(buf.is_empty());
+
+        Ok(()) as io::Result<()>
+    })?;
+
+    server.join().unwrap()?;
+
+    Ok(())
+}
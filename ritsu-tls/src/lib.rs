@@ -0,0 +1,258 @@
+//! TLS over ritsu streams, with rustls.
+//!
+//! rustls is sans-io, so a [TlsStream] moves the records between the rustls buffers
+//! and the owned-buffer reads and writes of the stream under it,
+//! and reads and writes owned buffers itself, like a plaintext `TcpStream`.
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//! use bytes::{ Bytes, BytesMut };
+//! use ritsu::action::tcp::TcpStream;
+//! use ritsu_tls::TlsConnector;
+//!
+//! # async fn f(config: Arc<rustls::ClientConfig>) -> std::io::Result<()> {
+//! let stream = TcpStream::connect_host("example.com:443").await?;
+//! let mut stream = TlsConnector::from(config).connect("example.com", stream).await?;
+//!
+//! stream.write_all(Bytes::from_static(b"GET / HTTP/1.0\r\nHost: example.com\r\n\r\n")).await?;
+//! let buf = stream.read(BytesMut::with_capacity(4096)).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{ io, slice };
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::future::Future;
+use std::io::{ Read, Write };
+use bytes::{ Buf, BufMut, Bytes, BytesMut };
+use rustls::{ ClientConfig, ClientConnection, ServerConfig, ServerConnection, Connection };
+use rustls::pki_types::ServerName;
+use ritsu::action::io::{ OwnedRead, OwnedWrite };
+
+
+const READ_CAPACITY: usize = 16 * 1024;
+
+/// Starts the client side of TLS sessions.
+#[derive(Clone)]
+pub struct TlsConnector {
+    config: Arc<ClientConfig>
+}
+
+/// Starts the server side of TLS sessions.
+#[derive(Clone)]
+pub struct TlsAcceptor {
+    config: Arc<ServerConfig>
+}
+
+/// A TLS session over `S`.
+pub struct TlsStream<S> {
+    io: S,
+    conn: Connection,
+    // the stream under it reached its end
+    eof: bool
+}
+
+impl From<Arc<ClientConfig>> for TlsConnector {
+    fn from(config: Arc<ClientConfig>) -> TlsConnector {
+        TlsConnector { config }
+    }
+}
+
+impl From<Arc<ServerConfig>> for TlsAcceptor {
+    fn from(config: Arc<ServerConfig>) -> TlsAcceptor {
+        TlsAcceptor { config }
+    }
+}
+
+impl TlsConnector {
+    /// Complete the handshake with `domain`, which the certificate of the server must be valid for.
+    pub async fn connect<S>(&self, domain: &str, io: S) -> io::Result<TlsStream<S>>
+    where S: OwnedRead + OwnedWrite
+    {
+        let domain = ServerName::try_from(domain)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?
+            .to_owned();
+        let conn = ClientConnection::new(self.config.clone(), domain)
+            .map_err(tls_error)?;
+
+        TlsStream::handshake(io, conn.into()).await
+    }
+}
+
+impl TlsAcceptor {
+    pub async fn accept<S>(&self, io: S) -> io::Result<TlsStream<S>>
+    where S: OwnedRead + OwnedWrite
+    {
+        let conn = ServerConnection::new(self.config.clone())
+            .map_err(tls_error)?;
+
+        TlsStream::handshake(io, conn.into()).await
+    }
+}
+
+impl<S: OwnedRead + OwnedWrite> TlsStream<S> {
+    async fn handshake(io: S, conn: Connection) -> io::Result<TlsStream<S>> {
+        let mut stream = TlsStream { io, conn, eof: false };
+
+        while stream.conn.is_handshaking() {
+            if stream.conn.wants_write() {
+                stream.write_tls().await?;
+            } else if !stream.read_tls().await? {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "tls handshake eof"));
+            }
+        }
+
+        // like the session tickets of TLS 1.3
+        stream.write_tls().await?;
+
+        Ok(stream)
+    }
+
+    /// Read into the spare capacity of `buf`, returns it with what was read appended.
+    ///
+    /// Nothing is appended at the end of the session. A stream that ends without
+    /// the `close_notify` of the peer fails with [io::ErrorKind::UnexpectedEof].
+    pub async fn read(&mut self, mut buf: BytesMut) -> io::Result<BytesMut> {
+        if !buf.has_remaining_mut() {
+            buf.reserve(READ_CAPACITY);
+        }
+
+        loop {
+            let spare = buf.bytes_mut();
+            let len = spare.len();
+
+            // rustls wants an initialized slice
+            let spare = unsafe {
+                std::ptr::write_bytes(spare.as_mut_ptr(), 0, len);
+                slice::from_raw_parts_mut(spare.as_mut_ptr() as *mut u8, len)
+            };
+
+            match self.conn.reader().read(spare) {
+                Ok(n) => {
+                    unsafe {
+                        buf.advance_mut(n);
+                    }
+
+                    return Ok(buf);
+                },
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => (),
+                Err(err) => return Err(err)
+            }
+
+            self.read_tls().await?;
+        }
+    }
+
+    /// Encrypt and write what fits in the buffer of the session,
+    /// returns what remains of `buf`.
+    pub async fn write(&mut self, mut buf: Bytes) -> io::Result<Bytes> {
+        let n = self.conn.writer().write(&buf)?;
+        buf.advance(n);
+        self.write_tls().await?;
+        Ok(buf)
+    }
+
+    pub async fn write_all(&mut self, mut buf: Bytes) -> io::Result<()> {
+        while buf.has_remaining() {
+            buf = self.write(buf).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Send `close_notify`, the peer reads the end of the session after what was written.
+    ///
+    /// The stream under it is left open.
+    pub async fn close(&mut self) -> io::Result<()> {
+        self.conn.send_close_notify();
+        self.write_tls().await
+    }
+
+    #[inline]
+    pub fn get_ref(&self) -> (&S, &Connection) {
+        (&self.io, &self.conn)
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> (S, Connection) {
+        (self.io, self.conn)
+    }
+
+    /// Write out every record rustls has queued.
+    async fn write_tls(&mut self) -> io::Result<()> {
+        while self.conn.wants_write() {
+            let mut out = Vec::new();
+            self.conn.write_tls(&mut out)?;
+
+            let mut out = Bytes::from(out);
+
+            while out.has_remaining() {
+                let len = out.len();
+                out = self.io.write(out).await?;
+
+                if out.len() == len {
+                    return Err(io::ErrorKind::WriteZero.into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Feed rustls one read of the stream, returns false at its end.
+    async fn read_tls(&mut self) -> io::Result<bool> {
+        if self.eof {
+            return Ok(false);
+        }
+
+        let buf = self.io.read(BytesMut::with_capacity(READ_CAPACITY)).await?;
+
+        if buf.is_empty() {
+            self.eof = true;
+
+            // tells rustls the stream ended
+            self.conn.read_tls(&mut &[][..])?;
+        }
+
+        let mut rest = &buf[..];
+
+        while !rest.is_empty() {
+            self.conn.read_tls(&mut rest)?;
+            let ret = self.conn.process_new_packets();
+
+            // send the alert before failing
+            if let Err(err) = ret {
+                let _ = self.write_tls().await;
+                return Err(tls_error(err));
+            }
+        }
+
+        if self.eof {
+            self.conn.process_new_packets().map_err(tls_error)?;
+        }
+
+        // like a key update
+        self.write_tls().await?;
+
+        Ok(!self.eof)
+    }
+}
+
+impl<S: OwnedRead + OwnedWrite> OwnedRead for TlsStream<S> {
+    #[inline]
+    fn read(&mut self, buf: BytesMut) -> impl Future<Output = io::Result<BytesMut>> {
+        TlsStream::read(self, buf)
+    }
+}
+
+impl<S: OwnedRead + OwnedWrite> OwnedWrite for TlsStream<S> {
+    #[inline]
+    fn write(&mut self, buf: Bytes) -> impl Future<Output = io::Result<Bytes>> {
+        TlsStream::write(self, buf)
+    }
+}
+
+fn tls_error(err: rustls::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
@@ -0,0 +1,35 @@
+use std::io;
+use std::fs::File as StdFile;
+use bytes::BytesMut;
+use ritsu::action::fs;
+use smol_ritsu::Handle;
+
+
+fn main() -> io::Result<()> {
+    let (driver, handle) = Handle::new();
+
+    let fd = StdFile::open("./Cargo.toml")?;
+    let stdout = StdFile::create("/dev/stdout")?;
+    let fd = fs::File::from_std(fd);
+    let stdout = fs::File::from_std(stdout);
+
+    let fut = async move {
+        let mut pos = 0;
+
+        loop {
+            let buf = fd.read_at(pos, BytesMut::with_capacity(64)).await?;
+
+            if buf.is_empty() {
+                break
+            }
+
+            pos += buf.len() as i64;
+            stdout.write_at(0, buf.freeze()).await?;
+        }
+
+        Ok(()) as io::Result<()>
+    };
+
+    // the tasks of an `Executor` go through `Handle::spawn` instead
+    driver.block_on(handle.wrap(fut))?
+}
@@ -0,0 +1,358 @@
+//! Submit ritsu ops from `smol`, or any `async-executor`.
+//!
+//! Tasks wrapped by a [Handle] send their entries to a [Driver],
+//! which drives the Proactor from the smol reactor on one thread.
+
+use std::{ io, mem };
+use std::pin::{ pin, Pin };
+use std::future::{ self as future, Future };
+use std::task::{ Context, Poll };
+use std::os::unix::io::{ AsFd, AsRawFd, BorrowedFd, RawFd };
+use smol::{ Async, Executor, Task, Timer };
+use smol::channel::{ self, Sender, Receiver };
+use smol::stream::Stream;
+use pin_project_lite::pin_project;
+use io_uring::opcode;
+use ritsu::action::{ Handle as TaskHandle, HandleVTable };
+use ritsu::error::errno;
+use ritsu::{
+    Proactor, RawHandle,
+    Ticket, TicketFuture,
+    SubmissionEntry
+};
+
+
+#[derive(Clone)]
+pub struct Handle {
+    inner: InnerHandle
+}
+
+#[derive(Clone)]
+struct InnerHandle(Sender<Submission>);
+
+pub struct Driver(Receiver<Submission>);
+
+enum Submission {
+    One(Ticket, SubmissionEntry),
+    Detached(SubmissionEntry),
+    Linked(Vec<(Ticket, SubmissionEntry)>)
+}
+
+impl Handle {
+    pub fn new() -> (Driver, Handle) {
+        let (tx, rx) = channel::unbounded();
+        (Driver(rx), Handle { inner: InnerHandle(tx) })
+    }
+
+    pub fn enter<R, F: FnOnce() -> R>(&self, f: F) -> R {
+        let handle = create_handle(self.inner.clone());
+
+        unsafe {
+            ritsu::handle::set(handle);
+        }
+
+        f()
+    }
+
+    /// Make the ops of `fut` go to the Driver, wherever it's polled.
+    pub fn wrap<F: Future>(&self, fut: F) -> WithHandle<F> {
+        WithHandle {
+            handle: self.inner.clone(),
+            fut
+        }
+    }
+
+    pub fn spawn<'a, F>(&self, ex: &Executor<'a>, fut: F) -> Task<F::Output>
+    where
+        F: Future + Send + 'a,
+        F::Output: Send + 'a
+    {
+        ex.spawn(self.wrap(fut))
+    }
+}
+
+impl Driver {
+    /// Drive `proactor` from the smol reactor, without a thread of its own.
+    ///
+    /// The ring fd is registered with `async-io`, and its completions are dispatched
+    /// when it's readable. The Proactor isn't `Send`,
+    /// so this runs on a `LocalExecutor` or under `smol::block_on`.
+    /// It returns once every [Handle] is dropped,
+    /// the threads that polled a wrapped task keep one though.
+    pub async fn drive(self, mut proactor: Proactor) -> io::Result<()> {
+        let handle = proactor.raw_handle();
+        let ring = Async::new(RingFd(proactor.as_raw_fd()))?;
+        let mut rx = pin!(self.0);
+        let mut timer: Option<Timer> = None;
+        let mut closed = false;
+
+        future::poll_fn(|cx| loop {
+            let mut readable = false;
+
+            while !closed {
+                match rx.as_mut().poll_next(cx) {
+                    Poll::Ready(Some(submission)) => unsafe { submission.push(&handle)? },
+                    Poll::Ready(None) => closed = true,
+                    Poll::Pending => break
+                }
+            }
+
+            if let Poll::Ready(ret) = ring.poll_readable(cx) {
+                ret?;
+                readable = true;
+            }
+
+            proactor.try_park()?;
+
+            if closed {
+                return Poll::Ready(Ok(()));
+            }
+
+            // the interest of `async-io` is oneshot, poll again to rearm it
+            if readable {
+                continue;
+            }
+
+            // the timers of the wheel don't wake the ring
+            match proactor.next_deadline() {
+                Some(deadline) => {
+                    let timer = timer.get_or_insert_with(|| Timer::at(deadline));
+                    timer.set_at(deadline);
+
+                    if Pin::new(timer).poll(cx).is_pending() {
+                        return Poll::Pending;
+                    }
+                },
+                None => return Poll::Pending
+            }
+        }).await
+    }
+
+    /// Run `fut` with `smol::block_on`, driving a new Proactor next to it.
+    ///
+    /// Fails if the Driver stops first, when every [Handle] is gone.
+    pub fn block_on<F: Future>(self, fut: F) -> io::Result<F::Output> {
+        let proactor = Proactor::new()?;
+
+        smol::block_on(smol::future::or(
+            async { Ok(fut.await) },
+            async {
+                self.drive(proactor).await?;
+                Err(io::Error::other("smol-ritsu driver closed"))
+            }
+        ))
+    }
+}
+
+impl Submission {
+    /// Push the entries, the tickets of the ones that fail complete with the error.
+    ///
+    /// Only fails once the Proactor is closed.
+    unsafe fn push(self, handle: &RawHandle) -> io::Result<()> {
+        let ret = match self {
+            Submission::One(ticket, sqe) => {
+                let sqe = ticket.register(sqe);
+                let ret = handle.raw_push(sqe.clone());
+
+                if let Err(err) = &ret {
+                    Ticket::unregister(&sqe).complete(-errno(err));
+                }
+
+                ret
+            },
+            Submission::Detached(sqe) => handle.raw_push_detached(sqe),
+            Submission::Linked(sqes) => {
+                let sqes = sqes.into_iter()
+                    .map(|(ticket, sqe)| ticket.register(sqe))
+                    .collect::<Vec<_>>();
+                let ret = handle.raw_push_linked(&sqes);
+
+                if let Err(err) = &ret {
+                    for sqe in &sqes {
+                        Ticket::unregister(sqe).complete(-errno(err));
+                    }
+                }
+
+                ret
+            }
+        };
+
+        match ret {
+            Err(err) if matches!(ritsu::Error::from_io(&err), Some(ritsu::Error::Closed)) => Err(err),
+            _ => Ok(())
+        }
+    }
+
+    /// Complete the tickets, the entries never reach a ring.
+    fn cancel(self) {
+        match self {
+            Submission::One(ticket, _) => ticket.complete(-libc::ECANCELED),
+            Submission::Detached(_) => (),
+            Submission::Linked(sqes) => for (ticket, _) in sqes {
+                ticket.complete(-libc::ECANCELED);
+            }
+        }
+    }
+}
+
+// the ring is owned by the Proactor.
+struct RingFd(RawFd);
+
+impl AsFd for RingFd {
+    #[inline]
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        unsafe { BorrowedFd::borrow_raw(self.0) }
+    }
+}
+
+fn create_handle(handle: InnerHandle) -> TaskHandle {
+    static VTABLE: HandleVTable = HandleVTable {
+        push, push_linked, push_detached, poll_ready, push_registered, cancel, clone, drop
+    };
+
+    unsafe fn push(ptr: *const (), entry: SubmissionEntry) -> io::Result<TicketFuture> {
+        let handle = Box::from_raw(ptr as *mut InnerHandle);
+
+        let (ticket, fut) = Ticket::new();
+
+        let ret = handle.0.try_send(Submission::One(ticket, entry));
+        mem::forget(handle);
+
+        ret.map_err(|_| io::Error::other("smol-ritsu driver closed"))?;
+
+        Ok(fut)
+    }
+
+    unsafe fn push_linked(ptr: *const (), entries: &[SubmissionEntry]) -> io::Result<Vec<TicketFuture>> {
+        let handle = Box::from_raw(ptr as *mut InnerHandle);
+
+        let mut futs = Vec::with_capacity(entries.len());
+        let entries = entries.iter()
+            .map(|entry| {
+                let (ticket, fut) = Ticket::new();
+                futs.push(fut);
+                (ticket, entry.clone())
+            })
+            .collect();
+
+        let ret = handle.0.try_send(Submission::Linked(entries));
+        mem::forget(handle);
+
+        ret.map_err(|_| io::Error::other("smol-ritsu driver closed"))?;
+
+        Ok(futs)
+    }
+
+    unsafe fn push_detached(ptr: *const (), entry: SubmissionEntry) -> io::Result<()> {
+        let handle = Box::from_raw(ptr as *mut InnerHandle);
+
+        let ret = handle.0.try_send(Submission::Detached(entry));
+        mem::forget(handle);
+
+        ret.map_err(|_| io::Error::other("smol-ritsu driver closed"))
+    }
+
+    // the driver pushes everything it receives, nothing is held back.
+    unsafe fn poll_ready(_: *const (), _: &mut Context<'_>) -> Poll<()> {
+        Poll::Ready(())
+    }
+
+    unsafe fn push_registered(ptr: *const (), entry: SubmissionEntry) {
+        let handle = Box::from_raw(ptr as *mut InnerHandle);
+
+        let ticket = Ticket::unregister(&entry);
+
+        if let Err(err) = handle.0.try_send(Submission::One(ticket, entry)) {
+            err.into_inner().cancel();
+        }
+
+        mem::forget(handle);
+    }
+
+    unsafe fn cancel(ptr: *const (), user_data: u64) {
+        let handle = Box::from_raw(ptr as *mut InnerHandle);
+
+        let entry = opcode::AsyncCancel::new(user_data).build();
+        let _ = handle.0.try_send(Submission::Detached(entry));
+
+        mem::forget(handle);
+    }
+
+    unsafe fn clone(ptr: *const ()) -> TaskHandle {
+        let handle = Box::from_raw(ptr as *mut InnerHandle);
+        let handle2 = InnerHandle::clone(&handle);
+        mem::forget(handle);
+
+        create_handle(handle2)
+    }
+
+    unsafe fn drop(ptr: *const ()) {
+        let _ = Box::from_raw(ptr as *mut InnerHandle);
+    }
+
+    let handle = Box::new(handle);
+
+    unsafe {
+        TaskHandle::new(Box::into_raw(handle) as *const (), &VTABLE)
+    }
+}
+
+
+pin_project!{
+    /// A future whose ops go to a [Driver], see [Handle::wrap].
+    pub struct WithHandle<F> {
+        handle: InnerHandle,
+        #[pin]
+        fut: F
+    }
+}
+
+impl<F: Future> Future for WithHandle<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        let handle = create_handle(this.handle.clone());
+
+        unsafe {
+            ritsu::handle::set(handle);
+        }
+
+        this.fut.poll(cx)
+    }
+}
+
+
+#[test]
+fn test_rejected_entry() {
+    use io_uring::opcode::types;
+    use ritsu::restrict::Restrictions;
+
+    let (driver, handle) = Handle::new();
+    let proactor = Proactor::new().unwrap();
+
+    let mut restrictions = Restrictions::new();
+    restrictions.allow_op(opcode::Nop::CODE);
+    proactor.raw_handle().restrict(restrictions).unwrap();
+
+    let fut = handle.wrap(async {
+        let entry = opcode::Read::new(types::Target::Fd(-1), std::ptr::null_mut(), 0).build();
+        let read = unsafe { ritsu::handle::push(entry).unwrap().await };
+
+        // the driver keeps going
+        let nop = unsafe { ritsu::handle::push(opcode::Nop::new().build()).unwrap().await };
+
+        (read.result(), nop.result())
+    });
+
+    let ret = smol::block_on(smol::future::or(
+        async { Some(fut.await) },
+        async {
+            driver.drive(proactor).await.unwrap();
+            None
+        }
+    ));
+
+    assert_eq!(ret, Some((-libc::EACCES, 0)));
+}
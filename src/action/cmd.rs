@@ -0,0 +1,383 @@
+//! `IORING_OP_URING_CMD` passthrough, e.g. for ublk or character devices.
+//!
+//! A regular 64 byte SQE has room for a 16 byte payload, which is enough
+//! for ublk `io_uring_cmd`s, and is submitted on the Proactor.
+//! NVMe passthrough needs 128 byte SQEs and 32 byte CQEs, which the Proactor doesn't have,
+//! so those commands go through a [CmdRing] of their own.
+
+use std::{ fs, io, mem, ptr };
+use std::rc::Rc;
+use std::pin::Pin;
+use std::io::Read;
+use std::future::Future;
+use std::cell::{ Cell, RefCell };
+use std::collections::HashMap;
+use std::task::{ Context, Poll, Waker };
+use std::os::unix::io::RawFd;
+use io_uring::opcode;
+use crate::{ handle, sys, SubmissionEntry };
+use crate::handle::SqeFlags;
+use super::poll::Async;
+use super::raw::Ring;
+
+
+/// The payload of a command on the Proactor.
+pub const PAYLOAD_LEN: usize = 16;
+
+/// The payload of a command on a [CmdRing], like `struct nvme_uring_cmd`.
+pub const PAYLOAD128_LEN: usize = 80;
+
+// the cancels of dropped commands, their completions are ignored
+const CANCEL_TOKEN: u64 = 0;
+
+/// Plain data that can be copied into the payload of a [UringCmd].
+///
+/// # Safety
+///
+/// Every byte of the type must be initialized, it can't have padding.
+pub unsafe trait Payload: Copy {}
+
+macro_rules! payload_impls {
+    ( $( $ty:ty ),* ) => {
+        $(
+            unsafe impl Payload for $ty {}
+        )*
+    }
+}
+
+payload_impls!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+unsafe impl<T: Payload, const N: usize> Payload for [T; N] {}
+
+pub struct UringCmd {
+    fd: RawFd,
+    cmd_op: u32,
+    flags: SqeFlags,
+    payload: [u8; PAYLOAD128_LEN],
+    // how much of the payload is used
+    len: usize
+}
+
+impl UringCmd {
+    pub fn new(fd: RawFd, cmd_op: u32) -> UringCmd {
+        UringCmd { fd, cmd_op, flags: SqeFlags::empty(), payload: [0; PAYLOAD128_LEN], len: 0 }
+    }
+
+    /// Issue the command with `flags`, like [SqeFlags::IO_DRAIN].
+    #[inline]
+    pub fn flags(mut self, flags: SqeFlags) -> UringCmd {
+        self.flags = flags;
+        self
+    }
+
+    #[inline]
+    pub fn payload(self, payload: [u8; PAYLOAD_LEN]) -> UringCmd {
+        self.payload_from(&payload)
+    }
+
+    /// The payload of a command for a [CmdRing].
+    #[inline]
+    pub fn payload128(self, payload: [u8; PAYLOAD128_LEN]) -> UringCmd {
+        self.payload_from(&payload)
+    }
+
+    /// Copy a driver specific command structure into the payload.
+    ///
+    /// A `#[repr(C)]` structure implements [Payload] if it has no padding.
+    /// One larger than [PAYLOAD_LEN] only fits on a [CmdRing].
+    ///
+    /// # Panics
+    ///
+    /// If `T` is larger than [PAYLOAD128_LEN].
+    pub fn payload_from<T: Payload>(mut self, cmd: &T) -> UringCmd {
+        assert!(mem::size_of::<T>() <= PAYLOAD128_LEN, "uring_cmd payload too large");
+
+        self.payload = [0; PAYLOAD128_LEN];
+        self.len = mem::size_of::<T>();
+        unsafe {
+            ptr::copy_nonoverlapping(
+                cmd as *const T as *const u8,
+                self.payload.as_mut_ptr(),
+                mem::size_of::<T>()
+            );
+        }
+        self
+    }
+
+    /// The entry for the Proactor.
+    ///
+    /// # Panics
+    ///
+    /// If the payload is larger than [PAYLOAD_LEN], submit it on a [CmdRing] instead.
+    pub fn build(self) -> SubmissionEntry {
+        assert!(self.len <= PAYLOAD_LEN, "uring_cmd payload needs a CmdRing");
+
+        self.build128().0
+    }
+
+    /// The entry, and the second half of the 128 byte SQE that holds the rest of the payload.
+    fn build128(&self) -> (SubmissionEntry, [u8; 64]) {
+        let mut entry = sys::entry(sys::IORING_OP_URING_CMD);
+        let mut ext = [0; 64];
+
+        {
+            let sqe = sys::sqe_mut(&mut entry);
+            sqe.fd = self.fd;
+            sqe.flags = self.flags.bits();
+            sqe.off = self.cmd_op as u64;
+
+            unsafe {
+                ptr::copy_nonoverlapping(
+                    self.payload.as_ptr(),
+                    &mut sqe.addr3 as *mut u64 as *mut u8,
+                    PAYLOAD_LEN
+                );
+            }
+        }
+
+        ext.copy_from_slice(&self.payload[PAYLOAD_LEN..]);
+
+        (entry, ext)
+    }
+
+    /// Submit the command and wait for its result.
+    ///
+    /// # Safety
+    ///
+    /// The driver may read or write any memory referenced by the payload,
+    /// it must stay valid until the returned future completes.
+    pub async unsafe fn submit(self) -> io::Result<i32> {
+        let entry = self.build();
+
+        let ret = safety_await!{
+            handle::push(entry)
+        };
+        ret?.ok().map(|n| n as i32)
+    }
+}
+
+
+/// A ring with 128 byte SQEs and 32 byte CQEs, for commands like NVMe passthrough.
+///
+/// It's a small ring of its own, the Proactor just polls an eventfd registered on it,
+/// and it's tied to the thread that created it.
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::os::unix::io::AsRawFd;
+/// use ritsu::action::cmd::{ CmdRing, UringCmd, PAYLOAD128_LEN };
+///
+/// # async fn f(nvme_cmd_io: u32) -> std::io::Result<()> {
+/// let dev = File::open("/dev/ng0n1")?;
+/// let ring = CmdRing::new(32)?;
+///
+/// // a `struct nvme_uring_cmd`
+/// let cmd = [0; PAYLOAD128_LEN];
+///
+/// let cqe = unsafe {
+///     ring.submit(UringCmd::new(dev.as_raw_fd(), nvme_cmd_io).payload128(cmd))?.await?
+/// };
+/// println!("{} {:?}", cqe.result, cqe.extra);
+/// # Ok(())
+/// # }
+/// ```
+pub struct CmdRing {
+    inner: Rc<Inner>
+}
+
+struct Inner {
+    ring: Ring,
+    eventfd: Async<fs::File>,
+    commands: RefCell<HashMap<u64, Slot>>,
+    next: Cell<u64>
+}
+
+#[derive(Default)]
+struct Slot {
+    cqe: Option<CmdCompletion>,
+    waker: Option<Waker>
+}
+
+/// The completion of a command on a [CmdRing].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CmdCompletion {
+    pub result: i32,
+    /// The second half of the 32 byte CQE, like the result of an NVMe command.
+    pub extra: [u64; 2]
+}
+
+impl CmdRing {
+    /// A ring with room for `entries` commands in flight.
+    pub fn new(entries: u32) -> io::Result<CmdRing> {
+        let ring = Ring::new(entries, entries * 2, true)?;
+        let eventfd = ring.eventfd()?;
+
+        Ok(CmdRing {
+            inner: Rc::new(Inner {
+                ring,
+                eventfd: Async::new(eventfd),
+                commands: RefCell::new(HashMap::new()),
+                next: Cell::new(CANCEL_TOKEN + 1)
+            })
+        })
+    }
+
+    /// Submit `cmd`, the future completes with its completion.
+    ///
+    /// # Safety
+    ///
+    /// The driver may read or write any memory referenced by the payload,
+    /// it must stay valid until the command completes.
+    /// Dropping the future cancels the command, but doesn't wait for it.
+    pub unsafe fn submit(&self, cmd: UringCmd) -> io::Result<CmdFuture> {
+        let key = self.inner.next.get();
+        self.inner.next.set(key + 1);
+
+        let (entry, ext) = cmd.build128();
+        let entry = entry.user_data(key);
+
+        self.inner.commands.borrow_mut().insert(key, Slot::default());
+
+        if let Err(err) = self.inner.ring.push128(&entry, &ext) {
+            self.inner.commands.borrow_mut().remove(&key);
+            return Err(err);
+        }
+
+        Ok(CmdFuture { ring: self.inner.clone(), key, done: false })
+    }
+}
+
+impl Inner {
+    /// Hand the completions to their commands.
+    fn drain(&self) -> io::Result<()> {
+        let mut eventfd = self.eventfd.get_ref();
+        let _ = eventfd.read(&mut [0; 8]);
+
+        let mut commands = self.commands.borrow_mut();
+
+        self.ring.reap(|cqe| {
+            // a dropped command
+            if let Some(slot) = commands.get_mut(&cqe.user_data) {
+                slot.cqe = Some(CmdCompletion { result: cqe.res, extra: cqe.big_cqe });
+
+                if let Some(waker) = slot.waker.take() {
+                    waker.wake();
+                }
+            }
+        })
+    }
+}
+
+/// Completes with the completion of a command, see [CmdRing::submit].
+pub struct CmdFuture {
+    ring: Rc<Inner>,
+    key: u64,
+    done: bool
+}
+
+impl Future for CmdFuture {
+    type Output = io::Result<CmdCompletion>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+
+        loop {
+            this.ring.drain()?;
+
+            let mut commands = this.ring.commands.borrow_mut();
+            let slot = commands.get_mut(&this.key).expect("`CmdFuture` polled after completion");
+
+            if let Some(cqe) = slot.cqe {
+                commands.remove(&this.key);
+                this.done = true;
+
+                return if cqe.result < 0 {
+                    Poll::Ready(Err(io::Error::from_raw_os_error(-cqe.result)))
+                } else {
+                    Poll::Ready(Ok(cqe))
+                };
+            }
+
+            slot.waker = Some(cx.waker().clone());
+            drop(commands);
+
+            futures_util::ready!(this.ring.eventfd.poll_readable(cx))?;
+        }
+    }
+}
+
+impl Drop for CmdFuture {
+    fn drop(&mut self) {
+        if self.done {
+            return
+        }
+
+        let mut commands = self.ring.commands.borrow_mut();
+
+        if commands.remove(&self.key).is_some_and(|slot| slot.cqe.is_none()) {
+            let entry = opcode::AsyncCancel::new(self.key)
+                .build()
+                .user_data(CANCEL_TOKEN);
+
+            // the ring is torn down with the `CmdRing` anyway.
+            let _ = self.ring.ring.push(&entry);
+        }
+
+        // this command may have been the one watching the eventfd for the others.
+        for slot in commands.values_mut() {
+            if let Some(waker) = slot.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+#[test]
+fn test_uring_cmd() {
+    use std::os::unix::io::AsRawFd;
+    use crate::executor::block_on;
+
+    let entry = UringCmd::new(3, 7)
+        .payload_from(&[1u32, 2, 3])
+        .flags(SqeFlags::ASYNC)
+        .build();
+    let sqe = sys::sqe(&entry);
+    assert_eq!(sqe.flags, SqeFlags::ASYNC.bits());
+    let payload = unsafe { std::slice::from_raw_parts(&sqe.addr3 as *const u64 as *const u8, PAYLOAD_LEN) };
+    assert_eq!(sqe.opcode, sys::IORING_OP_URING_CMD);
+    assert_eq!((sqe.fd, sqe.off), (3, 7));
+    assert_eq!(&payload[..12], &[1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0]);
+    assert_eq!(&payload[12..], &[0; 4]);
+
+    // a pipe has no commands
+    let (rx, _tx) = crate::action::splice::pipe().unwrap();
+    let err = block_on(unsafe { UringCmd::new(rx.as_raw_fd(), 0).submit() }).unwrap_err();
+    assert!(err.kind() == io::ErrorKind::Unsupported || err.raw_os_error() == Some(libc::EOPNOTSUPP));
+}
+
+#[test]
+fn test_cmd_ring() {
+    use std::os::unix::io::AsRawFd;
+    use crate::executor::block_on;
+
+    // the rest of the payload lands in the second half of the SQE
+    let mut payload = [0; PAYLOAD128_LEN];
+    payload.iter_mut().enumerate().for_each(|(i, b)| *b = i as u8);
+    let (entry, ext) = UringCmd::new(3, 7).payload128(payload).build128();
+    let sqe = sys::sqe(&entry);
+    assert_eq!(sqe.addr3.to_ne_bytes(), payload[..8]);
+    assert_eq!(sqe.pad.to_ne_bytes(), payload[8..16]);
+    assert_eq!(ext[..], payload[PAYLOAD_LEN..]);
+
+    let ring = CmdRing::new(4).unwrap();
+
+    // a pipe has no commands, the ring has no probe to reject it first
+    let (rx, _tx) = crate::action::splice::pipe().unwrap();
+    block_on(async {
+        for _ in 0..2 {
+            let cmd = UringCmd::new(rx.as_raw_fd(), 0).payload128(payload);
+            let err = unsafe { ring.submit(cmd).unwrap().await.unwrap_err() };
+            assert_eq!(err.raw_os_error(), Some(libc::EOPNOTSUPP));
+        }
+    });
+}
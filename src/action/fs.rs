@@ -1,67 +1,546 @@
-use std::{ fs, io };
-use std::os::unix::io::{ AsRawFd, RawFd };
+use std::{ fs, io, mem };
+use std::path::Path;
+use std::ffi::CString;
+use std::time::{ Duration, SystemTime };
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{ AsRawFd, IntoRawFd, RawFd };
+use bitflags::bitflags;
 use bytes::{ Buf, BufMut, Bytes, BytesMut };
 use io_uring::opcode::{ self, types };
-use crate::handle;
+use crate::sys;
+use crate::handle::{ self, IoPriority };
+use super::rate::{ self, RateLimit };
+#[cfg(feature = "futures-io")]
+use std::{ pin::Pin, task::{ Context, Poll } };
+#[cfg(feature = "futures-io")]
+use super::io::compat::Compat;
 
+mod open;
+mod dir;
+mod copy;
+mod xattr;
+mod watch;
+pub(crate) mod lock;
+pub mod ops;
 
+pub use open::{ OpenOptions, ResolveFlags };
+pub use dir::{ ReadDir, DirEntry, FileType, read_dir };
+pub use copy::{ CopyOptions, copy };
+pub use xattr::{ XattrFlags, get_xattr, set_xattr };
+pub use watch::{ Watch, WatchMask, WatchDescriptor, Event, watch };
+pub use lock::FileLock;
+
+
+bitflags!{
+    /// The mode of [File::allocate], see `fallocate(2)`.
+    pub struct FallocateFlags: i32 {
+        /// Don't change the file size, even when allocating beyond the end.
+        const KEEP_SIZE = libc::FALLOC_FL_KEEP_SIZE;
+        /// Deallocate the range, it must be combined with `KEEP_SIZE`.
+        const PUNCH_HOLE = libc::FALLOC_FL_PUNCH_HOLE;
+        /// Remove the range and shift the rest of the file down.
+        const COLLAPSE_RANGE = libc::FALLOC_FL_COLLAPSE_RANGE;
+        /// Zero the range, allocating it if needed.
+        const ZERO_RANGE = libc::FALLOC_FL_ZERO_RANGE;
+        /// Insert a hole at the range, shifting the rest of the file up.
+        const INSERT_RANGE = libc::FALLOC_FL_INSERT_RANGE;
+        /// Unshare shared blocks of the range, on filesystems with reflinks.
+        const UNSHARE_RANGE = libc::FALLOC_FL_UNSHARE_RANGE;
+    }
+}
+
+bitflags!{
+    /// What [File::sync_range] does, see `sync_file_range(2)`.
+    pub struct SyncRangeFlags: u32 {
+        /// Wait for the writeback of pages in the range that is already in progress.
+        const WAIT_BEFORE = libc::SYNC_FILE_RANGE_WAIT_BEFORE;
+        /// Start the writeback of dirty pages in the range.
+        const WRITE = libc::SYNC_FILE_RANGE_WRITE;
+        /// Wait for the writeback of the range to finish.
+        const WAIT_AFTER = libc::SYNC_FILE_RANGE_WAIT_AFTER;
+    }
+}
+
+bitflags!{
+    /// The flags of the reads and writes of a [File], see `preadv2(2)`.
+    pub struct RwFlags: i32 {
+        /// Poll for the completion, the ring must be set up with `IORING_SETUP_IOPOLL`,
+        /// otherwise the ops fail with `EINVAL`.
+        const HIPRI = libc::RWF_HIPRI;
+        /// Like a write to a file opened with `O_DSYNC`.
+        const DSYNC = libc::RWF_DSYNC;
+        /// Like a write to a file opened with `O_SYNC`.
+        const SYNC = libc::RWF_SYNC;
+        /// Fail with [io::ErrorKind::WouldBlock] instead of waiting,
+        /// like a read of data that isn't in the page cache.
+        const NOWAIT = libc::RWF_NOWAIT;
+        /// Append to the end of the file, whatever the offset.
+        const APPEND = libc::RWF_APPEND;
+    }
+}
+
+/// The expected access pattern of a range, see `posix_fadvise(2)`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Advice {
+    Normal,
+    Sequential,
+    Random,
+    /// The data will be accessed only once.
+    NoReuse,
+    /// Start the readahead of the range.
+    WillNeed,
+    /// Drop the clean cached pages of the range.
+    DontNeed
+}
+
+impl Advice {
+    fn to_raw(self) -> i32 {
+        match self {
+            Advice::Normal => libc::POSIX_FADV_NORMAL,
+            Advice::Sequential => libc::POSIX_FADV_SEQUENTIAL,
+            Advice::Random => libc::POSIX_FADV_RANDOM,
+            Advice::NoReuse => libc::POSIX_FADV_NOREUSE,
+            Advice::WillNeed => libc::POSIX_FADV_WILLNEED,
+            Advice::DontNeed => libc::POSIX_FADV_DONTNEED
+        }
+    }
+}
+
+/// The expected use of a memory range, see `madvise(2)`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemAdvice {
+    Normal,
+    Random,
+    Sequential,
+    /// Start reading the range in.
+    WillNeed,
+    /// Drop the range, private mappings read back zeros or the file content.
+    DontNeed,
+    /// The pages may be freed lazily, private anonymous mappings only.
+    Free,
+    /// Free the range and its backing store, shared mappings only.
+    Remove,
+    HugePage,
+    NoHugePage,
+    /// Deactivate the range, so it's reclaimed first under memory pressure.
+    Cold,
+    /// Reclaim the range right away.
+    PageOut
+}
+
+impl MemAdvice {
+    fn to_raw(self) -> i32 {
+        match self {
+            MemAdvice::Normal => libc::MADV_NORMAL,
+            MemAdvice::Random => libc::MADV_RANDOM,
+            MemAdvice::Sequential => libc::MADV_SEQUENTIAL,
+            MemAdvice::WillNeed => libc::MADV_WILLNEED,
+            MemAdvice::DontNeed => libc::MADV_DONTNEED,
+            MemAdvice::Free => libc::MADV_FREE,
+            MemAdvice::Remove => libc::MADV_REMOVE,
+            MemAdvice::HugePage => libc::MADV_HUGEPAGE,
+            MemAdvice::NoHugePage => libc::MADV_NOHUGEPAGE,
+            MemAdvice::Cold => libc::MADV_COLD,
+            MemAdvice::PageOut => libc::MADV_PAGEOUT
+        }
+    }
+}
+
+/// A file whose ops go through the ring.
+///
+/// The `_at` methods take an explicit offset, while [File::read], [File::write]
+/// and [File::seek] use a position tracked by the `File` like [std::io::Seek].
+/// The position lives in userspace, the ops don't move the offset of the fd.
+#[derive(Debug)]
 pub struct File {
-    fd: fs::File
+    // closed through the ring on drop
+    fd: mem::ManuallyDrop<fs::File>,
+    ioprio: Option<IoPriority>,
+    rw_flags: RwFlags,
+    limit: Option<RateLimit>,
+    // `None` for pipes and sockets, they always read and write at their current position.
+    pos: Option<u64>,
+    #[cfg(feature = "futures-io")]
+    compat: Compat
 }
 
 impl File {
+    /// The position starts where `fd` is.
     pub fn from_std(fd: fs::File) -> File {
-        File { fd }
+        let pos = unsafe { libc::lseek(fd.as_raw_fd(), 0, libc::SEEK_CUR) };
+        let pos = if pos >= 0 { Some(pos as u64) } else { None };
+
+        File {
+            fd: mem::ManuallyDrop::new(fd),
+            ioprio: None,
+            rw_flags: RwFlags::empty(),
+            limit: None,
+            pos,
+            #[cfg(feature = "futures-io")]
+            compat: Compat::default()
+        }
+    }
+
+    /// Take the fd back, it's closed synchronously again from now on.
+    ///
+    /// The offset of the fd is moved to the position of the `File`.
+    pub fn into_std(self) -> fs::File {
+        let pos = self.pos;
+        let fd = self.take_fd();
+
+        if let Some(pos) = pos {
+            unsafe {
+                libc::lseek(fd.as_raw_fd(), pos as _, libc::SEEK_SET);
+            }
+        }
+
+        fd
+    }
+
+    fn take_fd(self) -> fs::File {
+        let mut this = mem::ManuallyDrop::new(self);
+        this.limit = None;
+
+        #[cfg(feature = "futures-io")]
+        {
+            this.compat = Compat::default();
+        }
+
+        unsafe { mem::ManuallyDrop::take(&mut this.fd) }
+    }
+
+    /// Close the file with `IORING_OP_CLOSE` (5.6), and report the error of `close(2)`.
+    ///
+    /// Dropping the file closes it through the ring too, but ignores the result.
+    /// Some filesystems like NFS only report write-back failures here.
+    pub async fn close(self) -> io::Result<()> {
+        let fd = self.take_fd().into_raw_fd();
+        let entry = opcode::Close::new(fd).build();
+
+        let ret = safety_await!{
+            unsafe { handle::push(entry) }
+        };
+
+        let ret = match ret {
+            Ok(cqe) => cqe.result(),
+            // nothing else can close it
+            Err(err) => {
+                unsafe {
+                    libc::close(fd);
+                }
+
+                return Err(err);
+            }
+        };
+
+        if ret >= 0 {
+            Ok(())
+        } else {
+            Err(io::Error::from_raw_os_error(-ret))
+        }
+    }
+
+    /// Issue the ops of this file with `ioprio`, `None` uses the priority of the thread.
+    pub fn set_priority(&mut self, ioprio: Option<IoPriority>) {
+        self.ioprio = ioprio;
+    }
+
+    /// Issue the reads and writes of this file with `flags`, the vectored ones too.
+    ///
+    /// The `futures-io` traits don't use them.
+    pub fn set_rw_flags(&mut self, flags: RwFlags) {
+        self.rw_flags = flags;
+    }
+
+    /// Pace the reads and writes of this file with `limit`, `None` doesn't limit them.
+    ///
+    /// Each op takes tokens for the bytes it asks for before it's submitted,
+    /// the ones a short read didn't use are given back.
+    pub fn set_rate_limit(&mut self, limit: Option<RateLimit>) {
+        self.limit = limit;
+    }
+
+    pub async fn read_at(&self, offset: i64, buf: BytesMut) -> io::Result<BytesMut> {
+        let (ret, buf) = self.read_op(offset, buf, usize::MAX).await;
+        ret.map(|_| buf)
+    }
+
+    pub async fn write_at(&self, offset: i64, buf: Bytes) -> io::Result<Bytes> {
+        let (ret, buf) = self.write_op(offset, buf).await;
+        ret.map(|_| buf)
+    }
+
+    /// Read at the current position into the spare capacity of `buf`, and move past what was read.
+    pub async fn read(&mut self, buf: BytesMut) -> io::Result<BytesMut> {
+        let offset = self.pos.map_or(-1, |pos| pos as i64);
+        let (ret, buf) = self.read_op(offset, buf, usize::MAX).await;
+        let n = ret?;
+        self.pos = self.pos.map(|pos| pos + n as u64);
+        Ok(buf)
+    }
+
+    /// Write at the current position, and move past what was written.
+    ///
+    /// With `O_APPEND` the kernel writes at the end whatever the position is,
+    /// seek to [io::SeekFrom::End] to find it again.
+    pub async fn write(&mut self, buf: Bytes) -> io::Result<Bytes> {
+        let offset = self.pos.map_or(-1, |pos| pos as i64);
+        let (ret, buf) = self.write_op(offset, buf).await;
+        let n = ret?;
+        self.pos = self.pos.map(|pos| pos + n as u64);
+        Ok(buf)
+    }
+
+    /// Move the position, returns the new one.
+    ///
+    /// Seeking from the end asks the kernel for the size of the file.
+    /// Pipes and sockets fail with `ESPIPE`.
+    pub async fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let cur = self.pos.ok_or_else(|| io::Error::from_raw_os_error(libc::ESPIPE))?;
+
+        let (base, delta) = match pos {
+            io::SeekFrom::Start(pos) => (pos, 0),
+            io::SeekFrom::Current(delta) => (cur, delta),
+            io::SeekFrom::End(delta) => (self.metadata().await?.size(), delta)
+        };
+
+        let pos = seek_pos(base, delta)?;
+        self.pos = Some(pos);
+        Ok(pos)
+    }
+
+    /// The current position, `None` if the file isn't seekable.
+    #[inline]
+    pub fn position(&self) -> Option<u64> {
+        self.pos
+    }
+
+    /// Read exactly `len` bytes at `offset`, appended to `buf`.
+    ///
+    /// Short reads are continued after what was read, `EINTR` and `EAGAIN` are retried.
+    /// With [RwFlags::NOWAIT], `EAGAIN` fails with [io::ErrorKind::WouldBlock] instead.
+    /// Reaching the end of the file first fails with [io::ErrorKind::UnexpectedEof].
+    pub async fn read_exact_at(&self, mut offset: i64, mut buf: BytesMut, len: usize)
+        -> io::Result<BytesMut>
+    {
+        let end = buf.len() + len;
+        buf.reserve(len);
+
+        while buf.len() < end {
+            let max = end - buf.len();
+            let (ret, next) = self.read_op(offset, buf, max).await;
+            buf = next;
+
+            match ret {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => offset += n as i64,
+                Err(ref err) if is_retry(err, self.rw_flags) => (),
+                Err(err) => return Err(err)
+            }
+        }
+
+        Ok(buf)
+    }
+
+    /// Write all of `buf` at `offset`.
+    ///
+    /// Short writes are continued after what was written, `EINTR` and `EAGAIN` are retried,
+    /// like [File::read_exact_at].
+    /// A write of zero bytes fails with [io::ErrorKind::WriteZero].
+    pub async fn write_all_at(&self, mut offset: i64, mut buf: Bytes) -> io::Result<()> {
+        while buf.has_remaining() {
+            let (ret, next) = self.write_op(offset, buf).await;
+            buf = next;
+
+            match ret {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => offset += n as i64,
+                Err(ref err) if is_retry(err, self.rw_flags) => (),
+                Err(err) => return Err(err)
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read at `offset` into the spare capacity of `bufs` in order, with `IORING_OP_READV`.
+    ///
+    /// Each buffer is filled before the next one,
+    /// the length of each is advanced by what it received.
+    pub async fn read_vectored_at(&self, offset: i64, mut bufs: Vec<BytesMut>)
+        -> io::Result<Vec<BytesMut>>
+    {
+        let len = bufs.iter_mut().map(|buf| buf.bytes_mut().len()).sum::<usize>();
+        rate::acquire(self.limit.as_ref(), len).await;
+
+        let mut iovecs = bufs.iter_mut()
+            .map(|buf| {
+                let bytes = buf.bytes_mut();
+                libc::iovec { iov_base: bytes.as_mut_ptr() as *mut _, iov_len: bytes.len() }
+            })
+            .collect::<Vec<_>>();
+
+        let entry = opcode::Readv::new(
+            types::Target::Fd(self.fd.as_raw_fd()),
+            iovecs.as_mut_ptr(),
+            iovecs.len() as _
+        )
+            .offset(offset)
+            .rw_flags(self.rw_flags.bits())
+            .build();
+        let entry = handle::ioprio(self.ioprio, entry);
+
+        // the iovecs are read when the op is issued, the buffers until it completes.
+        let mut res = (bufs, iovecs);
+        let ret = safety_await!{
+            [ res ];
+            unsafe { handle::push(entry) }
+        };
+        let (mut bufs, iovecs) = res;
+        let ret = ret.inspect_err(|_| rate::release(self.limit.as_ref(), len))?.result();
+        rate::refund(self.limit.as_ref(), len - ret.max(0) as usize);
+
+        if ret >= 0 {
+            let mut n = ret as usize;
+
+            for (buf, iovec) in bufs.iter_mut().zip(iovecs.iter()) {
+                let len = iovec.iov_len.min(n);
+
+                unsafe {
+                    buf.advance_mut(len);
+                }
+
+                n -= len;
+            }
+
+            Ok(bufs)
+        } else {
+            Err(io::Error::from_raw_os_error(-ret))
+        }
     }
 
-    pub async fn read_at(&mut self, offset: i64, mut buf: BytesMut) -> io::Result<BytesMut> {
+    /// Write `bufs` at `offset` in order, with `IORING_OP_WRITEV`,
+    /// for example a header and a payload without copying them together.
+    ///
+    /// Each buffer is advanced by what was written of it,
+    /// what remains is returned like [File::write_at].
+    pub async fn write_vectored_at(&self, offset: i64, bufs: Vec<Bytes>) -> io::Result<Vec<Bytes>> {
+        let len = bufs.iter().map(Bytes::len).sum::<usize>();
+        rate::acquire(self.limit.as_ref(), len).await;
+
+        let iovecs = bufs.iter()
+            .map(|buf| libc::iovec { iov_base: buf.as_ptr() as *mut _, iov_len: buf.len() })
+            .collect::<Vec<_>>();
+
+        let entry = opcode::Writev::new(
+            types::Target::Fd(self.fd.as_raw_fd()),
+            iovecs.as_ptr(),
+            iovecs.len() as _
+        )
+            .offset(offset)
+            .rw_flags(self.rw_flags.bits())
+            .build();
+        let entry = handle::ioprio(self.ioprio, entry);
+
+        let mut res = (bufs, iovecs);
+        let ret = safety_await!{
+            [ res ];
+            unsafe { handle::push(entry) }
+        };
+        let (mut bufs, _) = res;
+        let ret = ret.inspect_err(|_| rate::release(self.limit.as_ref(), len))?.result();
+        rate::refund(self.limit.as_ref(), len - ret.max(0) as usize);
+
+        if ret >= 0 {
+            let mut n = ret as usize;
+
+            for buf in bufs.iter_mut() {
+                let len = buf.len().min(n);
+                buf.advance(len);
+                n -= len;
+            }
+
+            Ok(bufs)
+        } else {
+            Err(io::Error::from_raw_os_error(-ret))
+        }
+    }
+
+    // `buf` comes back on error too, for the loops above.
+    async fn read_op(&self, offset: i64, mut buf: BytesMut, max: usize) -> (io::Result<usize>, BytesMut) {
+        let len = buf.bytes_mut().len().min(max);
+        rate::acquire(self.limit.as_ref(), len).await;
+
         let bytes = buf.bytes_mut();
         let entry = opcode::Read::new(
             types::Target::Fd(self.fd.as_raw_fd()),
             bytes.as_mut_ptr() as *mut _,
-            bytes.len() as _
+            len as _
         )
             .offset(offset)
+            .rw_flags(self.rw_flags.bits())
             .build();
+        let entry = handle::ioprio(self.ioprio, entry);
 
         let ret = safety_await!{
             [ buf ];
             unsafe { handle::push(entry) }
         };
 
-        let ret = ret?.result();
+        let ret = match ret {
+            Ok(cqe) => cqe.result(),
+            Err(err) => {
+                rate::release(self.limit.as_ref(), len);
+                return (Err(err), buf);
+            }
+        };
+        rate::refund(self.limit.as_ref(), len - ret.max(0) as usize);
 
         if ret >= 0 {
             unsafe {
                 buf.advance_mut(ret as _);
             }
 
-            Ok(buf)
+            (Ok(ret as usize), buf)
         } else {
-            Err(io::Error::from_raw_os_error(-ret))
+            (Err(io::Error::from_raw_os_error(-ret)), buf)
         }
     }
 
-    pub async fn write_at(&mut self, offset: i64, mut buf: Bytes) -> io::Result<Bytes> {
+    async fn write_op(&self, offset: i64, mut buf: Bytes) -> (io::Result<usize>, Bytes) {
+        let len = buf.len();
+        rate::acquire(self.limit.as_ref(), len).await;
+
         let entry = opcode::Write::new(
             types::Target::Fd(self.fd.as_raw_fd()),
             buf.as_ptr() as *const _,
             buf.len() as _
         )
             .offset(offset)
+            .rw_flags(self.rw_flags.bits())
             .build();
+        let entry = handle::ioprio(self.ioprio, entry);
 
         let ret = safety_await!{
             [ buf ];
             unsafe { handle::push(entry) }
         };
-        let ret = ret?.result();
+
+        let ret = match ret {
+            Ok(cqe) => cqe.result(),
+            Err(err) => {
+                rate::release(self.limit.as_ref(), len);
+                return (Err(err), buf);
+            }
+        };
+        rate::refund(self.limit.as_ref(), len - ret.max(0) as usize);
 
         if ret >= 0 {
             buf.advance(ret as _);
-            Ok(buf)
+            (Ok(ret as usize), buf)
         } else {
-            Err(io::Error::from_raw_os_error(-ret))
+            (Err(io::Error::from_raw_os_error(-ret)), buf)
         }
     }
 
@@ -70,28 +549,137 @@ impl File {
         let entry = opcode::Fsync::new(op)
             .flags(flag)
             .build();
+        let entry = handle::ioprio(self.ioprio, entry);
 
         let ret = safety_await!{
             unsafe { handle::push(entry) }
         };
-        let ret = ret?.result();
-
-        if ret >= 0 {
-            Ok(())
-        } else {
-            Err(io::Error::from_raw_os_error(-ret))
-        }
+        ret?.ok()?;
+        Ok(())
     }
 
+    /// Flush the data and metadata of the file to the device, like `fsync(2)`.
+    ///
+    /// Only writes that have completed before are covered,
+    /// the ring doesn't order it after ops that are still in flight.
     #[inline]
     pub async fn sync_all(&self) -> io::Result<()> {
         self.fsync(types::FsyncFlags::empty()).await
     }
 
+    /// Like [File::sync_all], but skip metadata that isn't needed to read the data back,
+    /// like `fdatasync(2)`.
     #[inline]
     pub async fn sync_data(&self) -> io::Result<()> {
         self.fsync(types::FsyncFlags::DATASYNC).await
     }
+
+    /// Control the writeback of the dirty pages in `offset..offset + len`,
+    /// like `sync_file_range(2)`, `len` of 0 means up to the end of the file.
+    ///
+    /// Unlike [File::sync_data], it never flushes metadata or the device cache,
+    /// so it's no durability guarantee, only a way to pace writeback.
+    pub async fn sync_range(&self, offset: i64, len: u32, flags: SyncRangeFlags) -> io::Result<()> {
+        let op = types::Target::Fd(self.fd.as_raw_fd());
+        let entry = opcode::SyncFileRange::new(op, len)
+            .offset(offset)
+            .flags(flags.bits())
+            .build();
+        let entry = handle::ioprio(self.ioprio, entry);
+
+        let ret = safety_await!{
+            unsafe { handle::push(entry) }
+        };
+        ret?.ok()?;
+        Ok(())
+    }
+
+    /// Announce how `offset..offset + len` will be accessed,
+    /// `len` of 0 means up to the end of the file.
+    pub async fn advise(&self, offset: i64, len: i64, advice: Advice) -> io::Result<()> {
+        let entry = self.fadvise(offset, len, advice);
+
+        let ret = safety_await!{
+            unsafe { handle::push(entry) }
+        };
+        ret?.ok()?;
+        Ok(())
+    }
+
+    /// Like [File::advise], but don't wait for it, advice is only a hint anyway.
+    ///
+    /// The fd only needs to stay open until the op is submitted.
+    pub fn advise_detached(&self, offset: i64, len: i64, advice: Advice) -> io::Result<()> {
+        let entry = self.fadvise(offset, len, advice);
+
+        unsafe {
+            handle::push_detached(entry)
+        }
+    }
+
+    fn fadvise(&self, offset: i64, len: i64, advice: Advice) -> crate::SubmissionEntry {
+        let op = types::Target::Fd(self.fd.as_raw_fd());
+        let entry = opcode::Fadvise::new(op, len, advice.to_raw())
+            .offset(offset)
+            .build();
+        handle::ioprio(self.ioprio, entry)
+    }
+
+    /// Manipulate the disk space of `offset..offset + len`, like `fallocate(2)`.
+    ///
+    /// With empty `mode` it preallocates the range and grows the file as needed.
+    pub async fn allocate(&self, offset: i64, len: i64, mode: FallocateFlags) -> io::Result<()> {
+        let op = types::Target::Fd(self.fd.as_raw_fd());
+        let mut entry = opcode::Fallocate::new(op, 0)
+            .offset(offset)
+            .mode(mode.bits())
+            .build();
+        // the length is 64-bit in the kernel
+        sys::sqe_mut(&mut entry).addr = len as u64;
+        let entry = handle::ioprio(self.ioprio, entry);
+
+        let ret = safety_await!{
+            unsafe { handle::push(entry) }
+        };
+        ret?.ok()?;
+        Ok(())
+    }
+
+    /// Truncate or extend the file to `size` bytes, like `ftruncate(2)`.
+    ///
+    /// `IORING_OP_FTRUNCATE` needs 6.9, older kernels run it on the blocking pool.
+    pub async fn set_len(&self, size: u64) -> io::Result<()> {
+        let mut entry = sys::entry(sys::IORING_OP_FTRUNCATE);
+        let sqe = sys::sqe_mut(&mut entry);
+        sqe.fd = self.fd.as_raw_fd();
+        sqe.off = size;
+        let entry = handle::ioprio(self.ioprio, entry);
+
+        let ret = safety_await!{
+            unsafe { handle::push(entry) }
+        };
+        ret?.ok()?;
+        Ok(())
+    }
+
+    /// Query the metadata of the file with `statx(2)`.
+    pub async fn metadata(&self) -> io::Result<Metadata> {
+        statx(self.fd.as_raw_fd(), CString::default(), libc::AT_EMPTY_PATH).await
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        let fd = unsafe { mem::ManuallyDrop::take(&mut self.fd) }.into_raw_fd();
+        let entry = opcode::Close::new(fd).build();
+
+        // without a ring on this thread, close right here
+        if unsafe { handle::try_push_detached(entry) }.is_err() {
+            unsafe {
+                libc::close(fd);
+            }
+        }
+    }
 }
 
 impl AsRawFd for File {
@@ -100,3 +688,661 @@ impl AsRawFd for File {
         self.fd.as_raw_fd()
     }
 }
+
+fn seek_pos(base: u64, delta: i64) -> io::Result<u64> {
+    let pos = if delta >= 0 {
+        base.checked_add(delta as u64)
+    } else {
+        base.checked_sub(delta.unsigned_abs())
+    };
+
+    pos
+        .filter(|&pos| pos <= i64::MAX as u64)
+        .ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid seek to a negative or overflowing position"
+        ))
+}
+
+/// Reads at the position of the file like [File::read], through a buffer of the `File`.
+///
+/// Mixing it with the async methods can return data read ahead before them,
+/// and the rate limit of the file doesn't apply.
+#[cfg(feature = "futures-io")]
+impl futures_io::AsyncRead for File {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        if this.compat.is_drained() {
+            // the position is behind what was read ahead, so it's where the kernel is now
+            let offset = this.pos.map_or(-1, |pos| pos as i64);
+
+            // an empty fill is the end of the file
+            futures_util::ready!(this.compat.poll_fill(cx, this.fd.as_raw_fd(), offset, this.ioprio))?;
+        }
+
+        let n = this.compat.copy_to(buf);
+        this.pos = this.pos.map(|pos| pos + n as u64);
+        Poll::Ready(Ok(n))
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl futures_io::AsyncWrite for File {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        // what was read ahead may be written over
+        this.compat.discard();
+
+        let offset = this.pos.map_or(-1, |pos| pos as i64);
+        let n = futures_util::ready!(this.compat.poll_write(cx, this.fd.as_raw_fd(), offset, this.ioprio, buf))?;
+        this.pos = this.pos.map(|pos| pos + n as u64);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        let n = futures_util::ready!(this.compat.poll_flush(cx))?;
+        this.pos = this.pos.map(|pos| pos + n as u64);
+        Poll::Ready(Ok(()))
+    }
+
+    #[inline]
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+/// Like [File::seek], seeking from the end is an `fstat(2)` on the thread.
+#[cfg(feature = "futures-io")]
+impl futures_io::AsyncSeek for File {
+    fn poll_seek(mut self: Pin<&mut Self>, cx: &mut Context<'_>, pos: io::SeekFrom) -> Poll<io::Result<u64>> {
+        futures_util::ready!(futures_io::AsyncWrite::poll_flush(self.as_mut(), cx))?;
+
+        let this = self.get_mut();
+        let cur = this.pos.ok_or_else(|| io::Error::from_raw_os_error(libc::ESPIPE))?;
+
+        let (base, delta) = match pos {
+            io::SeekFrom::Start(pos) => (pos, 0),
+            io::SeekFrom::Current(delta) => (cur, delta),
+            io::SeekFrom::End(delta) => {
+                let mut stat: libc::stat = unsafe { mem::zeroed() };
+
+                if unsafe { libc::fstat(this.fd.as_raw_fd(), &mut stat) } < 0 {
+                    return Poll::Ready(Err(io::Error::last_os_error()));
+                }
+
+                (stat.st_size as u64, delta)
+            }
+        };
+
+        let pos = seek_pos(base, delta)?;
+        this.compat.discard();
+        this.pos = Some(pos);
+        Poll::Ready(Ok(pos))
+    }
+}
+
+/// Advise the kernel about `addr..addr + len`, like `madvise(2)`.
+///
+/// `addr` must be page aligned, `len` is limited to 4 GiB by the ring.
+///
+/// # Safety
+///
+/// The range must be a mapping owned by the caller, and stay mapped until this completes.
+/// Advice like [MemAdvice::DontNeed] or [MemAdvice::Remove] changes its content,
+/// so nothing may hold references into it.
+pub async unsafe fn madvise(addr: *mut u8, len: usize, advice: MemAdvice) -> io::Result<()> {
+    if len > u32::MAX as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "madvise range is larger than 4 GiB"));
+    }
+
+    let entry = opcode::Madvise::new(addr as *const _, len as _, advice.to_raw())
+        .build();
+
+    let ret = safety_await!{
+        handle::push(entry)
+    };
+    ret?.ok()?;
+    Ok(())
+}
+
+/// Query the metadata of `path` with `statx(2)`, following symlinks.
+pub async fn metadata<P: AsRef<Path>>(path: P) -> io::Result<Metadata> {
+    statx(libc::AT_FDCWD, cstr(path.as_ref())?, 0).await
+}
+
+/// Like [metadata], but query a symlink itself rather than what it points to.
+pub async fn symlink_metadata<P: AsRef<Path>>(path: P) -> io::Result<Metadata> {
+    statx(libc::AT_FDCWD, cstr(path.as_ref())?, libc::AT_SYMLINK_NOFOLLOW).await
+}
+
+fn cstr(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
+
+// the op was interrupted before doing anything, and can be issued again.
+// `EAGAIN` is what `RWF_NOWAIT` asked for, retrying it would spin.
+fn is_retry(err: &io::Error, flags: RwFlags) -> bool {
+    match err.raw_os_error() {
+        Some(libc::EINTR) => true,
+        Some(libc::EAGAIN) => !flags.contains(RwFlags::NOWAIT),
+        _ => false
+    }
+}
+
+async fn statx(dirfd: RawFd, path: CString, flags: i32) -> io::Result<Metadata> {
+    // the kernel writes into the buffer until the op completes
+    let mut buf = (path, Box::new(unsafe { mem::zeroed::<libc::statx>() }));
+
+    let entry = opcode::Statx::new(dirfd, buf.0.as_ptr(), &mut *buf.1 as *mut libc::statx as *mut _)
+        .flags(flags)
+        .mask(libc::STATX_BASIC_STATS | libc::STATX_BTIME)
+        .build();
+
+    let ret = safety_await!{
+        [ buf ];
+        unsafe { handle::push(entry) }
+    };
+    ret?.ok()?;
+    Ok(Metadata(*buf.1))
+}
+
+/// Metadata of a file, from `statx(2)`.
+#[derive(Clone, Copy)]
+pub struct Metadata(libc::statx);
+
+impl Metadata {
+    /// The size of the file in bytes.
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.0.stx_size
+    }
+
+    /// The file type and permission bits, see `inode(7)`.
+    #[inline]
+    pub fn mode(&self) -> u32 {
+        u32::from(self.0.stx_mode)
+    }
+
+    #[inline]
+    pub fn permissions(&self) -> fs::Permissions {
+        fs::Permissions::from_mode(self.mode())
+    }
+
+    #[inline]
+    pub fn is_file(&self) -> bool {
+        self.mode() & libc::S_IFMT == libc::S_IFREG
+    }
+
+    #[inline]
+    pub fn is_dir(&self) -> bool {
+        self.mode() & libc::S_IFMT == libc::S_IFDIR
+    }
+
+    #[inline]
+    pub fn is_symlink(&self) -> bool {
+        self.mode() & libc::S_IFMT == libc::S_IFLNK
+    }
+
+    #[inline]
+    pub fn ino(&self) -> u64 {
+        self.0.stx_ino
+    }
+
+    #[inline]
+    pub fn nlink(&self) -> u32 {
+        self.0.stx_nlink
+    }
+
+    #[inline]
+    pub fn uid(&self) -> u32 {
+        self.0.stx_uid
+    }
+
+    #[inline]
+    pub fn gid(&self) -> u32 {
+        self.0.stx_gid
+    }
+
+    /// The device the file resides on.
+    #[inline]
+    pub fn dev(&self) -> u64 {
+        libc::makedev(self.0.stx_dev_major, self.0.stx_dev_minor)
+    }
+
+    /// The device the file represents, if it's a device file.
+    #[inline]
+    pub fn rdev(&self) -> u64 {
+        libc::makedev(self.0.stx_rdev_major, self.0.stx_rdev_minor)
+    }
+
+    /// The preferred block size for I/O.
+    #[inline]
+    pub fn blksize(&self) -> u32 {
+        self.0.stx_blksize
+    }
+
+    /// The number of 512-byte blocks allocated.
+    #[inline]
+    pub fn blocks(&self) -> u64 {
+        self.0.stx_blocks
+    }
+
+    #[inline]
+    pub fn accessed(&self) -> SystemTime {
+        system_time(&self.0.stx_atime)
+    }
+
+    #[inline]
+    pub fn modified(&self) -> SystemTime {
+        system_time(&self.0.stx_mtime)
+    }
+
+    /// The last status change.
+    #[inline]
+    pub fn changed(&self) -> SystemTime {
+        system_time(&self.0.stx_ctime)
+    }
+
+    /// The creation time, if the filesystem records it.
+    pub fn created(&self) -> Option<SystemTime> {
+        if self.0.stx_mask & libc::STATX_BTIME != 0 {
+            Some(system_time(&self.0.stx_btime))
+        } else {
+            None
+        }
+    }
+}
+
+fn system_time(ts: &libc::statx_timestamp) -> SystemTime {
+    let nsec = Duration::from_nanos(u64::from(ts.tv_nsec));
+
+    if ts.tv_sec >= 0 {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(ts.tv_sec as u64) + nsec
+    } else {
+        SystemTime::UNIX_EPOCH - Duration::from_secs(ts.tv_sec.unsigned_abs()) + nsec
+    }
+}
+
+impl std::fmt::Debug for Metadata {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Metadata")
+            .field("size", &self.size())
+            .field("mode", &format_args!("{:o}", self.mode()))
+            .field("ino", &self.ino())
+            .field("dev", &self.dev())
+            .field("modified", &self.modified())
+            .finish()
+    }
+}
+
+
+#[cfg(test)]
+pub(crate) fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("ritsu-{}-{}", std::process::id(), name))
+}
+
+#[test]
+fn test_sync() {
+    use crate::executor::block_on;
+
+    let path = temp_path("sync");
+    let file = File::from_std(fs::File::create(&path).unwrap());
+
+    block_on(async {
+        file.write_at(0, Bytes::from_static(b"hello")).await.unwrap();
+        file.sync_data().await.unwrap();
+        file.sync_all().await.unwrap();
+    });
+
+    assert_eq!(fs::read(&path).unwrap(), b"hello");
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_rw_flags() {
+    use crate::executor::block_on;
+
+    let path = temp_path("rw-flags");
+    let mut file = File::from_std(fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path).unwrap());
+
+    block_on(async {
+        file.set_rw_flags(RwFlags::DSYNC);
+        file.write_at(0, Bytes::from_static(b"hello")).await.unwrap();
+
+        // the offset is ignored
+        file.set_rw_flags(RwFlags::APPEND);
+        file.write_at(0, Bytes::from_static(b" world")).await.unwrap();
+
+        // just written, so it's in the page cache
+        file.set_rw_flags(RwFlags::NOWAIT);
+        let buf = file.read_at(0, BytesMut::with_capacity(16)).await.unwrap();
+        assert_eq!(&buf[..], b"hello world");
+
+        // an empty pipe would block, so it fails instead of being retried
+        let (mut rx, _tx) = crate::action::splice::pipe().unwrap();
+        rx.set_rw_flags(RwFlags::NOWAIT);
+        let err = rx.read_exact_at(-1, BytesMut::new(), 5).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    });
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_ioprio() {
+    use crate::executor::block_on;
+    use crate::sys;
+
+    let entry = handle::ioprio(Some(IoPriority::BestEffort(3)), opcode::Nop::new().build());
+    assert_eq!(sys::sqe(&entry).ioprio, (2 << 13) | 3);
+    let entry = handle::ioprio(None, opcode::Nop::new().build());
+    assert_eq!(sys::sqe(&entry).ioprio, 0);
+
+    let path = temp_path("ioprio");
+    fs::write(&path, b"hello").unwrap();
+    let mut file = File::from_std(fs::File::open(&path).unwrap());
+
+    block_on(async {
+        for &prio in &[IoPriority::BestEffort(0), IoPriority::BestEffort(7), IoPriority::Idle] {
+            file.set_priority(Some(prio));
+            let buf = file.read_at(0, BytesMut::with_capacity(16)).await.unwrap();
+            assert_eq!(&buf[..], b"hello");
+        }
+    });
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_exact() {
+    use std::thread;
+    use std::io::{ Read, Write };
+    use std::os::unix::io::FromRawFd;
+    use crate::executor::block_on;
+
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    let (rx, tx) = unsafe { (fs::File::from_raw_fd(fds[0]), fs::File::from_raw_fd(fds[1])) };
+
+    // a pipe reads what is there, in two parts here
+    let writer = thread::spawn(move || {
+        let mut tx = tx;
+        tx.write_all(b"hel").unwrap();
+        thread::sleep(Duration::from_millis(10));
+        tx.write_all(b"lo").unwrap();
+    });
+
+    let rx = File::from_std(rx);
+    let buf = block_on(rx.read_exact_at(-1, BytesMut::new(), 5)).unwrap();
+    assert_eq!(&buf[..], b"hello");
+    writer.join().unwrap();
+
+    // the writer is gone
+    let err = block_on(rx.read_exact_at(-1, BytesMut::new(), 5)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    let (rx, tx) = unsafe { (fs::File::from_raw_fd(fds[0]), fs::File::from_raw_fd(fds[1])) };
+
+    // more than the pipe holds at once
+    let data = (0..256 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+    let reader = thread::spawn(move || {
+        let mut buf = Vec::new();
+        let mut rx = rx;
+        rx.read_to_end(&mut buf).unwrap();
+        buf
+    });
+
+    let tx = File::from_std(tx);
+    block_on(async {
+        tx.write_all_at(-1, Bytes::from(data.clone())).await.unwrap();
+        tx.close().await.unwrap();
+    });
+    assert_eq!(reader.join().unwrap(), data);
+}
+
+#[test]
+fn test_vectored() {
+    use crate::executor::block_on;
+
+    let path = temp_path("vectored");
+    let file = fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path).unwrap();
+    let file = File::from_std(file);
+
+    let bufs = vec![Bytes::from_static(b"head:"), Bytes::new(), Bytes::from_static(b"payload")];
+    let bufs = block_on(file.write_vectored_at(0, bufs)).unwrap();
+    assert!(bufs.iter().all(|buf| buf.is_empty()));
+    assert_eq!(fs::read(&path).unwrap(), b"head:payload");
+
+    let bufs = vec![BytesMut::with_capacity(5), BytesMut::with_capacity(64)];
+    let bufs = block_on(file.read_vectored_at(0, bufs)).unwrap();
+    assert_eq!(bufs[0].len() + bufs[1].len(), 12);
+    assert_eq!([&bufs[0][..], &bufs[1][..]].concat(), b"head:payload");
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_cursor() {
+    use std::io::{ Seek, SeekFrom };
+    use crate::executor::block_on;
+
+    let path = temp_path("cursor");
+    let file = fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path).unwrap();
+    let mut file = File::from_std(file);
+    assert_eq!(file.position(), Some(0));
+
+    block_on(async {
+        assert!(file.write(Bytes::from_static(b"hello")).await.unwrap().is_empty());
+        assert!(file.write(Bytes::from_static(b" world")).await.unwrap().is_empty());
+        assert_eq!(file.position(), Some(11));
+
+        assert_eq!(file.seek(SeekFrom::Start(0)).await.unwrap(), 0);
+        let buf = file.read(BytesMut::with_capacity(5)).await.unwrap();
+        assert_eq!(&buf[..], b"hello");
+
+        assert_eq!(file.seek(SeekFrom::End(-5)).await.unwrap(), 6);
+        let buf = file.read(BytesMut::with_capacity(64)).await.unwrap();
+        assert_eq!(&buf[..], b"world");
+        assert!(file.read(BytesMut::with_capacity(64)).await.unwrap().is_empty());
+
+        assert_eq!(file.seek(SeekFrom::Current(-11)).await.unwrap(), 0);
+        let err = file.seek(SeekFrom::Current(-1)).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    });
+
+    let mut file = file.into_std();
+    assert_eq!(file.stream_position().unwrap(), 0);
+
+    file.seek(SeekFrom::Start(6)).unwrap();
+    assert_eq!(File::from_std(file).position(), Some(6));
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_sync_range() {
+    use crate::executor::block_on;
+
+    let path = temp_path("sync_range");
+    let file = File::from_std(fs::File::create(&path).unwrap());
+
+    block_on(async {
+        file.write_at(0, Bytes::from_static(b"hello")).await.unwrap();
+        let flags = SyncRangeFlags::WAIT_BEFORE | SyncRangeFlags::WRITE | SyncRangeFlags::WAIT_AFTER;
+        file.sync_range(0, 0, flags).await.unwrap();
+    });
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_advise() {
+    use crate::executor::block_on;
+
+    let path = temp_path("advise");
+    fs::write(&path, b"hello").unwrap();
+    let file = File::from_std(fs::File::open(&path).unwrap());
+
+    block_on(async {
+        file.advise(0, 0, Advice::Sequential).await.unwrap();
+        file.advise(0, 5, Advice::WillNeed).await.unwrap();
+        file.advise_detached(0, 0, Advice::DontNeed).unwrap();
+    });
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_allocate() {
+    use crate::executor::block_on;
+
+    let path = temp_path("allocate");
+    let file = File::from_std(fs::File::create(&path).unwrap());
+
+    block_on(async {
+        file.allocate(0, 8192, FallocateFlags::empty()).await.unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 8192);
+
+        file.allocate(8192, 4096, FallocateFlags::KEEP_SIZE).await.unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 8192);
+
+        // not every filesystem can punch holes
+        let ret = file.allocate(0, 4096, FallocateFlags::PUNCH_HOLE | FallocateFlags::KEEP_SIZE).await;
+        assert!(ret.is_ok() || ret.unwrap_err().raw_os_error() == Some(libc::EOPNOTSUPP));
+        assert_eq!(fs::metadata(&path).unwrap().len(), 8192);
+    });
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_set_len() {
+    use crate::executor::block_on;
+
+    let path = temp_path("set_len");
+    let file = File::from_std(fs::File::create(&path).unwrap());
+
+    block_on(async {
+        file.set_len(4096).await.unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 4096);
+
+        file.set_len(10).await.unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 10);
+    });
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_metadata() {
+    use crate::executor::block_on;
+
+    let path = temp_path("metadata");
+    fs::write(&path, b"hello").unwrap();
+    let file = File::from_std(fs::File::open(&path).unwrap());
+
+    block_on(async {
+        let std_metadata = fs::metadata(&path).unwrap();
+        let file_metadata = file.metadata().await.unwrap();
+        assert_eq!(file_metadata.size(), 5);
+        assert!(file_metadata.is_file());
+        assert_eq!(file_metadata.modified(), std_metadata.modified().unwrap());
+
+        let path_metadata = metadata(&path).await.unwrap();
+        assert_eq!(path_metadata.ino(), file_metadata.ino());
+        assert!(metadata(std::env::temp_dir()).await.unwrap().is_dir());
+
+        let err = metadata(temp_path("missing")).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    });
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_madvise() {
+    use std::ptr;
+    use crate::executor::block_on;
+
+    let len = 4 * 4096;
+    let addr = unsafe {
+        libc::mmap(
+            ptr::null_mut(), len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1, 0
+        )
+    };
+    assert_ne!(addr, libc::MAP_FAILED);
+    let addr = addr as *mut u8;
+
+    block_on(async {
+        unsafe {
+            addr.write(1);
+            madvise(addr, len, MemAdvice::WillNeed).await.unwrap();
+            madvise(addr, len, MemAdvice::DontNeed).await.unwrap();
+            assert_eq!(addr.read(), 0);
+
+            let err = madvise(addr.add(1), len, MemAdvice::Normal).await.unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::EINVAL));
+        }
+    });
+
+    unsafe {
+        libc::munmap(addr as *mut _, len);
+    }
+}
+
+#[test]
+fn test_close() {
+    use std::os::unix::fs::MetadataExt;
+    use crate::executor::block_on;
+    use crate::time::sleep;
+
+    let path = temp_path("close");
+
+    // the fd may be reused by another test right away
+    let is_open = |fd: RawFd, ino: u64| unsafe {
+        let mut stat: libc::stat = mem::zeroed();
+        libc::fstat(fd, &mut stat) == 0 && stat.st_ino == ino
+    };
+
+    block_on(async {
+        let file = File::create(&path).await.unwrap();
+        file.close().await.unwrap();
+
+        let file = File::open(&path).await.unwrap();
+        let (fd, ino) = (file.as_raw_fd(), file.metadata().await.unwrap().ino());
+        assert!(is_open(fd, ino));
+        drop(file);
+
+        // submitted by the next park
+        sleep(std::time::Duration::from_millis(1)).await.unwrap();
+        assert!(!is_open(fd, ino));
+    });
+
+    // no ring on this thread
+    let path2 = path.clone();
+    let (fd, ino) = std::thread::spawn(move || {
+        let file = File::from_std(fs::File::open(&path2).unwrap());
+        let ret = (file.as_raw_fd(), file.fd.metadata().unwrap().ino());
+        drop(file);
+        ret
+    })
+        .join()
+        .unwrap();
+    assert!(!is_open(fd, ino));
+
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,131 @@
+use std::io;
+use std::cell::Cell;
+use std::path::Path;
+use bytes::BytesMut;
+use futures_util::future;
+use super::{ File, FallocateFlags, OpenOptions };
+
+
+/// Copy files with several reads and writes in flight, see [copy].
+#[derive(Clone, Debug)]
+pub struct CopyOptions {
+    queue_depth: usize,
+    chunk_size: usize
+}
+
+impl CopyOptions {
+    pub fn new() -> CopyOptions {
+        CopyOptions {
+            queue_depth: 8,
+            chunk_size: 1024 * 1024
+        }
+    }
+
+    /// How many chunks are read or written at the same time, at least one.
+    pub fn queue_depth(&mut self, depth: usize) -> &mut Self {
+        self.queue_depth = depth.max(1);
+        self
+    }
+
+    /// How many bytes a read and its write move, at least one.
+    pub fn chunk_size(&mut self, size: usize) -> &mut Self {
+        self.chunk_size = size.max(1);
+        self
+    }
+
+    /// Copy the content of `src` to `dst`, returns how many bytes were copied.
+    ///
+    /// `dst` is created with the permissions of `src` or truncated,
+    /// and allocated to the full size upfront. Then each of the `queue_depth`
+    /// workers reads a chunk and writes it at the same offset, and takes the next one.
+    pub async fn copy<P: AsRef<Path>, Q: AsRef<Path>>(&self, src: P, dst: Q) -> io::Result<u64> {
+        let src = File::open(src).await?;
+        let meta = src.metadata().await?;
+
+        if !meta.is_file() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the source path is not a file"));
+        }
+
+        let dst = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(meta.mode() & 0o7777)
+            .open(dst)
+            .await?;
+
+        let size = meta.size();
+
+        if size == 0 {
+            return Ok(0);
+        }
+
+        // it's only an optimization, not every filesystem supports it.
+        match dst.allocate(0, size as i64, FallocateFlags::empty()).await {
+            Ok(()) => (),
+            Err(ref err) if err.raw_os_error() == Some(libc::EOPNOTSUPP) => (),
+            Err(err) => return Err(err)
+        }
+
+        let next = Cell::new(0);
+        let chunk = self.chunk_size as u64;
+        let workers = (0..self.queue_depth).map(|_| async {
+            loop {
+                let offset = next.get();
+
+                if offset >= size {
+                    return Ok::<_, io::Error>(())
+                }
+
+                next.set(offset + chunk);
+                let len = chunk.min(size - offset) as usize;
+
+                let buf = src.read_exact_at(offset as i64, BytesMut::with_capacity(len), len).await?;
+                dst.write_all_at(offset as i64, buf.freeze()).await?;
+            }
+        });
+
+        future::try_join_all(workers).await?;
+
+        Ok(size)
+    }
+}
+
+impl Default for CopyOptions {
+    fn default() -> CopyOptions {
+        CopyOptions::new()
+    }
+}
+
+/// Copy the content of `src` to `dst` with the default [CopyOptions].
+pub async fn copy<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> io::Result<u64> {
+    CopyOptions::new().copy(src, dst).await
+}
+
+
+#[test]
+fn test_copy() {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use crate::executor::block_on;
+    use super::temp_path;
+
+    let src = temp_path("copy-src");
+    let dst = temp_path("copy-dst");
+    let data = (0..1_000_003u32).map(|i| (i % 241) as u8).collect::<Vec<_>>();
+    fs::write(&src, &data).unwrap();
+    fs::set_permissions(&src, fs::Permissions::from_mode(0o640)).unwrap();
+
+    let n = block_on(CopyOptions::new().queue_depth(4).chunk_size(100_000).copy(&src, &dst)).unwrap();
+    assert_eq!(n, data.len() as u64);
+    assert_eq!(fs::read(&dst).unwrap(), data);
+    assert_eq!(fs::metadata(&dst).unwrap().permissions().mode() & 0o777, 0o640);
+
+    // shorter than before, it's truncated
+    fs::write(&src, b"hello").unwrap();
+    assert_eq!(block_on(copy(&src, &dst)).unwrap(), 5);
+    assert_eq!(fs::read(&dst).unwrap(), b"hello");
+
+    fs::remove_file(&src).unwrap();
+    fs::remove_file(&dst).unwrap();
+}
@@ -0,0 +1,271 @@
+use std::{ io, mem, ptr };
+use std::rc::Rc;
+use std::pin::Pin;
+use std::ffi::{ OsStr, OsString };
+use std::path::{ Path, PathBuf };
+use std::task::{ Context, Poll };
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use futures_util::future::LocalBoxFuture;
+use futures_util::stream::Stream;
+use crate::{ sys, handle };
+use super::{ File, Metadata, OpenOptions, symlink_metadata };
+
+
+// enough for a few hundred entries per syscall
+const BUF_SIZE: usize = 32 * 1024;
+
+/// The entries of a directory, see [read_dir].
+pub struct ReadDir {
+    dir: Rc<File>,
+    path: Rc<Path>,
+    buf: Vec<u8>,
+    pos: usize,
+    fut: Option<LocalBoxFuture<'static, (io::Result<usize>, Vec<u8>)>>,
+    done: bool
+}
+
+/// An entry of a [ReadDir].
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    dir: Rc<Path>,
+    name: OsString,
+    ino: u64,
+    kind: u8
+}
+
+/// The type of a [DirEntry].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Dir,
+    Symlink,
+    BlockDevice,
+    CharDevice,
+    Fifo,
+    Socket
+}
+
+/// List the entries of `path`, without `.` and `..`.
+///
+/// There is no io_uring opcode for `getdents64(2)`, the directory is read
+/// on the blocking pool, a batch of entries per syscall.
+///
+/// ```
+/// use futures_util::stream::TryStreamExt;
+/// use ritsu::executor::block_on;
+/// use ritsu::action::fs;
+///
+/// block_on(async {
+///     let entries = fs::read_dir("/").await?.try_collect::<Vec<_>>().await?;
+///     assert!(entries.iter().any(|entry| entry.file_name() == "tmp"));
+///     Ok::<_, std::io::Error>(())
+/// }).unwrap();
+/// ```
+pub async fn read_dir<P: AsRef<Path>>(path: P) -> io::Result<ReadDir> {
+    let path = path.as_ref();
+    let dir = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECTORY)
+        .open(path)
+        .await?;
+
+    Ok(ReadDir {
+        dir: Rc::new(dir),
+        path: Rc::from(path),
+        buf: Vec::with_capacity(BUF_SIZE),
+        pos: 0,
+        fut: None,
+        done: false
+    })
+}
+
+async fn getdents(dir: Rc<File>, buf: Vec<u8>) -> (io::Result<usize>, Vec<u8>) {
+    let mut res = (dir, buf);
+    res.1.clear();
+
+    let mut entry = sys::entry(sys::RITSU_OP_GETDENTS);
+    let sqe = sys::sqe_mut(&mut entry);
+    sqe.fd = res.0.as_raw_fd();
+    sqe.addr = res.1.as_mut_ptr() as _;
+    sqe.len = res.1.capacity() as _;
+
+    // the directory is kept open while the pool reads it
+    let ret = safety_await!{
+        [ res ];
+        unsafe { handle::push(entry) }
+    };
+    let (_, mut buf) = res;
+
+    let ret = match ret {
+        Ok(ret) => ret.result(),
+        Err(err) => return (Err(err), buf)
+    };
+
+    if ret >= 0 {
+        unsafe {
+            buf.set_len(ret as usize);
+        }
+
+        (Ok(ret as usize), buf)
+    } else {
+        (Err(io::Error::from_raw_os_error(-ret)), buf)
+    }
+}
+
+impl ReadDir {
+    // the next entry of the batch, `linux_dirent64` in `getdents64(2)`.
+    fn next_entry(&mut self) -> Option<DirEntry> {
+        const NAME: usize = 19;
+
+        while self.pos + NAME < self.buf.len() {
+            let ent = &self.buf[self.pos..];
+
+            let (ino, reclen, kind) = unsafe {
+                let ino = ptr::read_unaligned(ent.as_ptr() as *const u64);
+                let reclen = ptr::read_unaligned(ent.as_ptr().add(16) as *const u16);
+                (ino, usize::from(reclen), ent[18])
+            };
+
+            let name = &ent[NAME..reclen];
+            let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+            self.pos += reclen;
+
+            if name == b"." || name == b".." {
+                continue
+            }
+
+            return Some(DirEntry {
+                dir: self.path.clone(),
+                name: OsStr::from_bytes(name).to_owned(),
+                ino, kind
+            });
+        }
+
+        None
+    }
+}
+
+impl Stream for ReadDir {
+    type Item = io::Result<DirEntry>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            if let Some(entry) = this.next_entry() {
+                return Poll::Ready(Some(Ok(entry)));
+            }
+
+            if this.done {
+                return Poll::Ready(None);
+            }
+
+            let (dir, buf) = (&this.dir, &mut this.buf);
+            let fut = this.fut.get_or_insert_with(|| Box::pin(getdents(dir.clone(), mem::take(buf))));
+
+            let (ret, buf) = match fut.as_mut().poll(cx) {
+                Poll::Ready(ret) => ret,
+                Poll::Pending => return Poll::Pending
+            };
+            this.fut = None;
+            this.buf = buf;
+            this.pos = 0;
+
+            match ret {
+                Ok(0) => this.done = true,
+                Ok(_) => (),
+                Err(err) => {
+                    this.done = true;
+                    return Poll::Ready(Some(Err(err)));
+                }
+            }
+        }
+    }
+}
+
+impl DirEntry {
+    /// The directory passed to [read_dir] joined with [DirEntry::file_name].
+    pub fn path(&self) -> PathBuf {
+        self.dir.join(&self.name)
+    }
+
+    #[inline]
+    pub fn file_name(&self) -> &OsStr {
+        &self.name
+    }
+
+    #[inline]
+    pub fn ino(&self) -> u64 {
+        self.ino
+    }
+
+    /// The type reported by the filesystem,
+    /// `None` if it doesn't and [DirEntry::metadata] is needed.
+    pub fn file_type(&self) -> Option<FileType> {
+        match self.kind {
+            libc::DT_REG => Some(FileType::File),
+            libc::DT_DIR => Some(FileType::Dir),
+            libc::DT_LNK => Some(FileType::Symlink),
+            libc::DT_BLK => Some(FileType::BlockDevice),
+            libc::DT_CHR => Some(FileType::CharDevice),
+            libc::DT_FIFO => Some(FileType::Fifo),
+            libc::DT_SOCK => Some(FileType::Socket),
+            _ => None
+        }
+    }
+
+    /// The metadata of the entry itself, symlinks are not followed.
+    pub async fn metadata(&self) -> io::Result<Metadata> {
+        symlink_metadata(self.path()).await
+    }
+}
+
+
+#[test]
+fn test_read_dir() {
+    use std::fs;
+    use futures_util::stream::TryStreamExt;
+    use crate::executor::block_on;
+    use super::temp_path;
+
+    let path = temp_path("read-dir");
+    fs::create_dir(&path).unwrap();
+    fs::create_dir(path.join("sub")).unwrap();
+    std::os::unix::fs::symlink("sub", path.join("link")).unwrap();
+
+    // more than fits in a batch
+    for i in 0..2000 {
+        fs::write(path.join(format!("file-{:04}", i)), b"").unwrap();
+    }
+
+    let mut entries = block_on(async {
+        read_dir(&path).await?.try_collect::<Vec<_>>().await
+    }).unwrap();
+    entries.sort_by(|a, b| a.file_name().cmp(b.file_name()));
+
+    assert_eq!(entries.len(), 2002);
+    assert_eq!(entries[0].file_name(), "file-0000");
+    assert_eq!(entries[0].path(), path.join("file-0000"));
+    assert_eq!(entries[2000].file_name(), "link");
+    assert_eq!(entries[2001].file_name(), "sub");
+
+    for entry in &entries {
+        let ino = fs::symlink_metadata(entry.path()).unwrap();
+        assert_eq!(entry.ino(), std::os::unix::fs::MetadataExt::ino(&ino));
+    }
+
+    if entries[2001].file_type().is_some() {
+        assert_eq!(entries[0].file_type(), Some(FileType::File));
+        assert_eq!(entries[2000].file_type(), Some(FileType::Symlink));
+        assert_eq!(entries[2001].file_type(), Some(FileType::Dir));
+    }
+
+    let meta = block_on(entries[2000].metadata()).unwrap();
+    assert!(meta.is_symlink());
+
+    let err = block_on(read_dir(path.join("file-0000"))).err().unwrap();
+    assert_eq!(err.raw_os_error(), Some(libc::ENOTDIR));
+
+    fs::remove_dir_all(&path).unwrap();
+}
@@ -0,0 +1,210 @@
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicU8, Ordering };
+use std::os::unix::io::{ AsRawFd, RawFd };
+use crate::{ sys, handle };
+use super::File;
+
+
+// the handoff of a lock taken on the blocking pool
+const PENDING: u8 = 0;
+const LOCKED: u8 = 1;
+const ABANDONED: u8 = 2;
+const DONE: u8 = 3;
+
+/// An advisory lock on a [File], released on drop.
+///
+/// See [File::lock_exclusive].
+#[must_use]
+#[derive(Debug)]
+pub struct FileLock<'a> {
+    file: &'a File
+}
+
+// unlocks a lock the pool took after the waiting future was dropped.
+struct Waiting<'a> {
+    file: &'a File,
+    state: Arc<AtomicU8>
+}
+
+impl File {
+    /// Wait for a shared lock on the whole file, see `flock(2)`.
+    ///
+    /// The lock belongs to the open file, so it conflicts with other opens of it,
+    /// even in this process. There is no io_uring op for it,
+    /// the wait runs on the blocking pool.
+    pub async fn lock_shared(&self) -> io::Result<FileLock<'_>> {
+        self.lock(libc::LOCK_SH).await
+    }
+
+    /// Wait for an exclusive lock on the whole file, like [File::lock_shared].
+    ///
+    /// Dropping the future gives up the wait, a lock taken anyway is released.
+    pub async fn lock_exclusive(&self) -> io::Result<FileLock<'_>> {
+        self.lock(libc::LOCK_EX).await
+    }
+
+    /// Take a shared lock if nobody holds an exclusive one, without waiting.
+    pub fn try_lock_shared(&self) -> io::Result<Option<FileLock<'_>>> {
+        self.try_lock_with(libc::LOCK_SH)
+    }
+
+    /// Take an exclusive lock if nobody holds one, without waiting.
+    pub fn try_lock(&self) -> io::Result<Option<FileLock<'_>>> {
+        self.try_lock_with(libc::LOCK_EX)
+    }
+
+    fn try_lock_with(&self, op: i32) -> io::Result<Option<FileLock<'_>>> {
+        if unsafe { libc::flock(self.as_raw_fd(), op | libc::LOCK_NB) } == 0 {
+            return Ok(Some(FileLock { file: self }));
+        }
+
+        match io::Error::last_os_error() {
+            err if err.raw_os_error() == Some(libc::EWOULDBLOCK) => Ok(None),
+            err => Err(err)
+        }
+    }
+
+    async fn lock(&self, op: i32) -> io::Result<FileLock<'_>> {
+        // the pool owns a dup, it shares the lock and outlives a close of `self`.
+        let fd = unsafe { libc::fcntl(self.as_raw_fd(), libc::F_DUPFD_CLOEXEC, 0) };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let state = Arc::new(AtomicU8::new(PENDING));
+        let waiting = Waiting { file: self, state: state.clone() };
+        let state = Arc::into_raw(state);
+
+        let mut entry = sys::entry(sys::RITSU_OP_FLOCK);
+        let sqe = sys::sqe_mut(&mut entry);
+        sqe.fd = fd;
+        sqe.op_flags = op as _;
+        sqe.addr = state as _;
+
+        let ret = match unsafe { handle::push(entry) } {
+            Ok(fut) => fut.await,
+            Err(err) => {
+                unsafe {
+                    drop(Arc::from_raw(state));
+                    libc::close(fd);
+                }
+
+                return Err(err);
+            }
+        };
+        let ret = ret.result();
+
+        waiting.state.store(DONE, Ordering::Release);
+
+        if ret >= 0 {
+            Ok(FileLock { file: self })
+        } else {
+            Err(io::Error::from_raw_os_error(-ret))
+        }
+    }
+}
+
+/// Take the lock on the blocking pool, and close `fd`.
+///
+/// # Safety
+///
+/// `state` must come from [File::lock].
+pub(crate) unsafe fn flock(fd: RawFd, op: i32, state: *const AtomicU8) -> i32 {
+    let state = Arc::from_raw(state);
+
+    let ret = loop {
+        if libc::flock(fd, op) == 0 {
+            break 0;
+        }
+
+        match io::Error::last_os_error().raw_os_error() {
+            Some(libc::EINTR) => (),
+            err => break -err.unwrap_or(libc::EIO)
+        }
+    };
+
+    if ret == 0 && state.compare_exchange(PENDING, LOCKED, Ordering::AcqRel, Ordering::Acquire).is_err() {
+        libc::flock(fd, libc::LOCK_UN);
+    }
+
+    libc::close(fd);
+
+    ret
+}
+
+impl FileLock<'_> {
+    pub fn unlock(self) -> io::Result<()> {
+        let fd = self.file.as_raw_fd();
+        std::mem::forget(self);
+
+        if unsafe { libc::flock(fd, libc::LOCK_UN) } == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
+
+impl Drop for FileLock<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            libc::flock(self.file.as_raw_fd(), libc::LOCK_UN);
+        }
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        if self.state.swap(ABANDONED, Ordering::AcqRel) == LOCKED {
+            unsafe {
+                libc::flock(self.file.as_raw_fd(), libc::LOCK_UN);
+            }
+        }
+    }
+}
+
+
+#[test]
+fn test_lock() {
+    use std::time::Duration;
+    use futures_util::future::{ self, FutureExt };
+    use crate::executor::block_on;
+
+    let path = super::temp_path("lock");
+    let open = || File::from_std(std::fs::OpenOptions::new().create(true).truncate(false).write(true).open(&path).unwrap());
+    let (a, b, c) = (open(), open(), open());
+
+    block_on(async {
+        let guard = a.lock_exclusive().await.unwrap();
+        assert!(b.try_lock_shared().unwrap().is_none());
+
+        let unlock = async {
+            crate::time::sleep(Duration::from_millis(10)).await.unwrap();
+            drop(guard);
+        };
+        let (lock, ()) = future::join(b.lock_shared(), unlock).await;
+        let shared = lock.unwrap();
+        assert!(c.try_lock_shared().unwrap().is_some());
+        assert!(c.try_lock().unwrap().is_none());
+
+        // given up while the pool waits, the lock it takes later is released.
+        let mut waiting = Box::pin(a.lock_exclusive());
+        assert!((&mut waiting).now_or_never().is_none());
+        drop(waiting);
+        shared.unlock().unwrap();
+
+        let mut tries = 0;
+        while c.try_lock().unwrap().is_none() {
+            tries += 1;
+            assert!(tries < 100);
+            crate::time::sleep(Duration::from_millis(1)).await.unwrap();
+        }
+    });
+
+    for file in [a, b, c] {
+        drop(file.into_std());
+    }
+    std::fs::remove_file(&path).unwrap();
+}
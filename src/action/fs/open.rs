@@ -0,0 +1,250 @@
+use std::{ fs, io };
+use std::path::Path;
+use std::os::unix::io::{ AsRawFd, FromRawFd, RawFd };
+use bitflags::bitflags;
+use io_uring::opcode::{ self, types };
+use crate::{ sys, handle };
+use super::{ cstr, File };
+
+
+bitflags!{
+    /// How [OpenOptions] resolves the path, see `openat2(2)`.
+    pub struct ResolveFlags: u64 {
+        /// Don't cross mount points.
+        const NO_XDEV = libc::RESOLVE_NO_XDEV;
+        /// Don't follow magic links like `/proc/self/fd/*`.
+        const NO_MAGICLINKS = libc::RESOLVE_NO_MAGICLINKS;
+        /// Don't follow any symlink.
+        const NO_SYMLINKS = libc::RESOLVE_NO_SYMLINKS;
+        /// Fail with `EXDEV` if the path escapes the directory it's resolved in,
+        /// through `..` or absolute symlinks.
+        const BENEATH = libc::RESOLVE_BENEATH;
+        /// Resolve as if the directory was the root, like `chroot(2)`.
+        const IN_ROOT = libc::RESOLVE_IN_ROOT;
+        /// Fail with `EAGAIN` rather than block on a lookup that isn't cached.
+        const CACHED = libc::RESOLVE_CACHED;
+    }
+}
+
+/// Open files with `IORING_OP_OPENAT2` (5.6), like [std::fs::OpenOptions].
+///
+/// ```no_run
+/// # async fn f(root: ritsu::action::fs::File) -> std::io::Result<()> {
+/// use ritsu::action::fs::{ OpenOptions, ResolveFlags };
+///
+/// // a request path can't escape the root
+/// let file = OpenOptions::new()
+///     .read(true)
+///     .resolve(ResolveFlags::BENEATH | ResolveFlags::NO_MAGICLINKS)
+///     .open_at(&root, "static/index.html")
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    append: bool,
+    truncate: bool,
+    create: bool,
+    create_new: bool,
+    mode: u32,
+    custom_flags: i32,
+    resolve: ResolveFlags
+}
+
+impl OpenOptions {
+    pub fn new() -> OpenOptions {
+        OpenOptions {
+            read: false,
+            write: false,
+            append: false,
+            truncate: false,
+            create: false,
+            create_new: false,
+            mode: 0o666,
+            custom_flags: 0,
+            resolve: ResolveFlags::empty()
+        }
+    }
+
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.read = read;
+        self
+    }
+
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.write = write;
+        self
+    }
+
+    pub fn append(&mut self, append: bool) -> &mut Self {
+        self.append = append;
+        self
+    }
+
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.truncate = truncate;
+        self
+    }
+
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    /// Create the file and fail if it exists already.
+    pub fn create_new(&mut self, create_new: bool) -> &mut Self {
+        self.create_new = create_new;
+        self
+    }
+
+    /// The permissions of a created file, before the umask. `0o666` by default.
+    pub fn mode(&mut self, mode: u32) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Extra `O_*` flags, the access mode bits are ignored.
+    pub fn custom_flags(&mut self, flags: i32) -> &mut Self {
+        self.custom_flags = flags;
+        self
+    }
+
+    pub fn resolve(&mut self, resolve: ResolveFlags) -> &mut Self {
+        self.resolve = resolve;
+        self
+    }
+
+    /// Open `path`, relative paths are resolved in the current directory.
+    pub async fn open<P: AsRef<Path>>(&self, path: P) -> io::Result<File> {
+        self.openat(libc::AT_FDCWD, path.as_ref()).await
+    }
+
+    /// Open `path` relative to the directory `dir`,
+    /// [ResolveFlags] are about this directory.
+    pub async fn open_at<D: AsRawFd, P: AsRef<Path>>(&self, dir: &D, path: P) -> io::Result<File> {
+        self.openat(dir.as_raw_fd(), path.as_ref()).await
+    }
+
+    async fn openat(&self, dirfd: RawFd, path: &Path) -> io::Result<File> {
+        let flags = libc::O_CLOEXEC
+            | self.access_mode()?
+            | self.creation_mode()?
+            | (self.custom_flags & !libc::O_ACCMODE);
+
+        // mode must be zero unless a file can be created,
+        // `O_TMPFILE` includes the bit of `O_DIRECTORY`.
+        let mode = if flags & libc::O_CREAT != 0 || flags & libc::O_TMPFILE == libc::O_TMPFILE {
+            self.mode
+        } else {
+            0
+        };
+
+        let how = sys::OpenHow {
+            flags: flags as u64,
+            mode: u64::from(mode),
+            resolve: self.resolve.bits()
+        };
+
+        // the kernel reads both when the op is submitted
+        let mut buf = (cstr(path)?, Box::new(how));
+        let entry = opcode::Openat2::new(
+            dirfd,
+            buf.0.as_ptr(),
+            &*buf.1 as *const sys::OpenHow as *const types::OpenHow
+        )
+            .build();
+
+        let ret = safety_await!{
+            [ buf ];
+            unsafe { handle::push(entry) }
+        };
+        drop(buf);
+        let fd = ret?.ok()? as RawFd;
+        Ok(File::from_std(unsafe { fs::File::from_raw_fd(fd) }))
+    }
+
+    fn access_mode(&self) -> io::Result<i32> {
+        match (self.read, self.write, self.append) {
+            (true, false, false) => Ok(libc::O_RDONLY),
+            (false, true, false) => Ok(libc::O_WRONLY),
+            (true, true, false) => Ok(libc::O_RDWR),
+            (false, _, true) => Ok(libc::O_WRONLY | libc::O_APPEND),
+            (true, _, true) => Ok(libc::O_RDWR | libc::O_APPEND),
+            (false, false, false) => Err(io::Error::from_raw_os_error(libc::EINVAL))
+        }
+    }
+
+    fn creation_mode(&self) -> io::Result<i32> {
+        match (self.write, self.append) {
+            (true, false) => (),
+            (false, false) => if self.truncate || self.create || self.create_new {
+                return Err(io::Error::from_raw_os_error(libc::EINVAL));
+            },
+            (_, true) => if self.truncate && !self.create_new {
+                return Err(io::Error::from_raw_os_error(libc::EINVAL));
+            }
+        }
+
+        Ok(match (self.create, self.truncate, self.create_new) {
+            (false, false, false) => 0,
+            (true, false, false) => libc::O_CREAT,
+            (false, true, false) => libc::O_TRUNC,
+            (true, true, false) => libc::O_CREAT | libc::O_TRUNC,
+            (_, _, true) => libc::O_CREAT | libc::O_EXCL
+        })
+    }
+}
+
+impl Default for OpenOptions {
+    fn default() -> OpenOptions {
+        OpenOptions::new()
+    }
+}
+
+impl File {
+    /// Open `path` read-only.
+    pub async fn open<P: AsRef<Path>>(path: P) -> io::Result<File> {
+        OpenOptions::new().read(true).open(path).await
+    }
+
+    /// Open `path` write-only, creating it or truncating it.
+    pub async fn create<P: AsRef<Path>>(path: P) -> io::Result<File> {
+        OpenOptions::new().write(true).create(true).truncate(true).open(path).await
+    }
+}
+
+
+#[test]
+fn test_open() {
+    use crate::executor::block_on;
+    use super::temp_path;
+
+    let dir = temp_path("open");
+    fs::create_dir(&dir).unwrap();
+    fs::write(dir.join("file"), b"hello").unwrap();
+
+    block_on(async {
+        let file = File::open(dir.join("file")).await.unwrap();
+        assert_eq!(file.metadata().await.unwrap().size(), 5);
+
+        let file = File::create(dir.join("new")).await.unwrap();
+        file.write_at(0, bytes::Bytes::from_static(b"world")).await.unwrap();
+        assert_eq!(fs::read(dir.join("new")).unwrap(), b"world");
+
+        let err = OpenOptions::new().write(true).create_new(true).open(dir.join("new")).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+        let root = File::open(&dir).await.unwrap();
+        let mut beneath = OpenOptions::new();
+        beneath.read(true).resolve(ResolveFlags::BENEATH);
+
+        assert!(beneath.open_at(&root, "file").await.is_ok());
+        let err = beneath.open_at(&root, "../open/file").await.unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EXDEV));
+    });
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,169 @@
+//! Path operations through the ring.
+//!
+//! Paths are resolved like `std::fs`, relative to the current directory.
+//! The path strings are owned by the op futures, and leaked if a future is
+//! dropped before the kernel is done with them.
+
+use std::io;
+use std::path::Path;
+use std::ffi::CString;
+use bitflags::bitflags;
+use crate::{ sys, handle, SubmissionEntry };
+use super::cstr;
+
+
+bitflags!{
+    /// See `renameat2(2)`.
+    pub struct RenameFlags: u32 {
+        /// Fail with `EEXIST` rather than replace the target.
+        const NOREPLACE = libc::RENAME_NOREPLACE;
+        /// Atomically swap the two paths, both must exist.
+        const EXCHANGE = libc::RENAME_EXCHANGE;
+    }
+}
+
+/// Rename `from` to `to` with `IORING_OP_RENAMEAT` (5.11),
+/// replacing `to` atomically if it exists.
+///
+/// Writing a temporary file, syncing it and renaming it over the target
+/// is the usual way to replace a file durably.
+#[inline]
+pub async fn rename<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
+    rename_with_flags(from, to, RenameFlags::empty()).await
+}
+
+/// Like [rename], with the flags of `renameat2(2)`.
+pub async fn rename_with_flags<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q, flags: RenameFlags)
+    -> io::Result<()>
+{
+    let paths = (cstr(from.as_ref())?, cstr(to.as_ref())?);
+
+    let mut entry = sys::entry(sys::IORING_OP_RENAMEAT);
+    let sqe = sys::sqe_mut(&mut entry);
+    sqe.fd = libc::AT_FDCWD;
+    sqe.addr = paths.0.as_ptr() as _;
+    sqe.len = libc::AT_FDCWD as _;
+    sqe.off = paths.1.as_ptr() as _;
+    sqe.op_flags = flags.bits();
+
+    push(entry, paths).await
+}
+
+/// Remove a file with `IORING_OP_UNLINKAT` (5.11).
+pub async fn remove_file<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    unlink(path.as_ref(), 0).await
+}
+
+/// Remove an empty directory with `IORING_OP_UNLINKAT` (5.11).
+pub async fn remove_dir<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    unlink(path.as_ref(), libc::AT_REMOVEDIR as u32).await
+}
+
+async fn unlink(path: &Path, flags: u32) -> io::Result<()> {
+    let path = cstr(path)?;
+
+    let mut entry = sys::entry(sys::IORING_OP_UNLINKAT);
+    let sqe = sys::sqe_mut(&mut entry);
+    sqe.fd = libc::AT_FDCWD;
+    sqe.addr = path.as_ptr() as _;
+    sqe.op_flags = flags;
+
+    push(entry, (path, CString::default())).await
+}
+
+/// Create a directory with `IORING_OP_MKDIRAT` (5.15), the parent must exist.
+#[inline]
+pub async fn create_dir<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    create_dir_with_mode(path, 0o777).await
+}
+
+/// Like [create_dir], with the permissions of the directory before the umask.
+pub async fn create_dir_with_mode<P: AsRef<Path>>(path: P, mode: u32) -> io::Result<()> {
+    let path = cstr(path.as_ref())?;
+
+    let mut entry = sys::entry(sys::IORING_OP_MKDIRAT);
+    let sqe = sys::sqe_mut(&mut entry);
+    sqe.fd = libc::AT_FDCWD;
+    sqe.addr = path.as_ptr() as _;
+    sqe.len = mode;
+
+    push(entry, (path, CString::default())).await
+}
+
+/// Create a symlink at `link` pointing to `target`, with `IORING_OP_SYMLINKAT` (5.15).
+pub async fn symlink<P: AsRef<Path>, Q: AsRef<Path>>(target: P, link: Q) -> io::Result<()> {
+    let paths = (cstr(target.as_ref())?, cstr(link.as_ref())?);
+
+    let mut entry = sys::entry(sys::IORING_OP_SYMLINKAT);
+    let sqe = sys::sqe_mut(&mut entry);
+    sqe.fd = libc::AT_FDCWD;
+    sqe.addr = paths.0.as_ptr() as _;
+    sqe.off = paths.1.as_ptr() as _;
+
+    push(entry, paths).await
+}
+
+/// Create a hard link at `link` to the file `original`,
+/// with `IORING_OP_LINKAT` (5.15). Symlinks are not followed.
+pub async fn hard_link<P: AsRef<Path>, Q: AsRef<Path>>(original: P, link: Q) -> io::Result<()> {
+    let paths = (cstr(original.as_ref())?, cstr(link.as_ref())?);
+
+    let mut entry = sys::entry(sys::IORING_OP_LINKAT);
+    let sqe = sys::sqe_mut(&mut entry);
+    sqe.fd = libc::AT_FDCWD;
+    sqe.addr = paths.0.as_ptr() as _;
+    sqe.len = libc::AT_FDCWD as _;
+    sqe.off = paths.1.as_ptr() as _;
+
+    push(entry, paths).await
+}
+
+async fn push(entry: SubmissionEntry, mut paths: (CString, CString)) -> io::Result<()> {
+    let ret = safety_await!{
+        [ paths ];
+        unsafe { handle::push(entry) }
+    };
+    drop(paths);
+    ret?.ok()?;
+    Ok(())
+}
+
+
+#[test]
+fn test_path_ops() {
+    use std::fs;
+    use crate::executor::block_on;
+    use super::temp_path;
+
+    let dir = temp_path("ops");
+
+    block_on(async {
+        create_dir(&dir).await.unwrap();
+        assert!(fs::metadata(&dir).unwrap().is_dir());
+
+        fs::write(dir.join("a"), b"a").unwrap();
+        fs::write(dir.join("b"), b"b").unwrap();
+
+        let err = rename_with_flags(dir.join("a"), dir.join("b"), RenameFlags::NOREPLACE).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        rename_with_flags(dir.join("a"), dir.join("b"), RenameFlags::EXCHANGE).await.unwrap();
+        assert_eq!(fs::read(dir.join("a")).unwrap(), b"b");
+        rename(dir.join("a"), dir.join("b")).await.unwrap();
+        assert_eq!(fs::read(dir.join("b")).unwrap(), b"b");
+        assert!(!dir.join("a").exists());
+
+        symlink("b", dir.join("sym")).await.unwrap();
+        assert_eq!(fs::read_link(dir.join("sym")).unwrap(), Path::new("b"));
+        hard_link(dir.join("b"), dir.join("hard")).await.unwrap();
+        assert_eq!(fs::read(dir.join("hard")).unwrap(), b"b");
+
+        for name in &["b", "sym", "hard"] {
+            remove_file(dir.join(name)).await.unwrap();
+        }
+
+        let err = remove_file(dir.join("b")).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        remove_dir(&dir).await.unwrap();
+        assert!(!dir.exists());
+    });
+}
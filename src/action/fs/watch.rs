@@ -0,0 +1,341 @@
+use std::{ io, mem, ptr };
+use std::rc::Rc;
+use std::pin::Pin;
+use std::ffi::{ OsStr, OsString };
+use std::path::{ Path, PathBuf };
+use std::collections::HashMap;
+use std::task::{ Context, Poll as TaskPoll };
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{ AsRawFd, FromRawFd, RawFd };
+use bitflags::bitflags;
+use futures_util::future::LocalBoxFuture;
+use futures_util::stream::Stream;
+use io_uring::opcode::{ self, types };
+use crate::handle;
+use crate::action::poll::{ Poll, ReadyExt };
+use super::{ cstr, File };
+
+
+const EVENT_SIZE: usize = mem::size_of::<libc::inotify_event>();
+
+// room for the longest name, and then some events.
+const BUF_SIZE: usize = 16 * 1024;
+
+bitflags!{
+    /// The events of a [Watch], see `inotify(7)`.
+    pub struct WatchMask: u32 {
+        const ACCESS = libc::IN_ACCESS;
+        const MODIFY = libc::IN_MODIFY;
+        const ATTRIB = libc::IN_ATTRIB;
+        const CLOSE_WRITE = libc::IN_CLOSE_WRITE;
+        const CLOSE_NOWRITE = libc::IN_CLOSE_NOWRITE;
+        const OPEN = libc::IN_OPEN;
+        /// A file was moved out of a watched directory, paired with `MOVED_TO` by [Event::cookie].
+        const MOVED_FROM = libc::IN_MOVED_FROM;
+        const MOVED_TO = libc::IN_MOVED_TO;
+        const CREATE = libc::IN_CREATE;
+        const DELETE = libc::IN_DELETE;
+        const DELETE_SELF = libc::IN_DELETE_SELF;
+        const MOVE_SELF = libc::IN_MOVE_SELF;
+
+        const CLOSE = libc::IN_CLOSE;
+        const MOVE = libc::IN_MOVE;
+        const ALL_EVENTS = libc::IN_ALL_EVENTS;
+
+        /// Only reported, the filesystem was unmounted.
+        const UNMOUNT = libc::IN_UNMOUNT;
+        /// Only reported, events were dropped.
+        const Q_OVERFLOW = libc::IN_Q_OVERFLOW;
+        /// Only reported, the watch was removed.
+        const IGNORED = libc::IN_IGNORED;
+        /// Only reported, the subject of the event is a directory.
+        const ISDIR = libc::IN_ISDIR;
+
+        /// Fail with `ENOTDIR` unless the path is a directory.
+        const ONLYDIR = libc::IN_ONLYDIR;
+        const DONT_FOLLOW = libc::IN_DONT_FOLLOW;
+        /// Stop reporting the children of a directory once they are unlinked.
+        const EXCL_UNLINK = libc::IN_EXCL_UNLINK;
+        /// Add to the mask of an existing watch instead of replacing it.
+        const MASK_ADD = libc::IN_MASK_ADD;
+        /// Remove the watch after the first event.
+        const ONESHOT = libc::IN_ONESHOT;
+    }
+}
+
+/// Filesystem events of the watched paths, see [watch].
+pub struct Watch {
+    fd: Rc<File>,
+    paths: HashMap<i32, Rc<Path>>,
+    buf: Vec<u8>,
+    pos: usize,
+    fut: Option<LocalBoxFuture<'static, (io::Result<usize>, Vec<u8>)>>,
+    done: bool
+}
+
+/// A watched path of a [Watch].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct WatchDescriptor(i32);
+
+/// An event of a [Watch].
+#[derive(Clone, Debug)]
+pub struct Event {
+    wd: WatchDescriptor,
+    mask: WatchMask,
+    cookie: u32,
+    dir: Option<Rc<Path>>,
+    name: Option<OsString>
+}
+
+/// Watch `path` with an inotify fd read through the ring.
+///
+/// A directory reports the events of its children, with their name.
+/// More paths can be added with [Watch::add].
+///
+/// ```no_run
+/// use futures_util::stream::TryStreamExt;
+/// use ritsu::action::fs::{ self, WatchMask };
+///
+/// # async fn f() -> std::io::Result<()> {
+/// let mut watch = fs::watch("config", WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO)?;
+///
+/// while let Some(event) = watch.try_next().await? {
+///     println!("reload {:?}", event.path());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn watch<P: AsRef<Path>>(path: P, mask: WatchMask) -> io::Result<Watch> {
+    let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let fd = File::from_std(unsafe { std::fs::File::from_raw_fd(fd) });
+
+    let mut watch = Watch {
+        fd: Rc::new(fd),
+        paths: HashMap::new(),
+        buf: Vec::with_capacity(BUF_SIZE),
+        pos: 0,
+        fut: None,
+        done: false
+    };
+    watch.add(path, mask)?;
+
+    Ok(watch)
+}
+
+impl Watch {
+    /// Watch another path, or change the mask of a watched one.
+    pub fn add<P: AsRef<Path>>(&mut self, path: P, mask: WatchMask) -> io::Result<WatchDescriptor> {
+        let path = path.as_ref();
+        let cpath = cstr(path)?;
+        let wd = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), cpath.as_ptr(), mask.bits()) };
+
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        self.paths.insert(wd, Rc::from(path));
+
+        Ok(WatchDescriptor(wd))
+    }
+
+    /// Stop watching a path, it still reports a final `IGNORED` event.
+    pub fn remove(&mut self, wd: WatchDescriptor) -> io::Result<()> {
+        if unsafe { libc::inotify_rm_watch(self.fd.as_raw_fd(), wd.0) } == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    fn next_event(&mut self) -> Option<Event> {
+        if self.pos + EVENT_SIZE > self.buf.len() {
+            return None;
+        }
+
+        let ev = unsafe {
+            ptr::read_unaligned(self.buf[self.pos..].as_ptr() as *const libc::inotify_event)
+        };
+
+        let name = &self.buf[self.pos + EVENT_SIZE..][..ev.len as usize];
+        let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+        self.pos += EVENT_SIZE + ev.len as usize;
+
+        let mask = WatchMask::from_bits_truncate(ev.mask);
+        let dir = if mask.contains(WatchMask::IGNORED) {
+            self.paths.remove(&ev.wd)
+        } else {
+            self.paths.get(&ev.wd).cloned()
+        };
+
+        Some(Event {
+            wd: WatchDescriptor(ev.wd),
+            mask,
+            cookie: ev.cookie,
+            dir,
+            name: if name.is_empty() { None } else { Some(OsStr::from_bytes(name).to_owned()) }
+        })
+    }
+}
+
+async fn read(fd: Rc<File>, buf: Vec<u8>) -> (io::Result<usize>, Vec<u8>) {
+    let mut res = (fd, buf);
+    res.1.clear();
+
+    loop {
+        let entry = opcode::Read::new(types::Target::Fd(res.0.as_raw_fd()), res.1.as_mut_ptr(), res.1.capacity() as _)
+            .build();
+
+        let ret = safety_await!{
+            [ res ];
+            unsafe { handle::push(entry) }
+        };
+
+        let ret = match ret {
+            Ok(ret) => ret.result(),
+            Err(err) => return (Err(err), res.1)
+        };
+
+        if ret >= 0 {
+            unsafe {
+                res.1.set_len(ret as usize);
+            }
+
+            return (Ok(ret as usize), res.1);
+        }
+
+        match -ret {
+            libc::EAGAIN => if let Err(err) = res.0.ready(Poll::READABLE).await {
+                return (Err(err), res.1);
+            },
+            libc::EINTR => (),
+            err => return (Err(io::Error::from_raw_os_error(err)), res.1)
+        }
+    }
+}
+
+impl Stream for Watch {
+    type Item = io::Result<Event>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> TaskPoll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            if let Some(event) = this.next_event() {
+                return TaskPoll::Ready(Some(Ok(event)));
+            }
+
+            if this.done {
+                return TaskPoll::Ready(None);
+            }
+
+            let (fd, buf) = (&this.fd, &mut this.buf);
+            let fut = this.fut.get_or_insert_with(|| Box::pin(read(fd.clone(), mem::take(buf))));
+
+            let (ret, buf) = match fut.as_mut().poll(cx) {
+                TaskPoll::Ready(ret) => ret,
+                TaskPoll::Pending => return TaskPoll::Pending
+            };
+            this.fut = None;
+            this.buf = buf;
+            this.pos = 0;
+
+            if let Err(err) = ret {
+                this.done = true;
+                return TaskPoll::Ready(Some(Err(err)));
+            }
+        }
+    }
+}
+
+impl AsRawFd for Watch {
+    /// The inotify fd.
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl Event {
+    #[inline]
+    pub fn wd(&self) -> WatchDescriptor {
+        self.wd
+    }
+
+    #[inline]
+    pub fn mask(&self) -> WatchMask {
+        self.mask
+    }
+
+    /// The same for the two halves of a rename, zero otherwise.
+    #[inline]
+    pub fn cookie(&self) -> u32 {
+        self.cookie
+    }
+
+    /// The name of the child of a watched directory.
+    #[inline]
+    pub fn file_name(&self) -> Option<&OsStr> {
+        self.name.as_deref()
+    }
+
+    /// The watched path joined with [Event::file_name],
+    /// `None` for `Q_OVERFLOW` which belongs to no path.
+    pub fn path(&self) -> Option<PathBuf> {
+        let dir = self.dir.as_ref()?;
+
+        Some(match &self.name {
+            Some(name) => dir.join(name),
+            None => dir.to_path_buf()
+        })
+    }
+}
+
+
+#[test]
+fn test_watch() {
+    use futures_util::stream::TryStreamExt;
+    use crate::executor::block_on;
+
+    let dir = super::temp_path("watch");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir(&dir).unwrap();
+
+    block_on(async {
+        let mut watch = watch(&dir, WatchMask::CREATE | WatchMask::MOVE | WatchMask::DELETE).unwrap();
+
+        std::fs::write(dir.join("a"), b"").unwrap();
+        std::fs::rename(dir.join("a"), dir.join("b")).unwrap();
+
+        let event = watch.try_next().await.unwrap().unwrap();
+        assert_eq!(event.mask(), WatchMask::CREATE);
+        assert_eq!(event.path().unwrap(), dir.join("a"));
+
+        let from = watch.try_next().await.unwrap().unwrap();
+        let to = watch.try_next().await.unwrap().unwrap();
+        assert_eq!(from.mask(), WatchMask::MOVED_FROM);
+        assert_eq!(to.file_name().unwrap(), "b");
+        assert_eq!(from.cookie(), to.cookie());
+
+        // waits for the next event
+        let remove = async {
+            crate::time::sleep(std::time::Duration::from_millis(5)).await.unwrap();
+            std::fs::remove_file(dir.join("b")).unwrap();
+        };
+        let (event, ()) = futures_util::future::join(watch.try_next(), remove).await;
+        assert_eq!(event.unwrap().unwrap().mask(), WatchMask::DELETE);
+
+        // the same path gets the same descriptor
+        let wd = watch.add(&dir, WatchMask::MASK_ADD | WatchMask::CREATE).unwrap();
+        watch.remove(wd).unwrap();
+        let event = watch.try_next().await.unwrap().unwrap();
+        assert!(event.mask().contains(WatchMask::IGNORED));
+        assert!(watch.paths.is_empty());
+    });
+
+    std::fs::remove_dir(&dir).unwrap();
+}
@@ -0,0 +1,166 @@
+//! Extended attributes with `IORING_OP_{F,}{GET,SET}XATTR` (5.19).
+
+use std::io;
+use std::path::Path;
+use std::ffi::CString;
+use std::os::unix::io::AsRawFd;
+use bitflags::bitflags;
+use crate::{ sys, handle, SubmissionEntry };
+use super::{ cstr, File };
+
+
+// `XATTR_SIZE_MAX`
+const MAX_SIZE: usize = 64 * 1024;
+
+bitflags!{
+    /// See `setxattr(2)`.
+    pub struct XattrFlags: u32 {
+        /// Fail with `EEXIST` if the attribute exists.
+        const CREATE = libc::XATTR_CREATE as u32;
+        /// Fail with `ENODATA` if the attribute doesn't exist.
+        const REPLACE = libc::XATTR_REPLACE as u32;
+    }
+}
+
+// the strings and the value, owned by the op
+struct Buf {
+    name: CString,
+    path: CString,
+    value: Vec<u8>
+}
+
+impl File {
+    /// The value of the attribute `name`, `None` if it isn't set.
+    pub async fn get_xattr(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        get(Some(self.as_raw_fd()), CString::default(), name).await
+    }
+
+    pub async fn set_xattr(&self, name: &str, value: &[u8], flags: XattrFlags) -> io::Result<()> {
+        set(Some(self.as_raw_fd()), CString::default(), name, value, flags).await
+    }
+}
+
+/// The value of the attribute `name` of `path`, `None` if it isn't set.
+pub async fn get_xattr<P: AsRef<Path>>(path: P, name: &str) -> io::Result<Option<Vec<u8>>> {
+    get(None, cstr(path.as_ref())?, name).await
+}
+
+pub async fn set_xattr<P: AsRef<Path>>(path: P, name: &str, value: &[u8], flags: XattrFlags)
+    -> io::Result<()>
+{
+    set(None, cstr(path.as_ref())?, name, value, flags).await
+}
+
+fn name(name: &str) -> io::Result<CString> {
+    CString::new(name).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
+
+fn entry(fd: Option<i32>, buf: &mut Buf, get: bool) -> SubmissionEntry {
+    let opcode = match (fd.is_some(), get) {
+        (true, true) => sys::IORING_OP_FGETXATTR,
+        (true, false) => sys::IORING_OP_FSETXATTR,
+        (false, true) => sys::IORING_OP_GETXATTR,
+        (false, false) => sys::IORING_OP_SETXATTR
+    };
+
+    let mut entry = sys::entry(opcode);
+    let sqe = sys::sqe_mut(&mut entry);
+    sqe.addr = buf.name.as_ptr() as _;
+    sqe.off = buf.value.as_mut_ptr() as _;
+
+    if get {
+        sqe.len = buf.value.capacity() as _;
+    } else {
+        sqe.len = buf.value.len() as _;
+    }
+
+    match fd {
+        Some(fd) => sqe.fd = fd,
+        None => sqe.addr3 = buf.path.as_ptr() as _
+    }
+
+    entry
+}
+
+async fn get(fd: Option<i32>, path: CString, name_: &str) -> io::Result<Option<Vec<u8>>> {
+    let mut buf = Buf { name: name(name_)?, path, value: Vec::with_capacity(256) };
+
+    loop {
+        let entry = entry(fd, &mut buf, true);
+
+        let ret = safety_await!{
+            [ buf ];
+            unsafe { handle::push(entry) }
+        };
+        let ret = ret?.result();
+
+        if ret >= 0 {
+            unsafe {
+                buf.value.set_len(ret as usize);
+            }
+
+            return Ok(Some(buf.value));
+        }
+
+        match -ret {
+            libc::ENODATA => return Ok(None),
+            // it's grown between, or the buffer was too small from the start
+            libc::ERANGE if buf.value.capacity() < MAX_SIZE => {
+                let cap = (buf.value.capacity() * 4).min(MAX_SIZE);
+                buf.value.reserve_exact(cap);
+            },
+            err => return Err(io::Error::from_raw_os_error(err))
+        }
+    }
+}
+
+async fn set(fd: Option<i32>, path: CString, name_: &str, value: &[u8], flags: XattrFlags)
+    -> io::Result<()>
+{
+    let mut buf = Buf { name: name(name_)?, path, value: value.to_vec() };
+
+    let mut entry = entry(fd, &mut buf, false);
+    sys::sqe_mut(&mut entry).op_flags = flags.bits();
+
+    let ret = safety_await!{
+        [ buf ];
+        unsafe { handle::push(entry) }
+    };
+    drop(buf);
+    ret?.ok()?;
+    Ok(())
+}
+
+
+#[test]
+fn test_xattr() {
+    use std::fs;
+    use crate::executor::block_on;
+    use super::temp_path;
+
+    let path = temp_path("xattr");
+    fs::write(&path, b"").unwrap();
+
+    block_on(async {
+        let file = File::open(&path).await.unwrap();
+
+        match file.set_xattr("user.ritsu", b"hello", XattrFlags::CREATE).await {
+            Err(ref err) if err.raw_os_error() == Some(libc::EOPNOTSUPP) => return,
+            ret => ret.unwrap()
+        }
+
+        assert_eq!(file.get_xattr("user.ritsu").await.unwrap().unwrap(), b"hello");
+        assert_eq!(get_xattr(&path, "user.ritsu").await.unwrap().unwrap(), b"hello");
+        assert_eq!(get_xattr(&path, "user.missing").await.unwrap(), None);
+
+        let err = set_xattr(&path, "user.ritsu", b"world", XattrFlags::CREATE).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+        // larger than the first guess
+        let large = vec![7; 1000];
+        set_xattr(&path, "user.ritsu", &large, XattrFlags::REPLACE).await.unwrap();
+        assert_eq!(file.get_xattr("user.ritsu").await.unwrap().unwrap(), large);
+    });
+
+    fs::remove_file(&path).unwrap();
+}
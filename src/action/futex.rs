@@ -0,0 +1,99 @@
+//! Futexes waited and woken through the ring (6.7).
+//!
+//! Unlike `futex(2)` a wait doesn't block the thread, so a task can park on a word
+//! shared with another process, like the head of a queue in shared memory.
+//! The kernel only reads the word, a dropped wait just cancels the op.
+
+use std::io;
+use std::sync::atomic::AtomicU32;
+use bitflags::bitflags;
+use crate::{ sys, handle };
+
+
+const SIZE_U32: u32 = libc::FUTEX2_SIZE_U32 as u32;
+const MATCH_ANY: u64 = libc::FUTEX_BITSET_MATCH_ANY as u32 as u64;
+
+bitflags!{
+    /// See `futex(2)`.
+    pub struct FutexFlags: u32 {
+        /// The futex is only used by this process, which is cheaper to look up.
+        const PRIVATE = libc::FUTEX2_PRIVATE as u32;
+    }
+}
+
+/// Wait until `futex` is woken, if it still holds `expected`.
+///
+/// Returns `false` without waiting if it doesn't, like `EAGAIN` of `FUTEX_WAIT`.
+/// It may also complete spuriously, so check the word again.
+pub async fn wait(futex: &AtomicU32, expected: u32, flags: FutexFlags) -> io::Result<bool> {
+    let mut entry = sys::entry(sys::IORING_OP_FUTEX_WAIT);
+    let sqe = sys::sqe_mut(&mut entry);
+    sqe.fd = (flags.bits() | SIZE_U32) as _;
+    sqe.addr = futex.as_ptr() as _;
+    sqe.off = expected.into();
+    sqe.addr3 = MATCH_ANY;
+
+    let ret = safety_await!{
+        unsafe { handle::push(entry) }
+    };
+    let ret = ret?.result();
+
+    match ret {
+        0.. => Ok(true),
+        _ if ret == -libc::EAGAIN => Ok(false),
+        _ => Err(io::Error::from_raw_os_error(-ret))
+    }
+}
+
+/// Wake at most `n` waiters of `futex`, returns how many were woken.
+pub async fn wake(futex: &AtomicU32, n: u32, flags: FutexFlags) -> io::Result<usize> {
+    let mut entry = sys::entry(sys::IORING_OP_FUTEX_WAKE);
+    let sqe = sys::sqe_mut(&mut entry);
+    sqe.fd = (flags.bits() | SIZE_U32) as _;
+    sqe.addr = futex.as_ptr() as _;
+    sqe.off = n.into();
+    sqe.addr3 = MATCH_ANY;
+
+    let ret = safety_await!{
+        unsafe { handle::push(entry) }
+    };
+    ret?.ok().map(|n| n as usize)
+}
+
+
+#[test]
+fn test_futex() {
+    use std::time::Duration;
+    use std::sync::atomic::Ordering;
+    use crate::executor::block_on;
+
+    static FUTEX: AtomicU32 = AtomicU32::new(0);
+    let futex = &FUTEX;
+
+    block_on(async move {
+        assert!(!wait(futex, 1, FutexFlags::PRIVATE).await.unwrap());
+
+        let wake = async move {
+            crate::time::sleep(Duration::from_millis(5)).await.unwrap();
+            futex.store(1, Ordering::Release);
+            wake(futex, 1, FutexFlags::PRIVATE).await.unwrap()
+        };
+        let (woken, n) = futures_util::future::join(wait(futex, 0, FutexFlags::PRIVATE), wake).await;
+        assert!(woken.unwrap());
+        assert_eq!(n, 1);
+
+        // woken by a thread blocked in no ring at all
+        let t = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(5));
+            futex.store(2, Ordering::Release);
+
+            unsafe {
+                libc::syscall(libc::SYS_futex, futex.as_ptr(), libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG, 1);
+            }
+        });
+        while futex.load(Ordering::Acquire) == 1 {
+            wait(futex, 1, FutexFlags::PRIVATE).await.unwrap();
+        }
+        t.join().unwrap();
+    });
+}
@@ -0,0 +1,563 @@
+//! Reads and writes of owned buffers, and helpers on top of them.
+//!
+//! Every op of a [File] or [TcpStream] is a submission,
+//! [BufReader] and [BufWriter] turn many small reads and writes into a few large ones.
+//! [stdin], [stdout] and [stderr] go through the ring too, and so do the ends of a [pipe].
+
+use std::{ io, cmp };
+use std::future::Future;
+use bytes::{ Buf, BufMut, Bytes, BytesMut };
+use futures_util::future;
+use futures_util::stream::{ self, Stream };
+use super::fs::File;
+use super::tcp::TcpStream;
+
+
+mod stdio;
+mod pipe;
+#[cfg(feature = "futures-io")]
+pub(crate) mod compat;
+
+pub use stdio::{ Stdin, Stdout, Stderr, stdin, stdout, stderr };
+pub use pipe::{ PipeReader, PipeWriter, pipe };
+
+
+const DEFAULT_CAPACITY: usize = 8 * 1024;
+
+/// Read into the spare capacity of an owned buffer, like [File::read].
+pub trait OwnedRead {
+    /// Returns `buf` with what was read appended, nothing at the end of the stream.
+    fn read(&mut self, buf: BytesMut) -> impl Future<Output = io::Result<BytesMut>>;
+}
+
+/// Write an owned buffer, like [File::write].
+pub trait OwnedWrite {
+    /// Returns what remains of `buf` after a possibly short write.
+    fn write(&mut self, buf: Bytes) -> impl Future<Output = io::Result<Bytes>>;
+}
+
+impl OwnedRead for File {
+    #[inline]
+    fn read(&mut self, buf: BytesMut) -> impl Future<Output = io::Result<BytesMut>> {
+        File::read(self, buf)
+    }
+}
+
+impl OwnedWrite for File {
+    #[inline]
+    fn write(&mut self, buf: Bytes) -> impl Future<Output = io::Result<Bytes>> {
+        File::write(self, buf)
+    }
+}
+
+impl OwnedRead for TcpStream {
+    #[inline]
+    fn read(&mut self, buf: BytesMut) -> impl Future<Output = io::Result<BytesMut>> {
+        TcpStream::read(self, buf)
+    }
+}
+
+impl OwnedWrite for TcpStream {
+    #[inline]
+    fn write(&mut self, buf: Bytes) -> impl Future<Output = io::Result<Bytes>> {
+        TcpStream::write(self, buf)
+    }
+}
+
+/// Reads `capacity` bytes at a time from `R`, and hands them out in smaller pieces.
+pub struct BufReader<R> {
+    inner: R,
+    buf: BytesMut,
+    capacity: usize
+}
+
+impl<R: OwnedRead> BufReader<R> {
+    pub fn new(inner: R) -> BufReader<R> {
+        BufReader::with_capacity(DEFAULT_CAPACITY, inner)
+    }
+
+    pub fn with_capacity(capacity: usize, inner: R) -> BufReader<R> {
+        BufReader { inner, buf: BytesMut::new(), capacity: capacity.max(1) }
+    }
+
+    /// The buffered data, empty if it needs to be filled again.
+    #[inline]
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    #[inline]
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Reading from the inner reader directly skips the buffered data.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// The buffered data is lost.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Returns the buffered data, after reading more if it's empty.
+    ///
+    /// It's empty only at the end of the stream, see [BufReader::consume].
+    pub async fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.buf.is_empty() {
+            let mut buf = std::mem::take(&mut self.buf);
+            buf.clear();
+            buf.reserve(self.capacity);
+            self.buf = self.inner.read(buf).await?;
+        }
+
+        Ok(&self.buf)
+    }
+
+    /// Mark `amt` bytes of [BufReader::fill_buf] as read.
+    pub fn consume(&mut self, amt: usize) {
+        let amt = cmp::min(amt, self.buf.len());
+        self.buf.advance(amt);
+    }
+
+    /// Append to `out` up to and including `byte`, or up to the end of the stream.
+    ///
+    /// Returns how many bytes were appended, zero at the end of the stream.
+    pub async fn read_until(&mut self, byte: u8, out: &mut Vec<u8>) -> io::Result<usize> {
+        let mut read = 0;
+
+        loop {
+            let (done, used) = {
+                let available = self.fill_buf().await?;
+
+                match available.iter().position(|&b| b == byte) {
+                    Some(i) => {
+                        out.extend_from_slice(&available[..=i]);
+                        (true, i + 1)
+                    },
+                    None => {
+                        out.extend_from_slice(available);
+                        (available.is_empty(), available.len())
+                    }
+                }
+            };
+
+            self.consume(used);
+            read += used;
+
+            if done {
+                return Ok(read);
+            }
+        }
+    }
+
+    /// Append a line to `out`, including its `\n` if any.
+    ///
+    /// Returns how many bytes were appended, zero at the end of the stream.
+    /// Fails with [io::ErrorKind::InvalidData] if the line isn't UTF-8,
+    /// `out` is unchanged then.
+    pub async fn read_line(&mut self, out: &mut String) -> io::Result<usize> {
+        let mut line = Vec::new();
+        let n = self.read_until(b'\n', &mut line).await?;
+
+        let line = String::from_utf8(line)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        out.push_str(&line);
+
+        Ok(n)
+    }
+}
+
+impl<R: OwnedRead> BufReader<R> {
+    /// The lines of the stream, without their `\n` or `\r\n`.
+    ///
+    /// It ends after the first error.
+    ///
+    /// ```no_run
+    /// use futures_util::stream::TryStreamExt;
+    /// use ritsu::action::fs::File;
+    /// use ritsu::action::io::BufReader;
+    ///
+    /// # async fn f() -> std::io::Result<()> {
+    /// let file = File::open("/var/log/syslog").await?;
+    /// let errors = BufReader::new(file)
+    ///     .lines()
+    ///     .try_filter(|line| futures_util::future::ready(line.contains("error")))
+    ///     .try_collect::<Vec<_>>()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn lines(self) -> impl Stream<Item = io::Result<String>> {
+        stream::unfold(Some(self), |reader| async move {
+            let mut reader = reader?;
+            let mut line = String::new();
+
+            match reader.read_line(&mut line).await {
+                Ok(0) => None,
+                Ok(_) => {
+                    if line.ends_with('\n') {
+                        line.pop();
+
+                        if line.ends_with('\r') {
+                            line.pop();
+                        }
+                    }
+
+                    Some((Ok(line), Some(reader)))
+                },
+                Err(err) => Some((Err(err), None))
+            }
+        })
+    }
+
+    /// The stream in chunks of `size` bytes, the last one may be shorter.
+    ///
+    /// It ends after the first error.
+    ///
+    /// # Panics
+    ///
+    /// If `size` is zero.
+    pub fn chunks(self, size: usize) -> impl Stream<Item = io::Result<Bytes>> {
+        assert!(size > 0, "chunk size must be non-zero");
+
+        stream::unfold(Some(self), move |reader| async move {
+            let mut reader = reader?;
+            let mut chunk = BytesMut::with_capacity(size);
+
+            while chunk.len() < size {
+                let available = match reader.fill_buf().await {
+                    Ok(available) => available,
+                    Err(err) => return Some((Err(err), None))
+                };
+
+                if available.is_empty() {
+                    break
+                }
+
+                let n = cmp::min(available.len(), size - chunk.len());
+                chunk.extend_from_slice(&available[..n]);
+                reader.consume(n);
+            }
+
+            if chunk.is_empty() {
+                None
+            } else {
+                Some((Ok(chunk.freeze()), Some(reader)))
+            }
+        })
+    }
+}
+
+impl<R: OwnedRead> OwnedRead for BufReader<R> {
+    /// Reads from the buffer, or directly into `buf` if the buffer is empty
+    /// and `buf` has room for at least a full buffer.
+    ///
+    /// `buf` is grown if it's full, reading nothing would look like the end of the stream.
+    async fn read(&mut self, mut buf: BytesMut) -> io::Result<BytesMut> {
+        if buf.capacity() == buf.len() {
+            buf.reserve(self.capacity);
+        }
+
+        if self.buf.is_empty() && buf.capacity() - buf.len() >= self.capacity {
+            return self.inner.read(buf).await;
+        }
+
+        let available = self.fill_buf().await?;
+        let n = cmp::min(available.len(), buf.capacity() - buf.len());
+        buf.put_slice(&available[..n]);
+        self.consume(n);
+
+        Ok(buf)
+    }
+}
+
+/// Collects writes up to `capacity` bytes before writing them to `W`.
+///
+/// The buffered data is only written by [BufWriter::flush] or when the buffer is full,
+/// it's lost if the writer is dropped before.
+pub struct BufWriter<W> {
+    inner: W,
+    buf: BytesMut,
+    capacity: usize
+}
+
+impl<W: OwnedWrite> BufWriter<W> {
+    pub fn new(inner: W) -> BufWriter<W> {
+        BufWriter::with_capacity(DEFAULT_CAPACITY, inner)
+    }
+
+    pub fn with_capacity(capacity: usize, inner: W) -> BufWriter<W> {
+        BufWriter { inner, buf: BytesMut::with_capacity(capacity), capacity }
+    }
+
+    /// The data that is not written yet.
+    #[inline]
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    #[inline]
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Writing to the inner writer directly skips ahead of the buffered data.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// The buffered data is lost, [BufWriter::flush] first.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// Buffer all of `data`, writing out the buffer when it's full.
+    pub async fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        if self.buf.len() + data.len() > self.capacity {
+            self.flush().await?;
+        }
+
+        if data.len() >= self.capacity {
+            write_all(&mut self.inner, Bytes::copy_from_slice(data)).await
+        } else {
+            self.buf.extend_from_slice(data);
+            Ok(())
+        }
+    }
+
+    /// Write out all of the buffered data.
+    ///
+    /// It's only handed to the inner writer, files still need [File::sync_data] to be durable.
+    pub async fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+
+        let mut buf = self.buf.split().freeze();
+
+        while !buf.is_empty() {
+            match self.inner.write(buf.clone()).await {
+                Ok(rest) if rest.len() == buf.len() => {
+                    self.buf.extend_from_slice(&buf);
+                    return Err(io::ErrorKind::WriteZero.into());
+                },
+                Ok(rest) => buf = rest,
+                Err(err) => {
+                    // keep what wasn't written for the next flush
+                    self.buf.extend_from_slice(&buf);
+                    return Err(err);
+                }
+            }
+        }
+
+        drop(buf);
+        self.buf.reserve(self.capacity);
+
+        Ok(())
+    }
+}
+
+impl<W: OwnedWrite> OwnedWrite for BufWriter<W> {
+    /// Buffers `buf` if it fits, otherwise writes the buffer and then `buf` as is.
+    async fn write(&mut self, buf: Bytes) -> io::Result<Bytes> {
+        if self.buf.len() + buf.len() > self.capacity {
+            self.flush().await?;
+        }
+
+        if buf.len() >= self.capacity {
+            self.inner.write(buf).await
+        } else {
+            self.buf.extend_from_slice(&buf);
+            Ok(Bytes::new())
+        }
+    }
+}
+
+/// Copy `reader` to `writer` until the end of `reader`, returns how many bytes were copied.
+///
+/// The next chunk is read while the previous one is written,
+/// so both sides are kept busy rather than taking turns.
+///
+/// ```no_run
+/// use ritsu::action::{ fs, io };
+///
+/// # async fn f() -> std::io::Result<()> {
+/// let mut src = fs::File::open("input").await?;
+/// let mut dst = fs::File::create("output").await?;
+/// io::copy(&mut src, &mut dst).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn copy<R, W>(reader: &mut R, writer: &mut W) -> io::Result<u64>
+where
+    R: OwnedRead,
+    W: OwnedWrite
+{
+    const CHUNK: usize = 64 * 1024;
+
+    let mut total = 0;
+    let mut pending = reader.read(BytesMut::with_capacity(CHUNK)).await?.freeze();
+
+    while !pending.is_empty() {
+        let len = pending.len();
+        let (next, written) = future::join(
+            reader.read(BytesMut::with_capacity(CHUNK)),
+            write_all(writer, pending)
+        ).await;
+
+        written?;
+        total += len as u64;
+        pending = next?.freeze();
+    }
+
+    Ok(total)
+}
+
+async fn write_all<W: OwnedWrite>(writer: &mut W, mut buf: Bytes) -> io::Result<()> {
+    while !buf.is_empty() {
+        let rest = writer.write(buf.clone()).await?;
+
+        if rest.len() == buf.len() {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+
+        buf = rest;
+    }
+
+    Ok(())
+}
+
+
+#[test]
+fn test_buf_reader() {
+    use std::fs;
+    use crate::executor::block_on;
+    use super::fs::temp_path;
+
+    let path = temp_path("buf-reader");
+    fs::write(&path, "hello\nworld\n\nlast").unwrap();
+
+    block_on(async {
+        let file = File::open(&path).await.unwrap();
+        let mut reader = BufReader::with_capacity(4, file);
+
+        let mut line = String::new();
+        assert_eq!(reader.read_line(&mut line).await.unwrap(), 6);
+        assert_eq!(line, "hello\n");
+
+        line.clear();
+        assert_eq!(reader.read_line(&mut line).await.unwrap(), 6);
+        assert_eq!(reader.read_line(&mut line).await.unwrap(), 1);
+        assert_eq!(line, "world\n\n");
+
+        let buf = OwnedRead::read(&mut reader, BytesMut::with_capacity(2)).await.unwrap();
+        assert_eq!(&buf[..], b"la");
+        assert_eq!(reader.buffer(), b"s");
+
+        // a full buffer still gets the data
+        let buf = OwnedRead::read(&mut reader, BytesMut::from(&b"xy"[..])).await.unwrap();
+        assert_eq!(&buf[..], b"xys");
+
+        line.clear();
+        assert_eq!(reader.read_line(&mut line).await.unwrap(), 1);
+        assert_eq!(line, "t");
+        assert_eq!(reader.read_line(&mut line).await.unwrap(), 0);
+    });
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_lines_and_chunks() {
+    use std::fs;
+    use futures_util::stream::TryStreamExt;
+    use crate::executor::block_on;
+    use super::fs::temp_path;
+
+    let path = temp_path("lines");
+    fs::write(&path, "one\r\ntwo\n\nthree").unwrap();
+
+    block_on(async {
+        let file = File::open(&path).await.unwrap();
+        let lines = BufReader::with_capacity(3, file).lines().try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(lines, ["one", "two", "", "three"]);
+
+        let file = File::open(&path).await.unwrap();
+        let chunks = BufReader::with_capacity(3, file).chunks(4).try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(chunks, [&b"one\r"[..], b"\ntwo", b"\n\nth", b"ree"]);
+    });
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_buf_writer() {
+    use std::fs;
+    use crate::executor::block_on;
+    use super::fs::temp_path;
+
+    let path = temp_path("buf-writer");
+
+    block_on(async {
+        let file = File::create(&path).await.unwrap();
+        let mut writer = BufWriter::with_capacity(8, file);
+
+        writer.write_all(b"hello").await.unwrap();
+        assert_eq!(writer.buffer(), b"hello");
+        assert!(fs::read(&path).unwrap().is_empty());
+
+        // doesn't fit, the buffer is written first
+        writer.write_all(b" world").await.unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"hello");
+
+        // larger than the buffer, written as is
+        let rest = OwnedWrite::write(&mut writer, Bytes::from_static(b", and more")).await.unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(fs::read(&path).unwrap(), b"hello world, and more");
+
+        writer.write_all(b"!").await.unwrap();
+        writer.flush().await.unwrap();
+        assert!(writer.buffer().is_empty());
+    });
+
+    assert_eq!(fs::read(&path).unwrap(), b"hello world, and more!");
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_copy() {
+    use std::fs;
+    use crate::executor::block_on;
+    use super::fs::temp_path;
+
+    let src = temp_path("copy-src");
+    let dst = temp_path("copy-dst");
+    let data = (0..300_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    fs::write(&src, &data).unwrap();
+
+    let n = block_on(async {
+        let mut reader = File::open(&src).await?;
+        let mut writer = File::create(&dst).await?;
+        copy(&mut reader, &mut writer).await
+    }).unwrap();
+
+    assert_eq!(n, data.len() as u64);
+    assert_eq!(fs::read(&dst).unwrap(), data);
+
+    fs::remove_file(&src).unwrap();
+    fs::remove_file(&dst).unwrap();
+}
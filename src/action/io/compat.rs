@@ -0,0 +1,184 @@
+//! The state behind the `futures-io` traits of [File](crate::action::fs::File)
+//! and [TcpStream](crate::action::tcp::TcpStream).
+//!
+//! The kernel owns the buffer of an op until it completes, so reads go into a buffer
+//! of the stream and are copied out, and writes are copied into one.
+//! Both are [Op]s, a dropped stream leaves its buffers to the ring rather than freeing them.
+//!
+//! A pending `poll_write` keeps its op, the next call completes it whatever the slice is,
+//! like the other poll based writers, callers retry with the same data.
+
+use std::{ io, cmp, fmt };
+use std::pin::Pin;
+use std::future::Future;
+use std::task::{ Context, Poll };
+use std::os::unix::io::RawFd;
+use bytes::{ Buf, BufMut, Bytes, BytesMut };
+use io_uring::opcode::{ self, types };
+use crate::handle::{ self, IoPriority };
+use crate::action::op::{ Op, Submit };
+
+
+const DEFAULT_CAPACITY: usize = 8 * 1024;
+
+#[derive(Default)]
+pub(crate) struct Compat {
+    rbuf: BytesMut,
+    read: Option<Submit<BytesMut>>,
+    write: Option<Submit<Bytes>>
+}
+
+impl Compat {
+    /// An op is in flight, the fd must stay open until it completes.
+    #[inline]
+    pub(crate) fn is_busy(&self) -> bool {
+        self.read.is_some() || self.write.is_some()
+    }
+
+    /// Read into the buffer of the stream, returns what the kernel read.
+    pub(crate) fn poll_fill(&mut self, cx: &mut Context<'_>, fd: RawFd, offset: i64, ioprio: Option<IoPriority>)
+        -> Poll<io::Result<usize>>
+    {
+        let read = self.read.get_or_insert_with(|| unsafe {
+            Op::new(BytesMut::with_capacity(DEFAULT_CAPACITY), |buf| {
+                let bytes = buf.bytes_mut();
+                let entry = opcode::Read::new(types::Target::Fd(fd), bytes.as_mut_ptr() as *mut _, bytes.len() as _)
+                    .offset(offset)
+                    .build();
+                handle::ioprio(ioprio, entry)
+            }).submit()
+        });
+
+        let ret = futures_util::ready!(Pin::new(read).poll(cx));
+        self.read = None;
+
+        let (cqe, mut buf) = ret?;
+        let n = cqe.bytes()?;
+
+        unsafe {
+            buf.advance_mut(n);
+        }
+
+        self.rbuf = buf;
+        Poll::Ready(Ok(n))
+    }
+
+    /// Copy out what was read.
+    pub(crate) fn copy_to(&mut self, buf: &mut [u8]) -> usize {
+        let n = cmp::min(buf.len(), self.rbuf.len());
+        buf[..n].copy_from_slice(&self.rbuf[..n]);
+        self.rbuf.advance(n);
+        n
+    }
+
+    #[inline]
+    pub(crate) fn is_drained(&self) -> bool {
+        self.rbuf.is_empty()
+    }
+
+    /// Drop what was read ahead, and cancel a read left in flight.
+    pub(crate) fn discard(&mut self) {
+        self.rbuf.clear();
+        self.read = None;
+    }
+
+    /// Write a copy of `buf`, or complete the write still in flight.
+    pub(crate) fn poll_write(&mut self, cx: &mut Context<'_>, fd: RawFd, offset: i64, ioprio: Option<IoPriority>, buf: &[u8])
+        -> Poll<io::Result<usize>>
+    {
+        let write = self.write.get_or_insert_with(|| {
+            let buf = Bytes::copy_from_slice(&buf[..cmp::min(buf.len(), DEFAULT_CAPACITY)]);
+
+            unsafe {
+                Op::new(buf, |buf| {
+                    let entry = opcode::Write::new(types::Target::Fd(fd), buf.as_ptr(), buf.len() as _)
+                        .offset(offset)
+                        .build();
+                    handle::ioprio(ioprio, entry)
+                }).submit()
+            }
+        });
+
+        let ret = futures_util::ready!(Pin::new(write).poll(cx));
+        self.write = None;
+
+        let (cqe, _) = ret?;
+        Poll::Ready(cqe.bytes())
+    }
+
+    /// Wait for a write left in flight by a caller that gave up on it,
+    /// returns what it wrote.
+    pub(crate) fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        match self.write.as_mut() {
+            Some(write) => {
+                let ret = futures_util::ready!(Pin::new(write).poll(cx));
+                self.write = None;
+
+                let (cqe, _) = ret?;
+                Poll::Ready(cqe.bytes())
+            },
+            None => Poll::Ready(Ok(0))
+        }
+    }
+}
+
+impl fmt::Debug for Compat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Compat")
+            .field("buffered", &self.rbuf.len())
+            .field("reading", &self.read.is_some())
+            .field("writing", &self.write.is_some())
+            .finish()
+    }
+}
+
+
+#[test]
+fn test_futures_io() {
+    use std::{ fs, net, thread };
+    use std::io::{ Read as _, Write as _ };
+    use futures_util::io::{ AsyncReadExt, AsyncWriteExt, AsyncSeekExt, BufReader, AsyncBufReadExt };
+    use crate::action::fs::{ File, temp_path };
+    use crate::action::tcp::TcpStream;
+    use crate::executor::block_on;
+
+    let path = temp_path("futures-io");
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let echo = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).unwrap();
+        stream.write_all(&buf).unwrap();
+    });
+
+    block_on(async {
+        let mut file = File::create(&path).await.unwrap();
+        file.write_all(b"hello\nworld\n").await.unwrap();
+        AsyncWriteExt::close(&mut file).await.unwrap();
+
+        let file = File::open(&path).await.unwrap();
+        let mut lines = BufReader::new(file).lines();
+        assert_eq!(futures_util::StreamExt::next(&mut lines).await.unwrap().unwrap(), "hello");
+
+        let mut file = File::open(&path).await.unwrap();
+        assert_eq!(AsyncSeekExt::seek(&mut file, io::SeekFrom::End(-6)).await.unwrap(), 6);
+        let mut buf = String::new();
+        file.read_to_string(&mut buf).await.unwrap();
+        assert_eq!(buf, "world\n");
+
+        // through a socket
+        AsyncSeekExt::seek(&mut file, io::SeekFrom::Start(0)).await.unwrap();
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        futures_util::io::copy(&mut file, &mut stream).await.unwrap();
+        stream.close().await.unwrap();
+
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"hello\nworld\n");
+    });
+
+    echo.join().unwrap();
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,164 @@
+use std::{ fs, io, process };
+use std::future::Future;
+use std::os::unix::io::{ AsRawFd, FromRawFd, IntoRawFd, RawFd };
+use bytes::{ Bytes, BytesMut };
+use super::{ OwnedRead, OwnedWrite };
+use super::stdio::{ read, write };
+
+
+/// The read end of a pipe, see [pipe].
+#[derive(Debug)]
+pub struct PipeReader {
+    fd: fs::File
+}
+
+/// The write end of a pipe, see [pipe].
+#[derive(Debug)]
+pub struct PipeWriter {
+    fd: fs::File
+}
+
+/// Create a pipe, returns its read end and its write end.
+///
+/// The ends are ordinary fds, so they can also be passed to [splice](crate::action::splice)
+/// and [tee](crate::action::splice::tee), or become the stdio of a child:
+///
+/// ```no_run
+/// use std::process::Command;
+/// use bytes::BytesMut;
+/// use ritsu::action::{ io, process };
+///
+/// # async fn f() -> std::io::Result<()> {
+/// let (mut rx, tx) = io::pipe()?;
+/// let mut command = Command::new("echo");
+/// command.arg("hello").stdout(tx);
+/// let mut child = process::spawn(&mut command)?;
+///
+/// // the command holds the write end until it is dropped
+/// drop(command);
+/// let buf = rx.read(BytesMut::with_capacity(64)).await?;
+/// child.wait().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn pipe() -> io::Result<(PipeReader, PipeWriter)> {
+    let mut fds = [0; 2];
+
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    unsafe {
+        Ok((PipeReader::from_raw_fd(fds[0]), PipeWriter::from_raw_fd(fds[1])))
+    }
+}
+
+impl PipeReader {
+    /// Returns `buf` with what was read appended, nothing once every write end is closed.
+    ///
+    /// A full `buf` grows first.
+    pub async fn read(&mut self, buf: BytesMut) -> io::Result<BytesMut> {
+        read(self.fd.as_raw_fd(), buf).await
+    }
+}
+
+impl PipeWriter {
+    /// Returns what remains of `buf` after a possibly short write.
+    ///
+    /// Fails with [io::ErrorKind::BrokenPipe] once every read end is closed.
+    pub async fn write(&mut self, buf: Bytes) -> io::Result<Bytes> {
+        write(self.fd.as_raw_fd(), buf).await
+    }
+
+    pub async fn write_all(&mut self, buf: Bytes) -> io::Result<()> {
+        super::write_all(self, buf).await
+    }
+}
+
+impl OwnedRead for PipeReader {
+    #[inline]
+    fn read(&mut self, buf: BytesMut) -> impl Future<Output = io::Result<BytesMut>> {
+        PipeReader::read(self, buf)
+    }
+}
+
+impl OwnedWrite for PipeWriter {
+    #[inline]
+    fn write(&mut self, buf: Bytes) -> impl Future<Output = io::Result<Bytes>> {
+        PipeWriter::write(self, buf)
+    }
+}
+
+macro_rules! fd_impls {
+    ( $( $ty:ident ),* ) => {
+        $(
+            impl AsRawFd for $ty {
+                #[inline]
+                fn as_raw_fd(&self) -> RawFd {
+                    self.fd.as_raw_fd()
+                }
+            }
+
+            impl IntoRawFd for $ty {
+                #[inline]
+                fn into_raw_fd(self) -> RawFd {
+                    self.fd.into_raw_fd()
+                }
+            }
+
+            impl FromRawFd for $ty {
+                #[inline]
+                unsafe fn from_raw_fd(fd: RawFd) -> $ty {
+                    $ty { fd: fs::File::from_raw_fd(fd) }
+                }
+            }
+
+            impl From<$ty> for process::Stdio {
+                #[inline]
+                fn from(pipe: $ty) -> process::Stdio {
+                    pipe.fd.into()
+                }
+            }
+        )*
+    }
+}
+
+fd_impls!(PipeReader, PipeWriter);
+
+
+#[test]
+fn test_pipe() {
+    use crate::action::splice::{ self, SpliceFlags };
+    use crate::executor::block_on;
+
+    block_on(async {
+        let (mut rx, mut tx) = pipe().unwrap();
+        let (mut rx2, tx2) = pipe().unwrap();
+
+        tx.write_all(Bytes::from_static(b"hello")).await.unwrap();
+
+        // tee leaves the data in the first pipe
+        let n = splice::tee(&rx, &tx2, 5, SpliceFlags::empty()).await.unwrap();
+        assert_eq!(n, 5);
+        drop(tx2);
+
+        let buf = rx.read(BytesMut::with_capacity(16)).await.unwrap();
+        assert_eq!(&buf[..], b"hello");
+        let buf = rx2.read(BytesMut::with_capacity(16)).await.unwrap();
+        assert_eq!(&buf[..], b"hello");
+        let buf = rx2.read(BytesMut::with_capacity(16)).await.unwrap();
+        assert!(buf.is_empty());
+
+        // a full buffer is not the end of the pipe
+        tx.write_all(Bytes::from_static(b"world")).await.unwrap();
+        let mut full = BytesMut::with_capacity(5);
+        full.extend_from_slice(b"hello");
+        assert_eq!(full.capacity(), full.len());
+        let buf = rx.read(full).await.unwrap();
+        assert_eq!(&buf[..], b"helloworld");
+
+        drop(rx);
+        let err = tx.write(Bytes::from_static(b"world")).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    });
+}
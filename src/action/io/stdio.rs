@@ -0,0 +1,204 @@
+use std::io;
+use std::future::Future;
+use std::os::unix::io::{ AsRawFd, RawFd };
+use bytes::{ Buf, BufMut, Bytes, BytesMut };
+use io_uring::opcode::{ self, types };
+use crate::handle;
+use crate::action::poll::{ Poll, ReadyExt };
+use super::{ OwnedRead, OwnedWrite };
+
+
+/// The standard input of the process, see [stdin].
+#[derive(Debug)]
+pub struct Stdin(());
+
+/// The standard output of the process, see [stdout].
+#[derive(Debug)]
+pub struct Stdout(());
+
+/// The standard error of the process, see [stderr].
+#[derive(Debug)]
+pub struct Stderr(());
+
+/// Read the standard input through the ring.
+///
+/// The reads are not buffered, wrap it in a [super::BufReader] for lines.
+/// The fd is never closed, and the ops use its current position,
+/// so it works the same for a terminal, a pipe or a redirected file.
+/// An fd in non-blocking mode waits for readiness instead of failing with `EAGAIN`.
+pub fn stdin() -> Stdin {
+    Stdin(())
+}
+
+/// Write the standard output through the ring, like [stdin].
+///
+/// The writes are not buffered, unlike [std::io::Stdout] they don't wait for a newline,
+/// wrap it in a [super::BufWriter] to coalesce small writes.
+pub fn stdout() -> Stdout {
+    Stdout(())
+}
+
+/// Write the standard error through the ring, like [stdout].
+pub fn stderr() -> Stderr {
+    Stderr(())
+}
+
+impl Stdin {
+    /// Returns `buf` with what was read appended, nothing at the end of the input.
+    ///
+    /// A full `buf` grows first.
+    pub async fn read(&mut self, buf: BytesMut) -> io::Result<BytesMut> {
+        read(libc::STDIN_FILENO, buf).await
+    }
+}
+
+impl Stdout {
+    /// Returns what remains of `buf` after a possibly short write.
+    pub async fn write(&mut self, buf: Bytes) -> io::Result<Bytes> {
+        write(libc::STDOUT_FILENO, buf).await
+    }
+
+    pub async fn write_all(&mut self, buf: Bytes) -> io::Result<()> {
+        super::write_all(self, buf).await
+    }
+}
+
+impl Stderr {
+    /// Returns what remains of `buf` after a possibly short write.
+    pub async fn write(&mut self, buf: Bytes) -> io::Result<Bytes> {
+        write(libc::STDERR_FILENO, buf).await
+    }
+
+    pub async fn write_all(&mut self, buf: Bytes) -> io::Result<()> {
+        super::write_all(self, buf).await
+    }
+}
+
+pub(super) async fn read(fd: RawFd, mut buf: BytesMut) -> io::Result<BytesMut> {
+    // a read of zero bytes would look like the end of the input,
+    // and `bytes_mut` would only grow a full buffer by a few bytes
+    if !buf.has_remaining_mut() {
+        buf.reserve(super::DEFAULT_CAPACITY);
+    }
+
+    loop {
+        let bytes = buf.bytes_mut();
+        let entry = opcode::Read::new(types::Target::Fd(fd), bytes.as_mut_ptr() as *mut _, bytes.len() as _)
+            .offset(-1)
+            .build();
+
+        let ret = safety_await!{
+            [ buf ];
+            unsafe { handle::push(entry) }
+        };
+        let ret = ret?.result();
+
+        match ret {
+            ret if ret >= 0 => {
+                unsafe {
+                    buf.advance_mut(ret as _);
+                }
+
+                return Ok(buf);
+            },
+            ret if -ret == libc::EAGAIN => fd.ready(Poll::READABLE).await?,
+            ret if -ret == libc::EINTR => (),
+            ret => return Err(io::Error::from_raw_os_error(-ret))
+        }
+    }
+}
+
+pub(super) async fn write(fd: RawFd, mut buf: Bytes) -> io::Result<Bytes> {
+    loop {
+        let entry = opcode::Write::new(types::Target::Fd(fd), buf.as_ptr() as *const _, buf.len() as _)
+            .offset(-1)
+            .build();
+
+        let ret = safety_await!{
+            [ buf ];
+            unsafe { handle::push(entry) }
+        };
+        let ret = ret?.result();
+
+        match ret {
+            ret if ret >= 0 => {
+                buf.advance(ret as _);
+                return Ok(buf);
+            },
+            ret if -ret == libc::EAGAIN => fd.ready(Poll::WRITABLE).await?,
+            ret if -ret == libc::EINTR => (),
+            ret => return Err(io::Error::from_raw_os_error(-ret))
+        }
+    }
+}
+
+impl OwnedRead for Stdin {
+    #[inline]
+    fn read(&mut self, buf: BytesMut) -> impl Future<Output = io::Result<BytesMut>> {
+        Stdin::read(self, buf)
+    }
+}
+
+impl OwnedWrite for Stdout {
+    #[inline]
+    fn write(&mut self, buf: Bytes) -> impl Future<Output = io::Result<Bytes>> {
+        Stdout::write(self, buf)
+    }
+}
+
+impl OwnedWrite for Stderr {
+    #[inline]
+    fn write(&mut self, buf: Bytes) -> impl Future<Output = io::Result<Bytes>> {
+        Stderr::write(self, buf)
+    }
+}
+
+impl AsRawFd for Stdin {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        libc::STDIN_FILENO
+    }
+}
+
+impl AsRawFd for Stdout {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        libc::STDOUT_FILENO
+    }
+}
+
+impl AsRawFd for Stderr {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        libc::STDERR_FILENO
+    }
+}
+
+
+#[test]
+fn test_nonblocking_pipe() {
+    use std::{ thread, time::Duration };
+    use crate::executor::block_on;
+
+    // a pipe in non-blocking mode, as a shell may leave stdin
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) }, 0);
+
+    let writer = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(20));
+        let n = unsafe { libc::write(fds[1], b"hello".as_ptr() as *const _, 5) };
+        assert_eq!(n, 5);
+    });
+
+    let buf = block_on(read(fds[0], BytesMut::with_capacity(16))).unwrap();
+    assert_eq!(&buf[..], b"hello");
+    writer.join().unwrap();
+
+    let rest = block_on(write(fds[1], Bytes::from_static(b"world"))).unwrap();
+    assert!(rest.is_empty());
+
+    unsafe {
+        libc::close(fds[0]);
+        libc::close(fds[1]);
+    }
+}
@@ -1,9 +1,22 @@
 pub mod fs;
 pub mod timeout;
 pub mod tcp;
+pub mod udp;
+pub mod net;
 pub mod poll;
+pub mod cmd;
+pub mod io;
+pub mod splice;
+pub mod process;
+pub mod signal;
+pub mod futex;
+pub mod rate;
+pub mod op;
+#[cfg(feature = "zcrx")]
+pub mod zcrx;
+mod raw;
 
-use std::io;
+use std::task::{ Context, Poll };
 use crate::sync::TicketFuture;
 use crate::SubmissionEntry;
 
@@ -14,20 +27,87 @@ pub struct Handle {
 }
 
 pub struct HandleVTable {
-    pub push: unsafe fn(*const (), SubmissionEntry) -> io::Result<TicketFuture>,
+    pub push: unsafe fn(*const (), SubmissionEntry) -> std::io::Result<TicketFuture>,
+    pub push_linked: unsafe fn(*const (), &[SubmissionEntry]) -> std::io::Result<Vec<TicketFuture>>,
+    pub push_detached: unsafe fn(*const (), SubmissionEntry) -> std::io::Result<()>,
+    pub poll_ready: unsafe fn(*const (), &mut Context<'_>) -> Poll<()>,
+    pub push_registered: unsafe fn(*const (), SubmissionEntry),
+    pub cancel: unsafe fn(*const (), u64),
     pub clone: unsafe fn(*const ()) -> Handle,
     pub drop: unsafe fn(*const ())
 }
 
 impl Handle {
+    /// # Safety
+    ///
+    /// `ptr` must be valid for every function in `vtable`.
     pub const unsafe fn new(ptr: *const (), vtable: &'static HandleVTable) -> Handle {
         Handle { ptr, vtable }
     }
 
+    /// # Safety
+    ///
+    /// The resources referenced by `entry` must stay valid until the returned future completes.
     #[inline]
-    pub unsafe fn push(&self, entry: SubmissionEntry) -> io::Result<TicketFuture> {
+    pub unsafe fn push(&self, entry: SubmissionEntry) -> std::io::Result<TicketFuture> {
         (self.vtable.push)(self.ptr, entry)
     }
+
+    /// Submit `entries` as one `IOSQE_IO_LINK` chain,
+    /// each one only starts after the previous one succeeded.
+    ///
+    /// Returns one future per entry, the ones after a failed link
+    /// complete with `ECANCELED`.
+    ///
+    /// # Safety
+    ///
+    /// Same as [Handle::push], for every entry.
+    #[inline]
+    pub unsafe fn push_linked(&self, entries: &[SubmissionEntry]) -> std::io::Result<Vec<TicketFuture>> {
+        (self.vtable.push_linked)(self.ptr, entries)
+    }
+
+    /// Submit `entry` without waiting for its result, see [crate::RawHandle::raw_push_detached].
+    ///
+    /// # Safety
+    ///
+    /// The resources referenced by `entry` must stay valid until the kernel is done with it,
+    /// which nobody is told about.
+    #[inline]
+    pub unsafe fn push_detached(&self, entry: SubmissionEntry) -> std::io::Result<()> {
+        (self.vtable.push_detached)(self.ptr, entry)
+    }
+
+    /// Ready once an op can be submitted, see [crate::Builder::max_inflight].
+    ///
+    /// The future returned by [Handle::push] waits for this itself
+    /// when the op was held back.
+    #[inline]
+    pub fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<()> {
+        unsafe {
+            (self.vtable.poll_ready)(self.ptr, cx)
+        }
+    }
+
+    /// Submit an entry held back by [Handle::push], registered with the ticket
+    /// of the future it returned. If this fails, the ticket completes with the errno.
+    ///
+    /// # Safety
+    ///
+    /// Same as [Handle::push], and `entry` comes from [crate::Ticket::register]
+    /// and never reached a ring.
+    #[inline]
+    pub unsafe fn push_registered(&self, entry: SubmissionEntry) {
+        (self.vtable.push_registered)(self.ptr, entry)
+    }
+
+    /// Ask the kernel to cancel the op with `user_data`, without waiting for it.
+    #[inline]
+    pub fn cancel(&self, user_data: u64) {
+        unsafe {
+            (self.vtable.cancel)(self.ptr, user_data)
+        }
+    }
 }
 
 impl Clone for Handle {
@@ -0,0 +1,116 @@
+//! Name resolution.
+
+use std::{ io, vec };
+use std::net::{ SocketAddr, ToSocketAddrs };
+use crate::{ sys, handle };
+
+
+// the query and its answer, owned by the op
+pub(crate) struct Lookup {
+    host: String,
+    port: u16,
+    ret: Option<io::Result<Vec<SocketAddr>>>
+}
+
+/// The addresses of a host, see [lookup_host].
+#[derive(Debug)]
+pub struct LookupHost(vec::IntoIter<SocketAddr>);
+
+/// Resolve `host`, a `name:port` like `example.com:443` or `[::1]:80`.
+///
+/// A name is resolved with `getaddrinfo(3)` on the blocking pool,
+/// so it follows `/etc/hosts`, `nsswitch.conf` and the resolver configuration
+/// like the rest of the system. An IP address is returned as is.
+///
+/// ```no_run
+/// use ritsu::action::net;
+///
+/// # async fn f() -> std::io::Result<()> {
+/// for addr in net::lookup_host("example.com:443").await? {
+///     println!("{}", addr);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub async fn lookup_host(host: &str) -> io::Result<LookupHost> {
+    if let Ok(addr) = host.parse::<SocketAddr>() {
+        return Ok(LookupHost(vec![addr].into_iter()));
+    }
+
+    let (name, port) = host.rsplit_once(':')
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "missing the port"))?;
+    let port = port.parse::<u16>()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid port"))?;
+    let name = name.strip_prefix('[')
+        .and_then(|name| name.strip_suffix(']'))
+        .unwrap_or(name);
+
+    let mut lookup = Box::new(Lookup { host: name.into(), port, ret: None });
+
+    let mut entry = sys::entry(sys::RITSU_OP_GETADDRINFO);
+    sys::sqe_mut(&mut entry).addr = &mut *lookup as *mut Lookup as _;
+
+    let ret = safety_await!{
+        [ lookup ];
+        unsafe { handle::push(entry) }
+    };
+    ret?.ok()?;
+
+    match lookup.ret.take() {
+        Some(Ok(addrs)) => Ok(LookupHost(addrs.into_iter())),
+        Some(Err(err)) => Err(err),
+        None => Err(io::Error::from_raw_os_error(libc::EIO))
+    }
+}
+
+/// Run the query of `lookup` on the blocking pool.
+///
+/// # Safety
+///
+/// `lookup` must come from the op of [lookup_host].
+pub(crate) unsafe fn resolve(lookup: *mut Lookup) {
+    let lookup = &mut *lookup;
+    let ret = (lookup.host.as_str(), lookup.port).to_socket_addrs();
+    lookup.ret = Some(ret.map(Iterator::collect));
+}
+
+impl Iterator for LookupHost {
+    type Item = SocketAddr;
+
+    #[inline]
+    fn next(&mut self) -> Option<SocketAddr> {
+        self.0.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+
+#[test]
+fn test_lookup_host() {
+    use crate::executor::block_on;
+
+    block_on(async {
+        let addrs = lookup_host("localhost:80").await.unwrap().collect::<Vec<_>>();
+        assert!(addrs.iter().all(|addr| addr.ip().is_loopback() && addr.port() == 80));
+        assert!(!addrs.is_empty());
+
+        let addrs = lookup_host("[::1]:443").await.unwrap().collect::<Vec<_>>();
+        assert_eq!(addrs, ["[::1]:443".parse().unwrap()]);
+
+        let err = lookup_host("localhost").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        assert!(lookup_host("nonexistent.invalid:80").await.is_err());
+
+        // `::1` may come first and be refused
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let host = format!("localhost:{}", port);
+        super::tcp::TcpStream::connect_host(&host).await.unwrap();
+        listener.accept().unwrap();
+    });
+}
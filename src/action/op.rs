@@ -0,0 +1,385 @@
+//! Arbitrary ops whose resources are owned by the crate.
+//!
+//! [Op] pairs an entry with the buffers, `CString`s or fds it references.
+//! They come back with the completion, and if the future is dropped first,
+//! the op is cancelled and they are kept alive until its completion arrives,
+//! instead of being freed under the kernel. The next `Op` submitted on the thread frees them.
+//!
+//! An [Operation] goes further, it builds its own entry and decodes its own completion,
+//! so an opcode is one type that can be tested on its own, and [submit] runs any of them.
+//!
+//! ```no_run
+//! use std::fs::File;
+//! use std::os::unix::io::AsRawFd;
+//! use io_uring::opcode::{ self, types };
+//! use ritsu::action::op::Op;
+//!
+//! # async fn f() -> std::io::Result<()> {
+//! let file = File::open("/etc/hostname")?;
+//!
+//! let op = unsafe {
+//!     Op::new((file, vec![0; 64]), |(file, buf)| {
+//!         opcode::Read::new(types::Target::Fd(file.as_raw_fd()), buf.as_mut_ptr(), buf.len() as _)
+//!             .build()
+//!     })
+//! };
+//! let (cqe, (_file, mut buf)) = op.submit().await?;
+//! buf.truncate(cqe.bytes()?);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io;
+use std::pin::Pin;
+use std::future::Future;
+use std::cell::RefCell;
+use std::task::{ Context, Poll };
+use crate::{ handle, CqeResult, SubmissionEntry, TicketFuture };
+use crate::handle::SqeFlags;
+
+
+// ops whose future was dropped before their completion, with their resources.
+thread_local!{
+    static ORPHANS: RefCell<Vec<Orphan>> = const { RefCell::new(Vec::new()) };
+}
+
+/// An op that owns what its entry references, and turns its completion into its output.
+///
+/// # Safety
+///
+/// The entry built must only reference memory owned by the op or that outlives it,
+/// and fds that stay open until it completes. The op is boxed before it's built,
+/// and isn't moved until it completes, so it may point into itself.
+pub unsafe trait Operation: 'static {
+    type Output;
+
+    fn build(&mut self) -> SubmissionEntry;
+
+    fn complete(self, cqe: CqeResult) -> Self::Output;
+
+    /// The completion of an op whose future was dropped.
+    ///
+    /// The output is dropped by default, so what the op produced,
+    /// like an accepted fd, is released rather than leaked.
+    fn orphaned(self, cqe: CqeResult)
+    where Self: Sized
+    {
+        drop(self.complete(cqe));
+    }
+}
+
+/// An entry and the resources it references.
+pub struct Op<R: 'static> {
+    entry: SubmissionEntry,
+    res: Box<R>
+}
+
+/// Submits an [Operation] on the first poll, and completes with its output.
+///
+/// Dropping it before that cancels the op, which is kept alive until it completes.
+pub struct Submission<O: Operation> {
+    op: Option<Box<O>>,
+    flags: SqeFlags,
+    fut: Option<TicketFuture>
+}
+
+/// Submits an [Op], and completes with its resources.
+pub type Submit<R> = Submission<Op<R>>;
+
+struct Orphan {
+    fut: TicketFuture,
+    op: Option<Box<dyn Orphaned>>
+}
+
+trait Orphaned {
+    fn finish(self: Box<Self>, cqe: CqeResult);
+}
+
+/// Submit `op` once the future is polled.
+#[inline]
+pub fn submit<O: Operation>(op: O) -> Submission<O> {
+    Submission { op: Some(Box::new(op)), flags: SqeFlags::empty(), fut: None }
+}
+
+impl<R: 'static> Op<R> {
+    /// Build the entry from `res`, it's boxed first,
+    /// so pointers to anything it owns stay valid when the `Op` moves.
+    ///
+    /// # Safety
+    ///
+    /// The memory referenced by the entry must be owned by `res` or outlive the op,
+    /// and the fds it uses must stay open until it completes, an owned fd in `res` does that.
+    pub unsafe fn new<F>(res: R, f: F) -> Op<R>
+    where F: FnOnce(&mut R) -> SubmissionEntry
+    {
+        let mut res = Box::new(res);
+        let entry = f(&mut res);
+
+        Op { entry, res }
+    }
+
+    /// Modify the entry, like to set its flags or personality.
+    ///
+    /// # Safety
+    ///
+    /// Same as [Op::new].
+    pub unsafe fn map_entry<F>(mut self, f: F) -> Op<R>
+    where F: FnOnce(SubmissionEntry, &mut R) -> SubmissionEntry
+    {
+        self.entry = f(self.entry, &mut self.res);
+        self
+    }
+
+    #[inline]
+    pub fn resources(&self) -> &R {
+        &self.res
+    }
+
+    /// Hand back the resources without submitting the op.
+    #[inline]
+    pub fn into_resources(self) -> R {
+        *self.res
+    }
+
+    /// Issue the op with `flags`, like [SqeFlags::IO_DRAIN].
+    #[inline]
+    pub fn flags(mut self, flags: SqeFlags) -> Op<R> {
+        self.entry = flags.apply(self.entry);
+        self
+    }
+
+    #[inline]
+    pub fn submit(self) -> Submit<R> {
+        submit(self)
+    }
+}
+
+impl<O: Operation> Submission<O> {
+    /// Issue the op with `flags`, like [SqeFlags::IO_DRAIN].
+    ///
+    /// It only applies before the first poll.
+    #[inline]
+    pub fn flags(mut self, flags: SqeFlags) -> Submission<O> {
+        self.flags |= flags;
+        self
+    }
+}
+
+unsafe impl<R: 'static> Operation for Op<R> {
+    type Output = (CqeResult, R);
+
+    #[inline]
+    fn build(&mut self) -> SubmissionEntry {
+        self.entry.clone()
+    }
+
+    #[inline]
+    fn complete(self, cqe: CqeResult) -> Self::Output {
+        (cqe, *self.res)
+    }
+}
+
+impl<O: Operation> Future for Submission<O> {
+    type Output = io::Result<O::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+
+        let fut = match (this.fut.as_mut(), this.op.as_mut()) {
+            (Some(fut), _) => fut,
+            (None, Some(op)) => {
+                sweep();
+
+                match unsafe { handle::push(this.flags.apply(op.build())) } {
+                    Ok(fut) => this.fut.get_or_insert(fut),
+                    Err(err) => {
+                        this.op = None;
+                        return Poll::Ready(Err(err));
+                    }
+                }
+            },
+            (None, None) => panic!("`Submission` polled after completion")
+        };
+
+        let cqe = futures_util::ready!(Pin::new(fut).poll(cx));
+        this.fut = None;
+
+        let op = this.op.take().expect("the op is only taken once");
+        Poll::Ready(Ok(op.complete(cqe)))
+    }
+}
+
+impl<O: Operation> Drop for Submission<O> {
+    fn drop(&mut self) {
+        if let (Some(mut fut), Some(op)) = (self.fut.take(), self.op.take()) {
+            if let Some(cqe) = fut.try_complete() {
+                op.orphaned(cqe);
+                return;
+            }
+
+            if fut.is_done() {
+                return;
+            }
+
+            handle::cancel(fut.user_data());
+
+            let orphan = Orphan { fut, op: Some(op) };
+            let _ = ORPHANS.try_with(|orphans| orphans.borrow_mut().push(orphan));
+        }
+    }
+}
+
+impl<O: Operation> Orphaned for O {
+    #[inline]
+    fn finish(self: Box<Self>, cqe: CqeResult) {
+        self.orphaned(cqe)
+    }
+}
+
+impl Drop for Orphan {
+    fn drop(&mut self) {
+        // the thread is exiting with the op in flight
+        if !self.fut.is_done() {
+            std::mem::forget(self.op.take());
+        }
+    }
+}
+
+/// Finish the orphans that completed, and free them.
+fn sweep() {
+    let done = ORPHANS.try_with(|orphans| {
+        let mut orphans = orphans.borrow_mut();
+        let mut done = Vec::new();
+
+        orphans.retain_mut(|orphan| match orphan.fut.try_complete() {
+            Some(cqe) => {
+                done.extend(orphan.op.take().map(|op| (op, cqe)));
+                false
+            },
+            None => !orphan.fut.is_done()
+        });
+
+        done
+    });
+
+    // outside the borrow, an op may submit another when it's finished
+    for (op, cqe) in done.into_iter().flatten() {
+        op.finish(cqe);
+    }
+}
+
+/// The number of orphans still in flight.
+#[cfg(test)]
+pub(crate) fn orphans() -> usize {
+    sweep();
+    ORPHANS.with(|orphans| orphans.borrow().len())
+}
+
+
+#[test]
+fn test_op() {
+    use std::fs;
+    use std::os::unix::io::AsRawFd;
+    use futures_util::future::FutureExt;
+    use io_uring::opcode::{ self, types };
+    use crate::executor::block_on;
+    use crate::action::fs::temp_path;
+
+    let path = temp_path("op");
+    fs::write(&path, "hello").unwrap();
+
+    block_on(async {
+        let file = fs::File::open(&path).unwrap();
+
+        let op = unsafe {
+            Op::new((file, vec![0; 16]), |(file, buf)| {
+                opcode::Read::new(types::Target::Fd(file.as_raw_fd()), buf.as_mut_ptr(), buf.len() as _)
+                    .build()
+            })
+        };
+        let (cqe, (_, mut buf)) = op.submit().await.unwrap();
+        buf.truncate(cqe.bytes().unwrap());
+        assert_eq!(buf, b"hello");
+
+        // only this op is drained
+        let op = unsafe { Op::new((), |_| opcode::Nop::new().build()) }
+            .flags(SqeFlags::IO_DRAIN);
+        assert_eq!(crate::sys::sqe(&op.entry).flags, SqeFlags::IO_DRAIN.bits());
+        op.submit().await.unwrap().0.ok().unwrap();
+
+        // dropped while in flight, the buffer lives until the cancellation completes
+        let (rx, tx) = crate::action::splice::pipe().unwrap();
+        let op = unsafe {
+            Op::new((rx, vec![0; 16]), |(rx, buf)| {
+                opcode::Read::new(types::Target::Fd(rx.as_raw_fd()), buf.as_mut_ptr(), buf.len() as _)
+                    .build()
+            })
+        };
+        let mut submit = op.submit();
+        assert!((&mut submit).now_or_never().is_none());
+        drop(submit);
+        assert_eq!(orphans(), 1);
+
+        crate::time::sleep(std::time::Duration::from_millis(10)).await.unwrap();
+        assert_eq!(orphans(), 0);
+        drop(tx);
+    });
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_operation() {
+    use std::rc::Rc;
+    use std::cell::Cell;
+    use std::os::unix::io::AsRawFd;
+    use futures_util::future::FutureExt;
+    use io_uring::opcode::{ self, types };
+    use crate::executor::block_on;
+
+    struct Read {
+        fd: crate::action::fs::File,
+        buf: Vec<u8>,
+        orphaned: Rc<Cell<Option<i32>>>
+    }
+
+    unsafe impl Operation for Read {
+        type Output = io::Result<Vec<u8>>;
+
+        fn build(&mut self) -> SubmissionEntry {
+            opcode::Read::new(types::Target::Fd(self.fd.as_raw_fd()), self.buf.as_mut_ptr(), self.buf.len() as _)
+                .build()
+        }
+
+        fn complete(mut self, cqe: CqeResult) -> Self::Output {
+            self.buf.truncate(cqe.bytes()?);
+            Ok(self.buf)
+        }
+
+        fn orphaned(self, cqe: CqeResult) {
+            self.orphaned.set(Some(cqe.result()));
+        }
+    }
+
+    block_on(async {
+        let orphaned = Rc::new(Cell::new(None));
+
+        let (rx, tx) = crate::action::splice::pipe().unwrap();
+        let n = unsafe { libc::write(tx.as_raw_fd(), b"hello".as_ptr() as *const _, 5) };
+        assert_eq!(n, 5);
+
+        let buf = submit(Read { fd: rx, buf: vec![0; 16], orphaned: orphaned.clone() }).await.unwrap().unwrap();
+        assert_eq!(buf, b"hello");
+
+        // the cancelled read finishes through `orphaned`
+        let (rx, tx) = crate::action::splice::pipe().unwrap();
+        let mut fut = submit(Read { fd: rx, buf: vec![0; 16], orphaned: orphaned.clone() });
+        assert!((&mut fut).now_or_never().is_none());
+        drop(fut);
+
+        crate::time::sleep(std::time::Duration::from_millis(10)).await.unwrap();
+        assert_eq!(orphans(), 0);
+        assert_eq!(orphaned.get(), Some(-libc::ECANCELED));
+        drop(tx);
+    });
+}
@@ -1,14 +1,21 @@
 use std::io;
 use std::pin::Pin;
+use std::cell::RefCell;
 use std::task::{ Context, Poll as TaskPoll };
 use std::future::Future;
+use std::sync::atomic::{ AtomicBool, Ordering };
 use std::os::unix::io::{ AsRawFd, RawFd };
 use bitflags::bitflags;
 use io_uring::opcode::{ self, types };
-use crate::handle;
+use crate::{ sys, time, handle };
+use crate::sync::multishot::Multishot;
 use crate::TicketFuture;
 
 
+// cleared once the kernel rejects `IORING_POLL_ADD_MULTI` (5.13)
+static MULTISHOT: AtomicBool = AtomicBool::new(true);
+
+
 bitflags!{
     pub struct Poll: i16 {
         const READABLE = libc::POLLIN;
@@ -67,3 +74,242 @@ impl Future for ReadyFuture {
         })
     }
 }
+
+/// An fd driven by readiness, for code that does its own non-blocking I/O on it.
+///
+/// Each direction is watched by a multishot `PollAdd` on the Proactor of this thread,
+/// armed on the first wait and kept until drop, so waiting again costs no submission.
+/// Kernels without it, or runtimes without a Proactor on this thread,
+/// submit a `PollAdd` for every wait instead.
+///
+/// The fd should be in non-blocking mode.
+///
+/// ```no_run
+/// use std::io::Read;
+/// use std::os::unix::net::UnixStream;
+/// use ritsu::action::poll::Async;
+///
+/// # async fn f(stream: UnixStream) -> std::io::Result<()> {
+/// stream.set_nonblocking(true)?;
+/// let stream = Async::new(stream);
+///
+/// let mut buf = [0; 1024];
+/// let n = stream.read_with(|mut stream| stream.read(&mut buf)).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Async<T: AsRawFd> {
+    inner: T,
+    read: RefCell<Watch>,
+    write: RefCell<Watch>
+}
+
+struct Watch {
+    poll: Poll,
+    multishot: Option<Multishot>,
+    oneshot: Option<ReadyFuture>
+}
+
+impl<T: AsRawFd> Async<T> {
+    pub fn new(inner: T) -> Async<T> {
+        Async {
+            inner,
+            read: RefCell::new(Watch::new(Poll::READABLE)),
+            write: RefCell::new(Watch::new(Poll::WRITABLE))
+        }
+    }
+
+    #[inline]
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Stop watching the fd.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Wait until the fd is readable.
+    ///
+    /// A multishot poll reports the fd as it becomes ready, and every report since the
+    /// previous wait completes this one, so it's only accurate right after an op failed with
+    /// [io::ErrorKind::WouldBlock], prefer [Async::read_with].
+    pub async fn readable(&self) -> io::Result<()> {
+        futures_util::future::poll_fn(|cx| self.poll_readable(cx)).await
+    }
+
+    /// Wait until the fd is writable, like [Async::readable].
+    pub async fn writable(&self) -> io::Result<()> {
+        futures_util::future::poll_fn(|cx| self.poll_writable(cx)).await
+    }
+
+    pub fn poll_readable(&self, cx: &mut Context<'_>) -> TaskPoll<io::Result<()>> {
+        self.read.borrow_mut().poll_ready(self.inner.as_raw_fd(), cx)
+    }
+
+    pub fn poll_writable(&self, cx: &mut Context<'_>) -> TaskPoll<io::Result<()>> {
+        self.write.borrow_mut().poll_ready(self.inner.as_raw_fd(), cx)
+    }
+
+    /// Run `f` until it doesn't fail with [io::ErrorKind::WouldBlock],
+    /// waiting for the fd to be readable in between.
+    pub async fn read_with<R, F>(&self, mut f: F) -> io::Result<R>
+    where F: FnMut(&T) -> io::Result<R>
+    {
+        loop {
+            // a report from before the attempt is stale either way.
+            self.read.borrow_mut().clear();
+
+            match f(&self.inner) {
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => self.readable().await?,
+                ret => return ret
+            }
+        }
+    }
+
+    /// Run `f` until it doesn't fail with [io::ErrorKind::WouldBlock],
+    /// waiting for the fd to be writable in between.
+    pub async fn write_with<R, F>(&self, mut f: F) -> io::Result<R>
+    where F: FnMut(&T) -> io::Result<R>
+    {
+        loop {
+            self.write.borrow_mut().clear();
+
+            match f(&self.inner) {
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => self.writable().await?,
+                ret => return ret
+            }
+        }
+    }
+}
+
+impl<T: AsRawFd> AsRawFd for Async<T> {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+impl Watch {
+    fn new(poll: Poll) -> Watch {
+        Watch { poll, multishot: None, oneshot: None }
+    }
+
+    fn clear(&mut self) {
+        if let Some(multishot) = self.multishot.as_ref() {
+            multishot.clear();
+
+            if multishot.is_done() {
+                self.multishot = None;
+            }
+        }
+    }
+
+    fn poll_ready(&mut self, fd: RawFd, cx: &mut Context<'_>) -> TaskPoll<io::Result<()>> {
+        loop {
+            if let Some(multishot) = self.multishot.as_ref() {
+                let ret = match multishot.poll_next(cx) {
+                    TaskPoll::Ready(Some(cqe)) => cqe.result(),
+                    TaskPoll::Ready(None) => {
+                        self.multishot = None;
+                        continue
+                    },
+                    TaskPoll::Pending => return TaskPoll::Pending
+                };
+
+                // the later reports say the same.
+                self.clear();
+
+                if ret >= 0 {
+                    return TaskPoll::Ready(Ok(()));
+                } else if ret == -libc::EINVAL && self.multishot.is_none() {
+                    MULTISHOT.store(false, Ordering::Relaxed);
+                } else {
+                    return TaskPoll::Ready(Err(io::Error::from_raw_os_error(-ret)));
+                }
+
+                continue
+            }
+
+            if self.oneshot.is_none() && !self.arm(fd) {
+                self.oneshot = Some(ReadyFuture::new(fd, self.poll));
+            }
+
+            if let Some(fut) = self.oneshot.as_mut() {
+                let ret = futures_util::ready!(Pin::new(fut).poll(cx));
+                self.oneshot = None;
+                return TaskPoll::Ready(ret);
+            }
+        }
+    }
+
+    fn arm(&mut self, fd: RawFd) -> bool {
+        if !MULTISHOT.load(Ordering::Relaxed) {
+            return false
+        }
+
+        let handle = match time::current() {
+            Some(handle) => handle,
+            None => return false
+        };
+
+        let mut entry = opcode::PollAdd::new(types::Target::Fd(fd), self.poll.bits() as _)
+            .build();
+        sys::sqe_mut(&mut entry).len = sys::IORING_POLL_ADD_MULTI;
+
+        match unsafe { Multishot::push(handle, entry) } {
+            Ok(multishot) => {
+                self.multishot = Some(multishot);
+                true
+            },
+            Err(_) => false
+        }
+    }
+}
+
+
+#[test]
+fn test_async() {
+    use std::io::{ Read, Write };
+    use std::time::Duration;
+    use std::os::unix::net::UnixStream;
+    use crate::executor::block_on;
+
+    fn run() {
+        let (rx, mut tx) = UnixStream::pair().unwrap();
+        rx.set_nonblocking(true).unwrap();
+        let rx = Async::new(rx);
+
+        block_on(async {
+            rx.writable().await.unwrap();
+
+            for i in 0..3u8 {
+                let write = async {
+                    crate::time::sleep(Duration::from_millis(5)).await.unwrap();
+                    tx.write_all(&[i; 2]).unwrap();
+                };
+
+                let mut buf = [0; 4];
+                let read = rx.read_with(|mut rx| rx.read(&mut buf));
+                let (n, ()) = futures_util::future::join(read, write).await;
+                assert_eq!(&buf[..n.unwrap()], &[i; 2]);
+            }
+
+            // the stream is drained, so it waits for a new report.
+            tx.write_all(b"x").unwrap();
+            let mut buf = [0; 4];
+            assert_eq!(rx.read_with(|mut rx| rx.read(&mut buf)).await.unwrap(), 1);
+        });
+    }
+
+    run();
+
+    MULTISHOT.store(false, Ordering::Relaxed);
+    run();
+    MULTISHOT.store(true, Ordering::Relaxed);
+}
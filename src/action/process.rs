@@ -0,0 +1,255 @@
+//! Child processes whose exit is awaited through the ring.
+//!
+//! A spawned child is tracked by a pidfd (5.3), so it can't be confused with
+//! another process reusing its pid. Its exit is awaited with `IORING_OP_WAITID` (6.7),
+//! or by polling the pidfd on older kernels. The piped stdio are [File]s.
+
+use std::{ fs, io, mem, process };
+use std::os::unix::io::{ AsRawFd, FromRawFd, OwnedFd, RawFd };
+use std::os::unix::process::ExitStatusExt;
+use bytes::BytesMut;
+use futures_util::future;
+use crate::{ sys, handle };
+use super::fs::File;
+use super::poll::{ Poll, ReadyExt };
+
+
+/// A running child process, see [spawn].
+///
+/// Dropping it doesn't kill or reap the child.
+#[derive(Debug)]
+pub struct Child {
+    child: process::Child,
+    pidfd: fs::File,
+    status: Option<process::ExitStatus>,
+    pub stdin: Option<File>,
+    pub stdout: Option<File>,
+    pub stderr: Option<File>
+}
+
+/// Spawn `command`, the stdio configured as [process::Stdio::piped] become [File]s.
+///
+/// ```no_run
+/// use std::process::{ Command, Stdio };
+/// use ritsu::action::process;
+///
+/// # async fn f() -> std::io::Result<()> {
+/// let mut command = Command::new("ls");
+/// command.stdout(Stdio::piped());
+/// let output = process::spawn(&mut command)?.wait_with_output().await?;
+/// assert!(output.status.success());
+/// # Ok(())
+/// # }
+/// ```
+pub fn spawn(command: &mut process::Command) -> io::Result<Child> {
+    let mut child = command.spawn()?;
+
+    // the child can't be reaped before we wait for it, so the pid is still its own.
+    let pidfd = unsafe { libc::syscall(libc::SYS_pidfd_open, child.id(), 0) };
+
+    if pidfd < 0 {
+        let err = io::Error::last_os_error();
+        let _ = child.kill();
+        let _ = child.wait();
+        return Err(err);
+    }
+
+    let pidfd = unsafe { fs::File::from_raw_fd(pidfd as RawFd) };
+    let stdin = child.stdin.take().map(|fd| file(fd.into()));
+    let stdout = child.stdout.take().map(|fd| file(fd.into()));
+    let stderr = child.stderr.take().map(|fd| file(fd.into()));
+
+    Ok(Child { child, pidfd, status: None, stdin, stdout, stderr })
+}
+
+fn file(fd: OwnedFd) -> File {
+    File::from_std(fs::File::from(fd))
+}
+
+impl Child {
+    #[inline]
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Send `SIGKILL` to the child, through its pidfd.
+    ///
+    /// It does nothing once the child was waited for.
+    pub fn kill(&mut self) -> io::Result<()> {
+        self.signal(libc::SIGKILL)
+    }
+
+    /// Send `signal` to the child, through its pidfd.
+    pub fn signal(&mut self, signal: i32) -> io::Result<()> {
+        if self.status.is_some() {
+            return Ok(());
+        }
+
+        let ret = unsafe {
+            libc::syscall(libc::SYS_pidfd_send_signal, self.pidfd.as_raw_fd(), signal, 0usize, 0)
+        };
+
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    /// Wait for the child to exit and reap it.
+    ///
+    /// The piped stdin is closed first, so a child reading it to the end can exit.
+    pub async fn wait(&mut self) -> io::Result<process::ExitStatus> {
+        self.wait_by(true).await
+    }
+
+    /// Wait with `IORING_OP_WAITID` if `waitid`, and by polling the pidfd otherwise
+    /// or when the ring doesn't support it.
+    async fn wait_by(&mut self, waitid: bool) -> io::Result<process::ExitStatus> {
+        if let Some(status) = self.status {
+            return Ok(status);
+        }
+
+        if let Some(stdin) = self.stdin.take() {
+            stdin.close().await?;
+        }
+
+        // the probe of the ring fails an unsupported opcode before it's submitted
+        let status = if waitid {
+            match self.waitid().await {
+                Err(ref err) if is_unsupported(err) => self.poll_wait().await?,
+                ret => ret?
+            }
+        } else {
+            self.poll_wait().await?
+        };
+
+        self.status = Some(status);
+        Ok(status)
+    }
+
+    /// Wait for the child to exit, while reading all of its piped stdout and stderr.
+    pub async fn wait_with_output(mut self) -> io::Result<process::Output> {
+        if let Some(stdin) = self.stdin.take() {
+            stdin.close().await?;
+        }
+
+        let (stdout, stderr) = (self.stdout.take(), self.stderr.take());
+        let (stdout, stderr) = future::try_join(read_to_end(stdout), read_to_end(stderr)).await?;
+        let status = self.wait().await?;
+
+        Ok(process::Output { status, stdout, stderr })
+    }
+
+    async fn waitid(&mut self) -> io::Result<process::ExitStatus> {
+        let mut info = Box::new(unsafe { mem::zeroed::<libc::siginfo_t>() });
+
+        let mut entry = sys::entry(sys::IORING_OP_WAITID);
+        let sqe = sys::sqe_mut(&mut entry);
+        sqe.fd = self.pidfd.as_raw_fd();
+        sqe.len = libc::P_PIDFD as _;
+        sqe.file_index = libc::WEXITED as _;
+        sqe.off = &mut *info as *mut libc::siginfo_t as _;
+
+        let ret = safety_await!{
+            [ info ];
+            unsafe { handle::push(entry) }
+        };
+        ret?.ok()?;
+
+        let status = unsafe { info.si_status() };
+
+        // encoded like the status of `waitpid(2)`
+        Ok(process::ExitStatus::from_raw(match info.si_code {
+            libc::CLD_EXITED => (status & 0xff) << 8,
+            libc::CLD_KILLED => status & 0x7f,
+            libc::CLD_DUMPED => (status & 0x7f) | 0x80,
+            _ => status
+        }))
+    }
+
+    async fn poll_wait(&mut self) -> io::Result<process::ExitStatus> {
+        loop {
+            // readable once the child exited
+            self.pidfd.ready(Poll::READABLE).await?;
+
+            if let Some(status) = self.child.try_wait()? {
+                return Ok(status);
+            }
+        }
+    }
+}
+
+impl AsRawFd for Child {
+    /// The pidfd of the child.
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.pidfd.as_raw_fd()
+    }
+}
+
+fn is_unsupported(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::Unsupported
+        || err.raw_os_error() == Some(libc::EINVAL)
+}
+
+async fn read_to_end(file: Option<File>) -> io::Result<Vec<u8>> {
+    let mut file = match file {
+        Some(file) => file,
+        None => return Ok(Vec::new())
+    };
+
+    let mut buf = BytesMut::new();
+
+    loop {
+        let len = buf.len();
+        buf.reserve(8 * 1024);
+        buf = file.read(buf).await?;
+
+        if buf.len() == len {
+            break
+        }
+    }
+
+    file.close().await?;
+
+    Ok(buf.to_vec())
+}
+
+
+#[test]
+fn test_process() {
+    use std::process::{ Command, Stdio };
+    use crate::executor::block_on;
+
+    block_on(async {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("read line; echo \"$line\"; echo err >&2; exit 3")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let mut child = spawn(&mut command).unwrap();
+
+        let stdin = child.stdin.take().unwrap();
+        stdin.write_all_at(-1, bytes::Bytes::from_static(b"hello\n")).await.unwrap();
+        stdin.close().await.unwrap();
+
+        let output = child.wait_with_output().await.unwrap();
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(output.stdout, b"hello\n");
+        assert_eq!(output.stderr, b"err\n");
+
+        let mut child = spawn(Command::new("sleep").arg("10")).unwrap();
+        child.kill().unwrap();
+        let status = child.wait().await.unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGKILL));
+
+        // reaped already
+        assert_eq!(child.wait().await.unwrap(), status);
+        child.kill().unwrap();
+
+        // the fallback
+        let mut child = spawn(&mut Command::new("true")).unwrap();
+        assert!(child.wait_by(false).await.unwrap().success());
+    });
+}
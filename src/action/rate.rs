@@ -0,0 +1,222 @@
+//! Token buckets that pace the ops of a class of I/O.
+//!
+//! A [RateLimit] is shared by every file or stream of a class,
+//! like background compaction next to latency-sensitive reads,
+//! see [File::set_rate_limit](super::fs::File::set_rate_limit).
+//! Their ops wait for tokens before they are submitted, so a throttled class
+//! never queues up in the ring in front of the others.
+
+use std::mem;
+use std::sync::{ Arc, Mutex };
+use std::time::{ Duration, Instant };
+use crate::time;
+
+
+/// Limits the bytes and ops per second of the ops it's attached to.
+///
+/// Each is a token bucket refilled continuously, which holds up to one second worth
+/// of tokens by default. A request larger than what the bucket holds still goes through,
+/// the ones after it wait for the debt to be paid, so requests are served in order.
+///
+/// Clones share the buckets, across threads too.
+#[derive(Clone, Debug)]
+pub struct RateLimit {
+    buckets: Arc<Mutex<Buckets>>
+}
+
+#[derive(Debug)]
+struct Buckets {
+    bytes: Option<Bucket>,
+    ops: Option<Bucket>
+}
+
+#[derive(Debug)]
+struct Bucket {
+    // tokens per second
+    rate: f64,
+    burst: f64,
+    // negative while in debt
+    tokens: f64,
+    last: Instant
+}
+
+impl Bucket {
+    fn new(rate: u64, burst: u64) -> Bucket {
+        assert!(rate > 0, "a rate of 0 would never let anything through");
+
+        Bucket {
+            rate: rate as f64,
+            burst: burst as f64,
+            tokens: burst as f64,
+            last: Instant::now()
+        }
+    }
+
+    /// Take `n` tokens, returns when the bucket is out of debt again.
+    fn take(&mut self, n: f64, now: Instant) -> Instant {
+        if now > self.last {
+            let refill = (now - self.last).as_secs_f64() * self.rate;
+            self.tokens = (self.tokens + refill).min(self.burst);
+            self.last = now;
+        }
+
+        self.tokens -= n;
+
+        if self.tokens >= 0.0 {
+            now
+        } else {
+            now + Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+
+    fn give(&mut self, n: f64) {
+        self.tokens = (self.tokens + n).min(self.burst);
+    }
+}
+
+// the tokens of an acquire that's dropped before it's done go back to the buckets.
+struct Reservation<'a> {
+    limit: &'a RateLimit,
+    bytes: usize
+}
+
+impl RateLimit {
+    /// Up to `bytes_per_sec` bytes and `ops_per_sec` ops per second, `None` doesn't limit them.
+    ///
+    /// # Panics
+    ///
+    /// If a rate is 0.
+    pub fn new(bytes_per_sec: Option<u64>, ops_per_sec: Option<u64>) -> RateLimit {
+        RateLimit::with_burst(
+            bytes_per_sec.map(|rate| (rate, rate)),
+            ops_per_sec.map(|rate| (rate, rate))
+        )
+    }
+
+    /// Like [RateLimit::new], with the `(rate, burst)` of each bucket,
+    /// the burst is how much goes through at once after an idle period.
+    pub fn with_burst(bytes: Option<(u64, u64)>, ops: Option<(u64, u64)>) -> RateLimit {
+        let buckets = Buckets {
+            bytes: bytes.map(|(rate, burst)| Bucket::new(rate, burst)),
+            ops: ops.map(|(rate, burst)| Bucket::new(rate, burst))
+        };
+
+        RateLimit { buckets: Arc::new(Mutex::new(buckets)) }
+    }
+
+    /// Wait for `bytes` and one op worth of tokens.
+    pub async fn acquire(&self, bytes: usize) {
+        let deadline = {
+            let mut buckets = self.buckets.lock().unwrap();
+            let now = Instant::now();
+            let Buckets { bytes: bytes_bucket, ops } = &mut *buckets;
+
+            let a = bytes_bucket.as_mut().map(|bucket| bucket.take(bytes as f64, now));
+            let b = ops.as_mut().map(|bucket| bucket.take(1.0, now));
+            a.into_iter().chain(b).max().unwrap_or(now)
+        };
+
+        if deadline > Instant::now() {
+            let reservation = Reservation { limit: self, bytes };
+            // a `Delay` would make the ops of a file `!Send`
+            let _ = time::sleep_until(deadline).await;
+            mem::forget(reservation);
+        }
+    }
+
+    /// Give back `bytes` that weren't used, like the rest of a short read.
+    pub fn refund(&self, bytes: usize) {
+        if let Some(bucket) = self.buckets.lock().unwrap().bytes.as_mut() {
+            bucket.give(bytes as f64);
+        }
+    }
+
+    // give back an acquire whose op never ran.
+    fn release(&self, bytes: usize) {
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if let Some(bucket) = buckets.bytes.as_mut() {
+            bucket.give(bytes as f64);
+        }
+
+        if let Some(bucket) = buckets.ops.as_mut() {
+            bucket.give(1.0);
+        }
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        self.limit.release(self.bytes);
+    }
+}
+
+#[inline]
+pub(crate) async fn acquire(limit: Option<&RateLimit>, bytes: usize) {
+    if let Some(limit) = limit {
+        limit.acquire(bytes).await;
+    }
+}
+
+/// Give back all of an [acquire], for an op that failed to be pushed.
+#[inline]
+pub(crate) fn release(limit: Option<&RateLimit>, bytes: usize) {
+    if let Some(limit) = limit {
+        limit.release(bytes);
+    }
+}
+
+#[inline]
+pub(crate) fn refund(limit: Option<&RateLimit>, bytes: usize) {
+    if let Some(limit) = limit {
+        limit.refund(bytes);
+    }
+}
+
+
+#[test]
+fn test_rate_limit() {
+    use futures_util::future::FutureExt;
+    use crate::executor::block_on;
+
+    let limit = RateLimit::with_burst(Some((10_000, 1000)), Some((1000, 1000)));
+
+    block_on(async {
+        // the burst goes through right away
+        let start = Instant::now();
+        limit.acquire(1000).await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        // then it's paced, 500 bytes are 50ms
+        limit.acquire(500).await;
+        assert!(start.elapsed() >= Duration::from_millis(45));
+
+        // unused bytes come back
+        limit.refund(1000);
+        let start = Instant::now();
+        limit.acquire(500).await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        // and so does a cancelled acquire
+        assert!(limit.acquire(2000).now_or_never().is_none());
+        let start = Instant::now();
+        limit.acquire(100).await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+    });
+}
+
+#[test]
+fn test_release() {
+    use crate::executor::block_on;
+
+    let limit = RateLimit::new(Some(1000), Some(1));
+
+    block_on(async {
+        // an op that was never pushed gives back its bytes and its op
+        let start = Instant::now();
+        limit.acquire(1000).await;
+        release(Some(&limit), 1000);
+        limit.acquire(1000).await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+    });
+}
@@ -0,0 +1,235 @@
+//! A ring of its own, set up with flags `io-uring` 0.3 can't do.
+//!
+//! Ops that need 32 byte completions or 128 byte entries, which the Proactor doesn't have,
+//! run on a small ring like this. It defers its completions until it's reaped,
+//! and the Proactor just polls an eventfd registered on it.
+//! The ring is tied to the thread that created it.
+
+use std::{ fs, io, mem, ptr };
+use std::sync::atomic::{ AtomicU32, Ordering };
+use std::os::unix::io::{ AsRawFd, FromRawFd, RawFd };
+use crate::{ sys, SubmissionEntry };
+
+
+/// A ring with `IORING_SETUP_CQE32`, and `IORING_SETUP_SQE128` if asked for.
+pub(crate) struct Ring {
+    fd: fs::File,
+    sq_head: *const AtomicU32,
+    sq_tail: *const AtomicU32,
+    sq_mask: u32,
+    sq_array: *mut u32,
+    sqes: *mut u8,
+    sqe128: bool,
+    cq_head: *const AtomicU32,
+    cq_tail: *const AtomicU32,
+    cq_mask: u32,
+    cqes: *const sys::Cqe32,
+    _maps: [Mmap; 3]
+}
+
+impl Ring {
+    pub(crate) fn new(sq_entries: u32, cq_entries: u32, sqe128: bool) -> io::Result<Ring> {
+        let mut params = sys::Params {
+            flags: sys::IORING_SETUP_SINGLE_ISSUER
+                | sys::IORING_SETUP_DEFER_TASKRUN
+                | sys::IORING_SETUP_CQE32
+                | sys::IORING_SETUP_CQSIZE
+                | sys::IORING_SETUP_CLAMP
+                | if sqe128 { sys::IORING_SETUP_SQE128 } else { 0 },
+            cq_entries,
+            ..Default::default()
+        };
+
+        let fd = unsafe { sys::io_uring_setup(sq_entries, &mut params)? };
+        let fd = unsafe { fs::File::from_raw_fd(fd) };
+        let sqe_size = mem::size_of::<sys::Sqe>() << usize::from(sqe128);
+
+        let sq = Mmap::ring(
+            fd.as_raw_fd(),
+            params.sq_off.array as usize + params.sq_entries as usize * mem::size_of::<u32>(),
+            sys::IORING_OFF_SQ_RING
+        )?;
+        let cq = Mmap::ring(
+            fd.as_raw_fd(),
+            params.cq_off.cqes as usize + params.cq_entries as usize * mem::size_of::<sys::Cqe32>(),
+            sys::IORING_OFF_CQ_RING
+        )?;
+        let sqes = Mmap::ring(
+            fd.as_raw_fd(),
+            params.sq_entries as usize * sqe_size,
+            sys::IORING_OFF_SQES
+        )?;
+
+        unsafe {
+            Ok(Ring {
+                sq_head: sq.at(params.sq_off.head),
+                sq_tail: sq.at(params.sq_off.tail),
+                sq_mask: *sq.at::<u32>(params.sq_off.ring_mask),
+                sq_array: sq.at(params.sq_off.array),
+                sqes: sqes.at(0),
+                sqe128,
+                cq_head: cq.at(params.cq_off.head),
+                cq_tail: cq.at(params.cq_off.tail),
+                cq_mask: *cq.at::<u32>(params.cq_off.ring_mask),
+                cqes: cq.at(params.cq_off.cqes),
+                fd,
+                _maps: [sq, cq, sqes]
+            })
+        }
+    }
+
+    /// An eventfd the kernel signals whenever the ring has completions to reap.
+    pub(crate) fn eventfd(&self) -> io::Result<fs::File> {
+        let eventfd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+        if eventfd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let eventfd = unsafe { fs::File::from_raw_fd(eventfd) };
+
+        unsafe {
+            sys::io_uring_register(
+                self.fd.as_raw_fd(),
+                sys::IORING_REGISTER_EVENTFD,
+                &eventfd.as_raw_fd() as *const RawFd as *const _,
+                1
+            )?;
+        }
+
+        Ok(eventfd)
+    }
+
+    /// Submit `entry` right away.
+    #[inline]
+    pub(crate) fn push(&self, entry: &SubmissionEntry) -> io::Result<()> {
+        self.push128(entry, &[0; 64])
+    }
+
+    /// Submit `entry` right away, with `ext` as the second half of a 128 byte SQE.
+    /// It's ignored on a ring of 64 byte SQEs.
+    pub(crate) fn push128(&self, entry: &SubmissionEntry, ext: &[u8; 64]) -> io::Result<()> {
+        unsafe {
+            let tail = (*self.sq_tail).load(Ordering::Relaxed);
+            let head = (*self.sq_head).load(Ordering::Acquire);
+
+            // everything is submitted as it's pushed, so only a failed enter leaves entries behind.
+            if tail.wrapping_sub(head) > self.sq_mask {
+                return Err(io::Error::from_raw_os_error(libc::EBUSY));
+            }
+
+            let index = tail & self.sq_mask;
+            let sqe = self.sqes.add((index as usize * mem::size_of::<sys::Sqe>()) << usize::from(self.sqe128));
+            ptr::copy_nonoverlapping(sys::sqe(entry), sqe as *mut sys::Sqe, 1);
+
+            if self.sqe128 {
+                ptr::copy_nonoverlapping(ext.as_ptr(), sqe.add(mem::size_of::<sys::Sqe>()), ext.len());
+            }
+
+            *self.sq_array.add(index as usize) = index;
+            (*self.sq_tail).store(tail.wrapping_add(1), Ordering::Release);
+
+            sys::io_uring_enter(self.fd.as_raw_fd(), tail.wrapping_add(1).wrapping_sub(head), 0, 0)?;
+        }
+
+        Ok(())
+    }
+
+    /// Run the deferred completions, then pass every completion to `f`.
+    pub(crate) fn reap<F: FnMut(&sys::Cqe32)>(&self, mut f: F) -> io::Result<()> {
+        loop {
+            unsafe {
+                match sys::io_uring_enter(self.fd.as_raw_fd(), 0, 0, sys::IORING_ENTER_GETEVENTS) {
+                    Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                    Err(err) => return Err(err),
+                    Ok(_) => ()
+                }
+
+                let start = (*self.cq_head).load(Ordering::Relaxed);
+                let tail = (*self.cq_tail).load(Ordering::Acquire);
+                let mut head = start;
+
+                while head != tail {
+                    f(&*self.cqes.add((head & self.cq_mask) as usize));
+                    head = head.wrapping_add(1);
+                }
+
+                (*self.cq_head).store(head, Ordering::Release);
+
+                // a full queue may have overflowed, the next enter flushes what it held back.
+                if tail.wrapping_sub(start) <= self.cq_mask {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+impl AsRawFd for Ring {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+pub(crate) struct Mmap {
+    pub(crate) addr: *mut libc::c_void,
+    pub(crate) len: usize
+}
+
+impl Mmap {
+    #[cfg(feature = "zcrx")]
+    pub(crate) fn anon(len: usize) -> io::Result<Mmap> {
+        Mmap::new(-1, len, libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, 0)
+    }
+
+    fn ring(fd: RawFd, len: usize, offset: i64) -> io::Result<Mmap> {
+        Mmap::new(fd, len, libc::MAP_SHARED | libc::MAP_POPULATE, offset)
+    }
+
+    fn new(fd: RawFd, len: usize, flags: i32, offset: i64) -> io::Result<Mmap> {
+        let addr = unsafe {
+            libc::mmap(ptr::null_mut(), len, libc::PROT_READ | libc::PROT_WRITE, flags, fd, offset)
+        };
+
+        if addr != libc::MAP_FAILED {
+            Ok(Mmap { addr, len })
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    #[inline]
+    pub(crate) unsafe fn at<T>(&self, off: u32) -> *mut T {
+        (self.addr as *mut u8).add(off as usize) as *mut T
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.addr, self.len);
+        }
+    }
+}
+
+
+#[test]
+fn test_ring() {
+    use io_uring::opcode;
+
+    for sqe128 in [false, true] {
+        let ring = Ring::new(8, 16, sqe128).unwrap();
+
+        for user_data in 1..=20 {
+            ring.push(&opcode::Nop::new().build().user_data(user_data)).unwrap();
+        }
+
+        let mut seen = Vec::new();
+        ring.reap(|cqe| {
+            assert_eq!(cqe.res, 0);
+            seen.push(cqe.user_data);
+        }).unwrap();
+        assert_eq!(seen, (1..=20).collect::<Vec<_>>());
+
+        ring.reap(|_| panic!()).unwrap();
+    }
+}
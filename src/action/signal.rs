@@ -0,0 +1,196 @@
+//! Receive signals as a [Stream], through a `signalfd(2)`.
+
+use std::{ io, mem, ptr };
+use std::rc::Rc;
+use std::pin::Pin;
+use std::task::{ Context, Poll as TaskPoll };
+use std::os::unix::io::{ AsRawFd, FromRawFd, RawFd };
+use futures_util::future::LocalBoxFuture;
+use futures_util::stream::Stream;
+use io_uring::opcode::{ self, types };
+use crate::handle;
+use super::fs::File;
+use super::poll::{ Poll, ReadyExt };
+
+
+const SIGINFO_SIZE: usize = mem::size_of::<libc::signalfd_siginfo>();
+
+// a few signals per read
+const BUF_SIZE: usize = 8 * SIGINFO_SIZE;
+
+/// The signals received by the process, a stream of their numbers.
+///
+/// The signals are blocked in the calling thread, so that they are queued
+/// for the signalfd instead of running their default action, and they stay blocked on drop.
+/// A process-directed signal is only held back if every thread blocks it,
+/// so create it before spawning other threads, which inherit the mask.
+///
+/// ```no_run
+/// use futures_util::stream::StreamExt;
+/// use ritsu::action::signal::Signals;
+///
+/// # async fn f() -> std::io::Result<()> {
+/// let mut signals = Signals::new([libc::SIGTERM, libc::SIGINT])?;
+///
+/// if let Some(signal) = signals.next().await {
+///     println!("shutting down on {}", signal?);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Signals {
+    fd: Rc<File>,
+    buf: Vec<u8>,
+    pos: usize,
+    fut: Option<LocalBoxFuture<'static, (io::Result<usize>, Vec<u8>)>>,
+    done: bool
+}
+
+impl Signals {
+    pub fn new<I: IntoIterator<Item = i32>>(signals: I) -> io::Result<Signals> {
+        let mut set = unsafe { mem::zeroed::<libc::sigset_t>() };
+
+        unsafe {
+            libc::sigemptyset(&mut set);
+
+            for signal in signals {
+                if libc::sigaddset(&mut set, signal) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+
+        let ret = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut()) };
+
+        if ret != 0 {
+            return Err(io::Error::from_raw_os_error(ret));
+        }
+
+        let fd = unsafe { libc::signalfd(-1, &set, libc::SFD_NONBLOCK | libc::SFD_CLOEXEC) };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let fd = File::from_std(unsafe { std::fs::File::from_raw_fd(fd) });
+
+        Ok(Signals {
+            fd: Rc::new(fd),
+            buf: Vec::with_capacity(BUF_SIZE),
+            pos: 0,
+            fut: None,
+            done: false
+        })
+    }
+}
+
+async fn read(fd: Rc<File>, buf: Vec<u8>) -> (io::Result<usize>, Vec<u8>) {
+    let mut res = (fd, buf);
+    res.1.clear();
+
+    loop {
+        let entry = opcode::Read::new(types::Target::Fd(res.0.as_raw_fd()), res.1.as_mut_ptr(), res.1.capacity() as _)
+            .build();
+
+        let ret = safety_await!{
+            [ res ];
+            unsafe { handle::push(entry) }
+        };
+
+        let ret = match ret {
+            Ok(ret) => ret.result(),
+            Err(err) => return (Err(err), res.1)
+        };
+
+        if ret >= 0 {
+            unsafe {
+                res.1.set_len(ret as usize);
+            }
+
+            return (Ok(ret as usize), res.1);
+        }
+
+        match -ret {
+            // the fd is non-blocking, so a cancelled read never holds on to a signal.
+            libc::EAGAIN => if let Err(err) = res.0.ready(Poll::READABLE).await {
+                return (Err(err), res.1);
+            },
+            libc::EINTR => (),
+            err => return (Err(io::Error::from_raw_os_error(err)), res.1)
+        }
+    }
+}
+
+impl Stream for Signals {
+    type Item = io::Result<i32>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> TaskPoll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            if this.pos + SIGINFO_SIZE <= this.buf.len() {
+                let info = unsafe {
+                    ptr::read_unaligned(this.buf[this.pos..].as_ptr() as *const libc::signalfd_siginfo)
+                };
+                this.pos += SIGINFO_SIZE;
+
+                return TaskPoll::Ready(Some(Ok(info.ssi_signo as i32)));
+            }
+
+            if this.done {
+                return TaskPoll::Ready(None);
+            }
+
+            let (fd, buf) = (&this.fd, &mut this.buf);
+            let fut = this.fut.get_or_insert_with(|| Box::pin(read(fd.clone(), mem::take(buf))));
+
+            let (ret, buf) = match fut.as_mut().poll(cx) {
+                TaskPoll::Ready(ret) => ret,
+                TaskPoll::Pending => return TaskPoll::Pending
+            };
+            this.fut = None;
+            this.buf = buf;
+            this.pos = 0;
+
+            if let Err(err) = ret {
+                this.done = true;
+                return TaskPoll::Ready(Some(Err(err)));
+            }
+        }
+    }
+}
+
+impl AsRawFd for Signals {
+    /// The signalfd.
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+
+#[test]
+fn test_signals() {
+    use futures_util::stream::StreamExt;
+    use crate::executor::block_on;
+
+    block_on(async {
+        let mut signals = Signals::new([libc::SIGUSR1, libc::SIGUSR2]).unwrap();
+
+        // directed at this thread, which blocks them
+        unsafe {
+            libc::raise(libc::SIGUSR2);
+        }
+        assert_eq!(signals.next().await.unwrap().unwrap(), libc::SIGUSR2);
+
+        let raise = async {
+            crate::time::sleep(std::time::Duration::from_millis(10)).await.unwrap();
+
+            unsafe {
+                libc::raise(libc::SIGUSR1);
+            }
+        };
+        let (signal, ()) = futures_util::future::join(signals.next(), raise).await;
+        assert_eq!(signal.unwrap().unwrap(), libc::SIGUSR1);
+    });
+}
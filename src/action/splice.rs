@@ -0,0 +1,310 @@
+//! Move data between fds inside the kernel with `IORING_OP_SPLICE` (5.7).
+//!
+//! One side of a splice must be a pipe, [transfer] goes through a pipe of its own
+//! to connect any two fds, like a file and a socket, see [send_file].
+//! [tee] copies between two pipes with `IORING_OP_TEE` (5.8) without consuming the input.
+
+use std::{ fs, io };
+use std::ops::Range;
+use std::os::unix::io::{ AsRawFd, FromRawFd, RawFd };
+use bitflags::bitflags;
+use bytes::BytesMut;
+use io_uring::opcode::{ self, types };
+use crate::{ sys, handle };
+use super::fs::File;
+use super::tcp::TcpStream;
+
+
+// the default capacity of a pipe
+const PIPE_SIZE: u32 = 64 * 1024;
+
+bitflags!{
+    /// See `splice(2)`.
+    pub struct SpliceFlags: u32 {
+        /// Move pages instead of copying, only a hint.
+        const MOVE = libc::SPLICE_F_MOVE;
+        /// Don't block on the pipes, the ring still waits for the other fd.
+        const NONBLOCK = libc::SPLICE_F_NONBLOCK;
+        /// More data is coming, like `MSG_MORE` on a socket.
+        const MORE = libc::SPLICE_F_MORE;
+    }
+}
+
+/// Create a pipe, returns its read end and its write end.
+///
+/// Both ends are [File]s, [io::pipe](super::io::pipe) returns typed ends.
+pub fn pipe() -> io::Result<(File, File)> {
+    let mut fds = [0; 2];
+
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let (rx, tx) = unsafe { (fs::File::from_raw_fd(fds[0]), fs::File::from_raw_fd(fds[1])) };
+    Ok((File::from_std(rx), File::from_std(tx)))
+}
+
+/// Move up to `len` bytes from `fd_in` to `fd_out`, one of them must be a pipe.
+///
+/// An offset of `None` uses the current position of the fd, it must be `None` for a pipe.
+/// Returns how many bytes were moved, zero at the end of `fd_in`.
+pub async fn splice<I, O>(
+    fd_in: &I, off_in: Option<i64>,
+    fd_out: &O, off_out: Option<i64>,
+    len: u32,
+    flags: SpliceFlags
+) -> io::Result<usize>
+where
+    I: AsRawFd,
+    O: AsRawFd
+{
+    splice_fd(fd_in.as_raw_fd(), off_in, fd_out.as_raw_fd(), off_out, len, flags).await
+}
+
+async fn splice_fd(
+    fd_in: RawFd, off_in: Option<i64>,
+    fd_out: RawFd, off_out: Option<i64>,
+    len: u32,
+    flags: SpliceFlags
+) -> io::Result<usize> {
+    let entry = opcode::Splice::new(
+        types::Target::Fd(fd_in), off_in.unwrap_or(-1),
+        types::Target::Fd(fd_out), off_out.unwrap_or(-1),
+        len
+    )
+        .flags(flags.bits())
+        .build();
+
+    let ret = safety_await!{
+        unsafe { handle::push(entry) }
+    };
+    ret?.bytes()
+}
+
+/// Copy up to `len` bytes from the pipe `pipe_in` to the pipe `pipe_out`,
+/// the data stays in `pipe_in` to be read or spliced again.
+///
+/// A tap on a [splice] pipeline, for example to mirror a stream to a log.
+/// Returns how many bytes were copied, zero if `pipe_in` is empty and has no writer.
+pub async fn tee<I, O>(pipe_in: &I, pipe_out: &O, len: u32, flags: SpliceFlags) -> io::Result<usize>
+where
+    I: AsRawFd,
+    O: AsRawFd
+{
+    let mut entry = sys::entry(sys::IORING_OP_TEE);
+    let sqe = sys::sqe_mut(&mut entry);
+    sqe.fd = pipe_out.as_raw_fd();
+    sqe.file_index = pipe_in.as_raw_fd() as _;
+    sqe.len = len;
+    sqe.op_flags = flags.bits();
+
+    let ret = safety_await!{
+        unsafe { handle::push(entry) }
+    };
+    ret?.bytes()
+}
+
+/// Move `len` bytes from `from` to `to` through a pipe, neither needs to be one.
+///
+/// The offsets are like [splice], and advanced by what was moved.
+/// Returns how many bytes were moved, less than `len` only if `from` ended first.
+pub async fn transfer<I, O>(
+    from: &I, off_in: Option<i64>,
+    to: &O, off_out: Option<i64>,
+    len: u64
+) -> io::Result<u64>
+where
+    I: AsRawFd,
+    O: AsRawFd
+{
+    let mut total = 0;
+    pump(from.as_raw_fd(), off_in, to.as_raw_fd(), off_out, len, &mut total).await?;
+    Ok(total)
+}
+
+/// Send `range` of `file` to `socket`, returns how many bytes were sent.
+///
+/// The data goes through a pipe with [transfer] and never reaches userspace.
+/// If the kernel can't splice it, because the ring doesn't have the opcode
+/// or the filesystem doesn't support it, it's read and written in chunks instead.
+pub async fn send_file(socket: &mut TcpStream, file: &File, range: Range<u64>) -> io::Result<u64> {
+    const CHUNK: usize = 64 * 1024;
+
+    let len = range.end.saturating_sub(range.start);
+    let mut total = 0;
+
+    match pump(file.as_raw_fd(), Some(range.start as i64), socket.as_raw_fd(), None, len, &mut total).await {
+        Ok(()) => return Ok(total),
+        Err(ref err) if total == 0 && is_unsupported(err) => (),
+        Err(err) => return Err(err)
+    }
+
+    while total < len {
+        let chunk = (len - total).min(CHUNK as u64) as usize;
+        let buf = file.read_at((range.start + total) as i64, BytesMut::with_capacity(chunk)).await?;
+
+        if buf.is_empty() {
+            break
+        }
+
+        let n = buf.len().min(chunk);
+        let mut buf = buf.freeze().slice(..n);
+
+        while !buf.is_empty() {
+            let rest = socket.write(buf.clone()).await?;
+
+            if rest.len() == buf.len() {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+
+            buf = rest;
+        }
+
+        total += n as u64;
+    }
+
+    Ok(total)
+}
+
+fn is_unsupported(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::Unsupported
+        || err.raw_os_error() == Some(libc::EINVAL)
+}
+
+// `total` counts what was moved so far, also when it fails.
+async fn pump(
+    from: RawFd, mut off_in: Option<i64>,
+    to: RawFd, mut off_out: Option<i64>,
+    len: u64,
+    total: &mut u64
+) -> io::Result<()> {
+    let (rx, tx) = pipe()?;
+
+    while *total < len {
+        let chunk = (len - *total).min(u64::from(PIPE_SIZE)) as u32;
+        let n = splice_fd(from, off_in, tx.as_raw_fd(), None, chunk, SpliceFlags::MOVE).await?;
+
+        if n == 0 {
+            break
+        }
+
+        off_in = off_in.map(|off| off + n as i64);
+
+        // drain the pipe, a socket may take less at a time
+        let mut pending = n;
+
+        while pending > 0 {
+            let m = splice_fd(
+                rx.as_raw_fd(), None,
+                to, off_out,
+                pending as u32,
+                SpliceFlags::MOVE | SpliceFlags::MORE
+            ).await?;
+
+            if m == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+
+            off_out = off_out.map(|off| off + m as i64);
+            pending -= m;
+            *total += m as u64;
+        }
+    }
+
+    rx.close().await?;
+    tx.close().await?;
+
+    Ok(())
+}
+
+
+#[test]
+fn test_splice() {
+    use std::io::Read;
+    use crate::executor::block_on;
+    use super::fs::temp_path;
+
+    let src = temp_path("splice-src");
+    let dst = temp_path("splice-dst");
+    let data = (0..200_000u32).map(|i| (i % 253) as u8).collect::<Vec<_>>();
+    fs::write(&src, &data).unwrap();
+
+    block_on(async {
+        let file = File::open(&src).await.unwrap();
+        let (rx, tx) = pipe().unwrap();
+
+        let n = splice(&file, Some(10), &tx, None, 5, SpliceFlags::empty()).await.unwrap();
+        assert_eq!(n, 5);
+
+        let mut buf = [0; 5];
+        let mut rx = rx.into_std();
+        rx.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[10..15]);
+        tx.close().await.unwrap();
+
+        let out = File::create(&dst).await.unwrap();
+        let n = transfer(&file, Some(100), &out, Some(0), data.len() as u64).await.unwrap();
+        assert_eq!(n, data.len() as u64 - 100);
+        out.close().await.unwrap();
+    });
+
+    assert_eq!(fs::read(&dst).unwrap(), &data[100..]);
+
+    fs::remove_file(&src).unwrap();
+    fs::remove_file(&dst).unwrap();
+}
+
+#[test]
+fn test_tee() {
+    use std::io::{ Read, Write };
+    use crate::executor::block_on;
+
+    let (rx, tx) = pipe().unwrap();
+    let (tap_rx, tap_tx) = pipe().unwrap();
+    tx.into_std().write_all(b"hello").unwrap();
+
+    let n = block_on(tee(&rx, &tap_tx, 64, SpliceFlags::empty())).unwrap();
+    assert_eq!(n, 5);
+
+    let mut buf = Vec::new();
+    drop(tap_tx.into_std());
+    tap_rx.into_std().read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, b"hello");
+
+    // still there
+    let mut buf = [0; 5];
+    rx.into_std().read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[test]
+fn test_send_file() {
+    use std::{ net, thread };
+    use std::io::Read;
+    use crate::executor::block_on;
+    use super::fs::temp_path;
+
+    let path = temp_path("send-file");
+    let data = (0..300_000u32).map(|i| (i % 247) as u8).collect::<Vec<_>>();
+    fs::write(&path, &data).unwrap();
+
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let reader = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).unwrap();
+        buf
+    });
+
+    let n = block_on(async {
+        let mut socket = TcpStream::from_std(net::TcpStream::connect(addr)?);
+        let file = File::open(&path).await?;
+        send_file(&mut socket, &file, 1000..250_000).await
+    }).unwrap();
+
+    assert_eq!(n, 249_000);
+    assert_eq!(reader.join().unwrap(), &data[1000..250_000]);
+
+    fs::remove_file(&path).unwrap();
+}
@@ -1,10 +1,21 @@
 use std::{ io, net, mem };
 use std::os::unix::io::{ AsRawFd, FromRawFd, RawFd };
+#[cfg(feature = "futures-io")]
+use std::{ pin::Pin, task::{ Context, Poll } };
+#[cfg(feature = "futures-io")]
+use std::os::unix::io::IntoRawFd;
 use bytes::{ Buf, BufMut, Bytes, BytesMut };
 use socket2::{ SockAddr, Socket, Domain, Type, Protocol };
 use io_uring::opcode::{ self, types };
 use crate::util::MaybeLock;
-use crate::handle;
+use crate::handle::{ self, IoPriority };
+use super::rate::{ self, RateLimit };
+#[cfg(feature = "futures-io")]
+use super::io::compat::Compat;
+
+mod reuseport;
+
+pub use reuseport::{ ReusePort, Incoming };
 
 
 pub struct TcpListener {
@@ -17,10 +28,15 @@ pub struct TcpConnector {
 }
 
 pub struct TcpStream {
-    fd: net::TcpStream
+    fd: mem::ManuallyDrop<net::TcpStream>,
+    ioprio: Option<IoPriority>,
+    limit: Option<RateLimit>,
+    #[cfg(feature = "futures-io")]
+    compat: Compat
 }
 
 impl TcpListener {
+    /// The fd is used as is, like a listener set up with `socket2`.
     pub fn from_std(fd: net::TcpListener) -> TcpListener {
         let sockaddr = MaybeLock::new(Box::new((
             unsafe { mem::zeroed() },
@@ -29,6 +45,23 @@ impl TcpListener {
         TcpListener { fd, sockaddr }
     }
 
+    /// Bind a listener with `SO_REUSEPORT`,
+    /// so every thread can bind its own listener to the same `addr`
+    /// and the kernel balances incoming connections across them.
+    pub fn bind_reuseport(addr: net::SocketAddr) -> io::Result<TcpListener> {
+        let domain = match &addr {
+            net::SocketAddr::V4(_) => Domain::ipv4(),
+            net::SocketAddr::V6(_) => Domain::ipv6(),
+        };
+        let socket = Socket::new(domain, Type::stream(), Some(Protocol::tcp()))?;
+        socket.set_reuse_address(true)?;
+        socket.set_reuse_port(true)?;
+        socket.bind(&SockAddr::from(addr))?;
+        socket.listen(1024)?;
+
+        Ok(TcpListener::from_std(socket.into_tcp_listener()))
+    }
+
     pub async fn accept(&mut self) -> io::Result<(TcpStream, net::SocketAddr)> {
         let entry = opcode::Accept::new(
             types::Target::Fd(self.fd.as_raw_fd()),
@@ -88,19 +121,33 @@ impl TcpConnector {
             unsafe { handle::push(entry) }
         };
         self.sockaddr.take();
-        let ret = ret?.result();
-
-        if ret >= 0 {
-            Ok(TcpStream { fd: stream.into_tcp_stream() })
-        } else {
-            Err(io::Error::from_raw_os_error(-ret))
-        }
+        ret?.ok()?;
+        Ok(TcpStream::from_std(stream.into_tcp_stream()))
     }
 }
 
 impl TcpStream {
+    /// The fd is used as is, its options and `O_NONBLOCK` are kept.
+    /// The ops of a nonblocking stream fail with `WouldBlock` instead of waiting,
+    /// see [ReadyExt::ready](super::poll::ReadyExt::ready).
     pub fn from_std(fd: net::TcpStream) -> TcpStream {
-        TcpStream { fd }
+        TcpStream {
+            fd: mem::ManuallyDrop::new(fd),
+            ioprio: None,
+            limit: None,
+            #[cfg(feature = "futures-io")]
+            compat: Compat::default()
+        }
+    }
+
+    /// Issue the ops of this stream with `ioprio`, `None` uses the priority of the thread.
+    pub fn set_priority(&mut self, ioprio: Option<IoPriority>) {
+        self.ioprio = ioprio;
+    }
+
+    /// Pace the reads and writes of this stream with `limit`, see [super::fs::File::set_rate_limit].
+    pub fn set_rate_limit(&mut self, limit: Option<RateLimit>) {
+        self.limit = limit;
     }
 
     #[inline]
@@ -108,7 +155,24 @@ impl TcpStream {
         TcpConnector::new().connect(addr).await
     }
 
+    /// Resolve `host` with [super::net::lookup_host], and connect to its addresses in turn.
+    pub async fn connect_host(host: &str) -> io::Result<TcpStream> {
+        let mut last = None;
+
+        for addr in super::net::lookup_host(host).await? {
+            match TcpStream::connect(addr).await {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last = Some(err)
+            }
+        }
+
+        Err(last.unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no addresses to connect to")))
+    }
+
     pub async fn read(&mut self, mut buf: BytesMut) -> io::Result<BytesMut> {
+        let len = buf.bytes_mut().len();
+        rate::acquire(self.limit.as_ref(), len).await;
+
         let bytes = buf.bytes_mut();
         let entry = opcode::Read::new(
             types::Target::Fd(self.fd.as_raw_fd()),
@@ -116,13 +180,15 @@ impl TcpStream {
             bytes.len() as _
         )
             .build();
+        let entry = handle::ioprio(self.ioprio, entry);
 
         let ret = safety_await!{
             [ buf ];
             unsafe { handle::push(entry) }
         };
 
-        let ret = ret?.result();
+        let ret = ret.inspect_err(|_| rate::release(self.limit.as_ref(), len))?.result();
+        rate::refund(self.limit.as_ref(), len - ret.max(0) as usize);
 
         if ret >= 0 {
             unsafe {
@@ -136,18 +202,23 @@ impl TcpStream {
     }
 
     pub async fn write(&mut self, mut buf: Bytes) -> io::Result<Bytes> {
+        let len = buf.len();
+        rate::acquire(self.limit.as_ref(), len).await;
+
         let entry = opcode::Write::new(
             types::Target::Fd(self.fd.as_raw_fd()),
             buf.as_ptr() as *const _,
             buf.len() as _
         )
             .build();
+        let entry = handle::ioprio(self.ioprio, entry);
 
         let ret = safety_await!{
             [ buf ];
             unsafe { handle::push(entry) }
         };
-        let ret = ret?.result();
+        let ret = ret.inspect_err(|_| rate::release(self.limit.as_ref(), len))?.result();
+        rate::refund(self.limit.as_ref(), len - ret.max(0) as usize);
 
         if ret >= 0 {
             buf.advance(ret as _);
@@ -158,6 +229,42 @@ impl TcpStream {
     }
 }
 
+impl From<net::TcpListener> for TcpListener {
+    #[inline]
+    fn from(fd: net::TcpListener) -> TcpListener {
+        TcpListener::from_std(fd)
+    }
+}
+
+impl From<Socket> for TcpListener {
+    /// The socket must be bound and listening.
+    #[inline]
+    fn from(socket: Socket) -> TcpListener {
+        TcpListener::from_std(socket.into_tcp_listener())
+    }
+}
+
+impl From<net::TcpStream> for TcpStream {
+    #[inline]
+    fn from(fd: net::TcpStream) -> TcpStream {
+        TcpStream::from_std(fd)
+    }
+}
+
+impl From<Socket> for TcpStream {
+    /// The socket must be connected.
+    #[inline]
+    fn from(socket: Socket) -> TcpStream {
+        TcpStream::from_std(socket.into_tcp_stream())
+    }
+}
+
+impl Default for TcpConnector {
+    fn default() -> TcpConnector {
+        TcpConnector::new()
+    }
+}
+
 impl Drop for TcpConnector {
     fn drop(&mut self) {
         if self.sockaddr.is_none() {
@@ -168,6 +275,30 @@ impl Drop for TcpConnector {
     }
 }
 
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        let fd = unsafe { mem::ManuallyDrop::take(&mut self.fd) };
+
+        // an op of the futures-io traits still uses the fd,
+        // close it through the ring behind that op, so the fd isn't reused under it
+        #[cfg(feature = "futures-io")]
+        if self.compat.is_busy() {
+            let fd = fd.into_raw_fd();
+            let entry = opcode::Close::new(fd).build();
+
+            if unsafe { handle::try_push_detached(entry) }.is_err() {
+                unsafe {
+                    libc::close(fd);
+                }
+            }
+
+            return;
+        }
+
+        drop(fd);
+    }
+}
+
 impl AsRawFd for TcpListener {
     #[inline]
     fn as_raw_fd(&self) -> RawFd {
@@ -181,3 +312,40 @@ impl AsRawFd for TcpStream {
         self.fd.as_raw_fd()
     }
 }
+
+/// Reads through a buffer of the stream, the rate limit doesn't apply.
+#[cfg(feature = "futures-io")]
+impl futures_io::AsyncRead for TcpStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        if this.compat.is_drained() {
+            futures_util::ready!(this.compat.poll_fill(cx, this.fd.as_raw_fd(), -1, this.ioprio))?;
+        }
+
+        Poll::Ready(Ok(this.compat.copy_to(buf)))
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl futures_io::AsyncWrite for TcpStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.compat.poll_write(cx, this.fd.as_raw_fd(), -1, this.ioprio, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        futures_util::ready!(this.compat.poll_flush(cx))?;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        futures_util::ready!(self.as_mut().poll_flush(cx))?;
+        Poll::Ready(self.fd.shutdown(net::Shutdown::Write))
+    }
+}
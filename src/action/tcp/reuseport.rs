@@ -0,0 +1,226 @@
+//! Listeners for every worker of a thread-per-core runtime, bound to one address.
+//!
+//! Each worker binds its own listener with `SO_REUSEPORT`, and the kernel hashes
+//! the connections across them. With `SO_INCOMING_CPU`, a listener is preferred
+//! for the connections that came in on its CPU, no eBPF program is needed.
+
+use std::{ io, net, mem };
+use std::pin::Pin;
+use std::future::Future;
+use std::sync::{ Arc, Mutex };
+use std::task::{ Context, Poll };
+use std::os::unix::io::{ AsRawFd, FromRawFd, RawFd };
+use futures_util::ready;
+use futures_util::stream::Stream;
+use socket2::SockAddr;
+use io_uring::opcode::{ self, types };
+use crate::{ CqeResult, SubmissionEntry };
+use crate::action::op::{ self, Operation, Submission };
+use super::{ TcpListener, TcpStream };
+
+
+// the asm-generic value, libc doesn't have it
+const SO_INCOMING_CPU: libc::c_int = 49;
+
+/// The address the listeners of the workers share.
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use futures_util::stream::StreamExt;
+/// use ritsu::action::tcp::ReusePort;
+/// use ritsu::executor::{ thread_per_core, spawn_local };
+///
+/// let group = Arc::new(ReusePort::bind("127.0.0.1:1234".parse().unwrap()).unwrap());
+/// let threads = thread_per_core(move |core| {
+///     let group = group.clone();
+///
+///     async move {
+///         let mut incoming = group.listen(Some(core.cpu()))?;
+///
+///         while let Some(ret) = incoming.next().await {
+///             let (stream, _) = ret?;
+///             spawn_local(async move { drop(stream) });
+///         }
+///
+///         Ok(())
+///     }
+/// }).unwrap();
+///
+/// for thread in threads {
+///     let _: std::io::Result<()> = thread.join().unwrap();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ReusePort {
+    addr: net::SocketAddr,
+    // bound up front to resolve the port, handed to the first worker
+    first: Mutex<Option<net::TcpListener>>
+}
+
+/// The connections accepted by a listener, as a `Stream`.
+///
+/// A connection accepted after the stream is dropped is closed.
+pub struct Incoming {
+    fd: Arc<net::TcpListener>,
+    accept: Option<Submission<Accept>>
+}
+
+struct Accept {
+    fd: Arc<net::TcpListener>,
+    addr: libc::sockaddr_storage,
+    len: libc::socklen_t
+}
+
+impl ReusePort {
+    /// Bind the first listener, a port `0` is resolved once for every worker.
+    pub fn bind(addr: net::SocketAddr) -> io::Result<ReusePort> {
+        let listener = TcpListener::bind_reuseport(addr)?.fd;
+        let addr = listener.local_addr()?;
+
+        Ok(ReusePort { addr, first: Mutex::new(Some(listener)) })
+    }
+
+    #[inline]
+    pub fn local_addr(&self) -> net::SocketAddr {
+        self.addr
+    }
+
+    /// Bind the listener of a worker, and accept from it on this thread.
+    ///
+    /// `cpu` is the CPU the worker is pinned to, like [Core::cpu](crate::executor::Core::cpu).
+    /// Connections that came in on it then go to this listener.
+    pub fn listen(&self, cpu: Option<usize>) -> io::Result<Incoming> {
+        let first = self.first.lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .take();
+
+        let listener = match first {
+            Some(listener) => listener,
+            None => TcpListener::bind_reuseport(self.addr)?.fd
+        };
+
+        if let Some(cpu) = cpu {
+            let cpu = cpu as libc::c_int;
+            let ret = unsafe {
+                libc::setsockopt(
+                    listener.as_raw_fd(),
+                    libc::SOL_SOCKET,
+                    SO_INCOMING_CPU,
+                    &cpu as *const _ as *const _,
+                    mem::size_of::<libc::c_int>() as _
+                )
+            };
+
+            if ret != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(Incoming::from_std(listener))
+    }
+}
+
+impl Incoming {
+    pub fn from_std(fd: net::TcpListener) -> Incoming {
+        Incoming { fd: Arc::new(fd), accept: None }
+    }
+
+    #[inline]
+    pub fn local_addr(&self) -> io::Result<net::SocketAddr> {
+        self.fd.local_addr()
+    }
+}
+
+impl TcpListener {
+    /// Accept with a `Stream`, that owns the listener.
+    #[inline]
+    pub fn incoming(self) -> Incoming {
+        Incoming::from_std(self.fd)
+    }
+}
+
+unsafe impl Operation for Accept {
+    type Output = io::Result<(TcpStream, net::SocketAddr)>;
+
+    fn build(&mut self) -> SubmissionEntry {
+        self.len = mem::size_of::<libc::sockaddr_storage>() as _;
+
+        opcode::Accept::new(
+            types::Target::Fd(self.fd.as_raw_fd()),
+            &mut self.addr as *mut _ as *mut _,
+            &mut self.len
+        )
+            .flags(libc::SOCK_CLOEXEC as _)
+            .build()
+    }
+
+    fn complete(self, cqe: CqeResult) -> Self::Output {
+        let fd = cqe.ok()?;
+        let stream = TcpStream::from_std(unsafe { net::TcpStream::from_raw_fd(fd as _) });
+
+        let addr = unsafe { SockAddr::from_raw_parts(&self.addr as *const _ as *const _, self.len) };
+        let addr = addr.as_std()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "connection from a non-IP address"))?;
+
+        Ok((stream, addr))
+    }
+}
+
+impl Stream for Incoming {
+    type Item = io::Result<(TcpStream, net::SocketAddr)>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let fd = &this.fd;
+
+        let accept = this.accept.get_or_insert_with(|| op::submit(Accept {
+            fd: fd.clone(),
+            addr: unsafe { mem::zeroed() },
+            len: 0
+        }));
+
+        let ret = ready!(Pin::new(accept).poll(cx));
+        this.accept = None;
+
+        Poll::Ready(Some(ret.and_then(|ret| ret)))
+    }
+}
+
+impl AsRawFd for Incoming {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+
+#[test]
+fn test_reuseport() {
+    use futures_util::stream::StreamExt;
+    use crate::executor::block_on;
+
+    let group = ReusePort::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = group.local_addr();
+    assert_ne!(addr.port(), 0);
+
+    block_on(async {
+        let a = group.listen(None).unwrap();
+        let b = group.listen(Some(0)).unwrap();
+        assert_eq!(a.local_addr().unwrap(), addr);
+        assert_eq!(b.local_addr().unwrap(), addr);
+
+        // the kernel picks a listener for each, between the two they see them all
+        let streams = (0..8)
+            .map(|_| net::TcpStream::connect(addr).unwrap())
+            .collect::<Vec<_>>();
+        let accepted = futures_util::stream::select(a, b)
+            .take(streams.len())
+            .collect::<Vec<_>>()
+            .await;
+
+        for ret in accepted {
+            let (_, peer) = ret.unwrap();
+            assert!(streams.iter().any(|stream| stream.local_addr().unwrap() == peer));
+        }
+    });
+}
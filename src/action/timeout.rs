@@ -1,7 +1,8 @@
 use std::io;
-use std::time::Duration;
+use std::time::{ Duration, Instant };
 use io_uring::opcode::{ self, types };
 use crate::handle;
+use crate::time::{ self, Deadline };
 use crate::util::MaybeLock;
 
 
@@ -17,22 +18,30 @@ impl Timer {
     }
 
     pub async fn delay_for(&mut self, dur: Duration) -> io::Result<()> {
-        self.timespec.tv_sec = dur.as_secs() as _;
-        self.timespec.tv_nsec = dur.subsec_nanos() as _;
+        self.delay(dur).await
+    }
+
+    pub async fn delay_until(&mut self, deadline: Instant) -> io::Result<()> {
+        self.delay(deadline).await
+    }
+
+    pub async fn delay(&mut self, deadline: impl Into<Deadline>) -> io::Result<()> {
+        let (timespec, flags) = deadline.into().timeout();
+        **self.timespec = timespec;
 
-        let entry = opcode::Timeout::new(&**self.timespec).build();
+        let entry = opcode::Timeout::new(&**self.timespec)
+            .flags(flags)
+            .build();
         let ret = safety_await!{
             ( self.timespec );
             unsafe { handle::push(entry) }
         };
-        let ret = ret?.result();
-
-        if ret >= 0 {
-            Ok(())
-        } else {
-            Err(io::Error::from_raw_os_error(-ret))
-        }
+        time::timeout_result(ret?.result())
     }
+}
 
-    // TODO delay_until
+impl Default for Timer {
+    fn default() -> Timer {
+        Timer::new()
+    }
 }
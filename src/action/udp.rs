@@ -0,0 +1,372 @@
+//! UDP sockets, and [UdpFramed] to use one as a `Stream` and `Sink` of datagrams.
+
+use std::{ io, net, mem, ptr };
+use std::pin::Pin;
+use std::sync::Arc;
+use std::future::Future;
+use std::task::{ Context, Poll };
+use std::os::unix::io::{ AsRawFd, RawFd };
+use bytes::{ BufMut, Bytes, BytesMut };
+use futures_util::ready;
+use futures_util::stream::Stream;
+use futures_util::sink::Sink;
+use socket2::{ SockAddr, Socket };
+use io_uring::opcode::{ self, types };
+use crate::handle::{ self, IoPriority };
+use crate::{ CqeResult, SubmissionEntry };
+use super::op::{ self, Operation, Submission };
+
+
+const DEFAULT_CAPACITY: usize = 64 * 1024;
+
+/// A UDP socket whose ops go through the ring.
+///
+/// Clones share the socket, an op in flight keeps it open.
+#[derive(Clone, Debug)]
+pub struct UdpSocket {
+    fd: Arc<net::UdpSocket>,
+    ioprio: Option<IoPriority>
+}
+
+// the message of an op, the header points into the box it lives in.
+// A `Bytes` one is sent, a `BytesMut` one received.
+struct Msg<B> {
+    fd: Arc<net::UdpSocket>,
+    ioprio: Option<IoPriority>,
+    buf: B,
+    addr: libc::sockaddr_storage,
+    iov: libc::iovec,
+    hdr: libc::msghdr
+}
+
+// the raw pointers only point at the rest of the `Msg`
+unsafe impl<B: Send> Send for Msg<B> {}
+
+impl UdpSocket {
+    /// The fd is used as is, like a socket bound to a device with `socket2`.
+    /// A nonblocking socket stays nonblocking, its ops fail with `WouldBlock` instead of waiting.
+    pub fn from_std(fd: net::UdpSocket) -> UdpSocket {
+        UdpSocket { fd: Arc::new(fd), ioprio: None }
+    }
+
+    pub fn bind(addr: net::SocketAddr) -> io::Result<UdpSocket> {
+        net::UdpSocket::bind(addr).map(UdpSocket::from_std)
+    }
+
+    /// Issue the ops of this socket with `ioprio`, `None` uses the priority of the thread.
+    pub fn set_priority(&mut self, ioprio: Option<IoPriority>) {
+        self.ioprio = ioprio;
+    }
+
+    #[inline]
+    pub fn local_addr(&self) -> io::Result<net::SocketAddr> {
+        self.fd.local_addr()
+    }
+
+    /// Send `buf` as one datagram to `target`, returns how much was sent.
+    pub async fn send_to(&self, buf: Bytes, target: net::SocketAddr) -> io::Result<usize> {
+        op::submit(self.send_msg(buf, target)).await?
+    }
+
+    /// Receive a datagram into the spare capacity of `buf`, and the address it came from.
+    ///
+    /// What doesn't fit in the capacity is lost, like `recvfrom(2)`.
+    pub async fn recv_from(&self, buf: BytesMut) -> io::Result<(BytesMut, net::SocketAddr)> {
+        op::submit(self.recv_msg(buf)).await?
+    }
+
+    fn send_msg(&self, buf: Bytes, target: net::SocketAddr) -> Msg<Bytes> {
+        let target = SockAddr::from(target);
+        let mut msg = Msg::new(self, buf);
+
+        unsafe {
+            ptr::copy_nonoverlapping(
+                target.as_ptr() as *const u8,
+                &mut msg.addr as *mut _ as *mut u8,
+                target.len() as usize
+            );
+        }
+
+        msg.hdr.msg_namelen = target.len();
+        msg
+    }
+
+    fn recv_msg(&self, buf: BytesMut) -> Msg<BytesMut> {
+        let mut msg = Msg::new(self, buf);
+        msg.hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+        msg
+    }
+}
+
+impl<B> Msg<B> {
+    fn new(socket: &UdpSocket, buf: B) -> Msg<B> {
+        Msg {
+            fd: socket.fd.clone(),
+            ioprio: socket.ioprio,
+            buf,
+            addr: unsafe { mem::zeroed() },
+            iov: libc::iovec { iov_base: ptr::null_mut(), iov_len: 0 },
+            hdr: unsafe { mem::zeroed() }
+        }
+    }
+
+    // the message is boxed by now, point the header at it.
+    fn point(&mut self, base: *mut u8, len: usize) {
+        self.iov.iov_base = base as *mut _;
+        self.iov.iov_len = len;
+        self.hdr.msg_name = &mut self.addr as *mut _ as *mut _;
+        self.hdr.msg_iov = &mut self.iov;
+        self.hdr.msg_iovlen = 1;
+    }
+}
+
+unsafe impl Operation for Msg<Bytes> {
+    type Output = io::Result<usize>;
+
+    fn build(&mut self) -> SubmissionEntry {
+        self.point(self.buf.as_ptr() as *mut u8, self.buf.len());
+
+        let entry = opcode::SendMsg::new(types::Target::Fd(self.fd.as_raw_fd()), &self.hdr)
+            .build();
+        handle::ioprio(self.ioprio, entry)
+    }
+
+    fn complete(self, cqe: CqeResult) -> Self::Output {
+        let n = cqe.bytes()?;
+
+        if n != self.buf.len() {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "datagram was truncated"));
+        }
+
+        Ok(n)
+    }
+}
+
+unsafe impl Operation for Msg<BytesMut> {
+    type Output = io::Result<(BytesMut, net::SocketAddr)>;
+
+    fn build(&mut self) -> SubmissionEntry {
+        let bytes = self.buf.bytes_mut();
+        let (base, len) = (bytes.as_mut_ptr() as *mut u8, bytes.len());
+        self.point(base, len);
+
+        let entry = opcode::RecvMsg::new(types::Target::Fd(self.fd.as_raw_fd()), &mut self.hdr)
+            .build();
+        handle::ioprio(self.ioprio, entry)
+    }
+
+    fn complete(self, cqe: CqeResult) -> Self::Output {
+        let n = cqe.bytes()?;
+        let Msg { mut buf, addr, hdr, .. } = self;
+
+        let addr = unsafe { SockAddr::from_raw_parts(&addr as *const _ as *const _, hdr.msg_namelen) };
+        let addr = addr.as_std()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "datagram from a non-IP address"))?;
+
+        unsafe {
+            buf.advance_mut(n);
+        }
+
+        Ok((buf, addr))
+    }
+}
+
+impl From<net::UdpSocket> for UdpSocket {
+    #[inline]
+    fn from(fd: net::UdpSocket) -> UdpSocket {
+        UdpSocket::from_std(fd)
+    }
+}
+
+impl From<Socket> for UdpSocket {
+    #[inline]
+    fn from(socket: Socket) -> UdpSocket {
+        UdpSocket::from_std(socket.into_udp_socket())
+    }
+}
+
+impl AsRawFd for UdpSocket {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+/// A `Stream` of the datagrams received by a [UdpSocket] and a `Sink` of the ones to send.
+///
+/// Datagrams are received into one buffer, split off as `Bytes`.
+/// Once the frames handed out are dropped, the next receive reuses its memory.
+///
+/// ```no_run
+/// use futures_util::{ SinkExt, StreamExt };
+/// use ritsu::action::udp::{ UdpSocket, UdpFramed };
+///
+/// # async fn f() -> std::io::Result<()> {
+/// let socket = UdpSocket::bind("127.0.0.1:5353".parse().unwrap())?;
+/// let mut framed = UdpFramed::new(socket);
+///
+/// // echo
+/// while let Some(frame) = framed.next().await {
+///     framed.send(frame?).await?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct UdpFramed {
+    socket: UdpSocket,
+    capacity: usize,
+    rbuf: BytesMut,
+    recv: Option<Submission<Msg<BytesMut>>>,
+    send: Option<Submission<Msg<Bytes>>>
+}
+
+impl UdpFramed {
+    #[inline]
+    pub fn new(socket: UdpSocket) -> UdpFramed {
+        UdpFramed::with_capacity(DEFAULT_CAPACITY, socket)
+    }
+
+    /// Receive datagrams of up to `capacity` bytes, the rest of a larger one is lost.
+    pub fn with_capacity(capacity: usize, socket: UdpSocket) -> UdpFramed {
+        UdpFramed {
+            socket,
+            capacity,
+            rbuf: BytesMut::new(),
+            recv: None,
+            send: None
+        }
+    }
+
+    #[inline]
+    pub fn get_ref(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// The datagrams in flight are cancelled.
+    pub fn into_inner(self) -> UdpSocket {
+        self.socket
+    }
+}
+
+impl Stream for UdpFramed {
+    type Item = io::Result<(Bytes, net::SocketAddr)>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        let recv = match this.recv.as_mut() {
+            Some(recv) => recv,
+            None => {
+                let mut buf = mem::take(&mut this.rbuf);
+                buf.reserve(this.capacity);
+
+                let recv = op::submit(this.socket.recv_msg(buf));
+                this.recv.get_or_insert(recv)
+            }
+        };
+
+        let ret = ready!(Pin::new(recv).poll(cx));
+        this.recv = None;
+
+        let (mut buf, addr) = ret??;
+        let frame = buf.split().freeze();
+        this.rbuf = buf;
+
+        Poll::Ready(Some(Ok((frame, addr))))
+    }
+}
+
+impl Sink<(Bytes, net::SocketAddr)> for UdpFramed {
+    type Error = io::Error;
+
+    /// Only one datagram is in flight, wait for the previous one.
+    #[inline]
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, (frame, target): (Bytes, net::SocketAddr)) -> io::Result<()> {
+        let this = self.get_mut();
+
+        assert!(this.send.is_none(), "`poll_ready` must be called before `start_send`");
+        this.send = Some(op::submit(this.socket.send_msg(frame, target)));
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        if let Some(send) = this.send.as_mut() {
+            let ret = ready!(Pin::new(send).poll(cx));
+            this.send = None;
+
+            ret??;
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    #[inline]
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+
+#[test]
+fn test_udp_framed() {
+    use futures_util::{ SinkExt, StreamExt };
+    use crate::executor::block_on;
+
+    let a = UdpSocket::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let b = UdpSocket::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let a_addr = a.local_addr().unwrap();
+    let b_addr = b.local_addr().unwrap();
+
+    block_on(async {
+        assert_eq!(a.send_to(Bytes::from_static(b"ping"), b_addr).await.unwrap(), 4);
+        let (buf, from) = b.recv_from(BytesMut::with_capacity(16)).await.unwrap();
+        assert_eq!(&buf[..], b"ping");
+        assert_eq!(from, a_addr);
+
+        let mut a = UdpFramed::new(a);
+        let mut b = UdpFramed::with_capacity(16, b);
+
+        for i in 0..3u8 {
+            a.send((Bytes::from(vec![i; 8]), b_addr)).await.unwrap();
+        }
+
+        for i in 0..3u8 {
+            let (frame, from) = b.next().await.unwrap().unwrap();
+            assert_eq!(&frame[..], &[i; 8]);
+            assert_eq!(from, a_addr);
+        }
+    });
+}
+
+#[test]
+fn test_from_socket2() {
+    use std::os::unix::io::FromRawFd;
+    use socket2::{ Domain, Type, Protocol };
+    use crate::executor::block_on;
+
+    let socket = Socket::new(Domain::ipv4(), Type::dgram(), Some(Protocol::udp())).unwrap();
+    socket.set_reuse_address(true).unwrap();
+    socket.set_recv_buffer_size(64 * 1024).unwrap();
+    socket.bind(&SockAddr::from("127.0.0.1:0".parse::<net::SocketAddr>().unwrap())).unwrap();
+    let size = socket.recv_buffer_size().unwrap();
+
+    let socket = UdpSocket::from(socket);
+    let addr = socket.local_addr().unwrap();
+
+    // the options set before are kept
+    let fd = unsafe { Socket::from_raw_fd(libc::dup(socket.as_raw_fd())) };
+    assert!(fd.reuse_address().unwrap());
+    assert_eq!(fd.recv_buffer_size().unwrap(), size);
+
+    block_on(async {
+        socket.send_to(Bytes::from_static(b"ping"), addr).await.unwrap();
+        let (buf, _) = socket.recv_from(BytesMut::with_capacity(16)).await.unwrap();
+        assert_eq!(&buf[..], b"ping");
+    });
+}
@@ -0,0 +1,510 @@
+//! Zero-copy receive, with `IORING_OP_RECV_ZC` (Linux 6.15).
+//!
+//! The NIC writes the payload of the packets steered to one of its rx queues straight into
+//! an area of memory registered with that queue, and each receive reports where a chunk landed.
+//! The chunks are loaned out as [Buf]s, and handed back to the kernel through a refill ring
+//! once dropped.
+//!
+//! The kernel only accepts it on a ring with 32 byte completions, which the Proactor doesn't have,
+//! so an [Ifq] sets up a small ring of its own and the Proactor just polls an eventfd registered on it.
+//! That ring is tied to the thread that created it, like the [Ifq].
+//!
+//! The queue needs header split and flow steering set up on the NIC, e.g. with `ethtool`,
+//! and registering it needs `CAP_NET_ADMIN`.
+
+use std::{ fs, io, mem, slice };
+use std::io::Read;
+use std::rc::Rc;
+use std::pin::Pin;
+use std::ops::Deref;
+use std::ffi::CString;
+use std::marker::PhantomData;
+use std::cell::{ Cell, RefCell };
+use std::collections::{ HashMap, VecDeque };
+use std::sync::atomic::{ AtomicU32, Ordering };
+use std::task::{ Context, Poll as TaskPoll, Waker };
+use std::os::unix::io::AsRawFd;
+use futures_util::stream::Stream;
+use io_uring::opcode;
+use crate::sys;
+use super::poll::Async;
+use super::raw::{ Ring, Mmap };
+
+
+const SQ_ENTRIES: u32 = 8;
+
+// the most refill entries the kernel takes
+const MAX_RQ_ENTRIES: u32 = 32768;
+
+// the cancels of dropped receives, their completions are ignored
+const CANCEL_TOKEN: u64 = 0;
+
+/// The index of the network interface `name`.
+pub fn if_index(name: &str) -> io::Result<u32> {
+    let name = CString::new(name)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(io::Error::last_os_error()),
+        index => Ok(index)
+    }
+}
+
+pub struct Builder {
+    if_idx: u32,
+    rxq: u32,
+    area_size: usize,
+    refill_entries: Option<u32>,
+    completion_entries: u32
+}
+
+impl Builder {
+    /// Receive from the rx queue `rxq` of the interface `if_idx`, see [if_index].
+    pub fn new(if_idx: u32, rxq: u32) -> Builder {
+        Builder {
+            if_idx,
+            rxq,
+            area_size: 32 << 20,
+            refill_entries: None,
+            completion_entries: 4096
+        }
+    }
+
+    /// The size of the memory the NIC receives into, rounded up to pages, 32 MiB by default.
+    pub fn area_size(&mut self, size: usize) -> &mut Self {
+        self.area_size = size;
+        self
+    }
+
+    /// The size of the refill ring, by default one entry per page of the area.
+    ///
+    /// Dropped [Buf]s that don't fit wait until the kernel catches up.
+    pub fn refill_entries(&mut self, entries: u32) -> &mut Self {
+        self.refill_entries = Some(entries);
+        self
+    }
+
+    /// The size of the completion queue of the ring, 4096 by default.
+    pub fn completion_entries(&mut self, entries: u32) -> &mut Self {
+        self.completion_entries = entries;
+        self
+    }
+
+    pub fn build(&self) -> io::Result<Ifq> {
+        let page = page_size();
+        let area_size = round_up(self.area_size.max(1), page);
+        let refill_entries = self.refill_entries
+            .unwrap_or((area_size / page) as u32)
+            .clamp(1, MAX_RQ_ENTRIES)
+            .next_power_of_two();
+
+        let ring = Ring::new(SQ_ENTRIES, self.completion_entries, false)?;
+        let eventfd = ring.eventfd()?;
+
+        let area = Mmap::anon(area_size)?;
+        let region = Mmap::anon(round_up(page + refill_entries as usize * mem::size_of::<sys::ZcrxRqe>(), page))?;
+
+        let mut area_reg = sys::ZcrxAreaReg {
+            addr: area.addr as u64,
+            len: area.len as u64,
+            ..Default::default()
+        };
+        let mut region_desc = sys::RegionDesc {
+            user_addr: region.addr as u64,
+            size: region.len as u64,
+            flags: sys::IORING_MEM_REGION_TYPE_USER,
+            ..Default::default()
+        };
+        let mut reg = sys::ZcrxIfqReg {
+            if_idx: self.if_idx,
+            if_rxq: self.rxq,
+            rq_entries: refill_entries,
+            area_ptr: &mut area_reg as *mut sys::ZcrxAreaReg as u64,
+            region_ptr: &mut region_desc as *mut sys::RegionDesc as u64,
+            ..Default::default()
+        };
+
+        unsafe {
+            sys::io_uring_register(
+                ring.as_raw_fd(),
+                sys::IORING_REGISTER_ZCRX_IFQ,
+                &mut reg as *mut sys::ZcrxIfqReg as *const _,
+                1
+            )?;
+        }
+
+        let refill = unsafe {
+            Refill::new(region, &reg.offsets, reg.rq_entries, area_reg.rq_area_token)
+        };
+
+        Ok(Ifq {
+            inner: Rc::new(Inner {
+                ring,
+                eventfd: Async::new(eventfd),
+                id: reg.zcrx_id,
+                area,
+                refill: RefCell::new(refill),
+                streams: RefCell::new(HashMap::new()),
+                next: Cell::new(CANCEL_TOKEN + 1)
+            })
+        })
+    }
+}
+
+/// An rx queue registered for zero-copy receive, see the [module docs](self).
+///
+/// ```no_run
+/// use futures_util::stream::StreamExt;
+/// use ritsu::action::tcp::TcpStream;
+/// use ritsu::action::zcrx::{ self, Builder };
+///
+/// # async fn f(stream: TcpStream) -> std::io::Result<()> {
+/// let ifq = Builder::new(zcrx::if_index("eth0")?, 1).build()?;
+/// let mut recv = ifq.recv(&stream)?;
+///
+/// while let Some(buf) = recv.next().await {
+///     let buf = buf?;
+///     println!("{} bytes", buf.len());
+///     // the memory goes back to the NIC here
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Ifq {
+    inner: Rc<Inner>
+}
+
+struct Inner {
+    ring: Ring,
+    eventfd: Async<fs::File>,
+    id: u32,
+    area: Mmap,
+    refill: RefCell<Refill>,
+    streams: RefCell<HashMap<u64, Queue>>,
+    next: Cell<u64>
+}
+
+#[derive(Default)]
+struct Queue {
+    completions: VecDeque<Completion>,
+    waker: Option<Waker>
+}
+
+struct Completion {
+    res: i32,
+    flags: u32,
+    off: u64
+}
+
+impl Ifq {
+    /// Receive from `socket`, a TCP socket whose flow is steered to the rx queue.
+    ///
+    /// The stream ends with the connection, and dropping it cancels the receive.
+    pub fn recv<'a, S: AsRawFd>(&self, socket: &'a S) -> io::Result<RecvZc<'a>> {
+        let key = self.inner.next.get();
+        self.inner.next.set(key + 1);
+
+        let mut entry = sys::entry(sys::IORING_OP_RECV_ZC);
+        let sqe = sys::sqe_mut(&mut entry);
+        sqe.fd = socket.as_raw_fd();
+        sqe.ioprio = sys::IORING_RECV_MULTISHOT;
+        sqe.file_index = self.inner.id;
+        sqe.user_data = key;
+
+        self.inner.streams.borrow_mut().insert(key, Queue::default());
+
+        if let Err(err) = self.inner.ring.push(&entry) {
+            self.inner.streams.borrow_mut().remove(&key);
+            return Err(err);
+        }
+
+        Ok(RecvZc {
+            ifq: self.inner.clone(),
+            key,
+            done: false,
+            _socket: PhantomData
+        })
+    }
+}
+
+impl Inner {
+    fn poll_ring(&self, key: u64, cx: &mut Context<'_>) -> TaskPoll<io::Result<()>> {
+        loop {
+            self.drain()?;
+
+            if let Some(queue) = self.streams.borrow_mut().get_mut(&key) {
+                if !queue.completions.is_empty() {
+                    return TaskPoll::Ready(Ok(()));
+                }
+
+                queue.waker = Some(cx.waker().clone());
+            }
+
+            futures_util::ready!(self.eventfd.poll_readable(cx))?;
+        }
+    }
+
+    /// Run the completions the kernel deferred to us, and hand them to their streams.
+    fn drain(&self) -> io::Result<()> {
+        let mut eventfd = self.eventfd.get_ref();
+        let _ = eventfd.read(&mut [0; 8]);
+
+        let mut streams = self.streams.borrow_mut();
+        let mut refill = self.refill.borrow_mut();
+        refill.flush();
+
+        self.ring.reap(|cqe| {
+            if cqe.user_data == CANCEL_TOKEN {
+                return
+            }
+
+            match streams.get_mut(&cqe.user_data) {
+                Some(queue) => {
+                    queue.completions.push_back(Completion {
+                        res: cqe.res,
+                        flags: cqe.flags,
+                        off: cqe.big_cqe[0]
+                    });
+
+                    if let Some(waker) = queue.waker.take() {
+                        waker.wake();
+                    }
+                },
+                // the stream is gone, give the buffer back right away.
+                None => if cqe.res > 0 {
+                    refill.give(cqe.big_cqe[0], cqe.res as u32);
+                }
+            }
+        })
+    }
+}
+
+/// The chunks received from a socket, see [Ifq::recv].
+pub struct RecvZc<'a> {
+    ifq: Rc<Inner>,
+    key: u64,
+    done: bool,
+    _socket: PhantomData<&'a ()>
+}
+
+impl Stream for RecvZc<'_> {
+    type Item = io::Result<Buf>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> TaskPoll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            let completion = this.ifq.streams.borrow_mut()
+                .get_mut(&this.key)
+                .and_then(|queue| queue.completions.pop_front());
+
+            if let Some(completion) = completion {
+                if completion.flags & sys::IORING_CQE_F_MORE == 0 {
+                    this.done = true;
+                }
+
+                if completion.res < 0 {
+                    return TaskPoll::Ready(Some(Err(io::Error::from_raw_os_error(-completion.res))));
+                }
+
+                if completion.res > 0 {
+                    return TaskPoll::Ready(Some(Ok(Buf {
+                        ifq: this.ifq.clone(),
+                        off: completion.off,
+                        len: completion.res as u32
+                    })));
+                }
+
+                continue
+            }
+
+            if this.done {
+                return TaskPoll::Ready(None);
+            }
+
+            if let Err(err) = futures_util::ready!(this.ifq.poll_ring(this.key, cx)) {
+                return TaskPoll::Ready(Some(Err(err)));
+            }
+        }
+    }
+}
+
+impl Drop for RecvZc<'_> {
+    fn drop(&mut self) {
+        let mut streams = self.ifq.streams.borrow_mut();
+
+        if let Some(queue) = streams.remove(&self.key) {
+            let mut refill = self.ifq.refill.borrow_mut();
+
+            for completion in queue.completions {
+                if completion.res > 0 {
+                    refill.give(completion.off, completion.res as u32);
+                }
+            }
+        }
+
+        if !self.done {
+            let entry = opcode::AsyncCancel::new(self.key)
+                .build()
+                .user_data(CANCEL_TOKEN);
+
+            // the ring is torn down with the `Ifq` anyway.
+            let _ = self.ifq.ring.push(&entry);
+        }
+
+        // this stream may have been the one watching the eventfd for the others.
+        for queue in streams.values_mut() {
+            if let Some(waker) = queue.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// A chunk received into the area of an [Ifq], it goes back to the kernel on drop.
+///
+/// Hold on to it for too long and the NIC runs out of memory to receive into.
+pub struct Buf {
+    ifq: Rc<Inner>,
+    off: u64,
+    len: u32
+}
+
+impl Deref for Buf {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        let off = (self.off & !sys::IORING_ZCRX_AREA_MASK) as usize;
+
+        unsafe {
+            slice::from_raw_parts((self.ifq.area.addr as *const u8).add(off), self.len as usize)
+        }
+    }
+}
+
+impl AsRef<[u8]> for Buf {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Drop for Buf {
+    fn drop(&mut self) {
+        self.ifq.refill.borrow_mut().give(self.off, self.len);
+    }
+}
+
+/// The ring the buffers go back to the kernel through.
+struct Refill {
+    head: *const AtomicU32,
+    tail: *const AtomicU32,
+    rqes: *mut sys::ZcrxRqe,
+    mask: u32,
+    token: u64,
+    backlog: VecDeque<(u64, u32)>,
+    _region: Mmap
+}
+
+impl Refill {
+    /// # Safety
+    ///
+    /// `offsets` and `entries` must describe the ring in `region`.
+    unsafe fn new(region: Mmap, offsets: &sys::ZcrxOffsets, entries: u32, token: u64) -> Refill {
+        Refill {
+            head: region.at(offsets.head),
+            tail: region.at(offsets.tail),
+            rqes: region.at(offsets.rqes),
+            mask: entries - 1,
+            token,
+            backlog: VecDeque::new(),
+            _region: region
+        }
+    }
+
+    /// Give the buffer at `off` back, or keep it until the ring has room.
+    fn give(&mut self, off: u64, len: u32) {
+        if !self.backlog.is_empty() || !self.push(off, len) {
+            self.backlog.push_back((off, len));
+        }
+    }
+
+    fn flush(&mut self) {
+        while let Some(&(off, len)) = self.backlog.front() {
+            if !self.push(off, len) {
+                break
+            }
+
+            self.backlog.pop_front();
+        }
+    }
+
+    fn push(&mut self, off: u64, len: u32) -> bool {
+        unsafe {
+            let tail = (*self.tail).load(Ordering::Relaxed);
+            let head = (*self.head).load(Ordering::Acquire);
+
+            if tail.wrapping_sub(head) > self.mask {
+                return false
+            }
+
+            self.rqes.add((tail & self.mask) as usize).write(sys::ZcrxRqe {
+                off: (off & !sys::IORING_ZCRX_AREA_MASK) | self.token,
+                len,
+                pad: 0
+            });
+            (*self.tail).store(tail.wrapping_add(1), Ordering::Release);
+        }
+
+        true
+    }
+}
+
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+#[inline]
+fn round_up(n: usize, align: usize) -> usize {
+    n.div_ceil(align) * align
+}
+
+
+#[test]
+fn test_zcrx_unsupported() {
+    assert!(if_index("ritsu-missing0").is_err());
+
+    // loopback has no rx queues to register, whether or not the kernel knows zcrx.
+    let lo = if_index("lo").unwrap();
+    assert!(Builder::new(lo, 0).area_size(1 << 20).build().is_err());
+}
+
+#[test]
+fn test_refill() {
+    let region = Mmap::anon(page_size()).unwrap();
+    let (head, tail) = unsafe { (&*region.at::<AtomicU32>(0), &*region.at::<AtomicU32>(4)) };
+    let rqes = unsafe { region.at::<sys::ZcrxRqe>(64) };
+    let offsets = sys::ZcrxOffsets { head: 0, tail: 4, rqes: 64, ..Default::default() };
+    let token = 1 << sys::IORING_ZCRX_AREA_SHIFT;
+    let mut refill = unsafe { Refill::new(region, &offsets, 2, token) };
+
+    // the area bits of the completion are replaced by the token of the area
+    refill.give(0xff << sys::IORING_ZCRX_AREA_SHIFT | 4096, 100);
+    assert_eq!(tail.load(Ordering::Acquire), 1);
+    let rqe = unsafe { rqes.read() };
+    assert_eq!(rqe.off, token | 4096);
+    assert_eq!(rqe.len, 100);
+
+    // a full ring keeps the rest until the kernel consumed some
+    refill.give(8192, 1);
+    refill.give(12288, 1);
+    assert_eq!(tail.load(Ordering::Acquire), 2);
+    assert_eq!(refill.backlog.len(), 1);
+
+    head.store(1, Ordering::Release);
+    refill.flush();
+    assert_eq!(tail.load(Ordering::Acquire), 3);
+    assert!(refill.backlog.is_empty());
+    assert_eq!(unsafe { rqes.read() }.off, token | 12288);
+}
@@ -0,0 +1,362 @@
+//! A small thread pool for work the ring can't do.
+//!
+//! Submissions whose opcode is missing on the running kernel are emulated
+//! with the equivalent blocking syscall. The results are queued for the ring
+//! that submitted them, which reaps them like completions from its CQ,
+//! so the ops count as in flight until then.
+//!
+//! Socket ops aren't emulated here, they wait for readiness on the ring instead.
+
+use std::{ io, mem, ptr, thread };
+use std::time::Duration;
+use std::task::Waker;
+use std::collections::{ HashMap, VecDeque };
+use std::sync::{ Arc, Mutex, Condvar };
+use io_uring::opcode;
+use io_uring::squeue::Flags;
+use crate::{ sys, SubmissionEntry, CompletionEntry };
+
+
+const MAX_THREADS: usize = 8;
+const KEEPALIVE: Duration = Duration::from_secs(10);
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+struct Pool {
+    state: Mutex<State>,
+    cond: Condvar
+}
+
+struct State {
+    jobs: VecDeque<Job>,
+    threads: usize,
+    idle: usize
+}
+
+static POOL: Pool = Pool {
+    state: Mutex::new(State {
+        jobs: VecDeque::new(),
+        threads: 0,
+        idle: 0
+    }),
+    cond: Condvar::new()
+};
+
+fn execute(job: Job) {
+    let mut state = POOL.state.lock().unwrap();
+
+    state.jobs.push_back(job);
+
+    if state.idle > 0 {
+        POOL.cond.notify_one();
+    } else if state.threads < MAX_THREADS {
+        state.threads += 1;
+
+        let ret = thread::Builder::new()
+            .name("ritsu-blocking".into())
+            .spawn(worker);
+
+        if ret.is_err() {
+            state.threads -= 1;
+        }
+    }
+}
+
+fn worker() {
+    let mut state = POOL.state.lock().unwrap();
+
+    loop {
+        if let Some(job) = state.jobs.pop_front() {
+            drop(state);
+            job();
+            state = POOL.state.lock().unwrap();
+            continue
+        }
+
+        state.idle += 1;
+        let (next, timeout) = POOL.cond.wait_timeout(state, KEEPALIVE).unwrap();
+        state = next;
+        state.idle -= 1;
+
+        if timeout.timed_out() && state.jobs.is_empty() {
+            state.threads -= 1;
+            break
+        }
+    }
+}
+
+/// The ops a ring sent to the pool, and their results until the ring reaps them.
+pub(crate) struct Completions {
+    state: Mutex<Pending>,
+    cond: Condvar,
+    // wakes the ring when a result is queued
+    waker: Waker
+}
+
+#[derive(Default)]
+struct Pending {
+    // the ops not done yet, and whether they were cancelled
+    running: HashMap<u64, bool>,
+    done: Vec<CompletionEntry>
+}
+
+impl Completions {
+    pub(crate) fn new(waker: Waker) -> Completions {
+        Completions {
+            state: Mutex::new(Pending::default()),
+            cond: Condvar::new(),
+            waker
+        }
+    }
+
+    /// Cancel the op `target`, or every op.
+    ///
+    /// A running syscall can't be interrupted,
+    /// only the ops that haven't started complete with `-ECANCELED` right away.
+    pub(crate) fn cancel(&self, target: Option<u64>) {
+        let mut state = self.state.lock().unwrap();
+
+        match target {
+            Some(user_data) => if let Some(cancelled) = state.running.get_mut(&user_data) {
+                *cancelled = true;
+            },
+            None => state.running.values_mut().for_each(|cancelled| *cancelled = true)
+        }
+    }
+
+    /// How many ops the ring hasn't reaped yet.
+    pub(crate) fn len(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.running.len() + state.done.len()
+    }
+
+    /// Take the results that are ready.
+    pub(crate) fn take(&self) -> Vec<CompletionEntry> {
+        mem::take(&mut self.state.lock().unwrap().done)
+    }
+
+    /// Block until a result is ready, or nothing runs.
+    pub(crate) fn wait(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        while state.done.is_empty() && !state.running.is_empty() {
+            state = self.cond.wait(state).unwrap();
+        }
+    }
+
+    fn is_cancelled(&self, user_data: u64) -> bool {
+        self.state.lock().unwrap().running.get(&user_data).copied().unwrap_or(false)
+    }
+
+    fn complete(&self, user_data: u64, res: i32) {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.running.remove(&user_data);
+            state.done.push(sys::completion(user_data, res, 0));
+        }
+
+        self.cond.notify_all();
+        self.waker.wake_by_ref();
+    }
+}
+
+/// Whether `entry` can be emulated by [submit].
+pub(crate) fn is_emulated(entry: &SubmissionEntry) -> bool {
+    let sqe = sys::sqe(entry);
+
+    // the pool can't honor ordering with ring submissions
+    let unsupported = Flags::FIXED_FILE | Flags::IO_DRAIN | Flags::IO_LINK | Flags::IO_HARDLINK;
+
+    if sqe.flags & unsupported.bits() != 0 {
+        return false;
+    }
+
+    matches!(sqe.opcode,
+        opcode::Read::CODE
+            | opcode::Write::CODE
+            | opcode::Readv::CODE
+            | opcode::Writev::CODE
+            | opcode::Fsync::CODE
+            | opcode::SyncFileRange::CODE
+            | opcode::Fallocate::CODE
+            | opcode::Fadvise::CODE
+            | opcode::Madvise::CODE
+            | opcode::Openat::CODE
+            | opcode::Openat2::CODE
+            | opcode::Close::CODE
+            | opcode::Statx::CODE
+            | opcode::Splice::CODE
+            | sys::IORING_OP_TEE
+            | opcode::Nop::CODE
+            | sys::IORING_OP_FTRUNCATE
+            | sys::RITSU_OP_GETDENTS
+            | sys::RITSU_OP_GETADDRINFO
+            | sys::RITSU_OP_FLOCK
+    )
+}
+
+/// Emulate `entry` on the blocking pool, its result is queued into `completions`.
+///
+/// # Safety
+///
+/// Same as `RawHandle::raw_push`.
+pub(crate) unsafe fn submit(entry: SubmissionEntry, completions: &Arc<Completions>) {
+    let user_data = sys::sqe(&entry).user_data;
+    let completions = completions.clone();
+
+    completions.state.lock().unwrap().running.insert(user_data, false);
+
+    execute(Box::new(move || {
+        let res = if completions.is_cancelled(user_data) {
+            -libc::ECANCELED
+        } else {
+            emulate(sys::sqe(&entry))
+        };
+
+        completions.complete(user_data, res);
+    }))
+}
+
+unsafe fn emulate(sqe: &sys::Sqe) -> i32 {
+    let fd = sqe.fd;
+    let ret = match sqe.opcode {
+        opcode::Nop::CODE => 0,
+        opcode::Read::CODE => {
+            let iov = libc::iovec { iov_base: sqe.addr as *mut _, iov_len: sqe.len as _ };
+            libc::preadv2(fd, &iov, 1, sqe.off as _, sqe.op_flags as _) as _
+        },
+        opcode::Write::CODE => {
+            let iov = libc::iovec { iov_base: sqe.addr as *mut _, iov_len: sqe.len as _ };
+            libc::pwritev2(fd, &iov, 1, sqe.off as _, sqe.op_flags as _) as _
+        },
+        opcode::Readv::CODE =>
+            libc::preadv2(fd, sqe.addr as *const _, sqe.len as _, sqe.off as _, sqe.op_flags as _) as _,
+        opcode::Writev::CODE =>
+            libc::pwritev2(fd, sqe.addr as *const _, sqe.len as _, sqe.off as _, sqe.op_flags as _) as _,
+        opcode::Fsync::CODE => if sqe.op_flags & opcode::types::FsyncFlags::DATASYNC.bits() != 0 {
+            libc::fdatasync(fd)
+        } else {
+            libc::fsync(fd)
+        },
+        opcode::SyncFileRange::CODE =>
+            libc::sync_file_range(fd, sqe.off as _, sqe.len as _, sqe.op_flags),
+        opcode::Fallocate::CODE =>
+            libc::fallocate(fd, sqe.len as _, sqe.off as _, sqe.addr as _),
+        opcode::Fadvise::CODE => {
+            // `posix_fadvise` returns the error number directly.
+            return -libc::posix_fadvise(fd, sqe.off as _, sqe.len as _, sqe.op_flags as _);
+        },
+        opcode::Madvise::CODE =>
+            libc::madvise(sqe.addr as *mut _, sqe.len as _, sqe.op_flags as _),
+        opcode::Openat::CODE =>
+            libc::openat(fd, sqe.addr as *const _, sqe.op_flags as _, sqe.len),
+        opcode::Openat2::CODE =>
+            libc::syscall(libc::SYS_openat2, fd, sqe.addr, sqe.off, sqe.len as usize) as _,
+        opcode::Close::CODE => libc::close(fd),
+        sys::IORING_OP_FTRUNCATE => libc::ftruncate(fd, sqe.off as _),
+        sys::RITSU_OP_GETDENTS =>
+            libc::syscall(libc::SYS_getdents64, fd, sqe.addr, sqe.len as usize) as _,
+        sys::RITSU_OP_GETADDRINFO => {
+            crate::action::net::resolve(sqe.addr as *mut _);
+            0
+        },
+        sys::RITSU_OP_FLOCK => {
+            // it closes `fd`, and reports the error itself.
+            return crate::action::fs::lock::flock(fd, sqe.op_flags as _, sqe.addr as *const _);
+        },
+        opcode::Statx::CODE =>
+            libc::statx(fd, sqe.addr as *const _, sqe.op_flags as _, sqe.len, sqe.off as *mut _),
+        opcode::Splice::CODE => {
+            let mut off_in = sqe.addr as libc::loff_t;
+            let mut off_out = sqe.off as libc::loff_t;
+            let off_in_ptr = if off_in == -1 { ptr::null_mut() } else { &mut off_in as *mut _ };
+            let off_out_ptr = if off_out == -1 { ptr::null_mut() } else { &mut off_out as *mut _ };
+
+            libc::splice(
+                sqe.file_index as _, off_in_ptr,
+                fd, off_out_ptr,
+                sqe.len as _, sqe.op_flags
+            ) as _
+        },
+        sys::IORING_OP_TEE =>
+            libc::tee(sqe.file_index as _, fd, sqe.len as _, sqe.op_flags) as _,
+        _ => return -libc::EINVAL
+    };
+
+    if ret >= 0 {
+        ret
+    } else {
+        -io::Error::last_os_error().raw_os_error().unwrap_or(libc::EIO)
+    }
+}
+
+
+#[test]
+fn test_emulated_write() {
+    use io_uring::opcode::types;
+    use crate::executor::Runtime;
+    use crate::sync::Ticket;
+
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+
+    let mut runtime = Runtime::new().unwrap();
+    let handle = runtime.raw_handle();
+
+    let buf = b"hello";
+    let (ticket, fut) = Ticket::new();
+    let entry = opcode::Write::new(types::Target::Fd(fds[1]), buf.as_ptr(), buf.len() as _)
+        .offset(-1)
+        .build();
+    let entry = ticket.register(entry);
+    assert!(is_emulated(&entry));
+
+    unsafe {
+        submit(entry, &handle.ring.blocking);
+    }
+
+    let cqe = runtime.run_until(fut);
+    assert_eq!(cqe.result(), buf.len() as i32);
+
+    let mut output = [0; 5];
+    let n = unsafe { libc::read(fds[0], output.as_mut_ptr() as *mut _, output.len()) };
+    assert_eq!(n, 5);
+    assert_eq!(&output, buf);
+
+    unsafe {
+        libc::close(fds[0]);
+        libc::close(fds[1]);
+    }
+}
+
+#[test]
+fn test_emulated_ftruncate() {
+    use std::fs;
+    use std::os::unix::io::AsRawFd;
+    use crate::executor::Runtime;
+    use crate::sync::Ticket;
+    use crate::action::fs::temp_path;
+
+    let path = temp_path("ftruncate");
+    let file = fs::File::create(&path).unwrap();
+
+    let mut runtime = Runtime::new().unwrap();
+    let handle = runtime.raw_handle();
+
+    let (ticket, fut) = Ticket::new();
+    let mut entry = sys::entry(sys::IORING_OP_FTRUNCATE);
+    sys::sqe_mut(&mut entry).fd = file.as_raw_fd();
+    sys::sqe_mut(&mut entry).off = 100;
+    let entry = ticket.register(entry);
+    assert!(is_emulated(&entry));
+
+    unsafe {
+        submit(entry, &handle.ring.blocking);
+    }
+
+    let cqe = runtime.run_until(fut);
+    assert_eq!(cqe.result(), 0);
+    assert_eq!(file.metadata().unwrap().len(), 100);
+
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,189 @@
+//! Cancel every op of a group of futures at once.
+//!
+//! Ops submitted while polling a [with_cancellation] future are tracked in its group.
+//! When the token is cancelled, the future is woken and cancels them
+//! with `IORING_OP_ASYNC_CANCEL`, so they complete with `ECANCELED`,
+//! and later submissions fail with `ECANCELED` right away.
+
+use std::io;
+use std::pin::Pin;
+use std::future::Future;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::task::{ Context, Poll, Waker };
+use std::sync::{ Arc, Weak, Mutex };
+use std::sync::atomic::{ AtomicBool, Ordering };
+use pin_project_lite::pin_project;
+use crate::handle;
+
+
+thread_local!{
+    static CURRENT: RefCell<Option<Arc<Group>>> = const { RefCell::new(None) };
+}
+
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<TokenInner>
+}
+
+#[derive(Default)]
+struct TokenInner {
+    cancelled: AtomicBool,
+    groups: Mutex<Vec<Weak<Group>>>
+}
+
+pub(crate) struct Group {
+    token: Arc<TokenInner>,
+    ops: Mutex<HashSet<u64>>,
+    waker: Mutex<Option<Waker>>
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Cancel the ops of every future linked to this token.
+    pub fn cancel(&self) {
+        if self.inner.cancelled.swap(true, Ordering::AcqRel) {
+            return
+        }
+
+        let groups = std::mem::take(&mut *self.inner.groups.lock().unwrap());
+
+        for group in groups.iter().filter_map(Weak::upgrade) {
+            if let Some(waker) = group.waker.lock().unwrap().take() {
+                waker.wake();
+            }
+        }
+    }
+
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Acquire)
+    }
+}
+
+impl Group {
+    #[inline]
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.token.cancelled.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn insert(&self, user_data: u64) {
+        self.ops.lock().unwrap().insert(user_data);
+    }
+
+    pub(crate) fn remove(&self, user_data: u64) {
+        self.ops.lock().unwrap().remove(&user_data);
+    }
+}
+
+/// The group of the [with_cancellation] future being polled on this thread.
+pub(crate) fn current() -> Option<Arc<Group>> {
+    CURRENT.with(|c| c.borrow().clone())
+}
+
+pub(crate) fn cancelled() -> io::Error {
+    io::Error::from_raw_os_error(libc::ECANCELED)
+}
+
+pin_project!{
+    pub struct Cancellable<F> {
+        group: Arc<Group>,
+        swept: bool,
+        #[pin]
+        fut: F
+    }
+}
+
+/// Link the ops submitted by `fut` to `token`.
+///
+/// The future keeps running after cancellation, so it can observe the `ECANCELED`
+/// errors and tear down cleanly. Only the innermost `with_cancellation` applies.
+pub fn with_cancellation<F: Future>(token: &CancellationToken, fut: F) -> Cancellable<F> {
+    let group = Arc::new(Group {
+        token: token.inner.clone(),
+        ops: Mutex::new(HashSet::new()),
+        waker: Mutex::new(None)
+    });
+
+    {
+        let mut groups = token.inner.groups.lock().unwrap();
+        groups.retain(|group| group.strong_count() != 0);
+        groups.push(Arc::downgrade(&group));
+    }
+
+    Cancellable { group, swept: false, fut }
+}
+
+impl<F: Future> Future for Cancellable<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        {
+            let mut waker = this.group.waker.lock().unwrap();
+            if !waker.as_ref().is_some_and(|w| w.will_wake(cx.waker())) {
+                *waker = Some(cx.waker().clone());
+            }
+        }
+
+        if this.group.is_cancelled() && !*this.swept {
+            *this.swept = true;
+
+            let ops = this.group.ops.lock().unwrap().clone();
+            for user_data in ops {
+                handle::cancel(user_data);
+            }
+        }
+
+        let prev = CURRENT.with(|c| c.replace(Some(this.group.clone())));
+        let ret = this.fut.poll(cx);
+        CURRENT.with(|c| c.replace(prev));
+
+        ret
+    }
+}
+
+
+#[test]
+fn test_cancellation_token() {
+    use io_uring::opcode::{ self, types };
+    use crate::executor::Runtime;
+
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+
+    let mut runtime = Runtime::new().unwrap();
+    let token = CancellationToken::new();
+    let token2 = token.clone();
+
+    let t = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        token2.cancel();
+    });
+
+    let mut buf = [0; 8];
+    let fd = fds[0];
+    let bufptr = buf.as_mut_ptr();
+    let (first, second) = runtime.run_until(with_cancellation(&token, async move {
+        let entry = opcode::Read::new(types::Target::Fd(fd), bufptr, 8).build();
+        let first = unsafe { handle::push(entry).unwrap().await.result() };
+
+        let entry = opcode::Nop::new().build();
+        let second = unsafe { handle::push(entry).map(drop) };
+
+        (first, second)
+    }));
+
+    t.join().unwrap();
+    assert_eq!(first, -libc::ECANCELED);
+    assert_eq!(second.unwrap_err().raw_os_error(), Some(libc::ECANCELED));
+
+    unsafe {
+        libc::close(fds[0]);
+        libc::close(fds[1]);
+    }
+}
@@ -0,0 +1,115 @@
+use std::{ io, fmt };
+use crate::{ sys, CompletionEntry };
+
+
+/// The completion of an op, as a [TicketFuture](crate::TicketFuture) returns it.
+///
+/// A negative result is `-errno`, the methods returning `io::Result` turn it into an error.
+#[derive(Clone)]
+pub struct CqeResult(CompletionEntry);
+
+impl CqeResult {
+    #[inline]
+    pub fn new(entry: CompletionEntry) -> CqeResult {
+        CqeResult(entry)
+    }
+
+    /// The raw result, `-errno` on failure.
+    #[inline]
+    pub fn result(&self) -> i32 {
+        sys::cqe(&self.0).res
+    }
+
+    /// The result, or the error it encodes.
+    #[inline]
+    pub fn ok(&self) -> io::Result<u32> {
+        let res = self.result();
+
+        if res >= 0 {
+            Ok(res as u32)
+        } else {
+            Err(io::Error::from_raw_os_error(-res))
+        }
+    }
+
+    /// The number of bytes a read or write transferred.
+    #[inline]
+    pub fn bytes(&self) -> io::Result<usize> {
+        self.ok().map(|n| n as usize)
+    }
+
+    #[inline]
+    pub fn user_data(&self) -> u64 {
+        sys::cqe(&self.0).user_data
+    }
+
+    #[inline]
+    pub fn flags(&self) -> u32 {
+        sys::cqe(&self.0).flags
+    }
+
+    /// The provided buffer the kernel picked, with `IOSQE_BUFFER_SELECT`.
+    #[inline]
+    pub fn buffer_id(&self) -> Option<u16> {
+        if self.flags() & sys::IORING_CQE_F_BUFFER != 0 {
+            Some((self.flags() >> sys::IORING_CQE_BUFFER_SHIFT) as u16)
+        } else {
+            None
+        }
+    }
+
+    /// Whether more completions follow for a multishot op.
+    #[inline]
+    pub fn has_more(&self) -> bool {
+        self.flags() & sys::IORING_CQE_F_MORE != 0
+    }
+
+    /// Whether a socket has more data to read after a receive.
+    #[inline]
+    pub fn is_socket_nonempty(&self) -> bool {
+        self.flags() & sys::IORING_CQE_F_SOCK_NONEMPTY != 0
+    }
+
+    /// Whether this is the notification of a zero-copy send,
+    /// telling that its buffer can be reused, rather than its result.
+    #[inline]
+    pub fn is_notification(&self) -> bool {
+        self.flags() & sys::IORING_CQE_F_NOTIF != 0
+    }
+
+    #[inline]
+    pub fn into_entry(self) -> CompletionEntry {
+        self.0
+    }
+}
+
+impl fmt::Debug for CqeResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CqeResult")
+            .field("user_data", &self.user_data())
+            .field("result", &self.result())
+            .field("flags", &self.flags())
+            .finish()
+    }
+}
+
+impl From<CompletionEntry> for CqeResult {
+    #[inline]
+    fn from(entry: CompletionEntry) -> CqeResult {
+        CqeResult(entry)
+    }
+}
+
+
+#[test]
+fn test_cqe_result() {
+    let cqe = CqeResult::new(sys::completion(1, 42, sys::IORING_CQE_F_BUFFER | (7 << 16)));
+    assert_eq!(cqe.bytes().unwrap(), 42);
+    assert_eq!(cqe.buffer_id(), Some(7));
+    assert!(!cqe.has_more());
+
+    let cqe = CqeResult::new(sys::completion(1, -libc::EAGAIN, sys::IORING_CQE_F_MORE));
+    assert_eq!(cqe.ok().unwrap_err().raw_os_error(), Some(libc::EAGAIN));
+    assert_eq!(cqe.buffer_id(), None);
+    assert!(cqe.has_more());
+}
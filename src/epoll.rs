@@ -0,0 +1,608 @@
+//! A readiness backend for systems where io_uring is missing or forbidden.
+//!
+//! It stands in for the kernel side of the ring: ops on sockets, pipes and other
+//! pollable fds wait in an epoll set and are retried with the plain syscall once
+//! their fd is ready, a connect is started nonblocking and waits to be writable, timeouts are kept in userspace, and everything else is
+//! emulated on the blocking pool like an opcode the kernel lacks.
+//! Completions are queued like the ones reaped from a CQ.
+//!
+//! It behaves like a kernel before 5.13: multishot polls and timeouts fail with `EINVAL`,
+//! so their users fall back to single shots, and linked chains are unsupported.
+
+use std::{ io, mem };
+use std::fs::File;
+use std::cell::RefCell;
+use std::collections::{ BTreeSet, HashMap, VecDeque };
+use std::time::{ Duration, Instant };
+use std::os::unix::io::{ AsRawFd, FromRawFd, RawFd };
+use io_uring::squeue;
+use io_uring::opcode::{ self, types };
+use crate::{ sys, SubmissionEntry, CompletionEntry, WAKE_TOKEN };
+
+
+const TIMEOUT_ABS: u32 = 1 << 0;
+
+const EVENTS: usize = 64;
+
+pub(crate) struct Epoll {
+    fd: File,
+    // the fd that wakes the Proactor, it's never unregistered.
+    eventfd: RawFd,
+    ops: RefCell<HashMap<u64, Op>>,
+    // the ops waiting for each registered fd
+    waiting: RefCell<HashMap<RawFd, Vec<u64>>>,
+    timeouts: RefCell<BTreeSet<(Instant, u64)>>
+}
+
+struct Op {
+    entry: SubmissionEntry,
+    kind: Kind
+}
+
+enum Kind {
+    Ready { fd: RawFd, events: u32 },
+    Timeout(Instant)
+}
+
+impl Epoll {
+    pub(crate) fn new(eventfd: RawFd) -> io::Result<Epoll> {
+        let fd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let epoll = Epoll {
+            fd: unsafe { File::from_raw_fd(fd) },
+            eventfd,
+            ops: RefCell::new(HashMap::new()),
+            waiting: RefCell::new(HashMap::new()),
+            timeouts: RefCell::new(BTreeSet::new())
+        };
+        epoll.ctl(libc::EPOLL_CTL_ADD, eventfd, libc::EPOLLIN as u32)?;
+
+        Ok(epoll)
+    }
+
+    /// Start `entry`, its completion is queued into `completed`.
+    ///
+    /// Returns it back if it's not for this backend, e.g. a read of a regular file,
+    /// which goes to the blocking pool instead.
+    pub(crate) fn submit(&self, entry: SubmissionEntry, completed: &mut VecDeque<CompletionEntry>)
+        -> Result<(), SubmissionEntry>
+    {
+        let sqe = sys::sqe(&entry);
+        let user_data = sqe.user_data;
+
+        if sqe.flags & squeue::Flags::FIXED_FILE.bits() != 0 {
+            return Err(entry);
+        }
+
+        match sqe.opcode {
+            opcode::Nop::CODE => complete(completed, user_data, 0),
+            opcode::AsyncCancel::CODE | opcode::PollRemove::CODE | opcode::TimeoutRemove::CODE => {
+                let all = sqe.opcode == opcode::AsyncCancel::CODE
+                    && sqe.op_flags & sys::IORING_ASYNC_CANCEL_ANY != 0;
+                let n = self.cancel(if all { None } else { Some(sqe.addr) }, completed);
+                complete(completed, user_data, if n != 0 { 0 } else { -libc::ENOENT });
+            },
+            opcode::Timeout::CODE => {
+                if sqe.op_flags & sys::IORING_TIMEOUT_MULTISHOT != 0 {
+                    complete(completed, user_data, -libc::EINVAL);
+                    return Ok(());
+                }
+
+                let ts = unsafe { &*(sqe.addr as *const types::Timespec) };
+                let deadline = deadline(ts, sqe.op_flags & TIMEOUT_ABS != 0);
+
+                self.timeouts.borrow_mut().insert((deadline, user_data));
+                self.ops.borrow_mut().insert(user_data, Op { entry, kind: Kind::Timeout(deadline) });
+            },
+            opcode::PollAdd::CODE if sqe.len & sys::IORING_POLL_ADD_MULTI != 0 => {
+                complete(completed, user_data, -libc::EINVAL)
+            },
+            opcode::PollAdd::CODE
+                | opcode::Read::CODE
+                | opcode::Readv::CODE
+                | opcode::Write::CODE
+                | opcode::Writev::CODE
+                | opcode::Recv::CODE
+                | opcode::RecvMsg::CODE
+                | opcode::Send::CODE
+                | opcode::SendMsg::CODE
+                | opcode::Accept::CODE =>
+            {
+                let events = interest(sqe);
+
+                if is_blocking(sqe) {
+                    return Err(entry);
+                }
+
+                if let Some(res) = attempt(sqe, events) {
+                    complete(completed, user_data, res);
+                    return Ok(());
+                }
+
+                self.watch(entry, completed);
+            },
+            opcode::Connect::CODE => {
+                if let Some(res) = connect(sqe) {
+                    complete(completed, user_data, res);
+                    return Ok(());
+                }
+
+                self.watch(entry, completed);
+            },
+            _ => return Err(entry)
+        }
+
+        Ok(())
+    }
+
+    /// Register `entry` for the readiness of its fd.
+    fn watch(&self, entry: SubmissionEntry, completed: &mut VecDeque<CompletionEntry>) {
+        let sqe = sys::sqe(&entry);
+        let (fd, events, user_data) = (sqe.fd, interest(sqe), sqe.user_data);
+
+        self.ops.borrow_mut().insert(user_data, Op { entry, kind: Kind::Ready { fd, events } });
+        self.waiting.borrow_mut().entry(fd).or_default().push(user_data);
+
+        if let Err(err) = self.update(fd) {
+            self.ops.borrow_mut().remove(&user_data);
+            self.unwait(fd, user_data);
+            complete(completed, user_data, -err.raw_os_error().unwrap_or(libc::EINVAL));
+        }
+    }
+
+    /// Wait for readiness up to `timeout`, `None` is forever,
+    /// and queue the completions of the ops that made progress.
+    ///
+    /// Returns whether the eventfd is readable.
+    pub(crate) fn wait(&self, timeout: Option<Duration>, completed: &mut VecDeque<CompletionEntry>)
+        -> io::Result<bool>
+    {
+        let now = Instant::now();
+        let next = self.timeouts.borrow()
+            .iter()
+            .next()
+            .map(|&(deadline, _)| deadline.saturating_duration_since(now));
+        let timeout = match (timeout, next) {
+            (Some(timeout), Some(next)) => Some(timeout.min(next)),
+            (timeout, next) => timeout.or(next)
+        };
+
+        // round up, so a timeout isn't polled again right before it expires.
+        let ms = match timeout {
+            Some(timeout) => timeout.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128) as i32,
+            None => -1
+        };
+
+        let mut events: [libc::epoll_event; EVENTS] = unsafe { mem::zeroed() };
+        let n = unsafe { libc::epoll_wait(self.fd.as_raw_fd(), events.as_mut_ptr(), EVENTS as _, ms) };
+
+        let n = if n >= 0 {
+            n as usize
+        } else {
+            match io::Error::last_os_error() {
+                err if err.raw_os_error() == Some(libc::EINTR) => 0,
+                err => return Err(err)
+            }
+        };
+
+        let mut eventfd = false;
+
+        for event in &events[..n] {
+            let fd = event.u64 as RawFd;
+
+            if fd == self.eventfd {
+                eventfd = true;
+            } else {
+                self.ready(fd, completed);
+            }
+        }
+
+        self.expire(Instant::now(), completed);
+
+        Ok(eventfd)
+    }
+
+    /// Retry the ops waiting for `fd`.
+    fn ready(&self, fd: RawFd, completed: &mut VecDeque<CompletionEntry>) {
+        let waiting = self.waiting.borrow().get(&fd).cloned().unwrap_or_default();
+
+        for user_data in waiting {
+            let res = match self.ops.borrow().get(&user_data) {
+                Some(Op { entry, kind: Kind::Ready { events, .. } }) => attempt(sys::sqe(entry), *events),
+                _ => None
+            };
+
+            if let Some(res) = res {
+                self.ops.borrow_mut().remove(&user_data);
+                self.unwait(fd, user_data);
+                completed.push_back(sys::completion(user_data, res, 0));
+            }
+        }
+
+        // the fd may have been closed, then it left the set on its own.
+        let _ = self.update(fd);
+    }
+
+    fn expire(&self, now: Instant, completed: &mut VecDeque<CompletionEntry>) {
+        loop {
+            let (deadline, user_data) = match self.timeouts.borrow().iter().next() {
+                Some(&next) => next,
+                None => break
+            };
+
+            if deadline > now {
+                break
+            }
+
+            self.timeouts.borrow_mut().remove(&(deadline, user_data));
+            self.ops.borrow_mut().remove(&user_data);
+            completed.push_back(sys::completion(user_data, -libc::ETIME, 0));
+        }
+    }
+
+    /// Cancel the op `target`, or every op, returns how many.
+    pub(crate) fn cancel(&self, target: Option<u64>, completed: &mut VecDeque<CompletionEntry>) -> usize {
+        let targets = match target {
+            Some(user_data) if self.ops.borrow().contains_key(&user_data) => vec![user_data],
+            Some(_) => Vec::new(),
+            None => self.ops.borrow().keys().copied().collect()
+        };
+
+        for &user_data in &targets {
+            let op = match self.ops.borrow_mut().remove(&user_data) {
+                Some(op) => op,
+                None => continue
+            };
+
+            match op.kind {
+                Kind::Ready { fd, .. } => {
+                    self.unwait(fd, user_data);
+                    let _ = self.update(fd);
+                },
+                Kind::Timeout(deadline) => {
+                    self.timeouts.borrow_mut().remove(&(deadline, user_data));
+                }
+            }
+
+            completed.push_back(sys::completion(user_data, -libc::ECANCELED, 0));
+        }
+
+        targets.len()
+    }
+
+    fn unwait(&self, fd: RawFd, user_data: u64) {
+        let mut waiting = self.waiting.borrow_mut();
+
+        if let Some(ops) = waiting.get_mut(&fd) {
+            ops.retain(|&op| op != user_data);
+        }
+    }
+
+    /// Register `fd` for what its ops wait for, or remove it once none does.
+    fn update(&self, fd: RawFd) -> io::Result<()> {
+        let mut waiting = self.waiting.borrow_mut();
+        let ops = self.ops.borrow();
+
+        let events = waiting.get(&fd)
+            .into_iter()
+            .flatten()
+            .filter_map(|user_data| match ops.get(user_data)?.kind {
+                Kind::Ready { events, .. } => Some(events),
+                Kind::Timeout(_) => None
+            })
+            .fold(0, |acc, events| acc | events);
+
+        if events == 0 {
+            if waiting.remove(&fd).is_some() {
+                let _ = self.ctl(libc::EPOLL_CTL_DEL, fd, 0);
+            }
+
+            return Ok(());
+        }
+
+        // `MOD` for an fd that is in the set already, `ADD` if it was closed and reused.
+        match self.ctl(libc::EPOLL_CTL_MOD, fd, events) {
+            Err(ref err) if err.raw_os_error() == Some(libc::ENOENT) =>
+                self.ctl(libc::EPOLL_CTL_ADD, fd, events),
+            ret => ret
+        }
+    }
+
+    fn ctl(&self, op: i32, fd: RawFd, events: u32) -> io::Result<()> {
+        let mut event = libc::epoll_event { events, u64: fd as u64 };
+
+        if unsafe { libc::epoll_ctl(self.fd.as_raw_fd(), op, fd, &mut event) } == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
+
+impl AsRawFd for Epoll {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+fn complete(completed: &mut VecDeque<CompletionEntry>, user_data: u64, res: i32) {
+    // a detached op
+    if user_data != WAKE_TOKEN {
+        completed.push_back(sys::completion(user_data, res, 0));
+    }
+}
+
+fn deadline(ts: &types::Timespec, abs: bool) -> Instant {
+    let ts = unsafe { &*(ts as *const types::Timespec as *const libc::timespec) };
+    let dur = Duration::new(ts.tv_sec.max(0) as u64, ts.tv_nsec.clamp(0, 999_999_999) as u32);
+
+    if !abs {
+        return Instant::now() + dur;
+    }
+
+    // absolute on `CLOCK_MONOTONIC`, which `Instant` is based on.
+    let mut now = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now);
+    }
+    let now = Duration::new(now.tv_sec as u64, now.tv_nsec as u32);
+
+    Instant::now() + dur.saturating_sub(now)
+}
+
+pub(crate) fn interest(sqe: &sys::Sqe) -> u32 {
+    let events = match sqe.opcode {
+        opcode::PollAdd::CODE => sqe.op_flags & 0xffff,
+        opcode::Write::CODE
+            | opcode::Writev::CODE
+            | opcode::Send::CODE
+            | opcode::SendMsg::CODE
+            | opcode::Connect::CODE => libc::POLLOUT as u32,
+        _ => libc::POLLIN as u32
+    };
+
+    // the `poll(2)` and `epoll(7)` bits are the same.
+    events | (libc::POLLERR | libc::POLLHUP) as u32
+}
+
+// reads and writes of regular files and block devices never wait for readiness.
+pub(crate) fn is_blocking(sqe: &sys::Sqe) -> bool {
+    if !matches!(sqe.opcode, opcode::Read::CODE | opcode::Readv::CODE | opcode::Write::CODE | opcode::Writev::CODE) {
+        return false;
+    }
+
+    let mut stat = unsafe { mem::zeroed::<libc::stat>() };
+
+    if unsafe { libc::fstat(sqe.fd, &mut stat) } != 0 {
+        return false;
+    }
+
+    matches!(stat.st_mode & libc::S_IFMT, libc::S_IFREG | libc::S_IFBLK | libc::S_IFDIR)
+}
+
+/// Start a connect without waiting, `None` while it's in progress,
+/// then [attempt] reports how it went once the socket is writable.
+pub(crate) fn connect(sqe: &sys::Sqe) -> Option<i32> {
+    let ret = nonblocking(sqe.fd, || unsafe {
+        libc::connect(sqe.fd, sqe.addr as *const _, sqe.off as _) as i64
+    });
+
+    match ret {
+        Ok(_) => Some(0),
+        // an interrupted connect goes on in the background
+        Err(libc::EINPROGRESS | libc::EINTR) => None,
+        Err(err) => Some(-err)
+    }
+}
+
+pub(crate) fn attempt(sqe: &sys::Sqe, events: u32) -> Option<i32> {
+    let fd = sqe.fd;
+    let mut pollfd = libc::pollfd { fd, events: events as i16, revents: 0 };
+
+    // the fd may be in blocking mode, the syscall must not wait.
+    let n = unsafe { libc::poll(&mut pollfd, 1, 0) };
+
+    if n < 0 {
+        return Some(-io::Error::last_os_error().raw_os_error().unwrap_or(libc::EIO));
+    }
+
+    if n == 0 || pollfd.revents == 0 {
+        return None;
+    }
+
+    let socket = is_socket(fd);
+
+    let ret = unsafe {
+        match sqe.opcode {
+            opcode::PollAdd::CODE => return Some(i32::from(pollfd.revents as u16)),
+            opcode::Connect::CODE => {
+                let mut err = 0i32;
+                let mut len = mem::size_of::<i32>() as libc::socklen_t;
+                let ret = libc::getsockopt(
+                    fd, libc::SOL_SOCKET, libc::SO_ERROR,
+                    &mut err as *mut i32 as *mut _, &mut len
+                );
+
+                return Some(if ret == 0 { -err } else { -libc::EIO });
+            },
+            // sockets can be asked not to wait per call, other fds only through their flags.
+            opcode::Read::CODE if socket =>
+                cvt(libc::recv(fd, sqe.addr as *mut _, sqe.len as _, libc::MSG_DONTWAIT) as i64),
+            opcode::Write::CODE if socket =>
+                cvt(libc::send(fd, sqe.addr as *const _, sqe.len as _, libc::MSG_DONTWAIT | libc::MSG_NOSIGNAL) as i64),
+            opcode::Readv::CODE if socket => {
+                let mut msg = iov_msg(sqe);
+                cvt(libc::recvmsg(fd, &mut msg, libc::MSG_DONTWAIT) as i64)
+            },
+            opcode::Writev::CODE if socket => {
+                let msg = iov_msg(sqe);
+                cvt(libc::sendmsg(fd, &msg, libc::MSG_DONTWAIT | libc::MSG_NOSIGNAL) as i64)
+            },
+            opcode::Read::CODE =>
+                nonblocking(fd, || libc::read(fd, sqe.addr as *mut _, sqe.len as _) as i64),
+            opcode::Readv::CODE =>
+                nonblocking(fd, || libc::readv(fd, sqe.addr as *const _, sqe.len as _) as i64),
+            opcode::Write::CODE =>
+                nonblocking(fd, || libc::write(fd, sqe.addr as *const _, sqe.len as _) as i64),
+            opcode::Writev::CODE =>
+                nonblocking(fd, || libc::writev(fd, sqe.addr as *const _, sqe.len as _) as i64),
+            opcode::Recv::CODE => cvt(libc::recv(
+                fd, sqe.addr as *mut _, sqe.len as _,
+                sqe.op_flags as i32 | libc::MSG_DONTWAIT
+            ) as i64),
+            opcode::Send::CODE => cvt(libc::send(
+                fd, sqe.addr as *const _, sqe.len as _,
+                sqe.op_flags as i32 | libc::MSG_DONTWAIT
+            ) as i64),
+            opcode::RecvMsg::CODE =>
+                cvt(libc::recvmsg(fd, sqe.addr as *mut _, sqe.op_flags as i32 | libc::MSG_DONTWAIT) as i64),
+            opcode::SendMsg::CODE =>
+                cvt(libc::sendmsg(fd, sqe.addr as *const _, sqe.op_flags as i32 | libc::MSG_DONTWAIT) as i64),
+            // another process may have taken the connection since the poll.
+            opcode::Accept::CODE => nonblocking(fd, || libc::accept4(
+                fd, sqe.addr as *mut _, sqe.off as *mut _,
+                sqe.op_flags as i32
+            ) as i64),
+            _ => return Some(-libc::EINVAL)
+        }
+    };
+
+    match ret {
+        Ok(n) => Some(n as i32),
+        Err(libc::EAGAIN | libc::EINTR) => None,
+        Err(err) => Some(-err)
+    }
+}
+
+/// The result of a syscall, or its errno.
+fn cvt(ret: i64) -> Result<i64, i32> {
+    if ret >= 0 {
+        Ok(ret)
+    } else {
+        Err(io::Error::last_os_error().raw_os_error().unwrap_or(libc::EIO))
+    }
+}
+
+/// Run the syscall `f` on `fd` with `O_NONBLOCK` set, the fd may be in blocking mode
+/// and the Proactor thread must not wait in it.
+fn nonblocking(fd: RawFd, f: impl FnOnce() -> i64) -> Result<i64, i32> {
+    let flags = cvt(unsafe { libc::fcntl(fd, libc::F_GETFL) } as i64)? as i32;
+    let blocking = flags & libc::O_NONBLOCK == 0;
+
+    if blocking {
+        cvt(unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } as i64)?;
+    }
+
+    let ret = cvt(f());
+
+    if blocking {
+        unsafe {
+            libc::fcntl(fd, libc::F_SETFL, flags);
+        }
+    }
+
+    ret
+}
+
+fn is_socket(fd: RawFd) -> bool {
+    let mut stat = unsafe { mem::zeroed::<libc::stat>() };
+
+    unsafe { libc::fstat(fd, &mut stat) == 0 && stat.st_mode & libc::S_IFMT == libc::S_IFSOCK }
+}
+
+/// A `msghdr` over the iovecs of a `Readv` or `Writev`.
+fn iov_msg(sqe: &sys::Sqe) -> libc::msghdr {
+    let mut msg = unsafe { mem::zeroed::<libc::msghdr>() };
+    msg.msg_iov = sqe.addr as *mut libc::iovec;
+    msg.msg_iovlen = sqe.len as _;
+    msg
+}
+
+
+#[test]
+fn test_epoll_backend() {
+    use std::fs;
+    use std::io::Write;
+    use bytes::BytesMut;
+    use futures_util::future::{ self, FutureExt };
+    use crate::{ Proactor, Backend };
+    use crate::action::{ fs::{ File, temp_path }, splice::pipe, timeout::Timer };
+    use crate::executor::Runtime;
+
+    let proactor = Proactor::builder()
+        .backend(Backend::Epoll)
+        .build()
+        .unwrap();
+    assert_eq!(proactor.backend(), Backend::Epoll);
+    assert!(!proactor.probe().is_supported(opcode::Read::CODE));
+
+    let path = temp_path("epoll");
+    fs::write(&path, "hello").unwrap();
+
+    let mut runtime = Runtime::from_proactor(proactor);
+
+    runtime.run_until(async {
+        // a regular file goes to the blocking pool
+        let mut file = File::open(&path).await.unwrap();
+        let buf = file.read(BytesMut::with_capacity(16)).await.unwrap();
+        assert_eq!(&buf[..], b"hello");
+
+        // a pipe waits for readiness
+        let (mut rx, tx) = pipe().unwrap();
+        let mut tx = tx.into_std();
+        let start = Instant::now();
+
+        let write = async {
+            Timer::new().delay_for(Duration::from_millis(20)).await.unwrap();
+            tx.write_all(b"world").unwrap();
+        };
+        let (buf, ()) = future::join(rx.read(BytesMut::with_capacity(16)), write).await;
+        assert_eq!(&buf.unwrap()[..], b"world");
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        // a dropped read is cancelled, the next one gets the data
+        assert!(rx.read(BytesMut::with_capacity(16)).now_or_never().is_none());
+        tx.write_all(b"again").unwrap();
+        let buf = rx.read(BytesMut::with_capacity(16)).await.unwrap();
+        assert_eq!(&buf[..], b"again");
+    });
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_epoll_blocking_socket() {
+    use std::net;
+    use std::io::Read;
+    use bytes::Bytes;
+    use crate::{ Proactor, Backend };
+    use crate::action::tcp::TcpStream;
+    use crate::executor::Runtime;
+
+    let proactor = Proactor::builder()
+        .backend(Backend::Epoll)
+        .build()
+        .unwrap();
+    let mut runtime = Runtime::from_proactor(proactor);
+
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let std_stream = net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (mut peer, _) = listener.accept().unwrap();
+
+    // a blocking socket, written to faster than the peer reads
+    let mut stream = TcpStream::from_std(std_stream);
+
+    runtime.run_until(async {
+        let buf = Bytes::from(vec![0; 32 << 20]);
+        let rest = stream.write(buf).await.unwrap();
+        assert!(!rest.is_empty());
+    });
+
+    let mut buf = [0; 16];
+    assert_eq!(peer.read(&mut buf).unwrap(), 16);
+}
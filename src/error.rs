@@ -0,0 +1,163 @@
+//! The failures of the Proactor, with the context an `io::Error` lacks.
+//!
+//! Actions still return `io::Result`, an [Error] converts into an `io::Error`
+//! that carries it, and [Error::from_io] gets it back.
+
+use std::{ io, fmt };
+
+
+/// Why an op failed.
+#[derive(Debug)]
+pub enum Error {
+    /// The entry couldn't be pushed, like a linked chain longer than the SQ
+    /// or an op forbidden by the restrictions of the ring.
+    Submit(io::Error),
+    /// The kernel completed the op with `-errno`.
+    Op {
+        opcode: u8,
+        errno: i32
+    },
+    /// The op was cancelled before it completed, see [crate::cancel].
+    Cancelled,
+    /// The Proactor was closed, it takes no more entries.
+    Closed,
+    /// The kernel doesn't support the opcode, and the blocking pool can't emulate it.
+    Unsupported(u8)
+}
+
+impl Error {
+    /// The error of a completion, `None` if `res` isn't negative.
+    pub fn from_cqe(opcode: u8, res: i32) -> Option<Error> {
+        if res >= 0 {
+            None
+        } else if res == -libc::ECANCELED {
+            Some(Error::Cancelled)
+        } else {
+            Some(Error::Op { opcode, errno: -res })
+        }
+    }
+
+    /// The `Error` carried by `err`, if it was converted from one.
+    ///
+    /// A bare `ECANCELED` is a cancellation too.
+    pub fn from_io(err: &io::Error) -> Option<&Error> {
+        match err.get_ref().and_then(|err| err.downcast_ref::<Error>()) {
+            Some(err) => Some(err),
+            None if err.raw_os_error() == Some(libc::ECANCELED) => Some(&Error::Cancelled),
+            None => None
+        }
+    }
+
+    /// The opcode of the failed op, if known.
+    pub fn opcode(&self) -> Option<u8> {
+        match *self {
+            Error::Op { opcode, .. } | Error::Unsupported(opcode) => Some(opcode),
+            _ => None
+        }
+    }
+
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            Error::Submit(err) => err.kind(),
+            Error::Op { errno, .. } => io::Error::from_raw_os_error(*errno).kind(),
+            Error::Cancelled => io::Error::from_raw_os_error(libc::ECANCELED).kind(),
+            Error::Closed => io::ErrorKind::BrokenPipe,
+            Error::Unsupported(_) => io::ErrorKind::Unsupported
+        }
+    }
+}
+
+/// The errno a ticket completes with when its entry failed to push.
+pub fn errno(err: &io::Error) -> i32 {
+    if let Some(errno) = err.raw_os_error() {
+        return errno;
+    }
+
+    match Error::from_io(err) {
+        Some(Error::Op { errno, .. }) => *errno,
+        Some(Error::Submit(err)) => errno(err),
+        Some(Error::Closed) => libc::ECANCELED,
+        _ => match err.kind() {
+            io::ErrorKind::PermissionDenied => libc::EACCES,
+            io::ErrorKind::Unsupported => libc::EOPNOTSUPP,
+            io::ErrorKind::InvalidInput => libc::EINVAL,
+            _ => libc::EIO
+        }
+    }
+}
+
+/// The name of `opcode` without the `IORING_OP_` prefix, for error messages.
+pub fn opcode_name(opcode: u8) -> Option<&'static str> {
+    const NAMES: &[&str] = &[
+        "NOP", "READV", "WRITEV", "FSYNC", "READ_FIXED", "WRITE_FIXED", "POLL_ADD", "POLL_REMOVE",
+        "SYNC_FILE_RANGE", "SENDMSG", "RECVMSG", "TIMEOUT", "TIMEOUT_REMOVE", "ACCEPT", "ASYNC_CANCEL",
+        "LINK_TIMEOUT", "CONNECT", "FALLOCATE", "OPENAT", "CLOSE", "FILES_UPDATE", "STATX", "READ",
+        "WRITE", "FADVISE", "MADVISE", "SEND", "RECV", "OPENAT2", "EPOLL_CTL", "SPLICE",
+        "PROVIDE_BUFFERS", "REMOVE_BUFFERS", "TEE", "SHUTDOWN", "RENAMEAT", "UNLINKAT", "MKDIRAT",
+        "SYMLINKAT", "LINKAT", "MSG_RING", "FSETXATTR", "SETXATTR", "FGETXATTR", "GETXATTR", "SOCKET",
+        "URING_CMD", "SEND_ZC", "SENDMSG_ZC", "READ_MULTISHOT", "WAITID", "FUTEX_WAIT", "FUTEX_WAKE",
+        "FUTEX_WAITV", "FIXED_FD_INSTALL", "FTRUNCATE", "BIND", "LISTEN"
+    ];
+
+    NAMES.get(usize::from(opcode)).copied()
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Submit(err) => write!(f, "failed to submit: {}", err),
+            Error::Op { opcode, errno } => match opcode_name(*opcode) {
+                Some(name) => write!(f, "{} failed: {}", name, io::Error::from_raw_os_error(*errno)),
+                None => write!(f, "opcode {} failed: {}", opcode, io::Error::from_raw_os_error(*errno))
+            },
+            Error::Cancelled => f.write_str("operation cancelled"),
+            Error::Closed => f.write_str("ritsu proactor closed"),
+            Error::Unsupported(opcode) => match opcode_name(*opcode) {
+                Some(name) => write!(f, "{} is unsupported on this kernel", name),
+                None => write!(f, "opcode {} is unsupported on this kernel", opcode)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Submit(err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl From<Error> for io::Error {
+    /// The `io::Error` keeps the kind, `raw_os_error` is lost though,
+    /// [Error::from_io] recovers the errno of an op.
+    fn from(err: Error) -> io::Error {
+        io::Error::new(err.kind(), err)
+    }
+}
+
+
+#[test]
+fn test_error() {
+    use io_uring::opcode;
+
+    let err = Error::from_cqe(opcode::Read::CODE, -libc::EINVAL).unwrap();
+    assert_eq!(err.to_string(), format!("READ failed: {}", io::Error::from_raw_os_error(libc::EINVAL)));
+    assert!(Error::from_cqe(opcode::Read::CODE, 0).is_none());
+    assert!(matches!(Error::from_cqe(opcode::Read::CODE, -libc::ECANCELED), Some(Error::Cancelled)));
+
+    let io_err = io::Error::from(err);
+    assert_eq!(io_err.kind(), io::ErrorKind::InvalidInput);
+    assert!(matches!(
+        Error::from_io(&io_err),
+        Some(Error::Op { opcode: opcode::Read::CODE, errno: libc::EINVAL })
+    ));
+
+    let err = Error::Unsupported(crate::sys::IORING_OP_FTRUNCATE);
+    assert_eq!(err.to_string(), "FTRUNCATE is unsupported on this kernel");
+    assert_eq!(err.opcode(), Some(crate::sys::IORING_OP_FTRUNCATE));
+
+    assert!(matches!(Error::from_io(&io::Error::from_raw_os_error(libc::ECANCELED)), Some(Error::Cancelled)));
+    assert!(Error::from_io(&io::Error::other("other")).is_none());
+}
@@ -2,21 +2,42 @@
 //!
 //! fork from `futures-executor/local_pool.rs`.
 
-use std::io;
+mod multi_thread;
+mod thread_per_core;
+mod remote;
+mod group;
+mod metrics;
+mod local_set;
+
+use std::{ io, fmt };
+use std::pin::Pin;
 use std::cell::RefCell;
 use std::future::Future;
 use std::rc::{ Rc, Weak };
+use std::sync::Arc;
 use std::task::{ Context, Poll };
 use futures_task::LocalFutureObj;
 use futures_util::pin_mut;
+use futures_util::future::{ FutureExt, AbortHandle, Abortable };
 use futures_util::stream::{ StreamExt, FuturesUnordered };
-use crate::{ handle, Proactor, RawHandle };
+use pin_project_lite::pin_project;
+use crate::sync::oneshot;
+use crate::{ handle, task, time, Proactor, RawHandle };
+pub use multi_thread::{ MultiThread, SendSpawner };
+pub use thread_per_core::{ thread_per_core, Core };
+pub use remote::RemoteHandle;
+pub use group::TaskGroup;
+pub use metrics::Metrics;
+pub use local_set::{ LocalSet, LocalSetHandle };
 
 /// A single-threaded task pool for polling futures to completion.
 pub struct Runtime {
     pool: FuturesUnordered<LocalFutureObj<'static, ()>>,
     incoming: Rc<Incoming>,
-    proactor: Proactor
+    remote: Arc<remote::Remote>,
+    proactor: Proactor,
+    budget: Option<u32>,
+    counters: Rc<metrics::Counters>
 }
 
 #[derive(Clone, Debug)]
@@ -26,14 +47,51 @@ pub struct Spawner {
 
 type Incoming = RefCell<Vec<LocalFutureObj<'static, ()>>>;
 
+thread_local!{
+    // the pool being run on this thread
+    static CURRENT: RefCell<Option<Spawner>> = const { RefCell::new(None) };
+
+    // created by the first `block_on` of this thread
+    static RUNTIME: RefCell<Option<Runtime>> = const { RefCell::new(None) };
+}
+
+/// Waits for the output of a spawned task.
+///
+/// Dropping it detaches the task, it keeps running in the pool.
+pub struct JoinHandle<T> {
+    rx: oneshot::Receiver<T>,
+    abort: AbortHandle
+}
+
+/// The task was aborted, or the pool was dropped before it completed.
+#[derive(Debug)]
+pub struct JoinError(());
+
 impl Runtime {
     /// Create a new, empty pool of tasks.
     pub fn new() -> io::Result<Runtime> {
-        Ok(Runtime {
+        Ok(Runtime::from_proactor(Proactor::new()?))
+    }
+
+    /// Create a new, empty pool of tasks driven by `proactor`.
+    pub fn from_proactor(proactor: Proactor) -> Runtime {
+        Runtime {
             pool: FuturesUnordered::new(),
             incoming: Default::default(),
-            proactor: Proactor::new()?
-        })
+            remote: Arc::new(remote::Remote::new(proactor.waker())),
+            proactor,
+            budget: None,
+            counters: Default::default()
+        }
+    }
+
+    /// Let a task take at most `budget` completions in one poll, `None` is unlimited.
+    ///
+    /// Beyond that its op futures return `Pending` and the task is queued again,
+    /// so a task that always finds its ops ready can't starve the others.
+    /// See also [crate::task::yield_now].
+    pub fn set_budget(&mut self, budget: Option<u32>) {
+        self.budget = budget;
     }
 
     /// Get a clonable handle to the pool as a `Spawn`.
@@ -43,6 +101,16 @@ impl Runtime {
         }
     }
 
+    /// Spawn `fut` into the pool, it starts running with the next
+    /// [Runtime::run] or [Runtime::run_until].
+    pub fn spawn_local<F>(&self, fut: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static
+    {
+        spawn_into(&self.incoming, fut)
+    }
+
     /// Run all tasks in the pool to completion.
     ///
     /// ```
@@ -50,7 +118,7 @@ impl Runtime {
     ///
     /// let mut pool: Runtime = Runtime::new().unwrap();
     ///
-    /// // ... spawn some initial tasks using `spawn.spawn()` or `spawn.spawn_local()`
+    /// // ... spawn some initial tasks using `pool.spawn_local()` or `spawner.spawn()`
     ///
     /// // run *all* tasks in the pool to completion, including any newly-spawned ones.
     /// pool.run();
@@ -59,8 +127,13 @@ impl Runtime {
     /// The function will block the calling thread until *all* tasks in the pool
     /// are complete, including any spawned while running existing tasks.
     pub fn run(&mut self) {
-        let Runtime { pool, incoming, proactor } = self;
-        run_executor(proactor, |cx| poll_pool(pool, incoming, cx))
+        let spawner = self.spawner();
+        let handle = self.raw_handle();
+        let Runtime { pool, incoming, remote, proactor, budget, counters } = self;
+        run_executor(proactor, spawner, *budget, counters, |cx| {
+            remote.drain(incoming, &handle);
+            poll_pool(pool, incoming, cx)
+        })
     }
 
     /// Runs all the tasks in the pool until the given future completes.
@@ -81,14 +154,18 @@ impl Runtime {
     /// one of the pool's run or poll methods. While the function is running,
     /// however, all tasks in the pool will try to make progress.
     pub fn run_until<F: Future>(&mut self, future: F) -> F::Output {
-        let Runtime { pool, incoming, proactor } = self;
+        let spawner = self.spawner();
+        let handle = self.raw_handle();
+        let Runtime { pool, incoming, remote, proactor, budget, counters } = self;
 
         pin_mut!(future);
 
-        run_executor(proactor, |cx| {
+        run_executor(proactor, spawner, *budget, counters, |cx| {
+            remote.drain(incoming, &handle);
+
             {
                 // if our main task is done, so are we
-                let result = future.as_mut().poll(cx);
+                let result = task::budget(|| future.as_mut().poll(cx));
                 if let Poll::Ready(output) = result {
                     return Poll::Ready(output);
                 }
@@ -108,10 +185,144 @@ impl Runtime {
 }
 
 impl Spawner {
-    pub fn spawn<F: Future<Output = ()> + 'static>(&self, fut: F) {
-        if let Some(incoming) = self.incoming.upgrade() {
-            incoming.borrow_mut().push(LocalFutureObj::from(Box::pin(fut)));
+    /// The spawner of the pool running on this thread, if any.
+    pub fn current() -> Option<Spawner> {
+        CURRENT.with(|c| c.borrow().clone())
+    }
+
+    /// Spawn `fut` into the pool.
+    ///
+    /// If the pool is gone already, the task is dropped
+    /// and the returned handle completes with [JoinError].
+    pub fn spawn<F>(&self, fut: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static
+    {
+        match self.incoming.upgrade() {
+            Some(incoming) => spawn_into(&incoming, fut),
+            None => spawn_into(&Incoming::default(), fut)
+        }
+    }
+}
+
+/// Spawn `fut` into the pool running on this thread.
+///
+/// # Panics
+///
+/// If it's called outside of a task of a [Runtime].
+pub fn spawn_local<F>(fut: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static
+{
+    Spawner::current()
+        .expect("not found ritsu runtime")
+        .spawn(fut)
+}
+
+/// Run `fut` to completion on a Runtime of this thread,
+/// which is created by the first call and reused afterwards.
+///
+/// Tasks spawned from `fut` stay in that Runtime,
+/// and make progress whenever `block_on` runs again.
+///
+/// # Panics
+///
+/// If it's nested in another `block_on`, or the Runtime can't be created.
+pub fn block_on<F: Future>(fut: F) -> F::Output {
+    RUNTIME.with(|runtime| {
+        let mut runtime = runtime.try_borrow_mut()
+            .expect("block_on can't be nested");
+
+        if runtime.is_none() {
+            *runtime = Some(Runtime::new().expect("failed to create ritsu runtime"));
         }
+
+        runtime.as_mut().unwrap().run_until(fut)
+    })
+}
+
+fn spawn_into<F>(incoming: &Incoming, fut: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static
+{
+    let (task, handle) = joinable(fut);
+    let task = Budgeted { fut: task };
+
+    incoming.borrow_mut().push(LocalFutureObj::from(Box::pin(task)));
+
+    handle
+}
+
+/// Split `fut` into a task that sends its output, and the handle that receives it.
+fn joinable<F: Future>(fut: F) -> (impl Future<Output = ()>, JoinHandle<F::Output>) {
+    let (tx, rx) = oneshot::channel();
+    let (abort, reg) = AbortHandle::new_pair();
+
+    let task = Abortable::new(fut, reg).map(move |ret| {
+        if let Ok(output) = ret {
+            let _ = tx.send(output);
+        }
+    });
+
+    (task, JoinHandle { rx, abort })
+}
+
+impl<T> JoinHandle<T> {
+    /// Drop the task the next time the pool polls it.
+    ///
+    /// Its in-flight ops are cancelled like any dropped future,
+    /// and this handle completes with [JoinError] unless the task finished first.
+    pub fn abort(&self) {
+        self.abort.abort();
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    #[inline]
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.rx)
+            .poll(cx)
+            .map(|ret| ret.ok_or(JoinError(())))
+    }
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("task was cancelled")
+    }
+}
+
+impl std::error::Error for JoinError {}
+
+impl From<JoinError> for io::Error {
+    fn from(err: JoinError) -> io::Error {
+        io::Error::other(err)
+    }
+}
+
+pin_project!{
+    // every poll of a task starts with a fresh budget
+    struct Budgeted<F> {
+        #[pin]
+        fut: F
+    }
+}
+
+impl<F: Future> Future for Budgeted<F> {
+    type Output = F::Output;
+
+    #[inline]
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("poll").entered();
+
+        let fut = self.project().fut;
+        metrics::poll(|| task::budget(|| fut.poll(cx)))
     }
 }
 
@@ -119,23 +330,49 @@ impl Spawner {
 // turn.
 fn run_executor<T>(
     proactor: &mut Proactor,
+    spawner: Spawner,
+    budget: Option<u32>,
+    counters: &Rc<metrics::Counters>,
     mut f: impl FnMut(&mut Context<'_>) -> Poll<T>
 ) -> T {
-    unsafe {
-        let raw_handle = proactor.raw_handle();
-        let handle = handle::default_handle(raw_handle);
-        handle::set(handle);
-    }
+    let _enter = enter(proactor.raw_handle(), spawner, budget);
+    let _counters = counters.enter();
 
     loop {
         let waker = proactor.waker_ref();
         let mut cx = Context::from_waker(&waker);
 
-        if let Poll::Ready(t) = f(&mut cx) {
+        let ret = f(&mut cx);
+        counters.tick();
+
+        if let Poll::Ready(t) = ret {
             return t;
         }
 
         proactor.park(None).expect("Proactor park failed");
+        counters.wakeup();
+    }
+}
+
+/// Make `raw_handle` the ring of this thread and `spawner` its pool until the guard drops.
+fn enter(raw_handle: RawHandle, spawner: Spawner, budget: Option<u32>) -> Enter {
+    unsafe {
+        handle::set(handle::default_handle(raw_handle.clone()));
+        time::set_wheel(raw_handle);
+    }
+
+    Enter(
+        CURRENT.with(|c| c.replace(Some(spawner))),
+        task::set_limit(budget)
+    )
+}
+
+struct Enter(Option<Spawner>, Option<u32>);
+
+impl Drop for Enter {
+    fn drop(&mut self) {
+        let _ = CURRENT.try_with(|c| c.replace(self.0.take()));
+        task::set_limit(self.1);
     }
 }
 
@@ -174,3 +411,59 @@ fn poll_pool(
         }
     }
 }
+
+
+#[test]
+fn test_join_handle() {
+    use std::time::Duration;
+    use crate::action::timeout::Timer;
+
+    let mut runtime = Runtime::new().unwrap();
+    let spawner = runtime.spawner();
+
+    let (output, aborted) = runtime.run_until(async move {
+        let output = spawner.spawn(async { 42 });
+
+        let sleeper = spawner.spawn(async {
+            Timer::new().delay_for(Duration::from_secs(10)).await.unwrap();
+        });
+        sleeper.abort();
+
+        (output.await, sleeper.await)
+    });
+
+    assert_eq!(output.unwrap(), 42);
+    assert!(aborted.is_err());
+}
+
+#[test]
+fn test_spawn_local() {
+    use std::cell::Cell;
+
+    let mut runtime = Runtime::new().unwrap();
+    let count = Rc::new(Cell::new(0));
+
+    let count2 = count.clone();
+    runtime.spawn_local(async move {
+        let count3 = count2.clone();
+        spawn_local(async move { count3.set(count3.get() + 1) });
+        count2.set(count2.get() + 1);
+    });
+
+    runtime.run();
+    assert_eq!(count.get(), 2);
+    assert!(Spawner::current().is_none());
+}
+
+#[test]
+fn test_block_on() {
+    use io_uring::opcode;
+
+    let ret = block_on(async {
+        unsafe { handle::push(opcode::Nop::new().build()).unwrap().await };
+        spawn_local(async { 1 }).await.unwrap() + 1
+    });
+
+    assert_eq!(ret, 2);
+    assert_eq!(block_on(async { 3 }), 3);
+}
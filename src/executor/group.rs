@@ -0,0 +1,207 @@
+//! Structured concurrency.
+//!
+//! A [TaskGroup] owns its child tasks and polls them itself,
+//! rather than handing them to the Runtime, so they can borrow from the stack.
+//! They only run while the group is awaited, and once the group is gone
+//! so are they, dropping a child cancels its in-flight ops.
+
+use std::pin::Pin;
+use std::cell::RefCell;
+use std::future::Future;
+use std::task::{ Context, Poll, Waker };
+use futures_util::future::{ self, LocalBoxFuture };
+use futures_util::stream::{ StreamExt, FuturesUnordered };
+
+
+/// A set of child tasks that are joined or cancelled before the group is gone.
+///
+/// ```
+/// use std::cell::Cell;
+/// use ritsu::executor::{ block_on, TaskGroup };
+///
+/// let count = Cell::new(0);
+///
+/// block_on(async {
+///     let group = TaskGroup::new();
+///
+///     group.scope(async {
+///         for _ in 0..4 {
+///             group.spawn(async { count.set(count.get() + 1) });
+///         }
+///     }).await;
+/// });
+///
+/// assert_eq!(count.get(), 4);
+/// ```
+pub struct TaskGroup<'a, T = ()> {
+    tasks: RefCell<FuturesUnordered<LocalBoxFuture<'a, T>>>,
+    // spawned from a child while the group polls it
+    incoming: RefCell<Vec<LocalBoxFuture<'a, T>>>,
+    waker: RefCell<Option<Waker>>
+}
+
+impl<'a, T> TaskGroup<'a, T> {
+    pub fn new() -> TaskGroup<'a, T> {
+        TaskGroup {
+            tasks: RefCell::new(FuturesUnordered::new()),
+            incoming: RefCell::new(Vec::new()),
+            waker: RefCell::new(None)
+        }
+    }
+
+    /// Add a child task, it starts running the next time the group is awaited.
+    pub fn spawn<F: Future<Output = T> + 'a>(&self, fut: F) {
+        self.incoming.borrow_mut().push(Box::pin(fut));
+
+        if let Some(waker) = self.waker.borrow_mut().take() {
+            waker.wake();
+        }
+    }
+
+    /// The number of children that haven't completed yet.
+    pub fn len(&self) -> usize {
+        self.tasks.borrow().len() + self.incoming.borrow().len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drop every child now, their in-flight ops are cancelled.
+    pub fn cancel(&self) {
+        self.incoming.borrow_mut().clear();
+        self.tasks.borrow_mut().clear();
+    }
+
+    /// Poll the children until one completes, `None` if there are none left.
+    pub fn poll_next(&self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut tasks = match self.tasks.try_borrow_mut() {
+            Ok(tasks) => tasks,
+            // awaited from one of the children
+            Err(_) => return Poll::Pending
+        };
+
+        loop {
+            tasks.extend(self.incoming.borrow_mut().drain(..));
+
+            let ret = tasks.poll_next_unpin(cx);
+
+            if !self.incoming.borrow().is_empty() {
+                continue
+            }
+
+            if ret.is_pending() {
+                *self.waker.borrow_mut() = Some(cx.waker().clone());
+            }
+
+            return ret;
+        }
+    }
+
+    /// Wait for the next child to complete.
+    pub async fn next(&self) -> Option<T> {
+        future::poll_fn(|cx| self.poll_next(cx)).await
+    }
+
+    /// Wait for every child, including the ones spawned meanwhile.
+    pub async fn join_all(&self) -> Vec<T> {
+        let mut outputs = Vec::new();
+
+        while let Some(output) = self.next().await {
+            outputs.push(output);
+        }
+
+        outputs
+    }
+
+    /// Run `body` alongside the children, then wait for every child.
+    ///
+    /// If this future is dropped early, the children are cancelled with the group.
+    pub async fn scope<F: Future>(&self, body: F) -> (F::Output, Vec<T>) {
+        let mut body = Box::pin(body);
+        let mut outputs = Vec::new();
+
+        let output = future::poll_fn(|cx| {
+            while let Poll::Ready(Some(output)) = self.poll_next(cx) {
+                outputs.push(output);
+            }
+
+            body.as_mut().poll(cx)
+        }).await;
+
+        outputs.extend(self.join_all().await);
+
+        (output, outputs)
+    }
+}
+
+impl<T> Default for TaskGroup<'_, T> {
+    fn default() -> Self {
+        TaskGroup::new()
+    }
+}
+
+impl<T> Drop for TaskGroup<'_, T> {
+    fn drop(&mut self) {
+        // children go first, before anything they borrow.
+        self.cancel();
+    }
+}
+
+impl<T> futures_util::stream::Stream for TaskGroup<'_, T> {
+    type Item = T;
+
+    #[inline]
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        TaskGroup::poll_next(&self, cx)
+    }
+}
+
+
+#[test]
+fn test_task_group_cancel() {
+    use io_uring::opcode::{ self, types };
+    use crate::{ handle, Proactor };
+    use super::Runtime;
+
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+
+    let proactor = Proactor::new().unwrap();
+    let handle = proactor.raw_handle();
+    let mut runtime = Runtime::from_proactor(proactor);
+
+    let mut buf = [0; 8];
+    let fd = fds[0];
+    let bufptr = buf.as_mut_ptr();
+
+    let outputs = runtime.run_until(async {
+        let group = TaskGroup::new();
+
+        group.spawn(async move {
+            let entry = opcode::Read::new(types::Target::Fd(fd), bufptr, 8).build();
+            unsafe { handle::push(entry).unwrap().await.result() }
+        });
+        group.spawn(async { 1 });
+
+        let first = group.next().await;
+        group.cancel();
+
+        (first, group.is_empty())
+    });
+
+    assert_eq!(outputs, (Some(1), true));
+
+    runtime.run_until(async {
+        // the read completes with `ECANCELED` soon
+        while !handle.ring.inflight.borrow().is_empty() {
+            crate::task::yield_now().await;
+        }
+    });
+
+    unsafe {
+        libc::close(fds[0]);
+        libc::close(fds[1]);
+    }
+}
@@ -0,0 +1,160 @@
+//! Run `!Send` futures on a Runtime thread on behalf of other threads.
+//!
+//! A future that can't cross threads is created on the Runtime thread instead,
+//! other threads send a closure that builds it through a [LocalSetHandle].
+
+use std::pin::Pin;
+use std::future::Future;
+use std::sync::{ Arc, Mutex };
+use std::task::{ Context, Poll, Waker };
+use futures_util::future::{ FutureExt, LocalBoxFuture, AbortHandle, Abortable };
+use crate::sync::oneshot;
+use super::{ JoinHandle, TaskGroup };
+
+
+type Spawn = Box<dyn FnOnce() -> LocalBoxFuture<'static, ()> + Send + 'static>;
+
+/// A set of `!Send` tasks, fed from any thread by its [LocalSetHandle]s.
+///
+/// It's a future that runs the tasks, and completes once every task
+/// has completed and every handle has been dropped.
+///
+/// ```
+/// use std::rc::Rc;
+/// use ritsu::executor::{ Runtime, LocalSet };
+///
+/// let mut runtime = Runtime::new().unwrap();
+/// let local_set = LocalSet::new();
+/// let handle = local_set.handle();
+///
+/// let t = std::thread::spawn(move || {
+///     handle.spawn_pinned(|| async {
+///         let rc = Rc::new(1);
+///         *rc + 1
+///     })
+/// });
+///
+/// let join = t.join().unwrap();
+/// runtime.run_until(local_set);
+/// assert_eq!(runtime.run_until(join).unwrap(), 2);
+/// ```
+pub struct LocalSet {
+    tasks: TaskGroup<'static>,
+    ingress: Arc<Ingress>
+}
+
+/// Sends tasks to a [LocalSet], it's `Send` and `Sync`.
+#[derive(Clone)]
+pub struct LocalSetHandle {
+    ingress: Arc<Ingress>
+}
+
+#[derive(Default)]
+struct Ingress {
+    queue: Mutex<Vec<Spawn>>,
+    waker: Mutex<Option<Waker>>
+}
+
+impl LocalSet {
+    pub fn new() -> LocalSet {
+        LocalSet {
+            tasks: TaskGroup::new(),
+            ingress: Arc::new(Ingress::default())
+        }
+    }
+
+    pub fn handle(&self) -> LocalSetHandle {
+        LocalSetHandle { ingress: self.ingress.clone() }
+    }
+
+    /// Spawn a `!Send` future from the thread of the set.
+    pub fn spawn_local<F>(&self, fut: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static
+    {
+        let (task, handle) = super::joinable(fut);
+        self.tasks.spawn(task);
+        handle
+    }
+}
+
+impl Default for LocalSet {
+    fn default() -> LocalSet {
+        LocalSet::new()
+    }
+}
+
+impl Future for LocalSet {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        {
+            let mut waker = self.ingress.waker.lock().unwrap();
+            if !waker.as_ref().is_some_and(|w| w.will_wake(cx.waker())) {
+                *waker = Some(cx.waker().clone());
+            }
+        }
+
+        let spawns = std::mem::take(&mut *self.ingress.queue.lock().unwrap());
+        for spawn in spawns {
+            self.tasks.spawn(spawn());
+        }
+
+        while let Poll::Ready(Some(())) = self.tasks.poll_next(cx) {}
+
+        // a handle may still send more tasks
+        if self.tasks.is_empty()
+            && Arc::strong_count(&self.ingress) == 1
+            && self.ingress.queue.lock().unwrap().is_empty()
+        {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl LocalSetHandle {
+    /// Run the future returned by `f` in the [LocalSet].
+    ///
+    /// `f` is called on the thread of the set, so the future doesn't need to be `Send`,
+    /// only its output does. If the set is dropped before the task completes,
+    /// the returned handle completes with [super::JoinError].
+    pub fn spawn_pinned<F, Fut>(&self, f: F) -> JoinHandle<Fut::Output>
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future + 'static,
+        Fut::Output: Send + 'static
+    {
+        let (tx, rx) = oneshot::channel();
+        let (abort, reg) = AbortHandle::new_pair();
+
+        let spawn: Spawn = Box::new(move || {
+            Abortable::new(f(), reg)
+                .map(move |ret| {
+                    if let Ok(output) = ret {
+                        let _ = tx.send(output);
+                    }
+                })
+                .boxed_local()
+        });
+
+        self.ingress.queue.lock().unwrap().push(spawn);
+
+        if let Some(waker) = self.ingress.waker.lock().unwrap().as_ref() {
+            waker.wake_by_ref();
+        }
+
+        JoinHandle { rx, abort }
+    }
+}
+
+impl Drop for LocalSetHandle {
+    fn drop(&mut self) {
+        // the set may be waiting for the last handle
+        if let Some(waker) = self.ingress.waker.lock().unwrap().as_ref() {
+            waker.wake_by_ref();
+        }
+    }
+}
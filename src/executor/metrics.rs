@@ -0,0 +1,128 @@
+//! Counters of a [Runtime], see [Runtime::metrics].
+
+use std::rc::Rc;
+use std::cell::{ Cell, RefCell };
+use std::time::{ Duration, Instant };
+use super::Runtime;
+
+
+thread_local!{
+    // the counters of the Runtime being run on this thread
+    static CURRENT: RefCell<Option<Rc<Counters>>> = const { RefCell::new(None) };
+}
+
+#[derive(Default, Debug)]
+pub(super) struct Counters {
+    polls: Cell<u64>,
+    tick_polls: Cell<u64>,
+    last_tick_polls: Cell<u64>,
+    max_poll: Cell<Duration>,
+    ticks: Cell<u64>,
+    wakeups: Cell<u64>
+}
+
+/// A snapshot of the counters of a [Runtime].
+///
+/// Counters only grow, gauges describe the Runtime at the time of the snapshot.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Metrics {
+    /// Gauge, tasks spawned and not completed yet.
+    pub alive_tasks: usize,
+    /// Gauge, tasks spawned but not picked up by the Runtime yet,
+    /// including the ones from [super::RemoteHandle].
+    pub queued_tasks: usize,
+    /// Counter, polls of spawned tasks.
+    pub polls: u64,
+    /// Gauge, polls of spawned tasks in the last turn of the Runtime.
+    pub polls_per_tick: u64,
+    /// The longest poll of a spawned task so far.
+    pub max_poll_duration: Duration,
+    /// Counter, turns of the Runtime, each one polls the ready tasks and then parks.
+    pub ticks: u64,
+    /// Counter, how often the Proactor returned from parking.
+    pub wakeups: u64
+}
+
+impl Runtime {
+    pub fn metrics(&self) -> Metrics {
+        let counters = &self.counters;
+        let queued = self.incoming.borrow().len() + self.remote.len();
+
+        Metrics {
+            alive_tasks: self.pool.len() + queued,
+            queued_tasks: queued,
+            polls: counters.polls.get(),
+            polls_per_tick: counters.last_tick_polls.get(),
+            max_poll_duration: counters.max_poll.get(),
+            ticks: counters.ticks.get(),
+            wakeups: counters.wakeups.get()
+        }
+    }
+}
+
+impl Counters {
+    /// Count the polls of tasks on this thread into `self` until the guard is dropped.
+    pub(super) fn enter(self: &Rc<Self>) -> impl Drop {
+        struct Reset(Option<Rc<Counters>>);
+
+        impl Drop for Reset {
+            fn drop(&mut self) {
+                let _ = CURRENT.try_with(|c| c.replace(self.0.take()));
+            }
+        }
+
+        Reset(CURRENT.with(|c| c.replace(Some(self.clone()))))
+    }
+
+    pub(super) fn tick(&self) {
+        self.ticks.set(self.ticks.get() + 1);
+        self.last_tick_polls.set(self.tick_polls.replace(0));
+    }
+
+    pub(super) fn wakeup(&self) {
+        self.wakeups.set(self.wakeups.get() + 1);
+    }
+}
+
+/// Poll a task, and count it into the Runtime of this thread.
+pub(super) fn poll<R>(f: impl FnOnce() -> R) -> R {
+    let start = Instant::now();
+    let ret = f();
+    let elapsed = start.elapsed();
+
+    let _ = CURRENT.try_with(|c| {
+        if let Some(counters) = c.borrow().as_ref() {
+            counters.polls.set(counters.polls.get() + 1);
+            counters.tick_polls.set(counters.tick_polls.get() + 1);
+
+            if elapsed > counters.max_poll.get() {
+                counters.max_poll.set(elapsed);
+            }
+        }
+    });
+
+    ret
+}
+
+
+#[test]
+fn test_metrics() {
+    let mut runtime = Runtime::new().unwrap();
+
+    for _ in 0..3 {
+        runtime.spawn_local(async {
+            crate::task::yield_now().await;
+        });
+    }
+
+    let metrics = runtime.metrics();
+    assert_eq!(metrics.alive_tasks, 3);
+    assert_eq!(metrics.queued_tasks, 3);
+
+    runtime.run();
+
+    let metrics = runtime.metrics();
+    assert_eq!(metrics.alive_tasks, 0);
+    assert_eq!(metrics.polls, 6);
+    assert!(metrics.ticks >= 1);
+}
@@ -0,0 +1,420 @@
+//! A pool of worker threads, each driving its own Proactor.
+//!
+//! A task can be taken by any worker until it's polled for the first time,
+//! from then on it stays on that worker, so its ops are submitted to,
+//! completed by and cancelled on the same ring.
+
+use std::{ io, thread };
+use std::pin::Pin;
+use std::rc::Rc;
+use std::cell::Cell;
+use std::future::Future;
+use std::collections::VecDeque;
+use std::task::{ Context, Poll };
+use std::sync::{ Arc, Weak, Mutex };
+use std::sync::atomic::{ AtomicBool, AtomicU64, AtomicUsize, Ordering };
+use futures_task::{ ArcWake, Waker };
+use futures_util::future::FutureExt;
+use futures_util::stream::FuturesUnordered;
+use crate::{ task, Proactor };
+use super::{ JoinHandle, Runtime, Spawner };
+
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+const UNSTARTED: usize = usize::MAX;
+
+// `Shared::budget` of an unlimited budget
+const UNLIMITED: u64 = u64::MAX;
+
+// tasks polled between two reaps of the ring
+const BATCH: usize = 32;
+
+thread_local!{
+    // the pool and the index of the worker running on this thread
+    static WORKER: Cell<(*const Shared, usize)> = const { Cell::new((std::ptr::null(), UNSTARTED)) };
+}
+
+/// A multi-threaded task pool for `Send` futures, see [Runtime::new_multi_thread].
+///
+/// Dropping it stops the workers, the tasks still running are dropped.
+pub struct MultiThread {
+    shared: Arc<Shared>,
+    threads: Vec<thread::JoinHandle<()>>
+}
+
+/// Spawns tasks into a [MultiThread] from any thread.
+#[derive(Clone)]
+pub struct SendSpawner {
+    shared: Weak<Shared>
+}
+
+struct Shared {
+    // tasks spawned from outside of the workers
+    injector: Mutex<VecDeque<Arc<Task>>>,
+    workers: Vec<Worker>,
+    next: AtomicUsize,
+    budget: AtomicU64,
+    shutdown: AtomicBool
+}
+
+struct Worker {
+    // unstarted tasks spawned on this worker, other workers may steal them
+    fresh: Mutex<VecDeque<Arc<Task>>>,
+    // woken tasks that live on this worker
+    pinned: Mutex<VecDeque<Arc<Task>>>,
+    waker: Mutex<Option<Waker>>
+}
+
+struct Task {
+    future: Mutex<Option<BoxFuture>>,
+    // the worker the task lives on, `UNSTARTED` until the first poll
+    home: AtomicUsize,
+    scheduled: AtomicBool,
+    shared: Weak<Shared>
+}
+
+impl Runtime {
+    /// Start `n` worker threads, each with its own Proactor.
+    pub fn new_multi_thread(n: usize) -> io::Result<MultiThread> {
+        MultiThread::new(n)
+    }
+}
+
+impl MultiThread {
+    pub fn new(n: usize) -> io::Result<MultiThread> {
+        let n = n.max(1);
+        let workers = (0..n)
+            .map(|_| Worker {
+                fresh: Mutex::new(VecDeque::new()),
+                pinned: Mutex::new(VecDeque::new()),
+                waker: Mutex::new(None)
+            })
+            .collect();
+        let shared = Arc::new(Shared {
+            injector: Mutex::new(VecDeque::new()),
+            workers,
+            next: AtomicUsize::new(0),
+            budget: AtomicU64::new(UNLIMITED),
+            shutdown: AtomicBool::new(false)
+        });
+
+        let mut pool = MultiThread { shared, threads: Vec::with_capacity(n) };
+        let (tx, rx) = std::sync::mpsc::channel();
+
+        for index in 0..n {
+            let shared = pool.shared.clone();
+            let tx = tx.clone();
+
+            let thread = thread::Builder::new()
+                .name(format!("ritsu-worker-{}", index))
+                .spawn(move || match Proactor::new() {
+                    Ok(proactor) => {
+                        let _ = tx.send(Ok(()));
+                        run_worker(shared, index, proactor);
+                    },
+                    Err(err) => {
+                        let _ = tx.send(Err(err));
+                    }
+                })?;
+
+            pool.threads.push(thread);
+        }
+
+        for _ in 0..n {
+            rx.recv().map_err(io::Error::other)??;
+        }
+
+        Ok(pool)
+    }
+
+    /// Let a task take at most `budget` completions in one poll, `None` is unlimited,
+    /// see [Runtime::set_budget].
+    pub fn set_budget(&self, budget: Option<u32>) {
+        let budget = budget.map(u64::from).unwrap_or(UNLIMITED);
+        self.shared.budget.store(budget, Ordering::Relaxed);
+    }
+
+    pub fn spawner(&self) -> SendSpawner {
+        SendSpawner { shared: Arc::downgrade(&self.shared) }
+    }
+
+    #[inline]
+    pub fn spawn<F>(&self, fut: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static
+    {
+        self.spawner().spawn(fut)
+    }
+
+    /// Run `fut` on the pool, and block the current thread until it completes.
+    pub fn block_on<F>(&self, fut: F) -> F::Output
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static
+    {
+        struct Unpark(thread::Thread);
+
+        impl ArcWake for Unpark {
+            fn wake_by_ref(arc_self: &Arc<Self>) {
+                arc_self.0.unpark();
+            }
+        }
+
+        let mut handle = self.spawn(fut);
+        let waker = futures_task::waker(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+
+        loop {
+            match handle.poll_unpin(&mut cx) {
+                Poll::Ready(ret) => return ret.expect("ritsu worker stopped"),
+                Poll::Pending => thread::park()
+            }
+        }
+    }
+}
+
+impl Drop for MultiThread {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::Release);
+
+        for worker in &self.shared.workers {
+            worker.wake();
+        }
+
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl SendSpawner {
+    /// Spawn `fut` into the pool.
+    ///
+    /// From a worker, the task is queued on that worker first.
+    /// If the pool is gone already, the returned handle completes with [super::JoinError].
+    pub fn spawn<F>(&self, fut: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static
+    {
+        let (fut, handle) = super::joinable(fut);
+
+        if let Some(shared) = self.shared.upgrade() {
+            let task = Arc::new(Task {
+                future: Mutex::new(Some(Box::pin(fut))),
+                home: AtomicUsize::new(UNSTARTED),
+                scheduled: AtomicBool::new(true),
+                shared: Arc::downgrade(&shared)
+            });
+
+            shared.push_fresh(task);
+        }
+
+        handle
+    }
+}
+
+impl Shared {
+    fn push_fresh(&self, task: Arc<Task>) {
+        let (pool, index) = WORKER.with(Cell::get);
+        let worker = if std::ptr::eq(pool, self) {
+            self.workers.get(index)
+        } else {
+            None
+        };
+
+        match worker {
+            // the worker is running, it finds the task before it parks.
+            Some(worker) => worker.fresh.lock().unwrap().push_back(task),
+            None => {
+                self.injector.lock().unwrap().push_back(task);
+
+                let index = self.next.fetch_add(1, Ordering::Relaxed) % self.workers.len();
+                self.workers[index].wake();
+            }
+        }
+    }
+
+    fn budget(&self) -> Option<u32> {
+        match self.budget.load(Ordering::Relaxed) {
+            UNLIMITED => None,
+            budget => Some(budget as u32)
+        }
+    }
+
+    fn has_work(&self, index: usize) -> bool {
+        let worker = &self.workers[index];
+
+        !worker.pinned.lock().unwrap().is_empty()
+            || !worker.fresh.lock().unwrap().is_empty()
+            || !self.injector.lock().unwrap().is_empty()
+    }
+
+    fn next_task(&self, index: usize) -> Option<Arc<Task>> {
+        let worker = &self.workers[index];
+
+        if let Some(task) = worker.pinned.lock().unwrap().pop_front() {
+            return Some(task);
+        }
+
+        if let Some(task) = worker.fresh.lock().unwrap().pop_front() {
+            return Some(task);
+        }
+
+        if let Some(task) = self.injector.lock().unwrap().pop_front() {
+            return Some(task);
+        }
+
+        // steal from the other end, the victim runs its newest tasks last anyway
+        (1..self.workers.len())
+            .map(|i| &self.workers[(index + i) % self.workers.len()])
+            .find_map(|victim| victim.fresh.lock().unwrap().pop_back())
+    }
+}
+
+impl Worker {
+    fn wake(&self) {
+        if let Some(waker) = self.waker.lock().unwrap().as_ref() {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+impl ArcWake for Task {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        if arc_self.scheduled.swap(true, Ordering::AcqRel) {
+            return
+        }
+
+        let shared = match arc_self.shared.upgrade() {
+            Some(shared) => shared,
+            None => return
+        };
+
+        match shared.workers.get(arc_self.home.load(Ordering::Acquire)) {
+            Some(worker) => {
+                worker.pinned.lock().unwrap().push_back(arc_self.clone());
+                worker.wake();
+            },
+            None => shared.push_fresh(arc_self.clone())
+        }
+    }
+}
+
+fn run_worker(shared: Arc<Shared>, index: usize, mut proactor: Proactor) {
+    WORKER.with(|w| w.set((Arc::as_ptr(&shared), index)));
+    *shared.workers[index].waker.lock().unwrap() = Some(proactor.waker());
+
+    // `!Send` tasks spawned from the tasks of this worker
+    let incoming = Rc::new(super::Incoming::default());
+    let mut local = FuturesUnordered::new();
+
+    let spawner = Spawner { incoming: Rc::downgrade(&incoming) };
+    let budget = shared.budget();
+    let enter = super::enter(proactor.raw_handle(), spawner, budget);
+
+    while !shared.shutdown.load(Ordering::Acquire) {
+        task::set_limit(shared.budget());
+
+        let mut polled = 0;
+
+        while polled < BATCH {
+            let task = match shared.next_task(index) {
+                Some(task) => task,
+                None => break
+            };
+            polled += 1;
+
+            task.home.store(index, Ordering::Release);
+            task.scheduled.store(false, Ordering::Release);
+
+            let waker = futures_task::waker_ref(&task);
+            let mut cx = Context::from_waker(&waker);
+            let mut future = task.future.lock().unwrap();
+
+            #[cfg(feature = "tracing")]
+            let _span = tracing::trace_span!("poll", worker = index).entered();
+
+            if let Some(fut) = future.as_mut() {
+                if task::budget(|| fut.as_mut().poll(&mut cx)).is_ready() {
+                    *future = None;
+                }
+            }
+        }
+
+        {
+            let waker = proactor.waker_ref();
+            let mut cx = Context::from_waker(&waker);
+            let _ = super::poll_pool(&mut local, &incoming, &mut cx);
+        }
+
+        // tasks may be left behind by a full batch, or queued by the local tasks,
+        // so only reap, don't wait.
+        if polled == BATCH || shared.has_work(index) {
+            proactor.try_park().expect("Proactor park failed");
+        } else {
+            proactor.park(None).expect("Proactor park failed");
+        }
+    }
+
+    *shared.workers[index].waker.lock().unwrap() = None;
+
+    // completions delivered by the shutdown wake more tasks,
+    // they are dropped along with the pool.
+    drop(local);
+    drop(enter);
+    drop(proactor);
+
+    let tasks = std::mem::take(&mut *shared.workers[index].pinned.lock().unwrap());
+    for task in tasks {
+        task.future.lock().unwrap().take();
+    }
+
+    WORKER.with(|w| w.set((std::ptr::null(), UNSTARTED)));
+}
+
+#[test]
+fn test_multi_thread() {
+    use io_uring::opcode;
+
+    let pool = Runtime::new_multi_thread(4).unwrap();
+    let spawner = pool.spawner();
+
+    let sum = pool.block_on(async move {
+        let handles = (0..64)
+            .map(|i| spawner.spawn(async move {
+                unsafe { crate::handle::push(opcode::Nop::new().build()).unwrap().await };
+                i
+            }))
+            .collect::<Vec<_>>();
+
+        let mut sum = 0;
+        for handle in handles {
+            sum += handle.await.unwrap();
+        }
+        sum
+    });
+
+    assert_eq!(sum, (0..64).sum::<i32>());
+}
+
+#[test]
+fn test_multi_thread_worker() {
+    use std::time::Duration;
+    use crate::time;
+
+    let pool = Runtime::new_multi_thread(2).unwrap();
+    pool.set_budget(Some(4));
+
+    // a worker runs `!Send` tasks next to the pool, with the timer wheel of its ring
+    let ret = pool.block_on(async {
+        super::spawn_local(async {
+            time::delay(Duration::from_millis(5)).await;
+            time::current().is_some()
+        }).await.unwrap()
+    });
+
+    assert!(ret);
+}
@@ -0,0 +1,144 @@
+//! Submit work to a [Runtime] from other threads.
+//!
+//! Futures and entries are queued behind a mutex, and the Proactor is woken
+//! through its eventfd, the Runtime picks them up on its next turn.
+
+use std::{ io, mem, ptr };
+use std::pin::Pin;
+use std::future::Future;
+use std::sync::{ Arc, Weak, Mutex };
+use futures_task::{ LocalFutureObj, Waker };
+use crate::{ sys, Ticket, TicketFuture, RawHandle, SubmissionEntry };
+use super::{ JoinHandle, Budgeted, Incoming, Runtime };
+
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+pub(super) struct Remote {
+    queue: Mutex<Vec<Submission>>,
+    waker: Waker
+}
+
+enum Submission {
+    Task(BoxFuture),
+    Entry(SubmissionEntry)
+}
+
+/// A thread-safe handle to a [Runtime], see [Runtime::remote_handle].
+///
+/// Whatever it submits runs on the thread of the Runtime,
+/// once that thread runs the Runtime again.
+#[derive(Clone)]
+pub struct RemoteHandle {
+    remote: Weak<Remote>
+}
+
+impl Runtime {
+    pub fn remote_handle(&self) -> RemoteHandle {
+        RemoteHandle { remote: Arc::downgrade(&self.remote) }
+    }
+}
+
+impl Remote {
+    pub(super) fn new(waker: Waker) -> Remote {
+        Remote { queue: Mutex::new(Vec::new()), waker }
+    }
+
+    /// The number of submissions waiting for the Runtime.
+    pub(super) fn len(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+
+    /// Move the submissions into the Runtime.
+    pub(super) fn drain(&self, incoming: &Incoming, handle: &RawHandle) {
+        let queue = mem::take(&mut *self.queue.lock().unwrap());
+
+        for submission in queue {
+            match submission {
+                Submission::Task(fut) => {
+                    let task = Budgeted { fut };
+                    incoming.borrow_mut().push(LocalFutureObj::from(Box::pin(task)));
+                },
+                Submission::Entry(entry) => unsafe {
+                    let user_data = sys::sqe(&entry).user_data;
+
+                    if let Err(err) = handle.raw_push(entry) {
+                        let res = -err.raw_os_error().unwrap_or(libc::EIO);
+                        let ticket = ptr::NonNull::new_unchecked(user_data as *mut Ticket);
+                        Ticket::from_raw(ticket).send(sys::completion(user_data, res, 0));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl RemoteHandle {
+    /// Spawn `fut` on the thread of the Runtime.
+    ///
+    /// If the Runtime is gone already, the returned handle completes with [super::JoinError].
+    pub fn spawn<F>(&self, fut: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static
+    {
+        let (task, handle) = super::joinable(fut);
+
+        // the task is dropped here if the Runtime is gone
+        let _ = self.submit(Submission::Task(Box::pin(task)));
+
+        handle
+    }
+
+    /// Submit `entry` from the thread of the Runtime.
+    ///
+    /// If the submission fails there, the future completes with its error code.
+    ///
+    /// # Safety
+    ///
+    /// The resources referenced by `entry` must stay valid until the returned future completes.
+    pub unsafe fn push(&self, entry: SubmissionEntry) -> io::Result<TicketFuture> {
+        let (ticket, fut) = Ticket::new();
+        let entry = ticket.register(entry);
+
+        if let Err(Submission::Entry(entry)) = self.submit(Submission::Entry(entry)) {
+            let ptr = sys::sqe(&entry).user_data as *mut Ticket;
+            drop(Ticket::from_raw(ptr::NonNull::new_unchecked(ptr)));
+            return Err(io::Error::other("ritsu runtime closed"));
+        }
+
+        Ok(fut)
+    }
+
+    fn submit(&self, submission: Submission) -> Result<(), Submission> {
+        match self.remote.upgrade() {
+            Some(remote) => {
+                remote.queue.lock().unwrap().push(submission);
+                remote.waker.wake_by_ref();
+                Ok(())
+            },
+            None => Err(submission)
+        }
+    }
+}
+
+
+#[test]
+fn test_remote_handle() {
+    use io_uring::opcode;
+
+    let mut runtime = Runtime::new().unwrap();
+    let remote = runtime.remote_handle();
+
+    let t = std::thread::spawn(move || {
+        let task = remote.spawn(async { 7 });
+        let nop = unsafe { remote.push(opcode::Nop::new().build()).unwrap() };
+        (task, nop)
+    });
+
+    let (task, nop) = t.join().unwrap();
+    let (task, nop) = runtime.run_until(async { (task.await, nop.await) });
+
+    assert_eq!(task.unwrap(), 7);
+    assert_eq!(nop.result(), 0);
+}
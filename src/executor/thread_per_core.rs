@@ -0,0 +1,121 @@
+//! One pinned single-threaded [Runtime] per CPU.
+//!
+//! Nothing is shared between the threads, each one accepts and serves
+//! its own connections, usually from a listener bound with
+//! [TcpListener::bind_reuseport](crate::action::tcp::TcpListener::bind_reuseport)
+//! so the kernel spreads connections across them,
+//! or [ReusePort](crate::action::tcp::ReusePort) that also keeps them on the CPU they came in on.
+
+use std::{ io, mem, thread };
+use std::sync::Arc;
+use std::future::Future;
+use super::Runtime;
+
+
+/// The CPU a [thread_per_core] thread runs on.
+#[derive(Clone, Copy, Debug)]
+pub struct Core {
+    index: usize,
+    cpu: usize
+}
+
+impl Core {
+    /// The index of the thread, from `0` to the number of threads.
+    #[inline]
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The CPU the thread is pinned to.
+    #[inline]
+    pub fn cpu(&self) -> usize {
+        self.cpu
+    }
+}
+
+/// Start one thread per CPU this process may run on, pin it there,
+/// and run the future returned by `f` on a new Runtime of that thread.
+///
+/// ```no_run
+/// use std::net::SocketAddr;
+/// use ritsu::action::tcp::TcpListener;
+/// use ritsu::executor::{ thread_per_core, spawn_local };
+///
+/// let addr: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+/// let threads = thread_per_core(move |_core| async move {
+///     let mut listener = TcpListener::bind_reuseport(addr)?;
+///
+///     loop {
+///         let (stream, _) = listener.accept().await?;
+///         spawn_local(async move { drop(stream) });
+///     }
+/// }).unwrap();
+///
+/// for thread in threads {
+///     let _: std::io::Result<()> = thread.join().unwrap();
+/// }
+/// ```
+pub fn thread_per_core<F, Fut, T>(f: F) -> io::Result<Vec<thread::JoinHandle<io::Result<T>>>>
+where
+    F: Fn(Core) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = io::Result<T>> + 'static,
+    T: Send + 'static
+{
+    let f = Arc::new(f);
+
+    available_cpus()?
+        .into_iter()
+        .enumerate()
+        .map(|(index, cpu)| {
+            let f = f.clone();
+            let core = Core { index, cpu };
+
+            thread::Builder::new()
+                .name(format!("ritsu-core-{}", cpu))
+                .spawn(move || {
+                    pin_to(cpu)?;
+                    Runtime::new()?.run_until(f(core))
+                })
+        })
+        .collect()
+}
+
+fn available_cpus() -> io::Result<Vec<usize>> {
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+
+        if libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok((0..libc::CPU_SETSIZE as usize)
+            .filter(|&cpu| libc::CPU_ISSET(cpu, &set))
+            .collect())
+    }
+}
+
+fn pin_to(cpu: usize) -> io::Result<()> {
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+
+        if libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+
+#[test]
+fn test_thread_per_core() {
+    let threads = thread_per_core(|core| async move { Ok(core) }).unwrap();
+    let n = threads.len();
+
+    assert_eq!(n, available_cpus().unwrap().len());
+
+    for (i, thread) in threads.into_iter().enumerate() {
+        assert_eq!(thread.join().unwrap().unwrap().index(), i);
+    }
+}
@@ -1,40 +1,388 @@
 use std::{ io, mem };
-use std::cell::RefCell;
+use std::pin::Pin;
+use std::future::Future;
+use std::task::{ Context, Poll };
+use std::cell::{ Cell, RefCell };
+use bitflags::bitflags;
+use pin_project_lite::pin_project;
+use io_uring::{ opcode, squeue };
 use crate::sync::{ Ticket, TicketFuture };
 use crate::action::{ Handle, HandleVTable };
-use crate::{ RawHandle, SubmissionEntry };
+use crate::error::errno;
+use crate::{ cancel, sys, RawHandle, SubmissionEntry };
 
 
 thread_local!{
-    static HANDLE: RefCell<Option<Handle>> = RefCell::new(None);
+    static HANDLE: RefCell<Option<Handle>> = const { RefCell::new(None) };
+    static PERSONALITY: Cell<u16> = const { Cell::new(0) };
+    static SQE_FLAGS: Cell<u8> = const { Cell::new(0) };
 }
 
+/// Credentials registered with `IORING_REGISTER_PERSONALITY`.
+///
+/// See [RawHandle::register_personality].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Personality(pub(crate) u16);
+
+impl Personality {
+    #[inline]
+    pub fn id(self) -> u16 {
+        self.0
+    }
+
+    /// Issue `entry` with these credentials.
+    #[inline]
+    pub fn apply(self, mut entry: SubmissionEntry) -> SubmissionEntry {
+        sys::sqe_mut(&mut entry).personality = self.0;
+        entry
+    }
+}
+
+/// The I/O scheduling class and level of an op, see `ioprio_set(2)`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoPriority {
+    /// Served before everything else, level `0..=7`, `0` is the highest.
+    RealTime(u8),
+    /// The default class, level `0..=7`, `0` is the highest.
+    BestEffort(u8),
+    /// Only served when nothing else wants the disk.
+    Idle
+}
+
+impl IoPriority {
+    /// The `ioprio` value of the SQE.
+    pub fn to_raw(self) -> u16 {
+        const CLASS_SHIFT: u16 = 13;
+
+        match self {
+            IoPriority::RealTime(level) => (1 << CLASS_SHIFT) | u16::from(level.min(7)),
+            IoPriority::BestEffort(level) => (2 << CLASS_SHIFT) | u16::from(level.min(7)),
+            IoPriority::Idle => 3 << CLASS_SHIFT
+        }
+    }
+
+    #[inline]
+    pub fn apply(self, mut entry: SubmissionEntry) -> SubmissionEntry {
+        sys::sqe_mut(&mut entry).ioprio = self.to_raw();
+        entry
+    }
+}
+
+#[inline]
+pub(crate) fn ioprio(prio: Option<IoPriority>, entry: SubmissionEntry) -> SubmissionEntry {
+    match prio {
+        Some(prio) => prio.apply(entry),
+        None => entry
+    }
+}
+
+pin_project!{
+    pub struct WithPersonality<F> {
+        personality: Personality,
+        #[pin]
+        fut: F
+    }
+}
+
+/// Every op submitted while polling `fut` is issued with `personality`,
+/// unless it already carries one.
+pub fn with_personality<F: Future>(personality: Personality, fut: F) -> WithPersonality<F> {
+    WithPersonality { personality, fut }
+}
+
+impl<F: Future> Future for WithPersonality<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        let prev = PERSONALITY.with(|p| p.replace(this.personality.0));
+        let ret = this.fut.poll(cx);
+        PERSONALITY.with(|p| p.set(prev));
+
+        ret
+    }
+}
+
+bitflags!{
+    /// The SQE flags an op can be issued with on its own.
+    ///
+    /// The link flags aren't here, chains are submitted with [push_linked].
+    pub struct SqeFlags: u8 {
+        /// Start the op only after every op submitted before it has completed,
+        /// ops submitted after it wait for it too. It stalls the whole ring.
+        ///
+        /// The ring holds the ops back itself rather than the kernel,
+        /// so multishot ops, which may never complete, aren't waited for.
+        const IO_DRAIN = squeue::Flags::IO_DRAIN.bits();
+        /// Issue the op from a worker thread, instead of trying it inline first.
+        const ASYNC = squeue::Flags::ASYNC.bits();
+    }
+}
+
+impl SqeFlags {
+    #[inline]
+    pub fn apply(self, mut entry: SubmissionEntry) -> SubmissionEntry {
+        sys::sqe_mut(&mut entry).flags |= self.bits();
+        entry
+    }
+}
+
+pin_project!{
+    pub struct WithSqeFlags<F> {
+        flags: SqeFlags,
+        #[pin]
+        fut: F
+    }
+}
+
+/// Every op submitted while polling `fut` also gets `flags`,
+/// for the actions that don't take them one op at a time
+/// like [Submission::flags](crate::action::op::Submission::flags) does.
+///
+/// With [SqeFlags::IO_DRAIN], an op only starts after every op submitted
+/// before it has completed, and ops submitted after it wait for it too.
+/// This orders e.g. an fsync after all previous writes of the ring
+/// without linking them. Every op of `fut` is drained, so keep it to one op.
+///
+/// ```no_run
+/// # async fn f(file: ritsu::action::fs::File) -> std::io::Result<()> {
+/// use ritsu::handle::{ with_sqe_flags, SqeFlags };
+///
+/// with_sqe_flags(SqeFlags::IO_DRAIN, file.sync_data()).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn with_sqe_flags<F: Future>(flags: SqeFlags, fut: F) -> WithSqeFlags<F> {
+    WithSqeFlags { flags, fut }
+}
+
+impl<F: Future> Future for WithSqeFlags<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        let prev = SQE_FLAGS.with(|p| p.replace(p.get() | this.flags.bits()));
+        let ret = this.fut.poll(cx);
+        SQE_FLAGS.with(|p| p.set(prev));
+
+        ret
+    }
+}
+
+/// # Safety
+///
+/// Replaces the handle used by every action on this thread.
 pub unsafe fn set(handle: Handle) {
     HANDLE.with(|h| {
         h.borrow_mut().replace(handle);
     });
 }
 
+/// # Safety
+///
+/// The resources referenced by `entry` must stay valid until the returned future completes.
 pub unsafe fn push(entry: SubmissionEntry) -> io::Result<TicketFuture> {
-    HANDLE.with(|h| Some(h.borrow().as_ref()?.push(entry)))
+    let entry = inherit(entry);
+    let group = cancel::current();
+
+    if group.as_ref().is_some_and(|group| group.is_cancelled()) {
+        return Err(cancel::cancelled());
+    }
+
+    let mut fut = HANDLE.with(|h| Some(h.borrow().as_ref()?.push(entry)))
+        .expect("not found ritsu runtime")?;
+
+    if let Some(group) = group {
+        fut.link(group);
+    }
+
+    Ok(fut)
+}
+
+/// Submit `entries` as one linked chain, see [Handle::push_linked].
+///
+/// # Safety
+///
+/// The resources referenced by every entry must stay valid until its future completes.
+pub unsafe fn push_linked(entries: &[SubmissionEntry]) -> io::Result<Vec<TicketFuture>> {
+    let entries = entries.iter()
+        .cloned()
+        .map(inherit)
+        .collect::<Vec<_>>();
+
+    let group = cancel::current();
+
+    if group.as_ref().is_some_and(|group| group.is_cancelled()) {
+        return Err(cancel::cancelled());
+    }
+
+    let mut futs = HANDLE.with(|h| Some(h.borrow().as_ref()?.push_linked(&entries)))
+        .expect("not found ritsu runtime")?;
+
+    if let Some(group) = group {
+        for fut in futs.iter_mut() {
+            fut.link(group.clone());
+        }
+    }
+
+    Ok(futs)
+}
+
+/// Submit `entry` and forget about it, see [Handle::push_detached].
+///
+/// # Safety
+///
+/// The resources referenced by `entry` must stay valid until the kernel is done with it.
+pub unsafe fn push_detached(entry: SubmissionEntry) -> io::Result<()> {
+    let entry = inherit(entry);
+
+    HANDLE.with(|h| Some(h.borrow().as_ref()?.push_detached(entry)))
         .expect("not found ritsu runtime")
 }
 
+/// Like [push_detached], but fail rather than panic without a handle on this thread,
+/// for destructors.
+///
+/// # Safety
+///
+/// Same as [push_detached].
+pub(crate) unsafe fn try_push_detached(entry: SubmissionEntry) -> io::Result<()> {
+    let entry = inherit(entry);
+
+    HANDLE.try_with(|h| {
+        let h = h.try_borrow().ok()?;
+        Some(h.as_ref()?.push_detached(entry))
+    })
+        .ok()
+        .flatten()
+        .unwrap_or_else(|| Err(io::Error::other("not found ritsu runtime")))
+}
+
+/// Ready once an op held back by [Handle::push] can be submitted.
+pub(crate) fn poll_ready(cx: &mut Context<'_>) -> Poll<()> {
+    HANDLE.with(|h| Some(h.borrow().as_ref()?.poll_ready(cx)))
+        .expect("not found ritsu runtime")
+}
+
+/// Submit an op held back by [Handle::push], see [Handle::push_registered].
+///
+/// # Safety
+///
+/// Same as [Handle::push_registered].
+pub(crate) unsafe fn push_registered(entry: SubmissionEntry) {
+    HANDLE.with(|h| h.borrow().as_ref()
+        .expect("not found ritsu runtime")
+        .push_registered(entry))
+}
+
+/// Cancel the op with `user_data` on the handle of this thread, if any.
+pub(crate) fn cancel(user_data: u64) {
+    let _ = HANDLE.try_with(|h| {
+        if let Some(handle) = h.try_borrow().ok().as_ref().and_then(|h| h.as_ref()) {
+            handle.cancel(user_data);
+        }
+    });
+}
+
+fn inherit(mut entry: SubmissionEntry) -> SubmissionEntry {
+    let personality = PERSONALITY.with(Cell::get);
+    let flags = SQE_FLAGS.with(Cell::get);
+    let sqe = sys::sqe_mut(&mut entry);
+
+    if personality != 0 && sqe.personality == 0 {
+        sqe.personality = personality;
+    }
+
+    sqe.flags |= flags;
+
+    entry
+}
+
 
 pub fn default_handle(raw_handle: RawHandle) -> Handle {
     static VTABLE: HandleVTable = HandleVTable {
-        push, clone, drop
+        push, push_linked, push_detached, poll_ready, push_registered, cancel, clone, drop
     };
 
     unsafe fn push(ptr: *const (), entry: SubmissionEntry) -> io::Result<TicketFuture> {
         let handle = RawHandle::from_raw(ptr as *const _);
 
         let (ticket, fut) = Ticket::new();
+        let entry = ticket.register(entry);
+
+        // over `Builder::max_inflight`, the future submits it once a slot frees up
+        if !handle.has_slot(&entry) {
+            mem::forget(handle);
+            return Ok(fut.hold(entry));
+        }
+
+        let ret = handle.raw_push(entry.clone());
+        mem::forget(handle);
+
+        if ret.is_err() {
+            let _ = Ticket::unregister(&entry);
+        }
+
+        ret.map(|()| fut)
+    }
+
+    unsafe fn push_linked(ptr: *const (), entries: &[SubmissionEntry]) -> io::Result<Vec<TicketFuture>> {
+        let handle = RawHandle::from_raw(ptr as *const _);
+
+        let mut futs = Vec::with_capacity(entries.len());
+        let entries = entries.iter()
+            .map(|entry| {
+                let (ticket, fut) = Ticket::new();
+                futs.push(fut);
+                ticket.register(entry.clone())
+            })
+            .collect::<Vec<_>>();
+
+        let ret = handle.raw_push_linked(&entries);
+        mem::forget(handle);
 
-        handle.raw_push(ticket.register(entry))?;
+        if ret.is_err() {
+            for entry in &entries {
+                let _ = Ticket::unregister(entry);
+            }
+        }
+
+        ret.map(|()| futs)
+    }
+
+    unsafe fn push_detached(ptr: *const (), entry: SubmissionEntry) -> io::Result<()> {
+        let handle = RawHandle::from_raw(ptr as *const _);
+        let ret = handle.raw_push_detached(entry);
+        mem::forget(handle);
+        ret
+    }
+
+    unsafe fn poll_ready(ptr: *const (), cx: &mut Context<'_>) -> Poll<()> {
+        let handle = RawHandle::from_raw(ptr as *const _);
+        let ret = handle.poll_ready(cx);
+        mem::forget(handle);
+        ret
+    }
+
+    unsafe fn push_registered(ptr: *const (), entry: SubmissionEntry) {
+        let handle = RawHandle::from_raw(ptr as *const _);
+
+        if let Err(err) = handle.raw_push(entry.clone()) {
+            Ticket::unregister(&entry).complete(-errno(&err));
+        }
+
+        mem::forget(handle);
+    }
+
+    unsafe fn cancel(ptr: *const (), user_data: u64) {
+        let handle = RawHandle::from_raw(ptr as *const _);
+
+        let entry = opcode::AsyncCancel::new(user_data).build();
+        let _ = handle.raw_push_detached(entry);
 
         mem::forget(handle);
-        Ok(fut)
     }
 
     unsafe fn clone(ptr: *const ()) -> Handle {
@@ -46,10 +394,52 @@ pub fn default_handle(raw_handle: RawHandle) -> Handle {
     }
 
     unsafe fn drop(ptr: *const ()) {
-        RawHandle::from_raw(ptr as *const _);
+        let _ = RawHandle::from_raw(ptr as *const _);
     }
 
     unsafe {
         Handle::new(raw_handle.into_raw() as *const (), &VTABLE)
     }
 }
+
+
+#[test]
+fn test_personality() {
+    use std::time::Duration;
+    use futures_util::FutureExt;
+    use crate::Proactor;
+
+    let mut proactor = Proactor::new().unwrap();
+    let personality = proactor.raw_handle().register_personality().unwrap();
+
+    unsafe {
+        set(default_handle(proactor.raw_handle()));
+    }
+
+    let mut fut = Box::pin(with_personality(personality, async {
+        let entry = inherit(opcode::Nop::new().build());
+        assert_eq!(sys::sqe(&entry).personality, personality.id());
+
+        let nop = unsafe { push(opcode::Nop::new().build()).unwrap().await };
+
+        // an id that was never registered
+        let entry = Personality(personality.id().wrapping_add(1)).apply(opcode::Nop::new().build());
+        let unknown = unsafe { push(entry).unwrap().await };
+
+        (nop.result(), unknown.result())
+    }));
+
+    let waker = futures_util::task::noop_waker();
+    let mut cx = Context::from_waker(&waker);
+
+    let ret = loop {
+        if let Poll::Ready(ret) = fut.poll_unpin(&mut cx) {
+            break ret;
+        }
+
+        proactor.park(Some(Duration::from_millis(10))).unwrap();
+    };
+    assert_eq!(ret, (0, -libc::EINVAL));
+
+    proactor.raw_handle().unregister_personality(personality).unwrap();
+}
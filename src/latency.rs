@@ -0,0 +1,245 @@
+//! Submit to complete latency of the ops of a Proactor, behind the `metrics` feature.
+//!
+//! Every op pushed with a ticket is timed from its push to the dispatch of its completion,
+//! so the time it waited in the backlog counts, and so do the ops emulated
+//! for an old kernel. Detached ops and multishot ops are not timed.
+//!
+//! ```
+//! # fn main() -> std::io::Result<()> {
+//! let proactor = ritsu::Proactor::new()?;
+//! // ... run ops on it
+//! for (opcode, histogram) in proactor.latency_snapshot().iter() {
+//!     println!("{}: p99 {:?}, p999 {:?}", opcode, histogram.quantile(0.99), histogram.quantile(0.999));
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+use std::convert::TryFrom;
+use std::cell::RefCell;
+use std::time::{ Duration, Instant };
+use std::collections::{ BTreeMap, HashMap };
+
+
+// sub-buckets per power of two, the error of a bucket is at most 1/16
+const SUB_BITS: u32 = 4;
+const SUB_COUNT: usize = 1 << SUB_BITS;
+const BUCKETS: usize = (64 - SUB_BITS as usize + 1) * SUB_COUNT;
+
+/// The distribution of the latencies of one opcode, in nanoseconds.
+///
+/// Buckets are log-linear like an HDR histogram, a value is reported
+/// as the highest value of its bucket, at most 1/16 above it.
+#[derive(Clone)]
+pub struct Histogram {
+    counts: Box<[u64]>,
+    count: u64,
+    sum: u64,
+    max: u64
+}
+
+/// The histograms of a Proactor by opcode, see [Proactor::latency_snapshot](crate::Proactor::latency_snapshot).
+#[derive(Clone, Debug, Default)]
+pub struct LatencySnapshot {
+    ops: BTreeMap<u8, Histogram>
+}
+
+#[derive(Default)]
+pub(crate) struct Recorder {
+    // the opcode and push time of the tickets in flight
+    started: RefCell<HashMap<u64, (u8, Instant)>>,
+    ops: RefCell<BTreeMap<u8, Histogram>>
+}
+
+impl Recorder {
+    #[inline]
+    pub(crate) fn start(&self, user_data: u64, opcode: u8) {
+        self.started.borrow_mut().insert(user_data, (opcode, Instant::now()));
+    }
+
+    /// Forget an op without timing it.
+    #[inline]
+    pub(crate) fn forget(&self, user_data: u64) {
+        self.started.borrow_mut().remove(&user_data);
+    }
+
+    pub(crate) fn complete(&self, user_data: u64) {
+        if let Some((opcode, start)) = self.started.borrow_mut().remove(&user_data) {
+            self.ops.borrow_mut()
+                .entry(opcode)
+                .or_default()
+                .record(start.elapsed());
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> LatencySnapshot {
+        LatencySnapshot { ops: self.ops.borrow().clone() }
+    }
+}
+
+impl Histogram {
+    pub fn new() -> Histogram {
+        Histogram {
+            counts: vec![0; BUCKETS].into_boxed_slice(),
+            count: 0,
+            sum: 0,
+            max: 0
+        }
+    }
+
+    pub fn record(&mut self, dur: Duration) {
+        let nanos = u64::try_from(dur.as_nanos()).unwrap_or(u64::MAX);
+
+        self.counts[index(nanos)] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(nanos);
+        self.max = self.max.max(nanos);
+    }
+
+    /// How many latencies were recorded.
+    #[inline]
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    #[inline]
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.max)
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::from_nanos(0),
+            count => Duration::from_nanos(self.sum / count)
+        }
+    }
+
+    /// The latency `q` of the ops completed within, like `0.99` for the p99.
+    pub fn quantile(&self, q: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::from_nanos(0);
+        }
+
+        let rank = ((q.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+
+        for (i, &n) in self.counts.iter().enumerate() {
+            seen += n;
+
+            if seen >= rank {
+                return Duration::from_nanos(highest(i).min(self.max));
+            }
+        }
+
+        self.max()
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram::new()
+    }
+}
+
+impl fmt::Debug for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Histogram")
+            .field("count", &self.count)
+            .field("mean", &self.mean())
+            .field("p50", &self.quantile(0.5))
+            .field("p99", &self.quantile(0.99))
+            .field("p999", &self.quantile(0.999))
+            .field("max", &self.max())
+            .finish()
+    }
+}
+
+impl LatencySnapshot {
+    /// The histogram of `opcode`, like `io_uring::opcode::Read::CODE`.
+    #[inline]
+    pub fn get(&self, opcode: u8) -> Option<&Histogram> {
+        self.ops.get(&opcode)
+    }
+
+    /// The opcodes that completed at least once, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (u8, &Histogram)> + '_ {
+        self.ops.iter().map(|(&opcode, histogram)| (opcode, histogram))
+    }
+}
+
+/// The bucket of `value`, values below `SUB_COUNT` have their own.
+#[inline]
+fn index(value: u64) -> usize {
+    if value < SUB_COUNT as u64 {
+        return value as usize;
+    }
+
+    let shift = 63 - value.leading_zeros() - SUB_BITS;
+    let sub = (value >> shift) as usize - SUB_COUNT;
+
+    (shift as usize + 1) * SUB_COUNT + sub
+}
+
+/// The highest value of the bucket `index`.
+#[inline]
+fn highest(index: usize) -> u64 {
+    if index < SUB_COUNT {
+        return index as u64;
+    }
+
+    let shift = (index / SUB_COUNT - 1) as u32;
+    let sub = (index % SUB_COUNT + SUB_COUNT) as u64;
+
+    (sub << shift) + ((1 << shift) - 1)
+}
+
+
+#[test]
+fn test_histogram() {
+    for &value in &[0, 1, 15, 16, 17, 1000, 123_456_789, u64::MAX] {
+        let i = index(value);
+        assert!(i < BUCKETS);
+        assert!(highest(i) >= value);
+        assert!(highest(i) - value <= value / SUB_COUNT as u64);
+    }
+
+    let mut histogram = Histogram::new();
+
+    for micros in 1..=1000 {
+        histogram.record(Duration::from_micros(micros));
+    }
+
+    assert_eq!(histogram.count(), 1000);
+    assert_eq!(histogram.max(), Duration::from_micros(1000));
+
+    let p99 = histogram.quantile(0.99);
+    assert!(p99 >= Duration::from_micros(990));
+    assert!(p99 <= Duration::from_micros(990) + Duration::from_micros(990) / 16);
+    assert_eq!(histogram.quantile(1.0), Duration::from_micros(1000));
+}
+
+#[test]
+fn test_latency_snapshot() {
+    use futures_util::future::FutureExt;
+    use io_uring::opcode;
+    use crate::{ handle, Proactor };
+
+    let mut proactor = Proactor::new().unwrap();
+
+    unsafe {
+        handle::set(handle::default_handle(proactor.raw_handle()));
+    }
+
+    let mut fut = unsafe { handle::push(opcode::Nop::new().build()).unwrap() };
+
+    while (&mut fut).now_or_never().is_none() {
+        proactor.park(Some(Duration::from_millis(10))).unwrap();
+    }
+
+    let snapshot = proactor.latency_snapshot();
+    let histogram = snapshot.get(opcode::Nop::CODE).unwrap();
+    assert_eq!(histogram.count(), 1);
+    assert!(histogram.quantile(0.99) <= histogram.max());
+    assert_eq!(snapshot.iter().count(), 1);
+}
@@ -1,27 +1,51 @@
 #[cfg(not(feature = "loom"))]
 mod loom;
 
+#[macro_use]
+mod trace;
+
+mod sys;
 mod waker;
-mod sync;
+mod blocking;
+mod epoll;
+mod cqe;
 
 #[macro_use]
 pub mod util;
 pub mod handle;
 pub mod action;
 pub mod executor;
+pub mod probe;
+pub mod restrict;
+pub mod cancel;
+pub mod task;
+pub mod time;
+pub mod sync;
+pub mod error;
+#[cfg(feature = "metrics")]
+pub mod latency;
 
 use std::{ io, ptr, mem };
 use std::sync::Arc;
-use std::cell::RefCell;
-use std::time::Duration;
-use std::os::unix::io::AsRawFd;
+use std::cell::{ Cell, RefCell, RefMut, OnceCell };
+use std::collections::{ HashMap, HashSet, VecDeque };
+use std::time::{ Duration, Instant };
+use std::task::{ Context, Poll };
+use std::os::unix::io::{ AsRawFd, RawFd };
 use std::rc::Rc;
-use futures_task::{ self as task, WakerRef, Waker };
+use futures_task::{ WakerRef, Waker };
 use static_assertions::const_assert_eq;
 use io_uring::opcode::{ self, types };
 use io_uring::{ squeue, cqueue, IoUring };
 use crate::waker::EventFd;
+use crate::probe::Probe;
+use crate::restrict::Restrictions;
+use crate::handle::Personality;
+use crate::sync::multishot;
 pub use crate::sync::{ Ticket, TicketFuture };
+pub use crate::waker::MsgRingWaker;
+pub use crate::error::Error;
+pub use crate::cqe::CqeResult;
 
 
 pub type SubmissionEntry = squeue::Entry;
@@ -29,36 +53,483 @@ pub type CompletionEntry = cqueue::Entry;
 
 const WAKE_TOKEN: u64 = 0x0;
 
+// the multishot poll on the eventfd, tickets are aligned so this never collides.
+const EVENTFD_TOKEN: u64 = 0x1;
+
+// the timeout that bounds a park, see `Proactor::rearm`.
+const TIMEOUT_TOKEN: u64 = 0x4;
+
 pub struct Proactor {
-    ring: Rc<RefCell<IoUring>>,
+    ring: Rc<Ring>,
     eventfd: Arc<EventFd>,
     eventbuf: mem::ManuallyDrop<Box<[u8; 8]>>,
     timeout: Box<types::Timespec>,
+    overflow: u32,
+    dropped: u64
 }
 
 #[derive(Clone)]
 pub struct RawHandle {
-    ring: Rc<RefCell<IoUring>>,
+    ring: Rc<Ring>,
+}
+
+struct Ring {
+    // the queues point into its mappings, it's only kept alive for them.
+    // There is neither with the epoll backend.
+    _uring: Option<Box<IoUring>>,
+    // the two sides are borrowed independently, and never while a ticket is completed,
+    // so wakers may push from there.
+    sq: RefCell<Option<ptr::NonNull<squeue::SubmissionQueue>>>,
+    cq: RefCell<Option<ptr::NonNull<cqueue::CompletionQueue>>>,
+    // it stands in for the kernel when io_uring is unavailable, see `Backend::Epoll`.
+    epoll: Option<epoll::Epoll>,
+    // completions reaped from the CQ but not dispatched yet
+    completed: RefCell<VecDeque<CompletionEntry>>,
+    probe: Probe,
+    // cleared once the kernel rejects `IORING_TIMEOUT_MULTISHOT` (6.4)
+    timeout_multishot: Cell<bool>,
+    fd: RawFd,
+    // index of the ring fd registered with `IORING_REGISTER_RING_FDS`
+    registered: Option<u32>,
+    restrictions: OnceCell<Restrictions>,
+    // tickets submitted to the kernel and not completed yet,
+    // with the ops emulated on the blocking pool or with readiness
+    inflight: RefCell<HashSet<u64>>,
+    // socket ops the kernel lacks, each waits on a poll with its own user_data
+    ready: RefCell<HashMap<u64, SubmissionEntry>>,
+    blocking: Arc<blocking::Completions>,
+    closed: Cell<bool>,
+    eventfd_poll: Cell<EventFdPoll>,
+    eventfd_readable: Cell<bool>,
+    // the deadline of the armed park timeout
+    park_timeout: Cell<Option<Instant>>,
+    // see `Builder::defer_submission`
+    deferred: bool,
+    // see `Builder::max_inflight`
+    limit: Option<usize>,
+    // the futures of ops held back by the limit, woken as ops complete
+    slots: RefCell<Vec<Waker>>,
+    backlog: RefCell<VecDeque<SubmissionEntry>>,
+    // the drained op in flight, the backlog waits for it to complete
+    drain: Cell<Option<u64>>,
+    // userspace deadlines, only the nearest one is armed when parking
+    timers: RefCell<time::wheel::Wheel>,
+    stats: RingStats,
+    #[cfg(feature = "metrics")]
+    latency: latency::Recorder
+}
+
+#[derive(Default)]
+struct RingStats {
+    submissions: Cell<u64>,
+    completions: Cell<u64>,
+    enters: Cell<u64>,
+    sq_full: Cell<u64>,
+    busy: Cell<u64>
+}
+
+/// What drives the ops of a [Proactor].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    IoUring,
+    /// Readiness with epoll, for when io_uring is missing or forbidden,
+    /// e.g. by a seccomp profile or `kernel.io_uring_disabled`.
+    ///
+    /// Ops on sockets, pipes and other pollable fds wait for readiness and run the
+    /// plain syscall, timeouts are kept in userspace, other ops run on the blocking pool,
+    /// or fail with [io::ErrorKind::Unsupported] if it can't emulate them.
+    /// It behaves like an old kernel: multishot ops fail with `EINVAL`,
+    /// and linked chains are unsupported.
+    Epoll
+}
+
+/// Counters of a ring since it was created, see [Proactor::stats].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stats {
+    /// Entries consumed by the kernel.
+    pub submissions: u64,
+    /// Completions reaped from the CQ, including ritsu's internal ones.
+    pub completions: u64,
+    /// `io_uring_enter` calls.
+    pub enters: u64,
+    /// How often a push found the SQ full,
+    /// and had to submit or wait in the backlog.
+    pub sq_full_events: u64,
+    /// Submissions retried after `EBUSY`, because the CQ was full.
+    pub busy_retries: u64,
+    /// Ops submitted and not completed yet, including the backlog.
+    pub inflight: usize,
+    /// Completions the kernel dropped because the CQ was full,
+    /// see [Proactor::dropped_completions].
+    pub dropped_completions: u64
+}
+
+/// How the eventfd is watched.
+///
+/// A multishot `PollAdd` (5.13) stays armed across wakeups,
+/// older kernels fall back to a `Read` for every wakeup.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum EventFdPoll {
+    Disarmed,
+    Armed,
+    // the kernel rejected the multishot poll and no `Read` is posted yet
+    Failed,
+    Unsupported
+}
+
+pub struct Builder {
+    entries: u32,
+    attach_wq: Option<RawFd>,
+    register_ring_fd: bool,
+    defer_submission: bool,
+    max_inflight: Option<usize>,
+    napi: Option<(Duration, bool)>,
+    backend: Option<Backend>
+}
+
+impl Builder {
+    pub fn new() -> Builder {
+        Builder {
+            entries: 256, // TODO better number
+            attach_wq: None,
+            register_ring_fd: false,
+            defer_submission: false,
+            max_inflight: None,
+            napi: None,
+            backend: None
+        }
+    }
+
+    /// The size of submission queue, it should be the power of two.
+    pub fn entries(&mut self, entries: u32) -> &mut Self {
+        self.entries = entries;
+        self
+    }
+
+    /// Share the kernel async worker pool of the ring `fd`
+    /// with `IORING_SETUP_ATTACH_WQ`, instead of creating a new one.
+    ///
+    /// `fd` usually comes from another Proactor's [AsRawFd] implementation,
+    /// it only needs to stay open until [Builder::build] returns.
+    pub fn attach_wq(&mut self, fd: RawFd) -> &mut Self {
+        self.attach_wq = Some(fd);
+        self
+    }
+
+    /// Register the ring fd with itself,
+    /// so that `io_uring_enter` doesn't need to look up the fd on every call.
+    ///
+    /// This is best-effort, see [Proactor::is_ring_fd_registered].
+    pub fn register_ring_fd(&mut self, enable: bool) -> &mut Self {
+        self.register_ring_fd = enable;
+        self
+    }
+
+    /// Never enter the kernel on push.
+    ///
+    /// Entries that don't fit in the SQ wait in a backlog,
+    /// everything is submitted by the next [Proactor::park] or [RawHandle::flush],
+    /// so a burst of ops costs one `io_uring_enter`.
+    pub fn defer_submission(&mut self, enable: bool) -> &mut Self {
+        self.defer_submission = enable;
+        self
+    }
+
+    /// Cap the number of ops in flight at once.
+    ///
+    /// Beyond it, the future of a pushed op waits for an earlier op to complete
+    /// before submitting its entry, dropping it before that never submits it.
+    /// Linked chains, [RawHandle::raw_push] and ritsu's own entries, like the
+    /// cancellation of a dropped future, are not held back.
+    ///
+    /// Ops that only complete after a later op made progress can deadlock
+    /// once they fill the limit.
+    pub fn max_inflight(&mut self, max: usize) -> &mut Self {
+        self.max_inflight = Some(max.max(1));
+        self
+    }
+
+    /// Enable NAPI busy polling of the sockets used by this ring
+    /// with `IORING_REGISTER_NAPI`.
+    ///
+    /// `busy_poll_timeout` is rounded to microseconds,
+    /// `prefer_busy_poll` sets `SO_PREFER_BUSY_POLL` semantics.
+    /// Building the ring fails with [io::ErrorKind::Unsupported] before Linux 6.9.
+    pub fn napi(&mut self, busy_poll_timeout: Duration, prefer_busy_poll: bool) -> &mut Self {
+        self.napi = Some((busy_poll_timeout, prefer_busy_poll));
+        self
+    }
+
+    /// Pick the backend, by default it's io_uring if the kernel lets us set up a ring,
+    /// and [Backend::Epoll] otherwise.
+    ///
+    /// The options of the ring are ignored by the epoll backend.
+    pub fn backend(&mut self, backend: Backend) -> &mut Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    pub fn build(&self) -> io::Result<Proactor> {
+        let mut builder = io_uring::Builder::default();
+
+        if let Some(fd) = self.attach_wq {
+            builder.setup_attach_wq(fd);
+        }
+
+        let ring = match self.backend {
+            Some(Backend::Epoll) => return Proactor::with_epoll(),
+            Some(Backend::IoUring) => builder.build(self.entries)?,
+            None => match builder.build(self.entries) {
+                Ok(ring) => ring,
+                Err(ref err) if is_uring_unavailable(err) => return Proactor::with_epoll(),
+                Err(err) => return Err(err)
+            }
+        };
+
+        if let Some((timeout, prefer_busy_poll)) = self.napi {
+            register_napi(ring.as_raw_fd(), timeout, prefer_busy_poll)?;
+        }
+
+        let mut proactor = Proactor::from_ring(ring)?;
+
+        let ring = Rc::get_mut(&mut proactor.ring).unwrap();
+        ring.deferred = self.defer_submission;
+        ring.limit = self.max_inflight;
+
+        if self.register_ring_fd {
+            ring.registered = register_ring_fd(ring.fd).ok();
+        }
+
+        Ok(proactor)
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder::new()
+    }
+}
+
+// the kernel lacks io_uring, or it's forbidden to us.
+fn is_uring_unavailable(err: &io::Error) -> bool {
+    matches!(err.raw_os_error(), Some(libc::ENOSYS | libc::EPERM | libc::EACCES))
+}
+
+fn register_ring_fd(fd: RawFd) -> io::Result<u32> {
+    let mut update = sys::RsrcUpdate {
+        offset: u32::MAX, // allocate any free index
+        resv: 0,
+        data: fd as _
+    };
+
+    unsafe {
+        sys::io_uring_register(fd, sys::IORING_REGISTER_RING_FDS, &mut update as *mut _ as *const _, 1)?;
+    }
+
+    Ok(update.offset)
+}
+
+fn register_napi(fd: RawFd, timeout: Duration, prefer_busy_poll: bool) -> io::Result<()> {
+    let mut napi = sys::Napi {
+        busy_poll_to: timeout.as_micros().min(u32::MAX as u128) as u32,
+        prefer_busy_poll: prefer_busy_poll as u8,
+        opcode: 0,
+        pad: [0; 2],
+        op_param: 0,
+        resv: 0
+    };
+
+    match unsafe { sys::io_uring_register(fd, sys::IORING_REGISTER_NAPI, &mut napi as *mut _ as *const _, 1) } {
+        Ok(_) => Ok(()),
+        // an opcode the kernel doesn't know
+        Err(ref err) if err.raw_os_error() == Some(libc::EINVAL) => {
+            Err(io::Error::new(io::ErrorKind::Unsupported, "NAPI busy polling needs Linux 6.9"))
+        },
+        Err(err) => Err(err)
+    }
+}
+
+impl Ring {
+    fn enter(&self, to_submit: u32, min_complete: u32, flags: u32) -> io::Result<usize> {
+        bump(&self.stats.enters, 1);
+
+        let ret = unsafe {
+            match self.registered {
+                Some(index) => sys::io_uring_enter(
+                    index as _,
+                    to_submit, min_complete,
+                    flags | sys::IORING_ENTER_REGISTERED_RING
+                ),
+                None => sys::io_uring_enter(self.fd, to_submit, min_complete, flags)
+            }
+        };
+
+        if let Ok(n) = ret {
+            bump(&self.stats.submissions, n as u64);
+        }
+
+        ret
+    }
+}
+
+#[inline]
+fn bump(counter: &Cell<u64>, n: u64) {
+    counter.set(counter.get().wrapping_add(n));
+}
+
+#[inline]
+fn is_drain(entry: &SubmissionEntry) -> bool {
+    sys::sqe(entry).flags & squeue::Flags::IO_DRAIN.bits() != 0
+}
+
+/// A single shot poll for what the socket op `sqe` waits for, with its user_data.
+fn ready_poll(sqe: &sys::Sqe) -> SubmissionEntry {
+    opcode::PollAdd::new(types::Target::Fd(sqe.fd), epoll::interest(sqe) as _)
+        .build()
+        .user_data(sqe.user_data)
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        if let Some(index) = self.registered {
+            let update = sys::RsrcUpdate { offset: index, resv: 0, data: 0 };
+
+            unsafe {
+                let _ = sys::io_uring_register(
+                    self.fd,
+                    sys::IORING_UNREGISTER_RING_FDS,
+                    &update as *const _ as *const _,
+                    1
+                );
+            }
+        }
+    }
 }
 
 impl Proactor {
     pub fn new() -> io::Result<Proactor> {
-        let ring = io_uring::IoUring::new(256)?; // TODO better number
+        Builder::new().build()
+    }
+
+    /// Use a ring that was set up by the application,
+    /// e.g. with setup flags or resources registered before.
+    ///
+    /// The ring must not have an eventfd registered,
+    /// and its `user_data` space belongs to ritsu from now on.
+    pub fn from_ring(ring: IoUring) -> io::Result<Proactor> {
+        let probe = Probe::new(&ring);
+        let fd = ring.as_raw_fd();
+        let eventfd = Arc::new(EventFd::new()?);
+
+        let mut ring = Box::new(ring);
+        let (_, sq, cq) = ring.split();
+        let (sq, cq) = (ptr::NonNull::from(sq), ptr::NonNull::from(cq));
+
+        Ok(Proactor::with_ring(Ring {
+            _uring: Some(ring),
+            sq: RefCell::new(Some(sq)),
+            cq: RefCell::new(Some(cq)),
+            epoll: None,
+            completed: RefCell::new(VecDeque::new()),
+            probe,
+            timeout_multishot: Cell::new(true),
+            fd,
+            registered: None,
+            restrictions: OnceCell::new(),
+            inflight: RefCell::new(HashSet::new()),
+            ready: RefCell::new(HashMap::new()),
+            blocking: Arc::new(blocking::Completions::new(futures_task::waker(eventfd.clone()))),
+            closed: Cell::new(false),
+            eventfd_poll: Cell::new(EventFdPoll::Disarmed),
+            eventfd_readable: Cell::new(false),
+            park_timeout: Cell::new(None),
+            deferred: false,
+            limit: None,
+            slots: RefCell::new(Vec::new()),
+            backlog: RefCell::new(VecDeque::new()),
+            drain: Cell::new(None),
+            timers: RefCell::new(time::wheel::Wheel::new()),
+            stats: RingStats::default(),
+            #[cfg(feature = "metrics")]
+            latency: latency::Recorder::default()
+        }, eventfd))
+    }
+
+    fn with_epoll() -> io::Result<Proactor> {
+        let eventfd = Arc::new(EventFd::new()?);
+        let epoll = epoll::Epoll::new(eventfd.as_raw_fd())?;
+        let fd = epoll.as_raw_fd();
+
+        Ok(Proactor::with_ring(Ring {
+            _uring: None,
+            sq: RefCell::new(None),
+            cq: RefCell::new(None),
+            epoll: Some(epoll),
+            completed: RefCell::new(VecDeque::new()),
+            probe: Probe::none(),
+            timeout_multishot: Cell::new(true),
+            fd,
+            registered: None,
+            restrictions: OnceCell::new(),
+            inflight: RefCell::new(HashSet::new()),
+            ready: RefCell::new(HashMap::new()),
+            blocking: Arc::new(blocking::Completions::new(futures_task::waker(eventfd.clone()))),
+            closed: Cell::new(false),
+            eventfd_poll: Cell::new(EventFdPoll::Unsupported),
+            eventfd_readable: Cell::new(false),
+            park_timeout: Cell::new(None),
+            deferred: false,
+            limit: None,
+            slots: RefCell::new(Vec::new()),
+            backlog: RefCell::new(VecDeque::new()),
+            drain: Cell::new(None),
+            timers: RefCell::new(time::wheel::Wheel::new()),
+            stats: RingStats::default(),
+            #[cfg(feature = "metrics")]
+            latency: latency::Recorder::default()
+        }, eventfd))
+    }
 
-        Ok(Proactor {
-            ring: Rc::new(RefCell::new(ring)),
-            eventfd: Arc::new(EventFd::new()?),
+    fn with_ring(ring: Ring, eventfd: Arc<EventFd>) -> Proactor {
+        Proactor {
+            ring: Rc::new(ring),
+            eventfd,
             eventbuf: mem::ManuallyDrop::new(Box::new([0; 8])), // TODO not leak it :(
-            timeout: Box::new(types::Timespec::default())
-        })
+            timeout: Box::new(types::Timespec::default()),
+            overflow: 0,
+            dropped: 0
+        }
+    }
+
+    /// Which backend drives the ops, see [Builder::backend].
+    #[inline]
+    pub fn backend(&self) -> Backend {
+        if self.ring.epoll.is_some() {
+            Backend::Epoll
+        } else {
+            Backend::IoUring
+        }
+    }
+
+    #[inline]
+    pub fn builder() -> Builder {
+        Builder::new()
     }
 
     pub fn waker(&self) -> Waker {
-        task::waker(self.eventfd.clone())
+        futures_task::waker(self.eventfd.clone())
     }
 
-    pub fn waker_ref(&self) -> WakerRef {
-        task::waker_ref(&self.eventfd)
+    pub fn waker_ref(&self) -> WakerRef<'_> {
+        futures_task::waker_ref(&self.eventfd)
+    }
+
+    /// A waker that posts into this ring with `IORING_OP_MSG_RING`,
+    /// see [MsgRingWaker].
+    ///
+    /// It must not outlive the Proactor.
+    pub fn msg_ring_waker(&self) -> io::Result<Waker> {
+        Ok(futures_task::waker(Arc::new(MsgRingWaker::new(self.ring.fd)?)))
     }
 
     pub fn raw_handle(&self) -> RawHandle {
@@ -67,124 +538,1910 @@ impl Proactor {
         }
     }
 
+    #[inline]
+    pub fn probe(&self) -> &Probe {
+        &self.ring.probe
+    }
+
+    #[inline]
+    pub fn is_ring_fd_registered(&self) -> bool {
+        self.ring.registered.is_some()
+    }
+
+    /// The number of completions the kernel has dropped because the CQ was full.
+    ///
+    /// This can only grow on kernels without `IORING_FEAT_NODROP`, or when
+    /// the kernel failed to allocate its overflow backlog.
+    /// Their tickets are lost, and the futures waiting on them will never complete.
+    #[inline]
+    pub fn dropped_completions(&self) -> u64 {
+        self.dropped
+    }
+
+    /// The submit to complete latency of the ops of this Proactor by opcode,
+    /// since it was created, see [latency].
+    #[cfg(feature = "metrics")]
+    pub fn latency_snapshot(&self) -> latency::LatencySnapshot {
+        self.ring.latency.snapshot()
+    }
+
+    pub fn stats(&self) -> Stats {
+        let stats = &self.ring.stats;
+
+        Stats {
+            submissions: stats.submissions.get(),
+            completions: stats.completions.get(),
+            enters: stats.enters.get(),
+            sq_full_events: stats.sq_full.get(),
+            busy_retries: stats.busy.get(),
+            inflight: self.ring.inflight.borrow().len(),
+            dropped_completions: self.dropped
+        }
+    }
+
+    #[inline]
     pub fn park(&mut self, dur: Option<Duration>) -> io::Result<()> {
-        let mut ring = self.ring.borrow_mut();
-        let (submitter, sq, cq) = ring.split();
-        let (mut sq, mut cq) = (sq.available(), cq.available());
-        let cq_is_not_empty = cq.len() != 0;
+        self.park_n(1, dur)
+    }
+
+    /// Like [Proactor::park], but sleep until at least `min` completions
+    /// are available, or `dur` elapsed.
+    ///
+    /// A wakeup from another thread only counts as one completion
+    /// and doesn't end the wait on its own, `dur` bounds how long it can be delayed.
+    pub fn park_n(&mut self, min: u32, dur: Option<Duration>) -> io::Result<()> {
+        let min = min.max(1);
+
+        trace_event!(min, ?dur, "park");
+
+        if self.ring.epoll.is_some() {
+            return self.park_epoll(dur);
+        }
+
+        self.ring.flush_backlog()?;
 
         // clean cq
-        cq_drain(&mut cq);
+        let (reaped, _) = self.ring.reap();
+        let cq_is_not_empty = reaped != 0 || !self.ring.completed.borrow().is_empty();
+        self.ring.dispatch();
+        let fired = self.ring.fire_timers();
+
+        // wake up for the nearest timer at the latest
+        let deadline = match (dur.map(|dur| Instant::now() + dur), self.ring.timers.borrow().next_deadline()) {
+            (Some(deadline), Some(timer)) => Some(deadline.min(timer)),
+            (deadline, timer) => deadline.or(timer)
+        };
 
         let state = self.eventfd.park();
 
+        if self.ring.eventfd_readable.take() {
+            self.eventfd.consume();
+        }
+
         // we has events, so we don't need to wait for timeout
         let nowait = state.is_ready()
             || cq_is_not_empty
-            || dur == Some(Duration::from_secs(0));
+            || fired != 0
+            || deadline.is_some_and(|deadline| deadline <= Instant::now());
 
-        let mut event_e = if !state.is_park() {
-            let op = types::Target::Fd(self.eventfd.as_raw_fd());
-            let bufptr = self.eventbuf.as_mut_ptr();
-            let entry = opcode::Read::new(op, bufptr, 8)
-                .build()
-                .user_data(WAKE_TOKEN);
-            Some(entry)
-        } else {
-            None
+        let op = types::Target::Fd(self.eventfd.as_raw_fd());
+        let mut event_e = match self.ring.eventfd_poll.get() {
+            EventFdPoll::Disarmed => {
+                self.ring.eventfd_poll.set(EventFdPoll::Armed);
+
+                let mut entry = opcode::PollAdd::new(op, libc::POLLIN as _)
+                    .build()
+                    .user_data(EVENTFD_TOKEN);
+                sys::sqe_mut(&mut entry).len = sys::IORING_POLL_ADD_MULTI;
+                Some(entry)
+            },
+            EventFdPoll::Armed => None,
+            poll @ (EventFdPoll::Failed | EventFdPoll::Unsupported) => {
+                self.ring.eventfd_poll.set(EventFdPoll::Unsupported);
+
+                if poll == EventFdPoll::Failed || !state.is_park() {
+                    let bufptr = self.eventbuf.as_mut_ptr();
+                    let entry = opcode::Read::new(op, bufptr, 8)
+                        .build()
+                        .user_data(WAKE_TOKEN);
+                    Some(entry)
+                } else {
+                    None
+                }
+            }
         };
 
-        let mut timeout_e = if let Some(dur) = dur.filter(|_| !nowait) {
-            self.timeout.tv_sec = dur.as_secs() as _;
-            self.timeout.tv_nsec = dur.subsec_nanos() as _;
-            let entry = opcode::Timeout::new(&*self.timeout)
-                .build()
-                .user_data(WAKE_TOKEN);
-            Some(entry)
+        let (mut remove_e, mut timeout_e) = if nowait {
+            (None, None)
         } else {
-            None
+            self.rearm(deadline, min)
         };
 
         const_assert_eq!(false as usize , 0);
         const_assert_eq!(true as usize , 1);
 
-        let n = event_e.is_some() as usize + timeout_e.is_some() as usize;
-        if sq.capacity() - sq.len() < n {
-            submitter.submit()?;
-        }
+        let n = event_e.is_some() as usize + remove_e.is_some() as usize + timeout_e.is_some() as usize;
 
-        unsafe {
-            if let Some(entry) = event_e.take() {
-                sq.push(entry).ok().unwrap();
+        {
+            let mut sq_ref = self.ring.sq();
+            let mut sq = sq_ref.available();
+
+            if sq.capacity() - sq.len() < n {
+                bump(&self.ring.stats.sq_full, 1);
+                self.ring.enter(sq.len() as _, 0, 0)?;
+                sq.sync();
             }
 
-            if let Some(entry) = timeout_e.take() {
-                sq.push(entry).ok().unwrap();
+            unsafe {
+                if let Some(entry) = event_e.take() {
+                    sq.push(entry).ok().unwrap();
+                }
+
+                // the old timeout goes first, the new one has the same user_data
+                if let Some(entry) = remove_e.take() {
+                    sq.push(entry).ok().unwrap();
+                }
+
+                if let Some(entry) = timeout_e.take() {
+                    sq.push(entry).ok().unwrap();
+                }
             }
+
+            // publish the tail, otherwise the entries above are only submitted by next park.
+            sq.sync();
         }
 
+        let to_submit = self.ring.sq().len() as _;
+
         if nowait {
-            submitter.submit()?;
+            self.ring.enter(to_submit, 0, 0)?;
         } else {
-            submitter.submit_and_wait(1)?;
+            self.ring.enter(to_submit, min, sys::IORING_ENTER_GETEVENTS)?;
         }
 
-        cq.sync();
+        trace_event!(nowait, "unpark");
 
-        cq_drain(&mut cq);
+        self.ring.reap_all()?;
+        self.ring.dispatch();
+        self.ring.fire_timers();
 
         // reset eventfd
         self.eventfd.reset();
 
+        let overflow = self.ring.cq().overflow();
+        self.account_overflow(overflow);
+
         Ok(())
     }
-}
 
-fn cq_drain(cq: &mut cqueue::AvailableQueue) {
-    for entry in cq {
-        match entry.user_data() {
-            WAKE_TOKEN => (),
-            ptr => unsafe {
-                Ticket::from_raw(ptr::NonNull::new_unchecked(ptr as _))
-                    .send(entry.clone());
+    /// The entries that move the park timeout to `deadline`, `None` removes it.
+    ///
+    /// One timeout stays armed across parks, it's replaced only when the deadline changes,
+    /// so the parks of a busy ring don't leave a trail of timeouts in the kernel.
+    /// Kernels before 5.5 can't remove a timeout, they get a new one on every park that waits.
+    fn rearm(&mut self, deadline: Option<Instant>, min: u32) -> (Option<SubmissionEntry>, Option<SubmissionEntry>) {
+        if !self.ring.probe.is_supported(opcode::TimeoutRemove::CODE) {
+            let entry = deadline.map(|deadline| {
+                *self.timeout = time::timespec(deadline.saturating_duration_since(Instant::now()));
+                // a timeout always ends the wait, and with a count
+                // it completes as soon as `min` completions arrived.
+                let count = if min > 1 { min } else { 0 };
+                opcode::Timeout::new(&*self.timeout)
+                    .count(count)
+                    .build()
+                    .user_data(WAKE_TOKEN)
+            });
+
+            return (None, entry);
+        }
+
+        let armed = self.ring.park_timeout.get();
+
+        if deadline == armed {
+            return (None, None);
+        }
+
+        let remove = armed.map(|_| opcode::TimeoutRemove::new(TIMEOUT_TOKEN)
+            .build()
+            .user_data(WAKE_TOKEN));
+        let timeout = deadline.map(|deadline| {
+            // absolute, so it stays right however many parks it spans
+            let (timespec, flags) = time::Deadline::At(deadline).timeout();
+            *self.timeout = timespec;
+            opcode::Timeout::new(&*self.timeout)
+                .flags(flags)
+                .build()
+                .user_data(TIMEOUT_TOKEN)
+        });
+
+        self.ring.park_timeout.set(deadline);
+
+        (remove, timeout)
+    }
+
+    fn park_epoll(&mut self, dur: Option<Duration>) -> io::Result<()> {
+        let epoll = self.ring.epoll.as_ref().unwrap();
+
+        let cq_is_not_empty = !self.ring.completed.borrow().is_empty();
+        self.ring.dispatch();
+        let fired = self.ring.fire_timers();
+
+        let dur = match (dur, self.ring.next_timer()) {
+            (Some(dur), Some(timer)) => Some(dur.min(timer)),
+            (dur, timer) => dur.or(timer)
+        };
+
+        let state = self.eventfd.park();
+
+        if self.ring.eventfd_readable.take() {
+            self.eventfd.consume();
+        }
+
+        let nowait = state.is_ready() || cq_is_not_empty || fired != 0;
+        let dur = if nowait { Some(Duration::from_secs(0)) } else { dur };
+
+        let readable = epoll.wait(dur, &mut self.ring.completed.borrow_mut())?;
+        self.ring.eventfd_readable.set(readable);
+
+        trace_event!(nowait, "unpark");
+
+        self.ring.dispatch();
+        self.ring.fire_timers();
+
+        self.eventfd.reset();
+
+        Ok(())
+    }
+
+    /// Submit pending entries and dispatch the available completions,
+    /// without ever sleeping.
+    ///
+    /// Returns the number of tickets completed and timers fired,
+    /// for embedding the Proactor into another event loop.
+    pub fn try_park(&mut self) -> io::Result<usize> {
+        if let Some(epoll) = self.ring.epoll.as_ref() {
+            let readable = epoll.wait(Some(Duration::from_secs(0)), &mut self.ring.completed.borrow_mut())?;
+
+            if readable {
+                self.eventfd.consume();
             }
+
+            return Ok(self.ring.dispatch() + self.ring.fire_timers());
+        }
+
+        self.ring.flush_backlog()?;
+
+        {
+            let mut sq = self.ring.sq();
+
+            if !sq.is_empty() {
+                self.ring.submit(&mut sq)?;
+            }
+        }
+
+        self.ring.reap_all()?;
+        let n = self.ring.dispatch() + self.ring.fire_timers();
+
+        if self.ring.eventfd_readable.take() {
+            self.eventfd.consume();
+        }
+
+        let overflow = self.ring.cq().overflow();
+        self.account_overflow(overflow);
+
+        Ok(n)
+    }
+
+    /// The deadline of the nearest timer on the wheel,
+    /// another event loop embedding the Proactor must call [Proactor::try_park] by then.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.ring.timers.borrow().next_deadline()
+    }
+
+    /// Count the completions dropped since the last park,
+    /// they are reported by [Proactor::dropped_completions] rather than failing the park.
+    fn account_overflow(&mut self, overflow: u32) {
+        if overflow != self.overflow {
+            let n = overflow.wrapping_sub(self.overflow);
+            self.overflow = overflow;
+            self.dropped += u64::from(n);
+
+            trace_event!(dropped = n, "cq overflow");
         }
     }
 }
 
-impl RawHandle {
-    pub unsafe fn raw_push(&self, mut entry: SubmissionEntry) -> io::Result<()> {
-        let mut ring = self.ring.borrow_mut();
-        let (submitter, sq, cq) = ring.split();
+impl Proactor {
+    /// Shut the ring down.
+    ///
+    /// New submissions fail from now on,
+    /// in-flight ops are cancelled with `IORING_ASYNC_CANCEL_ANY`,
+    /// and this waits until all their completions are delivered.
+    ///
+    /// Kernels before 5.19 can't cancel by wildcard,
+    /// in which case this waits for in-flight ops to complete on their own.
+    pub fn shutdown(mut self) -> io::Result<()> {
+        self.close()
+    }
 
-        loop {
-            let mut sq = sq.available();
+    fn close(&mut self) -> io::Result<()> {
+        self.ring.closed.set(true);
+        self.ring.blocking.cancel(None);
 
-            match sq.push(entry) {
-                Ok(_) => break,
-                Err(e) => entry = e
+        // their pushes fail now rather than wait for a slot
+        for waker in self.ring.slots.take() {
+            waker.wake();
+        }
+
+        // the kernel never saw these, complete them right here.
+        for entry in self.ring.backlog.borrow_mut().drain(..) {
+            let user_data = sys::sqe(&entry).user_data;
+
+            if user_data != WAKE_TOKEN {
+                self.ring.completed.borrow_mut()
+                    .push_back(sys::completion(user_data, -libc::ECANCELED, 0));
             }
+        }
 
-            match submitter.submit() {
-                Ok(_) => (),
-                Err(ref err) if err.raw_os_error() == Some(libc::EBUSY) => {
-                    cq_drain(&mut cq.available());
-                    submitter.submit()?;
-                },
-                Err(err) => return Err(err)
+        self.ring.dispatch();
+
+        // what's left after the waiting ops runs on the blocking pool.
+        if let Some(epoll) = self.ring.epoll.as_ref() {
+            epoll.cancel(None, &mut self.ring.completed.borrow_mut());
+            self.ring.dispatch();
+
+            while self.ring.blocking.len() != 0 {
+                self.ring.blocking.wait();
+                self.ring.dispatch();
+            }
+
+            self.eventfd.reset();
+
+            return Ok(());
+        }
+
+        // detached entries are only known to the SQ, like the close of a dropped file.
+        {
+            let mut sq = self.ring.sq();
+
+            if !sq.is_empty() {
+                self.ring.submit(&mut sq)?;
+            }
+        }
+
+        let mut cancel = true;
+
+        while !self.ring.inflight.borrow().is_empty() {
+            // the kernel has nothing left, and won't wake us for the blocking pool.
+            if self.ring.inflight.borrow().len() <= self.ring.blocking.len() {
+                self.ring.blocking.wait();
+                self.ring.dispatch();
+                continue
+            }
+
+            let mut sq = self.ring.sq();
+            let mut sq = sq.available();
+
+            if cancel {
+                let mut entry = opcode::AsyncCancel::new(0)
+                    .build()
+                    .user_data(WAKE_TOKEN);
+                sys::sqe_mut(&mut entry).op_flags = sys::IORING_ASYNC_CANCEL_ALL
+                    | sys::IORING_ASYNC_CANCEL_ANY;
+
+                unsafe {
+                    if sq.push(entry).is_ok() {
+                        cancel = false;
+                    }
+                }
             }
+
+            sq.sync();
+            let to_submit = sq.len() as _;
+            drop(sq);
+
+            self.ring.enter(to_submit, 1, sys::IORING_ENTER_GETEVENTS)?;
+            self.ring.reap_all()?;
+            self.ring.dispatch();
         }
 
+        // reset eventfd
+        self.eventfd.reset();
+
         Ok(())
     }
+}
 
-    fn into_raw(self) -> *const RawHandle {
-        Rc::into_raw(self.ring) as *const _
+impl Drop for Proactor {
+    fn drop(&mut self) {
+        if self.close().is_err() {
+            // we can't wait for the kernel anymore,
+            // at least release the tickets so their futures don't hang.
+            for ptr in self.ring.inflight.borrow_mut().drain() {
+                unsafe {
+                    if multishot::is_multishot(ptr) {
+                        multishot::release(ptr);
+                    } else {
+                        drop(Ticket::from_raw(ptr::NonNull::new_unchecked(ptr as _)));
+                    }
+                }
+            }
+        }
     }
+}
 
-    unsafe fn from_raw(ptr: *const RawHandle) -> RawHandle {
-        RawHandle {
-            ring: Rc::from_raw(ptr as *const _)
+impl AsRawFd for Proactor {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.ring.fd
+    }
+}
+
+impl AsRawFd for RawHandle {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.ring.fd
+    }
+}
+
+impl Ring {
+    fn sq(&self) -> RefMut<'_, squeue::SubmissionQueue> {
+        RefMut::map(self.sq.borrow_mut(), |sq| unsafe {
+            sq.as_mut().expect("the epoll backend has no SQ").as_mut()
+        })
+    }
+
+    fn cq(&self) -> RefMut<'_, cqueue::CompletionQueue> {
+        RefMut::map(self.cq.borrow_mut(), |cq| unsafe {
+            cq.as_mut().expect("the epoll backend has no CQ").as_mut()
+        })
+    }
+
+    /// Submit the SQ without waiting, reaping completions if the kernel is busy.
+    fn submit(&self, sq: &mut squeue::SubmissionQueue) -> io::Result<()> {
+        match self.enter(sq.len() as _, 0, 0) {
+            Ok(_) => Ok(()),
+            Err(ref err) if err.raw_os_error() == Some(libc::EBUSY) => {
+                bump(&self.stats.busy, 1);
+                self.reap();
+                self.enter(sq.len() as _, 0, 0)?;
+                Ok(())
+            },
+            Err(err) => Err(err)
+        }
+    }
+
+    /// Queue `entry` into the SQ, or into the backlog behind the entries waiting there.
+    ///
+    /// # Safety
+    ///
+    /// Same as [RawHandle::raw_push].
+    unsafe fn push(&self, mut entry: SubmissionEntry) -> io::Result<()> {
+        let mut sq = self.sq();
+        let mut backlog = self.backlog.borrow_mut();
+
+        // keep the order once something is waiting in the backlog
+        if !backlog.is_empty()
+            || is_drain(&entry)
+            || self.is_draining()
+            || (self.deferred && sq.is_full())
+        {
+            if sq.is_full() {
+                bump(&self.stats.sq_full, 1);
+            }
+
+            backlog.push_back(entry);
+        } else {
+            loop {
+                match sq.available().push(entry) {
+                    Ok(_) => break,
+                    Err(e) => entry = e
+                }
+
+                bump(&self.stats.sq_full, 1);
+
+                self.submit(&mut sq)?;
+            }
         }
+
+        Ok(())
+    }
+
+    /// Count `user_data` as in flight until its completion is dispatched.
+    fn track(&self, user_data: u64, _opcode: u8) {
+        self.inflight.borrow_mut().insert(user_data);
+
+        #[cfg(feature = "metrics")]
+        self.latency.start(user_data, _opcode);
     }
+
+    /// Whether `entry` is a socket op, or a read or write of a pipe, socket or other
+    /// pollable fd, that can wait for readiness with a poll rather than park a thread
+    /// of the blocking pool.
+    fn is_ready_emulated(&self, entry: &SubmissionEntry) -> bool {
+        let sqe = sys::sqe(entry);
+        let unsupported = squeue::Flags::FIXED_FILE
+            | squeue::Flags::IO_DRAIN
+            | squeue::Flags::IO_LINK
+            | squeue::Flags::IO_HARDLINK;
+
+        if self.epoll.is_some()
+            || !self.probe.is_supported(opcode::PollAdd::CODE)
+            || sqe.flags & unsupported.bits() != 0
+        {
+            return false;
+        }
+
+        match sqe.opcode {
+            opcode::Recv::CODE
+                | opcode::Send::CODE
+                | opcode::RecvMsg::CODE
+                | opcode::SendMsg::CODE
+                | opcode::Accept::CODE
+                | opcode::Connect::CODE => true,
+            // an idle pipe or socket would hold a pool thread for good.
+            opcode::Read::CODE
+                | opcode::Write::CODE
+                | opcode::Readv::CODE
+                | opcode::Writev::CODE => !epoll::is_blocking(sqe),
+            _ => false
+        }
+    }
+
+    /// The poll a socket op waits on, `None` if it completed right away,
+    /// like a connect to a local address.
+    fn wait_ready(&self, entry: &SubmissionEntry) -> Option<SubmissionEntry> {
+        let sqe = sys::sqe(entry);
+
+        if sqe.opcode == opcode::Connect::CODE {
+            if let Some(res) = epoll::connect(sqe) {
+                self.completed.borrow_mut().push_back(sys::completion(sqe.user_data, res, 0));
+                return None;
+            }
+        }
+
+        Some(ready_poll(sqe))
+    }
+
+    /// The poll of a socket op completed, run the op with the nonblocking syscall.
+    ///
+    /// Returns the completion of the op, `None` if the socket wasn't ready after all
+    /// and the poll is armed again.
+    fn retry_ready(&self, cqe: CompletionEntry) -> Option<CompletionEntry> {
+        let user_data = cqe.user_data();
+
+        let res = if cqe.result() < 0 {
+            // the poll failed or was cancelled, so did the op
+            cqe.result()
+        } else if self.closed.get() {
+            -libc::ECANCELED
+        } else {
+            let ready = self.ready.borrow();
+            let sqe = sys::sqe(&ready[&user_data]);
+
+            match epoll::attempt(sqe, epoll::interest(sqe)) {
+                Some(res) => res,
+                None => {
+                    let poll = ready_poll(sqe);
+                    drop(ready);
+
+                    // the op was pushed already, its resources outlive the poll
+                    match unsafe { self.push(poll) } {
+                        Ok(()) => return None,
+                        Err(err) => -err.raw_os_error().unwrap_or(libc::EIO)
+                    }
+                }
+            }
+        };
+
+        self.ready.borrow_mut().remove(&user_data);
+        Some(sys::completion(user_data, res, 0))
+    }
+
+    /// Whether `Builder::max_inflight` ops are in flight already.
+    fn is_saturated(&self) -> bool {
+        match self.limit {
+            Some(limit) => self.inflight.borrow().len() >= limit,
+            None => false
+        }
+    }
+
+    /// Move deferred entries into the SQ, submitting whenever it fills up.
+    ///
+    /// A drained entry waits until the ops submitted before it have completed,
+    /// and the entries after it wait for it to complete.
+    fn flush_backlog(&self) -> io::Result<()> {
+        let mut sq = self.sq();
+        let mut backlog = self.backlog.borrow_mut();
+
+        while let Some(entry) = backlog.front() {
+            if self.is_draining() {
+                break
+            }
+
+            let drain = is_drain(entry);
+
+            if drain && self.has_pending(&backlog) {
+                break
+            }
+
+            let mut entry = backlog.pop_front().unwrap();
+
+            if drain {
+                // the kernel would also wait for our own multishot ops, which never complete
+                let sqe = sys::sqe_mut(&mut entry);
+                sqe.flags &= !squeue::Flags::IO_DRAIN.bits();
+                self.drain.set(Some(sqe.user_data));
+            }
+
+            loop {
+                match unsafe { sq.available().push(entry) } {
+                    Ok(_) => break,
+                    Err(e) => entry = e
+                }
+
+                bump(&self.stats.sq_full, 1);
+                self.submit(&mut sq)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether a drained op is still in flight.
+    fn is_draining(&self) -> bool {
+        match self.drain.get() {
+            Some(user_data) if self.inflight.borrow().contains(&user_data) => true,
+            Some(_) => {
+                self.drain.set(None);
+                false
+            },
+            None => false
+        }
+    }
+
+    /// Whether ops submitted before the backlog are still in flight, multishot ops aside.
+    fn has_pending(&self, backlog: &VecDeque<SubmissionEntry>) -> bool {
+        let queued = backlog.iter()
+            .map(|entry| sys::sqe(entry).user_data)
+            .collect::<HashSet<_>>();
+
+        self.inflight.borrow()
+            .iter()
+            .any(|user_data| !queued.contains(user_data) && !multishot::is_multishot(*user_data))
+    }
+
+    /// Move the CQ into `completed`,
+    /// returns the number of entries and whether the CQ was full.
+    fn reap(&self) -> (usize, bool) {
+        let mut cq = self.cq();
+        let mut cq = cq.available();
+        let cq_is_full = cq.is_full();
+        let mut completed = self.completed.borrow_mut();
+        let mut n = 0;
+
+        for entry in &mut cq {
+            n += 1;
+
+            match entry.user_data() {
+                WAKE_TOKEN => (),
+                EVENTFD_TOKEN => self.eventfd_event(sys::cqe(&entry)),
+                TIMEOUT_TOKEN => self.timeout_event(sys::cqe(&entry)),
+                _ => completed.push_back(entry)
+            }
+        }
+
+        bump(&self.stats.completions, n as u64);
+
+        (n, cq_is_full)
+    }
+
+    /// Reap until the CQ is empty.
+    ///
+    /// The kernel keeps completions that didn't fit in a backlog,
+    /// and only flushes them into the ring when we enter with `GETEVENTS`.
+    fn reap_all(&self) -> io::Result<()> {
+        while self.reap().1 {
+            self.enter(0, 0, sys::IORING_ENTER_GETEVENTS)?;
+        }
+
+        Ok(())
+    }
+
+    /// Complete the reaped tickets, returns how many.
+    ///
+    /// Tasks are woken once all tickets are completed,
+    /// and only once however many of their tickets completed.
+    fn dispatch(&self) -> usize {
+        let mut n = 0;
+        let mut wakers = Vec::new();
+        let mut woken = HashSet::new();
+
+        self.completed.borrow_mut().extend(self.blocking.take());
+
+        // no queue is borrowed while a ticket is completed.
+        loop {
+            let entry = match self.completed.borrow_mut().pop_front() {
+                Some(entry) => entry,
+                None => break
+            };
+
+            let entry = if self.ready.borrow().contains_key(&entry.user_data()) {
+                match self.retry_ready(entry) {
+                    Some(entry) => entry,
+                    None => continue
+                }
+            } else {
+                entry
+            };
+
+            let ptr = entry.user_data();
+            let more = sys::cqe(&entry).flags & sys::IORING_CQE_F_MORE != 0;
+
+            trace_event!(user_data = ptr, res = entry.result(), flags = sys::cqe(&entry).flags, "complete");
+
+            let waker = if multishot::is_multishot(ptr) {
+                if !more {
+                    self.inflight.borrow_mut().remove(&ptr);
+
+                    #[cfg(feature = "metrics")]
+                    self.latency.forget(ptr);
+                }
+
+                unsafe {
+                    multishot::complete(ptr, entry)
+                }
+            } else {
+                self.inflight.borrow_mut().remove(&ptr);
+
+                #[cfg(feature = "metrics")]
+                self.latency.complete(ptr);
+
+                unsafe {
+                    Ticket::from_raw(ptr::NonNull::new_unchecked(ptr as _))
+                        .send_deferred(entry)
+                }
+            };
+
+            if let Some(waker) = waker {
+                if woken.insert((waker.data(), waker.vtable() as *const _)) {
+                    wakers.push(waker);
+                }
+            }
+
+            n += 1;
+        }
+
+        if n != 0 && !self.is_saturated() {
+            wakers.append(&mut self.slots.borrow_mut());
+        }
+
+        for waker in wakers {
+            waker.wake();
+        }
+
+        n
+    }
+
+    /// Wake the expired timers, returns how many.
+    fn fire_timers(&self) -> usize {
+        if self.timers.borrow().len() == 0 {
+            return 0;
+        }
+
+        // wakers may start timers
+        let wakers = self.timers.borrow_mut().fire(Instant::now());
+        let n = wakers.len();
+
+        for waker in wakers {
+            waker.wake();
+        }
+
+        n
+    }
+
+    /// How long until the nearest timer expires.
+    fn next_timer(&self) -> Option<Duration> {
+        let deadline = self.timers.borrow().next_deadline()?;
+        Some(deadline.saturating_duration_since(Instant::now()))
+    }
+
+    fn timeout_event(&self, cqe: &sys::Cqe) {
+        // a replaced timeout is cancelled. One that fired right before it was replaced
+        // disarms the new one too, the next park arms another rather than risk sleeping
+        // without any.
+        if cqe.res != -libc::ECANCELED {
+            self.park_timeout.set(None);
+        }
+    }
+
+    fn eventfd_event(&self, cqe: &sys::Cqe) {
+        if cqe.res == -libc::EINVAL {
+            self.eventfd_poll.set(EventFdPoll::Failed);
+            return
+        }
+
+        if cqe.res > 0 {
+            self.eventfd_readable.set(true);
+        }
+
+        if cqe.flags & sys::IORING_CQE_F_MORE == 0 {
+            self.eventfd_poll.set(EventFdPoll::Disarmed);
+        }
+    }
+}
+
+impl RawHandle {
+    /// # Safety
+    ///
+    /// The resources referenced by `entry` must stay valid until its completion is reaped.
+    pub unsafe fn raw_push(&self, mut entry: SubmissionEntry) -> io::Result<()> {
+        if self.ring.closed.get() {
+            return Err(Error::Closed.into());
+        }
+
+        if let Some(restrictions) = self.ring.restrictions.get() {
+            restrictions.check(sys::sqe(&entry))?;
+        }
+
+        // the kernel doesn't know the ops on the blocking pool
+        if sys::sqe(&entry).opcode == opcode::AsyncCancel::CODE {
+            let sqe = sys::sqe(&entry);
+            let all = sqe.op_flags & sys::IORING_ASYNC_CANCEL_ANY != 0;
+            self.ring.blocking.cancel(if all { None } else { Some(sqe.addr) });
+        }
+
+        if let Some(epoll) = self.ring.epoll.as_ref() {
+            let user_data = sys::sqe(&entry).user_data;
+            #[cfg(feature = "metrics")]
+            let opcode = sys::sqe(&entry).opcode;
+            let ret = epoll.submit(entry, &mut self.ring.completed.borrow_mut());
+
+            match ret {
+                Ok(()) => {
+                    if user_data != WAKE_TOKEN {
+                        self.ring.inflight.borrow_mut().insert(user_data);
+
+                        #[cfg(feature = "metrics")]
+                        self.ring.latency.start(user_data, opcode);
+                    }
+
+                    return Ok(());
+                },
+                Err(e) => entry = e
+            }
+        }
+
+        let opcode = sys::sqe(&entry).opcode;
+        let user_data = sys::sqe(&entry).user_data;
+        let mut ready = None;
+
+        if !self.ring.probe.is_supported(opcode) {
+            // ritsu's own entries and multishot ops are never emulated
+            let emulate = user_data != WAKE_TOKEN && !multishot::is_multishot(user_data);
+
+            if emulate && self.ring.is_ready_emulated(&entry) {
+                match self.ring.wait_ready(&entry) {
+                    Some(poll) => ready = Some(mem::replace(&mut entry, poll)),
+                    None => {
+                        self.ring.track(user_data, opcode);
+                        return Ok(());
+                    }
+                }
+            } else if emulate && blocking::is_emulated(&entry) {
+                blocking::submit(entry, &self.ring.blocking);
+                self.ring.track(user_data, opcode);
+                return Ok(());
+            } else {
+                self.ring.probe.check(opcode)?;
+            }
+        }
+
+        trace_event!(user_data, opcode, "submit");
+
+        self.ring.push(entry)?;
+
+        if let Some(op) = ready {
+            self.ring.ready.borrow_mut().insert(user_data, op);
+        }
+
+        if user_data != WAKE_TOKEN {
+            self.ring.track(user_data, opcode);
+        }
+
+        Ok(())
+    }
+
+    /// Whether `entry` can be submitted without going over [Builder::max_inflight].
+    ///
+    /// ritsu's own cleanup entries never wait, so dropped futures can still
+    /// cancel the ops that fill the limit.
+    pub(crate) fn has_slot(&self, entry: &SubmissionEntry) -> bool {
+        let sqe = sys::sqe(entry);
+
+        sqe.user_data == WAKE_TOKEN
+            || restrict::is_internal_op(sqe.opcode)
+            || !self.ring.is_saturated()
+    }
+
+    /// Ready once an op can be submitted without going over [Builder::max_inflight],
+    /// or the Proactor is closed.
+    pub fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.ring.closed.get() || !self.ring.is_saturated() {
+            return Poll::Ready(());
+        }
+
+        let mut slots = self.ring.slots.borrow_mut();
+
+        if !slots.iter().any(|waker| waker.will_wake(cx.waker())) {
+            slots.push(cx.waker().clone());
+        }
+
+        Poll::Pending
+    }
+
+    /// Push `entry` without a ticket, nobody is told about its result.
+    ///
+    /// It gets `IOSQE_CQE_SKIP_SUCCESS` when the kernel supports it,
+    /// otherwise its completion is just discarded.
+    ///
+    /// # Safety
+    ///
+    /// Same as [RawHandle::raw_push], and since nobody waits for the completion,
+    /// the resources referenced by `entry` usually need to be `'static`.
+    pub unsafe fn raw_push_detached(&self, entry: SubmissionEntry) -> io::Result<()> {
+        let mut entry = entry.user_data(WAKE_TOKEN);
+
+        if self.ring.probe.is_feature_cqe_skip() {
+            sys::sqe_mut(&mut entry).flags |= sys::IOSQE_CQE_SKIP_SUCCESS;
+        }
+
+        self.raw_push(entry)
+    }
+
+    /// Push `entries` as one chain, every entry but the last gets `IOSQE_IO_LINK`
+    /// unless it already has `IOSQE_IO_HARDLINK`.
+    ///
+    /// The chain is pushed in one go or not at all,
+    /// so it can't be split across two submissions.
+    ///
+    /// # Safety
+    ///
+    /// Same as [RawHandle::raw_push], for every entry.
+    pub unsafe fn raw_push_linked(&self, entries: &[SubmissionEntry]) -> io::Result<()> {
+        if self.ring.closed.get() {
+            return Err(Error::Closed.into());
+        }
+
+        if self.ring.epoll.is_some() {
+            return Err(Error::Submit(io::Error::new(io::ErrorKind::Unsupported, "linked chains need io_uring")).into());
+        }
+
+        for entry in entries {
+            if let Some(restrictions) = self.ring.restrictions.get() {
+                restrictions.check(sys::sqe(entry))?;
+            }
+
+            // a linked entry can't be emulated on the blocking pool.
+            let opcode = sys::sqe(entry).opcode;
+            if !self.ring.probe.is_supported(opcode) {
+                self.ring.probe.check(opcode)?;
+            }
+        }
+
+        // the chain goes after the deferred entries
+        self.ring.flush_backlog()?;
+
+        let mut sq = self.ring.sq();
+
+        if entries.len() > sq.capacity() {
+            let err = io::Error::new(io::ErrorKind::InvalidInput, "linked chain is larger than the submission queue");
+            return Err(Error::Submit(err).into());
+        }
+
+        while sq.capacity() - sq.len() < entries.len() {
+            bump(&self.ring.stats.sq_full, 1);
+            self.ring.submit(&mut sq)?;
+        }
+
+        let mut sq = sq.available();
+        let mut inflight = self.ring.inflight.borrow_mut();
+
+        for (i, entry) in entries.iter().enumerate() {
+            let mut entry = entry.clone();
+
+            if i + 1 != entries.len() {
+                let sqe = sys::sqe_mut(&mut entry);
+                if sqe.flags & squeue::Flags::IO_HARDLINK.bits() == 0 {
+                    sqe.flags |= squeue::Flags::IO_LINK.bits();
+                }
+            }
+
+            let user_data = sys::sqe(&entry).user_data;
+            #[cfg(feature = "metrics")]
+            let opcode = sys::sqe(&entry).opcode;
+            trace_event!(user_data, opcode = sys::sqe(&entry).opcode, linked = true, "submit");
+            sq.push(entry).ok().unwrap();
+
+            if user_data != WAKE_TOKEN {
+                inflight.insert(user_data);
+
+                #[cfg(feature = "metrics")]
+                self.ring.latency.start(user_data, opcode);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Submit everything pushed so far, including the entries deferred by
+    /// [Builder::defer_submission], without waiting for completions.
+    pub fn flush(&self) -> io::Result<()> {
+        if self.ring.epoll.is_some() {
+            return Ok(());
+        }
+
+        self.ring.flush_backlog()?;
+
+        let mut sq = self.ring.sq();
+
+        if !sq.is_empty() {
+            self.ring.submit(&mut sq)?;
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    pub fn probe(&self) -> &Probe {
+        &self.ring.probe
+    }
+
+    /// Whether multishot timeouts may work, the probe can't tell.
+    #[inline]
+    pub(crate) fn timeout_multishot(&self) -> &Cell<bool> {
+        &self.ring.timeout_multishot
+    }
+
+    /// Lock the ring down to `restrictions`,
+    /// submissions outside of it fail with `ErrorKind::PermissionDenied`.
+    ///
+    /// Restrictions can only be set once. They are checked in userspace,
+    /// so they are not a security boundary, see [restrict](crate::restrict).
+    pub fn restrict(&self, restrictions: Restrictions) -> io::Result<()> {
+        self.ring.restrictions.set(restrictions)
+            .map_err(|_| io::Error::from_raw_os_error(libc::EBUSY))
+    }
+
+    /// Register the credentials of the current thread,
+    /// ops issued with the returned personality use them
+    /// even after this thread drops its privileges.
+    pub fn register_personality(&self) -> io::Result<Personality> {
+        let id = unsafe {
+            sys::io_uring_register(self.ring.fd, sys::IORING_REGISTER_PERSONALITY, ptr::null(), 0)?
+        };
+        Ok(Personality(id as u16))
+    }
+
+    pub fn unregister_personality(&self, personality: Personality) -> io::Result<()> {
+        unsafe {
+            sys::io_uring_register(
+                self.ring.fd,
+                sys::IORING_UNREGISTER_PERSONALITY,
+                ptr::null(),
+                personality.0 as _
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Enable NAPI busy polling, or change its settings, see [Builder::napi].
+    ///
+    /// Fails with [io::ErrorKind::Unsupported] before Linux 6.9 and on the epoll backend.
+    pub fn register_napi(&self, busy_poll_timeout: Duration, prefer_busy_poll: bool) -> io::Result<()> {
+        register_napi(self.ring.fd, busy_poll_timeout, prefer_busy_poll)
+    }
+
+    /// Stop NAPI busy polling.
+    pub fn unregister_napi(&self) -> io::Result<()> {
+        // the kernel hands back the settings it had
+        let mut napi = sys::Napi::default();
+
+        unsafe {
+            sys::io_uring_register(self.ring.fd, sys::IORING_UNREGISTER_NAPI, &mut napi as *mut _ as *const _, 1)?;
+        }
+
+        Ok(())
+    }
+
+    /// A timer on the timer wheel of this ring, see [time::Delay].
+    pub fn delay_until(&self, deadline: Instant) -> time::Delay {
+        time::Delay::new(self.clone(), deadline)
+    }
+
+    fn into_raw(self) -> *const RawHandle {
+        Rc::into_raw(self.ring) as *const _
+    }
+
+    unsafe fn from_raw(ptr: *const RawHandle) -> RawHandle {
+        RawHandle {
+            ring: Rc::from_raw(ptr as *const _)
+        }
+    }
+}
+
+
+#[test]
+fn test_napi() {
+    // epoll has no NAPI of its own
+    let proactor = Proactor::builder().backend(Backend::Epoll).build().unwrap();
+    let err = proactor.raw_handle().register_napi(Duration::from_micros(50), false).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+
+    let proactor = match Proactor::builder().napi(Duration::from_micros(50), true).build() {
+        Ok(proactor) => proactor,
+        Err(err) => {
+            assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+            return
+        }
+    };
+    let handle = proactor.raw_handle();
+
+    handle.unregister_napi().unwrap();
+    handle.register_napi(Duration::from_micros(10), false).unwrap();
+    handle.unregister_napi().unwrap();
+}
+
+#[test]
+fn test_registered_ring_fd() {
+    let mut proactor = Proactor::builder()
+        .register_ring_fd(true)
+        .build()
+        .unwrap();
+
+    assert!(proactor.is_ring_fd_registered());
+    proactor.park(Some(Duration::from_millis(1))).unwrap();
+}
+
+#[test]
+fn test_from_ring() {
+    let ring = io_uring::Builder::default()
+        .setup_cqsize(1024)
+        .build(32)
+        .unwrap();
+    let fd = ring.as_raw_fd();
+    let mut proactor = Proactor::from_ring(ring).unwrap();
+
+    assert_eq!(proactor.as_raw_fd(), fd);
+    proactor.park(Some(Duration::from_millis(1))).unwrap();
+}
+
+#[test]
+fn test_remote_wake() {
+    let mut proactor = Proactor::new().unwrap();
+
+    for _ in 0..3 {
+        let waker = proactor.waker();
+        let t = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            waker.wake();
+        });
+
+        proactor.park(None).unwrap();
+        t.join().unwrap();
+    }
+
+    assert!(proactor.ring.eventfd_poll.get() == EventFdPoll::Armed);
+}
+
+#[test]
+fn test_msg_ring_wake() {
+    let mut proactor = Proactor::new().unwrap();
+    let waker = proactor.msg_ring_waker().unwrap();
+
+    let t = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(10));
+        waker.wake();
+    });
+
+    proactor.park(None).unwrap();
+    t.join().unwrap();
+}
+
+#[test]
+fn test_push_linked() {
+    use crate::executor::Runtime;
+
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+
+    let mut runtime = Runtime::new().unwrap();
+    let input = *b"chain";
+    let mut output = [0; 5];
+
+    let entries = [
+        opcode::Write::new(types::Target::Fd(fds[1]), input.as_ptr(), 5).build(),
+        opcode::Read::new(types::Target::Fd(fds[0]), output.as_mut_ptr(), 5).build(),
+        opcode::Read::new(types::Target::Fd(-1), output.as_mut_ptr(), 5).build(),
+        opcode::Nop::new().build()
+    ];
+
+    let results = runtime.run_until(async {
+        let futs = unsafe { handle::push_linked(&entries).unwrap() };
+        let mut results = Vec::new();
+        for fut in futs {
+            results.push(fut.await.result());
+        }
+        results
+    });
+
+    assert_eq!(results, [5, 5, -libc::EBADF, -libc::ECANCELED]);
+    assert_eq!(&output, b"chain");
+
+    unsafe {
+        libc::close(fds[0]);
+        libc::close(fds[1]);
+    }
+}
+
+#[test]
+fn test_cancel_on_drop() {
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+
+    let mut proactor = Proactor::new().unwrap();
+    let raw_handle = proactor.raw_handle();
+
+    unsafe {
+        handle::set(handle::default_handle(proactor.raw_handle()));
+    }
+
+    let mut buf = mem::ManuallyDrop::new(vec![0; 8]);
+    let entry = opcode::Read::new(types::Target::Fd(fds[0]), buf.as_mut_ptr(), buf.len() as _)
+        .build();
+    let fut = unsafe { handle::push(entry).unwrap() };
+    assert_eq!(raw_handle.ring.inflight.borrow().len(), 1);
+
+    drop(fut);
+
+    while !raw_handle.ring.inflight.borrow().is_empty() {
+        proactor.park(Some(Duration::from_millis(100))).unwrap();
+    }
+
+    unsafe {
+        libc::close(fds[0]);
+        libc::close(fds[1]);
+    }
+}
+
+#[test]
+fn test_restricted_cancel_on_drop() {
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+
+    let mut proactor = Proactor::new().unwrap();
+    let raw_handle = proactor.raw_handle();
+
+    let mut restrictions = Restrictions::new();
+    restrictions.allow_op(opcode::Read::CODE);
+    raw_handle.restrict(restrictions).unwrap();
+
+    unsafe {
+        handle::set(handle::default_handle(proactor.raw_handle()));
+    }
+
+    let mut buf = mem::ManuallyDrop::new(vec![0; 8]);
+    let entry = opcode::Read::new(types::Target::Fd(fds[0]), buf.as_mut_ptr(), buf.len() as _)
+        .build();
+    let fut = unsafe { handle::push(entry).unwrap() };
+
+    // the cancel isn't in the allowlist, it goes through anyway
+    drop(fut);
+
+    while !raw_handle.ring.inflight.borrow().is_empty() {
+        proactor.park(Some(Duration::from_millis(100))).unwrap();
+    }
+
+    let err = unsafe { raw_handle.raw_push(opcode::Nop::new().build()).unwrap_err() };
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+
+    unsafe {
+        libc::close(fds[0]);
+        libc::close(fds[1]);
+    }
+}
+
+#[test]
+fn test_push_detached() {
+    let mut proactor = Proactor::new().unwrap();
+    let handle = proactor.raw_handle();
+
+    unsafe {
+        handle.raw_push_detached(opcode::Nop::new().build()).unwrap();
+    }
+
+    proactor.park(Some(Duration::from_millis(1))).unwrap();
+    assert!(handle.ring.inflight.borrow().is_empty());
+}
+
+#[test]
+fn test_push_from_waker() {
+    use futures_task::ArcWake;
+
+    struct PushOnWake;
+
+    impl ArcWake for PushOnWake {
+        fn wake_by_ref(_: &Arc<Self>) {
+            unsafe {
+                handle::push_detached(opcode::Nop::new().build()).unwrap();
+            }
+        }
+    }
+
+    let mut proactor = Proactor::new().unwrap();
+    let handle = proactor.raw_handle();
+
+    unsafe {
+        handle::set(handle::default_handle(proactor.raw_handle()));
+    }
+
+    let (ticket, mut fut) = Ticket::new();
+    unsafe {
+        handle.raw_push(ticket.register(opcode::Nop::new().build())).unwrap();
+    }
+
+    let waker = futures_task::waker(Arc::new(PushOnWake));
+    let mut cx = std::task::Context::from_waker(&waker);
+    assert!(futures_util::future::FutureExt::poll_unpin(&mut fut, &mut cx).is_pending());
+
+    // the waker pushes while the completion is being dispatched
+    proactor.park(Some(Duration::from_millis(1))).unwrap();
+    assert_eq!(handle.ring.sq().len(), 1);
+
+    proactor.park(Some(Duration::from_millis(1))).unwrap();
+    assert!(handle.ring.inflight.borrow().is_empty());
+}
+
+#[test]
+fn test_dispatch_wakes_once() {
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+    use futures_task::ArcWake;
+    use futures_util::future::FutureExt;
+
+    struct Count(AtomicUsize);
+
+    impl ArcWake for Count {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    let proactor = Proactor::new().unwrap();
+    let count = Arc::new(Count(AtomicUsize::new(0)));
+    let waker = futures_task::waker(count.clone());
+    let mut cx = std::task::Context::from_waker(&waker);
+
+    let mut futs = Vec::new();
+    for _ in 0..3 {
+        let (ticket, mut fut) = Ticket::new();
+        let user_data = sys::sqe(&ticket.register(opcode::Nop::new().build())).user_data;
+        assert!(fut.poll_unpin(&mut cx).is_pending());
+
+        proactor.ring.completed.borrow_mut().push_back(sys::completion(user_data, 0, 0));
+        futs.push(fut);
+    }
+
+    assert_eq!(proactor.ring.dispatch(), 3);
+    assert_eq!(count.0.load(Ordering::Relaxed), 1);
+
+    for mut fut in futs {
+        assert!(fut.poll_unpin(&mut cx).is_ready());
+    }
+}
+
+#[test]
+fn test_defer_submission() {
+    let mut proactor = Proactor::builder()
+        .entries(2)
+        .defer_submission(true)
+        .build()
+        .unwrap();
+    let handle = proactor.raw_handle();
+
+    let mut futs = Vec::new();
+    for _ in 0..5 {
+        let (ticket, fut) = Ticket::new();
+        unsafe {
+            handle.raw_push(ticket.register(opcode::Nop::new().build())).unwrap();
+        }
+        futs.push(fut);
+    }
+
+    assert_eq!(handle.ring.backlog.borrow().len(), 3);
+
+    while !handle.ring.inflight.borrow().is_empty() {
+        proactor.park(Some(Duration::from_millis(1))).unwrap();
+    }
+
+    for fut in futs {
+        let cqe = futures_util::future::FutureExt::now_or_never(fut).unwrap();
+        assert_eq!(cqe.result(), 0);
+    }
+}
+
+#[test]
+fn test_stats() {
+    let mut proactor = Proactor::builder()
+        .entries(2)
+        .build()
+        .unwrap();
+    let handle = proactor.raw_handle();
+
+    for _ in 0..3 {
+        let (ticket, _fut) = Ticket::new();
+        unsafe {
+            handle.raw_push(ticket.register(opcode::Nop::new().build())).unwrap();
+        }
+    }
+
+    let stats = proactor.stats();
+    assert_eq!(stats.submissions, 2);
+    assert_eq!(stats.sq_full_events, 1);
+    assert_eq!(stats.inflight, 3);
+
+    proactor.park(Some(Duration::from_millis(1))).unwrap();
+
+    let stats = proactor.stats();
+    assert!(stats.submissions >= 3);
+    assert!(stats.completions >= 3);
+    assert!(stats.enters >= 2);
+    assert_eq!(stats.inflight, 0);
+    assert_eq!(stats.dropped_completions, 0);
+
+    // as if the kernel had dropped two completions
+    let overflow = proactor.overflow;
+    proactor.account_overflow(overflow.wrapping_add(2));
+    assert_eq!(proactor.stats().dropped_completions, 2);
+    assert_eq!(proactor.dropped_completions(), 2);
+}
+
+#[test]
+fn test_drain() {
+    use futures_util::future::FutureExt;
+
+    let mut proactor = Proactor::new().unwrap();
+    let handle = proactor.raw_handle();
+
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) }, 0);
+    let mut buf = [0u8; 8];
+
+    let push = |entry: SubmissionEntry| {
+        let (ticket, fut) = Ticket::new();
+        unsafe { handle.raw_push(ticket.register(entry)).unwrap() };
+        fut
+    };
+
+    let read = push(opcode::Read::new(types::Target::Fd(fds[0]), buf.as_mut_ptr(), 8).build());
+    let mut drained = push(opcode::Nop::new().build().flags(squeue::Flags::IO_DRAIN));
+    let mut after = push(opcode::Nop::new().build());
+
+    // the eventfd poll is in flight too, the drained op doesn't wait for it
+    for _ in 0..3 {
+        proactor.park(Some(Duration::from_millis(5))).unwrap();
+    }
+
+    assert!((&mut drained).now_or_never().is_none());
+    assert!((&mut after).now_or_never().is_none());
+
+    assert_eq!(unsafe { libc::write(fds[1], b"hello".as_ptr() as *const _, 5) }, 5);
+
+    while (&mut after).now_or_never().is_none() {
+        proactor.park(Some(Duration::from_millis(5))).unwrap();
+    }
+
+    assert_eq!(read.now_or_never().unwrap().result(), 5);
+    assert_eq!(drained.now_or_never().unwrap().result(), 0);
+
+    unsafe {
+        libc::close(fds[0]);
+        libc::close(fds[1]);
+    }
+}
+
+#[test]
+fn test_park_n() {
+    let mut proactor = Proactor::new().unwrap();
+    let handle = proactor.raw_handle();
+
+    for _ in 0..3 {
+        let (ticket, _fut) = Ticket::new();
+        unsafe {
+            handle.raw_push(ticket.register(opcode::Nop::new().build())).unwrap();
+        }
+    }
+
+    proactor.park_n(3, Some(Duration::from_secs(1))).unwrap();
+    assert!(handle.ring.inflight.borrow().is_empty());
+
+    let now = std::time::Instant::now();
+    proactor.park_n(8, Some(Duration::from_millis(10))).unwrap();
+    assert!(now.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn test_park_timeout() {
+    use std::thread;
+
+    let mut proactor = Proactor::new().unwrap();
+    let handle = proactor.raw_handle();
+    let waker = proactor.waker();
+
+    let noop = futures_util::task::noop_waker();
+    let deadline = Instant::now() + Duration::from_millis(100);
+    let key = handle.ring.timers.borrow_mut().insert(deadline, &noop).unwrap();
+
+    let wakeups = thread::spawn(move || for _ in 0..5 {
+        thread::sleep(Duration::from_millis(2));
+        waker.wake_by_ref();
+    });
+
+    // the timer doesn't move, its timeout is armed once for every wakeup
+    for _ in 0..5 {
+        proactor.park(None).unwrap();
+    }
+
+    wakeups.join().unwrap();
+    assert!(proactor.stats().submissions <= 2);
+    assert!(handle.ring.park_timeout.get().is_some());
+
+    while !handle.ring.timers.borrow_mut().poll(key, &noop) {
+        proactor.park(None).unwrap();
+    }
+
+    assert!(Instant::now() >= deadline);
+    assert!(handle.ring.park_timeout.get().is_none());
+}
+
+#[test]
+fn test_try_park() {
+    let mut proactor = Proactor::new().unwrap();
+    let handle = proactor.raw_handle();
+
+    assert_eq!(proactor.try_park().unwrap(), 0);
+
+    for _ in 0..3 {
+        let (ticket, _fut) = Ticket::new();
+        unsafe {
+            handle.raw_push(ticket.register(opcode::Nop::new().build())).unwrap();
+        }
+    }
+
+    // nops complete inline on submission
+    assert_eq!(proactor.try_park().unwrap(), 3);
+}
+
+#[test]
+fn test_max_inflight() {
+    use std::future::Future;
+    use std::pin::Pin;
+
+    let mut proactor = Proactor::builder()
+        .max_inflight(1)
+        .build()
+        .unwrap();
+    let raw_handle = proactor.raw_handle();
+
+    unsafe {
+        handle::set(handle::default_handle(proactor.raw_handle()));
+    }
+
+    let mut futs = (0..3)
+        .map(|_| unsafe { handle::push(opcode::Nop::new().build()).unwrap() })
+        .collect::<Vec<_>>();
+
+    // the others wait in their futures
+    assert_eq!(raw_handle.ring.inflight.borrow().len(), 1);
+
+    let waker = futures_task::noop_waker();
+    let mut cx = Context::from_waker(&waker);
+
+    while !futs.is_empty() {
+        futs.retain_mut(|fut| Pin::new(fut).poll(&mut cx).is_pending());
+        assert!(raw_handle.ring.inflight.borrow().len() <= 1);
+
+        proactor.park(Some(Duration::from_millis(1))).unwrap();
+    }
+}
+
+#[test]
+fn test_max_inflight_cancel() {
+    use std::future::Future;
+    use std::pin::Pin;
+
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+
+    let mut proactor = Proactor::builder()
+        .max_inflight(2)
+        .build()
+        .unwrap();
+    let raw_handle = proactor.raw_handle();
+
+    unsafe {
+        handle::set(handle::default_handle(proactor.raw_handle()));
+    }
+
+    // reads of an idle pipe, they never complete
+    let mut bufs = mem::ManuallyDrop::new(vec![[0u8; 8]; 2]);
+    let reads = bufs.iter_mut()
+        .map(|buf| {
+            let entry = opcode::Read::new(types::Target::Fd(fds[0]), buf.as_mut_ptr(), buf.len() as _)
+                .build();
+            unsafe { handle::push(entry).unwrap() }
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(raw_handle.ring.inflight.borrow().len(), 2);
+
+    // a held op dropped before a slot frees up is never submitted
+    let held = unsafe { handle::push(opcode::Nop::new().build()).unwrap() };
+    drop(held);
+
+    let mut fut = unsafe { handle::push(opcode::Nop::new().build()).unwrap() };
+    let waker = futures_task::noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    assert!(Pin::new(&mut fut).poll(&mut cx).is_pending());
+    assert_eq!(raw_handle.ring.inflight.borrow().len(), 2);
+
+    // their cancellations aren't held back by the limit they fill
+    drop(reads);
+
+    let cqe = loop {
+        proactor.park(Some(Duration::from_millis(100))).unwrap();
+
+        if let Poll::Ready(cqe) = Pin::new(&mut fut).poll(&mut cx) {
+            break cqe;
+        }
+    };
+    assert_eq!(cqe.result(), 0);
+
+    unsafe {
+        libc::close(fds[0]);
+        libc::close(fds[1]);
+    }
+}
+
+#[test]
+fn test_shutdown() {
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+
+    let proactor = Proactor::new().unwrap();
+    let handle = proactor.raw_handle();
+
+    let mut buf = [0; 8];
+    let (ticket, fut) = Ticket::new();
+    let entry = opcode::Read::new(types::Target::Fd(fds[0]), buf.as_mut_ptr(), buf.len() as _)
+        .build();
+
+    unsafe {
+        handle.raw_push(ticket.register(entry)).unwrap();
+    }
+
+    proactor.shutdown().unwrap();
+
+    let cqe = futures_util::future::FutureExt::now_or_never(fut).unwrap();
+    assert_eq!(cqe.result(), -libc::ECANCELED);
+
+    let entry = opcode::Nop::new().build();
+    assert!(unsafe { handle.raw_push(entry) }.is_err());
+
+    unsafe {
+        libc::close(fds[0]);
+        libc::close(fds[1]);
+    }
+}
+
+#[test]
+fn test_emulated_sockets() {
+    use std::net::TcpListener;
+    use std::os::unix::io::IntoRawFd;
+    use futures_util::future::{ self, FutureExt };
+
+    let mut proactor = Proactor::new().unwrap();
+    let probe = proactor.ring.probe.unprobed();
+    Rc::get_mut(&mut proactor.ring).unwrap().probe = probe;
+    let handle = proactor.raw_handle();
+
+    let push = |entry: SubmissionEntry| {
+        let (ticket, fut) = Ticket::new();
+        unsafe { handle.raw_push(ticket.register(entry)).unwrap() };
+        fut
+    };
+
+    // a blocking listener and socket, they wait for readiness on the ring
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = socket2::SockAddr::from(listener.local_addr().unwrap());
+    let listener = listener.into_raw_fd();
+    let client = unsafe { libc::socket(libc::AF_INET, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+    assert!(client >= 0);
+
+    let accept = opcode::Accept::new(types::Target::Fd(listener), ptr::null_mut(), ptr::null_mut())
+        .flags(libc::SOCK_CLOEXEC as _)
+        .build();
+    let accept = push(accept);
+    assert_eq!(handle.ring.ready.borrow().len(), 1);
+    let connect = push(opcode::Connect::new(types::Target::Fd(client), addr.as_ptr(), addr.len()).build());
+
+    let mut both = future::join(accept, connect);
+    let (accepted, connected) = loop {
+        proactor.park(Some(Duration::from_millis(5))).unwrap();
+
+        if let Some(ret) = (&mut both).now_or_never() {
+            break ret;
+        }
+    };
+
+    assert!(accepted.result() >= 0);
+    assert_eq!(connected.result(), 0);
+    assert!(handle.ring.ready.borrow().is_empty());
+    assert!(handle.ring.inflight.borrow().is_empty());
+    // the connect left the socket blocking
+    assert_eq!(unsafe { libc::fcntl(client, libc::F_GETFL) } & libc::O_NONBLOCK, 0);
+
+    let mut buf = [0u8; 8];
+    let mut recv = push(opcode::Recv::new(types::Target::Fd(accepted.result()), buf.as_mut_ptr(), 8).build());
+
+    for _ in 0..3 {
+        proactor.park(Some(Duration::from_millis(5))).unwrap();
+    }
+
+    assert!((&mut recv).now_or_never().is_none());
+    assert_eq!(unsafe { libc::send(client, b"hello".as_ptr() as *const _, 5, 0) }, 5);
+
+    let res = loop {
+        proactor.park(Some(Duration::from_millis(5))).unwrap();
+
+        if let Some(cqe) = (&mut recv).now_or_never() {
+            break cqe.result();
+        }
+    };
+    assert_eq!(res, 5);
+    assert_eq!(&buf[..5], b"hello");
+
+    // shutdown cancels the waiting ones
+    let recv = push(opcode::Recv::new(types::Target::Fd(accepted.result()), buf.as_mut_ptr(), 8).build());
+    proactor.shutdown().unwrap();
+    assert_eq!(recv.now_or_never().unwrap().result(), -libc::ECANCELED);
+
+    unsafe {
+        libc::close(listener);
+        libc::close(client);
+        libc::close(accepted.result());
+    }
+}
+
+#[test]
+fn test_emulated_reads() {
+    use std::os::unix::net::UnixDatagram;
+    use std::os::unix::io::AsRawFd;
+    use futures_util::future::{ self, FutureExt };
+
+    let mut proactor = Proactor::new().unwrap();
+    let probe = proactor.ring.probe.unprobed();
+    Rc::get_mut(&mut proactor.ring).unwrap().probe = probe;
+    let handle = proactor.raw_handle();
+    assert!(!handle.ring.probe.is_supported(opcode::Read::CODE));
+
+    let push = |entry: SubmissionEntry| {
+        let (ticket, fut) = Ticket::new();
+        unsafe { handle.raw_push(ticket.register(entry)).unwrap() };
+        fut
+    };
+
+    // reads of idle sockets wait on the ring, not on the blocking pool
+    let (a, b) = UnixDatagram::pair().unwrap();
+    let mut bufs = [[0u8; 8]; 4];
+    let reads = bufs.iter_mut()
+        .map(|buf| push(opcode::Read::new(types::Target::Fd(a.as_raw_fd()), buf.as_mut_ptr(), 8).build()))
+        .collect::<Vec<_>>();
+    assert_eq!(handle.ring.ready.borrow().len(), 4);
+    assert_eq!(handle.ring.blocking.len(), 0);
+
+    for _ in 0..3 {
+        proactor.park(Some(Duration::from_millis(5))).unwrap();
+    }
+
+    let write = push(opcode::Write::new(types::Target::Fd(b.as_raw_fd()), b"hello".as_ptr(), 5).build());
+    for _ in 0..3 {
+        b.send(b"world").unwrap();
+    }
+
+    let mut both = future::join(future::join_all(reads), write);
+    let (reads, write) = loop {
+        proactor.park(Some(Duration::from_millis(5))).unwrap();
+
+        if let Some(ret) = (&mut both).now_or_never() {
+            break ret;
+        }
+    };
+
+    assert_eq!(write.result(), 5);
+    assert!(reads.iter().all(|cqe| cqe.result() == 5));
+    assert!(handle.ring.ready.borrow().is_empty());
+    assert!(handle.ring.inflight.borrow().is_empty());
+}
+
+#[test]
+fn test_shutdown_blocking() {
+    use futures_util::future::FutureExt;
+    use crate::action::fs::temp_path;
+
+    let path = temp_path("shutdown-blocking");
+    std::fs::write(&path, "hello").unwrap();
+    let file = std::fs::File::open(&path).unwrap();
+
+    let mut proactor = Proactor::new().unwrap();
+    let probe = proactor.ring.probe.unprobed();
+    Rc::get_mut(&mut proactor.ring).unwrap().probe = probe;
+    let handle = proactor.raw_handle();
+
+    // a read the kernel "lacks" runs on the blocking pool, shutdown waits for it
+    let mut buf = [0; 8];
+    let (ticket, fut) = Ticket::new();
+    let entry = opcode::Read::new(types::Target::Fd(file.as_raw_fd()), buf.as_mut_ptr(), 8).build();
+
+    unsafe {
+        handle.raw_push(ticket.register(entry)).unwrap();
+    }
+
+    assert_eq!(handle.ring.inflight.borrow().len(), 1);
+    proactor.shutdown().unwrap();
+
+    let res = fut.now_or_never().unwrap().result();
+    assert!(res == 5 || res == -libc::ECANCELED);
+    assert!(handle.ring.inflight.borrow().is_empty());
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_drop_inflight() {
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+
+    let proactor = Proactor::new().unwrap();
+    let handle = proactor.raw_handle();
+
+    let mut buf = [0; 8];
+    let (ticket, fut) = Ticket::new();
+    let entry = opcode::Read::new(types::Target::Fd(fds[0]), buf.as_mut_ptr(), buf.len() as _)
+        .build();
+
+    unsafe {
+        handle.raw_push(ticket.register(entry)).unwrap();
+    }
+
+    drop(proactor);
+
+    assert!(handle.ring.inflight.borrow().is_empty());
+    let cqe = futures_util::future::FutureExt::now_or_never(fut).unwrap();
+    assert_eq!(cqe.result(), -libc::ECANCELED);
+
+    unsafe {
+        libc::close(fds[0]);
+        libc::close(fds[1]);
+    }
+}
+
+#[test]
+fn test_attach_wq() {
+    let proactor = Proactor::new().unwrap();
+    let mut proactor2 = Proactor::builder()
+        .attach_wq(proactor.as_raw_fd())
+        .build()
+        .unwrap();
+
+    proactor2.park(Some(Duration::from_millis(1))).unwrap();
 }
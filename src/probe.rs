@@ -0,0 +1,137 @@
+//! Kernel capability probe.
+//!
+//! The probe runs once when the `Proactor` is created,
+//! every submission is checked against it so that an opcode
+//! missing from the running kernel is either emulated, socket ops with a poll
+//! and the rest on the blocking pool, or fails with a clean error instead of
+//! an `EINVAL` completion.
+
+use std::{ io, fmt };
+use io_uring::{ opcode, IoUring };
+use crate::sys;
+
+
+pub struct Probe {
+    // `None` if the kernel does not support `IORING_REGISTER_PROBE` (before 5.6),
+    // we can't know anything in this case and let the kernel decide.
+    ops: Option<io_uring::Probe>,
+    params: io_uring::Parameters
+}
+
+impl Probe {
+    pub(crate) fn new(ring: &IoUring) -> Probe {
+        let mut probe = io_uring::Probe::new();
+
+        let ops = match ring.submitter().register_probe(&mut probe) {
+            Ok(()) => Some(probe),
+            Err(_) => None
+        };
+
+        Probe { ops, params: ring.params().clone() }
+    }
+
+    /// Nothing is supported, for the epoll backend.
+    pub(crate) fn none() -> Probe {
+        Probe {
+            ops: Some(io_uring::Probe::new()),
+            // `io_uring_params` is plain old data
+            params: unsafe { std::mem::zeroed() }
+        }
+    }
+
+    /// Like a kernel too old to be probed.
+    #[cfg(test)]
+    pub(crate) fn unprobed(&self) -> Probe {
+        Probe { ops: None, params: self.params.clone() }
+    }
+
+    /// Whether the kernel reported its opcode table.
+    #[inline]
+    pub fn is_available(&self) -> bool {
+        self.ops.is_some()
+    }
+
+    /// Whether `opcode` is known to be supported.
+    ///
+    /// A kernel too old to be probed is assumed to only support the 5.4 opcodes.
+    #[inline]
+    pub fn is_supported(&self, opcode: u8) -> bool {
+        match self.ops.as_ref() {
+            Some(probe) => probe.is_supported(opcode),
+            None => opcode <= opcode::Timeout::CODE
+        }
+    }
+
+    /// Returns an error if `opcode` is known to be unsupported.
+    ///
+    /// Unlike [Probe::is_supported], this lets the kernel decide if it can't be probed.
+    pub fn check(&self, opcode: u8) -> io::Result<()> {
+        if self.ops.is_none() || self.is_supported(opcode) {
+            Ok(())
+        } else {
+            Err(unsupported(opcode))
+        }
+    }
+
+    #[inline]
+    pub fn is_feature_nodrop(&self) -> bool {
+        self.params.is_feature_nodrop()
+    }
+
+    #[inline]
+    pub fn is_feature_submit_stable(&self) -> bool {
+        self.params.is_feature_submit_stable()
+    }
+
+    #[inline]
+    pub fn is_feature_rw_cur_pos(&self) -> bool {
+        self.params.is_feature_rw_cur_pos()
+    }
+
+    #[inline]
+    pub fn is_feature_cur_personality(&self) -> bool {
+        self.params.is_feature_cur_personality()
+    }
+
+    #[inline]
+    pub fn is_feature_fast_poll(&self) -> bool {
+        self.params.is_feature_fast_poll()
+    }
+
+    #[inline]
+    pub fn is_feature_cqe_skip(&self) -> bool {
+        sys::features(&self.params) & sys::IORING_FEAT_CQE_SKIP != 0
+    }
+}
+
+impl fmt::Debug for Probe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut list = f.debug_list();
+
+        if self.ops.is_some() {
+            for op in 0..=u8::MAX {
+                if self.is_supported(op) {
+                    list.entry(&op);
+                }
+            }
+        }
+
+        list.finish()
+    }
+}
+
+pub(crate) fn unsupported(opcode: u8) -> io::Error {
+    crate::Error::Unsupported(opcode).into()
+}
+
+
+#[test]
+fn test_probe_nop() {
+    use io_uring::opcode;
+
+    let proactor = crate::Proactor::new().unwrap();
+    let probe = proactor.probe();
+
+    assert!(probe.is_supported(opcode::Nop::CODE));
+    assert!(probe.check(opcode::Read::CODE).is_ok());
+}
@@ -0,0 +1,151 @@
+//! Ring restrictions.
+//!
+//! Once restricted, the ring refuses every submission whose opcode or
+//! submission flags are outside the allowlist, so a `Handle` can be given
+//! to less-trusted code.
+//!
+//! This mirrors `IORING_REGISTER_RESTRICTIONS`, which needs a ring created
+//! with `IORING_SETUP_R_DISABLED`. `io-uring` 0.3 can't create such a ring,
+//! so the allowlist is enforced on the submission path instead.
+//!
+//! That makes restrictions a guard against mistakes, not a security boundary:
+//! the kernel knows nothing of them, and anything holding the ring fd can
+//! still submit whatever it likes with `io_uring_enter`.
+//!
+//! The ops ritsu issues on its own — cancelling an op whose future was dropped,
+//! closing the fd of a dropped file, removing a poll or a timeout — are always
+//! allowed, whatever their flags, so a restricted ring still cleans up after itself.
+
+use std::{ io, fmt };
+use io_uring::squeue::Flags;
+use io_uring::opcode;
+use crate::sys;
+
+
+#[derive(Clone)]
+pub struct Restrictions {
+    ops: [u64; 4],
+    allowed_flags: u8,
+    required_flags: u8
+}
+
+impl Restrictions {
+    /// Nothing is allowed.
+    pub fn new() -> Restrictions {
+        Restrictions {
+            ops: [0; 4],
+            allowed_flags: 0,
+            required_flags: 0
+        }
+    }
+
+    pub fn allow_op(&mut self, opcode: u8) -> &mut Self {
+        self.ops[opcode as usize / 64] |= 1 << (opcode % 64);
+        self
+    }
+
+    pub fn allow_sqe_flags(&mut self, flags: Flags) -> &mut Self {
+        self.allowed_flags |= flags.bits();
+        self
+    }
+
+    /// Every submission must carry `flags`, this implies [Restrictions::allow_sqe_flags].
+    pub fn require_sqe_flags(&mut self, flags: Flags) -> &mut Self {
+        self.required_flags |= flags.bits();
+        self
+    }
+
+    #[inline]
+    pub fn is_allowed_op(&self, opcode: u8) -> bool {
+        self.ops[opcode as usize / 64] & (1 << (opcode % 64)) != 0
+    }
+
+    pub(crate) fn check(&self, sqe: &sys::Sqe) -> io::Result<()> {
+        if is_internal_op(sqe.opcode) {
+            return Ok(());
+        }
+
+        if !self.is_allowed_op(sqe.opcode) {
+            return Err(restricted(format!("opcode {} is not allowed on this ring", sqe.opcode)));
+        }
+
+        let allowed = self.allowed_flags | self.required_flags;
+
+        // detached ops get `IOSQE_CQE_SKIP_SUCCESS` from ritsu, not from the caller
+        let flags = if sqe.user_data == crate::WAKE_TOKEN {
+            sqe.flags & !sys::IOSQE_CQE_SKIP_SUCCESS
+        } else {
+            sqe.flags
+        };
+
+        if flags & !allowed != 0 || flags & self.required_flags != self.required_flags {
+            return Err(restricted(format!("sqe flags {:#x} are not allowed on this ring", sqe.flags)));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Restrictions {
+    fn default() -> Restrictions {
+        Restrictions::new()
+    }
+}
+
+impl fmt::Debug for Restrictions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ops = (0..=u8::MAX)
+            .filter(|&op| self.is_allowed_op(op))
+            .collect::<Vec<_>>();
+
+        f.debug_struct("Restrictions")
+            .field("ops", &ops)
+            .field("allowed_flags", &self.allowed_flags)
+            .field("required_flags", &self.required_flags)
+            .finish()
+    }
+}
+
+/// Ops that ritsu submits by itself when a future or a resource is dropped.
+#[inline]
+pub(crate) fn is_internal_op(opcode: u8) -> bool {
+    opcode == opcode::AsyncCancel::CODE
+        || opcode == opcode::Close::CODE
+        || opcode == opcode::PollRemove::CODE
+        || opcode == opcode::TimeoutRemove::CODE
+}
+
+fn restricted(msg: String) -> io::Error {
+    crate::Error::Submit(io::Error::new(io::ErrorKind::PermissionDenied, msg)).into()
+}
+
+
+#[test]
+fn test_restrictions() {
+    let mut restrictions = Restrictions::new();
+    restrictions
+        .allow_op(opcode::Read::CODE)
+        .allow_op(opcode::Nop::CODE)
+        .allow_sqe_flags(Flags::IO_LINK);
+
+    let read = opcode::Read::new(opcode::types::Target::Fd(0), std::ptr::null_mut(), 0).build();
+    let write = opcode::Write::new(opcode::types::Target::Fd(0), std::ptr::null(), 0).build();
+
+    assert!(restrictions.check(sys::sqe(&read)).is_ok());
+    assert!(restrictions.check(sys::sqe(&read.clone().flags(Flags::IO_LINK))).is_ok());
+    assert!(restrictions.check(sys::sqe(&read.flags(Flags::IO_DRAIN))).is_err());
+
+    let err = restrictions.check(sys::sqe(&write)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+
+    // ritsu's own cleanup always goes through
+    let cancel = opcode::AsyncCancel::new(1).build().flags(Flags::IO_DRAIN);
+    let close = opcode::Close::new(0).build();
+    assert!(restrictions.check(sys::sqe(&cancel)).is_ok());
+    assert!(restrictions.check(sys::sqe(&close)).is_ok());
+
+    // so does the skip flag of a detached op
+    let mut detached = opcode::Nop::new().build().user_data(crate::WAKE_TOKEN);
+    sys::sqe_mut(&mut detached).flags |= sys::IOSQE_CQE_SKIP_SUCCESS;
+    assert!(restrictions.check(sys::sqe(&detached)).is_ok());
+}
@@ -1,21 +1,63 @@
-pub mod oneshot;
+//! Synchronization between the tasks of a thread.
+//!
+//! The primitives are not `Sync`, they rely on the tasks sharing them running on
+//! the same thread, so they need no atomics and work with `spawn_local`.
+
+pub(crate) mod oneshot;
+pub(crate) mod multishot;
+mod semaphore;
+mod mutex;
+mod notify;
+
+pub use semaphore::{ Semaphore, SemaphorePermit, Acquire };
+pub use mutex::{ Mutex, MutexGuard };
+pub use notify::{ Notify, Notified };
+pub use multishot::Multishot;
 
 use std::ptr;
 use std::pin::Pin;
-use std::task::{ Context, Poll };
+use std::task::{ Context, Poll, Waker };
 use std::future::Future;
-use pin_project_lite::pin_project;
-use crate::{ SubmissionEntry, CompletionEntry };
+use std::sync::Arc;
+use std::cell::RefCell;
+use crate::cancel::Group;
+use crate::{ handle, task, SubmissionEntry, CompletionEntry, CqeResult };
+
+
+// closed channels kept around for reuse,
+// so a ticket doesn't cost an allocation on the hot path.
+thread_local!{
+    static SLOTS: RefCell<Vec<oneshot::Slot<CompletionEntry>>> = const { RefCell::new(Vec::new()) };
+}
 
+const MAX_SLOTS: usize = 1024;
+
+fn recycle(slot: oneshot::Slot<CompletionEntry>) {
+    let mut slot = Some(slot);
+
+    let _ = SLOTS.try_with(|slots| {
+        if let Ok(mut slots) = slots.try_borrow_mut() {
+            if slots.len() < MAX_SLOTS {
+                slots.extend(slot.take());
+            }
+        }
+    });
+
+    // the slot is freed here if the free list is full or gone
+    drop(slot);
+}
 
 pub struct Ticket(oneshot::Sender<CompletionEntry>);
 
 impl Ticket {
     #[inline]
     pub fn new() -> (Ticket, TicketFuture) {
-        let (tx, rx) = oneshot::channel();
+        let slot = SLOTS.try_with(|slots| slots.try_borrow_mut().ok()?.pop())
+            .ok()
+            .flatten();
+        let (tx, rx) = oneshot::channel_in(slot, Some(recycle));
 
-        (Ticket(tx), TicketFuture { fut: rx })
+        (Ticket(tx), TicketFuture { fut: rx, held: None, group: None })
     }
 
     #[inline]
@@ -23,34 +65,199 @@ impl Ticket {
         entry.user_data(self.0.into_raw().as_ptr() as _)
     }
 
+    /// Take back the ticket of an entry returned by [Ticket::register],
+    /// like when pushing it failed.
+    ///
+    /// # Safety
+    ///
+    /// `entry` must come from [Ticket::register] and never reach a ring,
+    /// and its ticket is only taken back once.
+    #[inline]
+    pub unsafe fn unregister(entry: &SubmissionEntry) -> Ticket {
+        let ptr = crate::sys::sqe(entry).user_data as *mut Ticket;
+        Ticket::from_raw(ptr::NonNull::new_unchecked(ptr))
+    }
+
+    /// Complete the op with `res` without submitting it,
+    /// like `-ECANCELED` for an entry that never reached a ring.
+    pub fn complete(self, res: i32) {
+        let ptr = self.0.into_raw();
+        let ticket = unsafe { Ticket::from_raw(ptr.cast()) };
+        ticket.send(crate::sys::completion(ptr.as_ptr() as _, res, 0));
+    }
+
     #[inline]
     pub(crate) unsafe fn from_raw(ptr: ptr::NonNull<Ticket>) -> Ticket {
         Ticket(oneshot::Sender::from_raw(ptr.cast()))
     }
 
+    /// Whether its [TicketFuture] was dropped, so nothing waits for the completion.
+    #[inline]
+    pub fn is_canceled(&self) -> bool {
+        self.0.is_canceled()
+    }
+
+    /// Completes once its [TicketFuture] is dropped.
+    #[inline]
+    pub fn poll_canceled(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        self.0.poll_canceled(cx)
+    }
+
     #[inline]
     pub(crate) fn send(self, entry: CompletionEntry) {
-        let _ = self.0.send(entry);
+        if let Some(waker) = self.send_deferred(entry) {
+            waker.wake();
+        }
+    }
+
+    /// Like [Ticket::send], but hand back the waker to wake instead of waking it.
+    #[inline]
+    pub(crate) fn send_deferred(self, entry: CompletionEntry) -> Option<Waker> {
+        // an abandoned op, just release the channel
+        if self.is_canceled() {
+            return None;
+        }
+
+        self.0.send_deferred(entry).ok().flatten()
     }
 }
 
-pin_project!{
-    pub struct TicketFuture {
-        #[pin]
-        fut: oneshot::Receiver<CompletionEntry>
+/// Completes with the completion of its [Ticket].
+///
+/// An op held back by [Builder::max_inflight](crate::Builder::max_inflight) is submitted
+/// by polling it once a slot frees up.
+///
+/// Dropping it before that cancels the op with `IORING_OP_ASYNC_CANCEL`.
+/// The resources the op references are still owned by the kernel
+/// until the cancellation completes, the `safety_await!` in actions
+/// leaks them in this case rather than freeing them under the kernel.
+pub struct TicketFuture {
+    fut: oneshot::Receiver<CompletionEntry>,
+    // the entry of a held back op, registered with the ticket of `fut`
+    held: Option<SubmissionEntry>,
+    group: Option<Arc<Group>>
+}
+
+impl TicketFuture {
+    /// The `user_data` of the op this future waits for.
+    #[inline]
+    pub fn user_data(&self) -> u64 {
+        self.fut.as_ptr() as u64
+    }
+
+    /// Whether the completion was delivered, or the ticket dropped.
+    #[inline]
+    pub(crate) fn is_done(&self) -> bool {
+        self.fut.is_closed()
+    }
+
+    /// Take the completion if it was delivered, without waiting for it.
+    pub(crate) fn try_complete(&mut self) -> Option<CqeResult> {
+        let mut cx = Context::from_waker(futures_util::task::noop_waker_ref());
+
+        match Pin::new(&mut self.fut).poll(&mut cx) {
+            Poll::Ready(Some(entry)) => {
+                if let Some(group) = self.group.take() {
+                    group.remove(self.user_data());
+                }
+
+                Some(CqeResult::new(entry))
+            },
+            Poll::Ready(None) | Poll::Pending => None
+        }
+    }
+
+    /// Submit `entry`, registered with the ticket of this future,
+    /// only once the handle is ready for it.
+    pub(crate) fn hold(mut self, entry: SubmissionEntry) -> TicketFuture {
+        self.held = Some(entry);
+        self
+    }
+
+    pub(crate) fn link(&mut self, group: Arc<Group>) {
+        group.insert(self.user_data());
+        self.group = Some(group);
     }
 }
 
 impl Future for TicketFuture {
-    type Output = CompletionEntry;
+    type Output = CqeResult;
 
     #[inline]
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let this = self.project();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        futures_util::ready!(task::poll_proceed(cx));
 
-        match this.fut.poll(cx) {
-            Poll::Ready(Some(entry)) => Poll::Ready(entry),
+        if self.held.is_some() {
+            futures_util::ready!(handle::poll_ready(cx));
+
+            let entry = self.held.take().unwrap();
+
+            if self.group.as_ref().is_some_and(|group| group.is_cancelled()) {
+                unsafe { Ticket::unregister(&entry) }.complete(-libc::ECANCELED);
+            } else {
+                unsafe { handle::push_registered(entry) };
+            }
+        }
+
+        match Pin::new(&mut self.fut).poll(cx) {
+            Poll::Ready(Some(entry)) => {
+                task::consume();
+
+                if let Some(group) = self.group.take() {
+                    group.remove(self.user_data());
+                }
+
+                Poll::Ready(CqeResult::new(entry))
+            },
             Poll::Ready(None) | Poll::Pending => Poll::Pending
         }
     }
 }
+
+impl Drop for TicketFuture {
+    fn drop(&mut self) {
+        if let Some(group) = self.group.take() {
+            group.remove(self.user_data());
+        }
+
+        // never submitted, nothing to cancel
+        if let Some(entry) = self.held.take() {
+            drop(unsafe { Ticket::unregister(&entry) });
+        }
+
+        // the ticket is still alive, so the op may still be in flight.
+        if !self.fut.is_closed() {
+            handle::cancel(self.user_data());
+        }
+    }
+}
+
+
+#[test]
+fn test_ticket_reuse() {
+    let (ticket, fut) = Ticket::new();
+    let entry = ticket.register(crate::sys::entry(0));
+    let user_data = crate::sys::sqe(&entry).user_data;
+
+    unsafe {
+        let ptr = ptr::NonNull::new_unchecked(user_data as *mut Ticket);
+        Ticket::from_raw(ptr).send(crate::sys::completion(user_data, 0, 0));
+    }
+    drop(fut);
+
+    let (_ticket, fut) = Ticket::new();
+    assert_eq!(fut.user_data(), user_data);
+}
+
+#[test]
+fn test_ticket_complete() {
+    use futures_util::future::FutureExt;
+
+    let (ticket, fut) = Ticket::new();
+    let user_data = fut.user_data();
+    ticket.complete(-libc::ECANCELED);
+
+    let cqe = fut.now_or_never().unwrap();
+    assert_eq!(cqe.result(), -libc::ECANCELED);
+    assert_eq!(cqe.user_data(), user_data);
+}
@@ -0,0 +1,249 @@
+//! Completions of an op that completes more than once, like a multishot timeout.
+//!
+//! The ring owns a reference from the push until the final completion,
+//! the one without `IORING_CQE_F_MORE`. Its `user_data` is tagged,
+//! tickets are aligned so the tag never collides with them.
+//!
+//! The kernel can't be told to wait for a slow reader, so a bounded channel
+//! cancels its op once that many completions are queued. The reader still sees
+//! every completion, up to the final `-ECANCELED`, and re-arms the op if it wants more.
+
+use std::mem;
+use std::rc::Rc;
+use std::any::Any;
+use std::pin::Pin;
+use std::cell::{ Cell, RefCell };
+use std::collections::VecDeque;
+use std::task::{ Context, Poll, Waker };
+use futures_util::stream::Stream;
+use io_uring::opcode;
+use crate::{ sys, RawHandle, SubmissionEntry, CompletionEntry };
+
+
+const TAG: u64 = 0b10;
+
+struct Shared {
+    queue: RefCell<VecDeque<CompletionEntry>>,
+    waker: RefCell<Option<Waker>>,
+    done: Cell<bool>,
+    capacity: usize,
+    overflowed: Cell<bool>,
+    // what the entry references, see `Multishot::hold`
+    resources: RefCell<Vec<Box<dyn Any>>>,
+    handle: RawHandle
+}
+
+/// The completions of a multishot op, it's cancelled on drop.
+pub struct Multishot {
+    shared: Rc<Shared>
+}
+
+#[inline]
+pub(crate) fn is_multishot(user_data: u64) -> bool {
+    user_data & TAG != 0
+}
+
+/// Queue a completion, returns the waker to wake.
+///
+/// # Safety
+///
+/// `user_data` must come from [Multishot::push] and its final completion
+/// must not have been delivered yet.
+pub(crate) unsafe fn complete(user_data: u64, entry: CompletionEntry) -> Option<Waker> {
+    let more = sys::cqe(&entry).flags & sys::IORING_CQE_F_MORE != 0;
+    let shared = Rc::from_raw((user_data & !TAG) as *const Shared);
+
+    let len = {
+        let mut queue = shared.queue.borrow_mut();
+        queue.push_back(entry);
+        queue.len()
+    };
+    let waker = shared.waker.borrow_mut().take();
+
+    // stop the op until the reader catches up
+    if more && len >= shared.capacity && !shared.overflowed.get() {
+        shared.overflowed.set(true);
+        shared.cancel();
+    }
+
+    if more {
+        // the ring keeps its reference
+        mem::forget(shared);
+    } else {
+        shared.done.set(true);
+    }
+
+    waker
+}
+
+/// Drop the reference of the ring.
+///
+/// # Safety
+///
+/// Same as [complete].
+pub(crate) unsafe fn release(user_data: u64) {
+    drop(Rc::from_raw((user_data & !TAG) as *const Shared));
+}
+
+impl Shared {
+    fn cancel(&self) {
+        let entry = opcode::AsyncCancel::new(self as *const Shared as u64 | TAG).build();
+
+        unsafe {
+            let _ = self.handle.raw_push_detached(entry);
+        }
+    }
+}
+
+impl Multishot {
+    /// Push a multishot op on `handle`, its completions queue up without bound.
+    ///
+    /// # Safety
+    ///
+    /// The resources referenced by `entry` must stay valid until the final completion.
+    #[inline]
+    pub unsafe fn push(handle: RawHandle, entry: SubmissionEntry) -> std::io::Result<Multishot> {
+        Multishot::with_capacity(handle, usize::MAX, entry)
+    }
+
+    /// Like [Multishot::push], but cancel the op once `capacity` completions are queued.
+    ///
+    /// # Safety
+    ///
+    /// Same as [Multishot::push].
+    pub unsafe fn with_capacity(handle: RawHandle, capacity: usize, entry: SubmissionEntry)
+        -> std::io::Result<Multishot>
+    {
+        let shared = Rc::new(Shared {
+            queue: RefCell::new(VecDeque::new()),
+            waker: RefCell::new(None),
+            done: Cell::new(false),
+            capacity: capacity.max(1),
+            overflowed: Cell::new(false),
+            resources: RefCell::new(Vec::new()),
+            handle
+        });
+
+        let user_data = Rc::into_raw(shared.clone()) as u64 | TAG;
+
+        if let Err(err) = shared.handle.raw_push(entry.user_data(user_data)) {
+            release(user_data);
+            return Err(err);
+        }
+
+        Ok(Multishot { shared })
+    }
+
+    #[inline]
+    pub fn user_data(&self) -> u64 {
+        Rc::as_ptr(&self.shared) as u64 | TAG
+    }
+
+    pub(crate) fn poll_next(&self, cx: &mut Context<'_>) -> Poll<Option<CompletionEntry>> {
+        if let Some(entry) = self.shared.queue.borrow_mut().pop_front() {
+            return Poll::Ready(Some(entry));
+        }
+
+        if self.shared.done.get() {
+            return Poll::Ready(None);
+        }
+
+        self.shared.waker.replace(Some(cx.waker().clone()));
+        Poll::Pending
+    }
+
+    /// Keep `res` alive until the final completion, even if this is dropped before it,
+    /// like the memory the entry references.
+    pub(crate) fn hold(&self, res: Box<dyn Any>) {
+        self.shared.resources.borrow_mut().push(res);
+    }
+
+    /// Whether the final completion was delivered.
+    #[inline]
+    pub fn is_done(&self) -> bool {
+        self.shared.done.get()
+    }
+
+    /// Whether the op was cancelled because the queue was full.
+    #[inline]
+    pub fn is_overflowed(&self) -> bool {
+        self.shared.overflowed.get()
+    }
+
+    /// Drop the queued completions, returns how many.
+    pub(crate) fn clear(&self) -> usize {
+        let mut queue = self.shared.queue.borrow_mut();
+        let n = queue.len();
+        queue.clear();
+        n
+    }
+}
+
+impl Stream for Multishot {
+    type Item = CompletionEntry;
+
+    #[inline]
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Multishot::poll_next(&self, cx)
+    }
+}
+
+impl Drop for Multishot {
+    fn drop(&mut self) {
+        // an overflowed op is being cancelled already
+        if !self.shared.done.get() && !self.shared.overflowed.get() {
+            self.shared.cancel();
+        }
+    }
+}
+
+
+#[test]
+fn test_multishot_capacity() {
+    use std::time::Duration;
+    use crate::Proactor;
+
+    let mut proactor = Proactor::new().unwrap();
+
+    let timespec = crate::time::timespec(Duration::from_millis(1));
+    let mut entry = opcode::Timeout::new(&timespec).build();
+    sys::sqe_mut(&mut entry).op_flags |= sys::IORING_TIMEOUT_MULTISHOT;
+
+    let completions = unsafe { Multishot::with_capacity(proactor.raw_handle(), 2, entry).unwrap() };
+
+    // nobody reads, the op must stop by itself
+    while !completions.is_done() {
+        proactor.park(Some(Duration::from_millis(10))).unwrap();
+    }
+
+    assert!(completions.is_overflowed());
+
+    let entries = completions.shared.queue.borrow_mut().drain(..).collect::<Vec<_>>();
+    let (last, ticks) = entries.split_last().unwrap();
+    assert!(ticks.len() >= 2);
+    assert!(ticks.iter().all(|entry| entry.result() == -libc::ETIME));
+    assert_eq!(last.result(), -libc::ECANCELED);
+}
+
+#[test]
+fn test_multishot_hold() {
+    use std::time::Duration;
+    use crate::Proactor;
+
+    let mut proactor = Proactor::new().unwrap();
+
+    let timespec = Box::new(crate::time::timespec(Duration::from_secs(60)));
+    let mut entry = opcode::Timeout::new(&*timespec).build();
+    sys::sqe_mut(&mut entry).op_flags |= sys::IORING_TIMEOUT_MULTISHOT;
+
+    let completions = unsafe { Multishot::push(proactor.raw_handle(), entry).unwrap() };
+    let timespec = Rc::new(timespec);
+    completions.hold(Box::new(timespec.clone()));
+
+    // cancelled, the timespec is freed with the final completion
+    drop(completions);
+
+    while Rc::strong_count(&timespec) != 1 {
+        proactor.park(Some(Duration::from_millis(10))).unwrap();
+    }
+}
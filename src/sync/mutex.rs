@@ -0,0 +1,119 @@
+use std::fmt;
+use std::ops::{ Deref, DerefMut };
+use std::cell::UnsafeCell;
+use super::{ Semaphore, SemaphorePermit };
+
+
+/// A lock held across awaits by the tasks of this thread.
+///
+/// Tasks get the lock in the order they asked for it.
+pub struct Mutex<T: ?Sized> {
+    sem: Semaphore,
+    value: UnsafeCell<T>
+}
+
+/// Access to the value of a [Mutex], unlocked on drop.
+#[must_use]
+pub struct MutexGuard<'a, T: ?Sized> {
+    _permit: SemaphorePermit<'a>,
+    value: &'a mut T
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Mutex<T> {
+        Mutex {
+            sem: Semaphore::new(1),
+            value: UnsafeCell::new(value)
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    pub async fn lock(&self) -> MutexGuard<'_, T> {
+        let permit = self.sem.acquire().await;
+        self.guard(permit)
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let permit = self.sem.try_acquire()?;
+        Some(self.guard(permit))
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    fn guard<'a>(&'a self, permit: SemaphorePermit<'a>) -> MutexGuard<'a, T> {
+        // the only permit is held by the guard
+        let value = unsafe { &mut *self.value.get() };
+        MutexGuard { _permit: permit, value }
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Mutex<T> {
+        Mutex::new(T::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f.debug_struct("Mutex").field("value", &&*guard).finish(),
+            None => f.debug_struct("Mutex").field("value", &format_args!("<locked>")).finish()
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        self.value
+    }
+}
+
+
+#[test]
+fn test_mutex() {
+    use std::rc::Rc;
+    use crate::executor::Runtime;
+
+    let mut runtime = Runtime::new().unwrap();
+    let mutex = Rc::new(Mutex::new(Vec::new()));
+
+    let handles = (0..3)
+        .map(|i| {
+            let mutex = mutex.clone();
+            runtime.spawn_local(async move {
+                let mut guard = mutex.lock().await;
+                guard.push(i);
+
+                // the others wait while it's held
+                crate::task::yield_now().await;
+                guard.push(i);
+            })
+        })
+        .collect::<Vec<_>>();
+
+    runtime.run_until(async {
+        for handle in handles {
+            handle.await.unwrap();
+        }
+    });
+
+    assert_eq!(*mutex.try_lock().unwrap(), [0, 0, 1, 1, 2, 2]);
+}
@@ -0,0 +1,177 @@
+use std::rc::Rc;
+use std::pin::Pin;
+use std::future::Future;
+use std::cell::{ Cell, RefCell };
+use std::collections::VecDeque;
+use std::task::{ Context, Poll, Waker };
+
+
+/// Wakes the tasks of this thread waiting for an event.
+///
+/// [Notify::notify_one] is stored if nobody waits, so a notification sent
+/// between checking a condition and waiting for it isn't lost.
+pub struct Notify {
+    permit: Cell<bool>,
+    // bumped by `notify_waiters`
+    generation: Cell<u64>,
+    waiters: RefCell<VecDeque<Rc<Waiter>>>
+}
+
+struct Waiter {
+    waker: Cell<Option<Waker>>,
+    state: Cell<State>
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Waiting,
+    NotifiedOne,
+    NotifiedAll
+}
+
+/// Waits for a notification, see [Notify::notified].
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    generation: u64,
+    waiter: Option<Rc<Waiter>>,
+    done: bool
+}
+
+impl Notify {
+    pub const fn new() -> Notify {
+        Notify {
+            permit: Cell::new(false),
+            generation: Cell::new(0),
+            waiters: RefCell::new(VecDeque::new())
+        }
+    }
+
+    /// Wait for the next notification.
+    ///
+    /// It already counts [Notify::notify_waiters] calls before it's first polled.
+    pub fn notified(&self) -> Notified<'_> {
+        Notified {
+            notify: self,
+            generation: self.generation.get(),
+            waiter: None,
+            done: false
+        }
+    }
+
+    /// Wake the oldest waiter, or let the next one through if there's none.
+    pub fn notify_one(&self) {
+        let waiter = self.waiters.borrow_mut().pop_front();
+
+        match waiter {
+            Some(waiter) => waiter.notify(State::NotifiedOne),
+            None => self.permit.set(true)
+        }
+    }
+
+    /// Wake every current waiter, it isn't stored for later ones.
+    pub fn notify_waiters(&self) {
+        self.generation.set(self.generation.get() + 1);
+
+        let waiters = std::mem::take(&mut *self.waiters.borrow_mut());
+
+        for waiter in waiters {
+            waiter.notify(State::NotifiedAll);
+        }
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Notify {
+        Notify::new()
+    }
+}
+
+impl Waiter {
+    fn notify(&self, state: State) {
+        self.state.set(state);
+
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.done {
+            return Poll::Ready(());
+        }
+
+        let notify = self.notify;
+
+        match self.waiter.as_ref() {
+            Some(waiter) if waiter.state.get() != State::Waiting => (),
+            Some(waiter) => {
+                waiter.waker.set(Some(cx.waker().clone()));
+                return Poll::Pending
+            },
+            None if notify.generation.get() != self.generation => (),
+            None if notify.permit.replace(false) => (),
+            None => {
+                let waiter = Rc::new(Waiter {
+                    waker: Cell::new(Some(cx.waker().clone())),
+                    state: Cell::new(State::Waiting)
+                });
+                notify.waiters.borrow_mut().push_back(waiter.clone());
+                self.waiter = Some(waiter);
+                return Poll::Pending
+            }
+        }
+
+        self.waiter = None;
+        self.done = true;
+        Poll::Ready(())
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        let waiter = match self.waiter.take() {
+            Some(waiter) => waiter,
+            None => return
+        };
+
+        match waiter.state.get() {
+            State::Waiting => self.notify.waiters.borrow_mut().retain(|w| !Rc::ptr_eq(w, &waiter)),
+            // pass it on, it was meant for one of the waiters.
+            State::NotifiedOne => self.notify.notify_one(),
+            State::NotifiedAll => ()
+        }
+    }
+}
+
+
+#[test]
+fn test_notify() {
+    use futures_util::future::FutureExt;
+
+    let notify = Notify::new();
+
+    // stored for the next waiter
+    notify.notify_one();
+    assert!(notify.notified().now_or_never().is_some());
+    assert!(notify.notified().now_or_never().is_none());
+
+    let mut a = Box::pin(notify.notified());
+    let mut b = Box::pin(notify.notified());
+    let c = notify.notified();
+    assert!(a.as_mut().now_or_never().is_none());
+    assert!(b.as_mut().now_or_never().is_none());
+
+    // `a` is dropped before it saw it, so `b` gets it.
+    notify.notify_one();
+    drop(a);
+    assert!(b.as_mut().now_or_never().is_some());
+
+    // counts for `c` which was never polled
+    notify.notify_waiters();
+    assert!(c.now_or_never().is_some());
+    assert!(!notify.permit.get());
+}
@@ -21,22 +21,53 @@ struct InlineRc<T>(ptr::NonNull<Inner<T>>);
 struct Inner<T> {
     state: AtomicU8,
     waker: UnsafeCell<mem::MaybeUninit<Waker>>,
+    // the waker of the sender, woken when the receiver is dropped
+    tx_waker: UnsafeCell<mem::MaybeUninit<Waker>>,
     value: UnsafeCell<mem::MaybeUninit<T>>,
+    recycle: Option<fn(Slot<T>)>
 }
 
+/// The allocation of a closed channel, ready to be reused by [channel_in].
+pub struct Slot<T>(ptr::NonNull<Inner<T>>);
+
 const WAKER_READY: u8 = 0b001;
 const VALUE_READY: u8 = 0b010;
 const CLOSED:      u8 = 0b100;
+const CANCELED:    u8 = 0b1000;
+const TX_WAKER_READY: u8 = 0b10000;
 
 
 pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
-    let inner = Box::new(Inner {
-        waker: UnsafeCell::new(mem::MaybeUninit::uninit()),
-        value: UnsafeCell::new(mem::MaybeUninit::uninit()),
-        state: AtomicU8::new(0)
-    });
+    channel_in(None, None)
+}
+
+/// Create a channel in `slot` if any,
+/// and hand its allocation to `recycle` instead of freeing it once both sides are gone.
+pub fn channel_in<T>(slot: Option<Slot<T>>, recycle: Option<fn(Slot<T>)>) -> (Sender<T>, Receiver<T>) {
+    let raw_ptr = match slot {
+        Some(slot) => {
+            let raw_ptr = slot.0;
+            mem::forget(slot);
+
+            // a slot is always cleared, and nobody else can see it
+            unsafe {
+                (*raw_ptr.as_ptr()).recycle = recycle;
+            }
 
-    let raw_ptr = ptr::NonNull::from(Box::leak(inner));
+            raw_ptr
+        },
+        None => {
+            let inner = Box::new(Inner {
+                waker: UnsafeCell::new(mem::MaybeUninit::uninit()),
+                tx_waker: UnsafeCell::new(mem::MaybeUninit::uninit()),
+                value: UnsafeCell::new(mem::MaybeUninit::uninit()),
+                state: AtomicU8::new(0),
+                recycle
+            });
+
+            ptr::NonNull::from(Box::leak(inner))
+        }
+    };
 
     (Sender(InlineRc(raw_ptr)), Receiver(InlineRc(raw_ptr)))
 }
@@ -46,6 +77,23 @@ impl<T> InlineRc<T> {
     unsafe fn as_ref(&self) -> &Inner<T> {
         self.0.as_ref()
     }
+
+    /// Free the channel if the other side was gone already,
+    /// `state` is from before this side closed it.
+    fn release(&mut self, state: u8) {
+        // check reference count
+        if state & CLOSED == CLOSED {
+            unsafe {
+                match self.as_ref().recycle {
+                    Some(recycle) => {
+                        (*self.0.as_ptr()).clear();
+                        recycle(Slot(self.0));
+                    },
+                    None => drop(Box::from_raw(self.0.as_ptr()))
+                }
+            }
+        }
+    }
 }
 
 impl<T> Sender<T> {
@@ -66,6 +114,16 @@ impl<T> Sender<T> {
     }
 
     pub fn send(self, entry: T) -> Result<(), T> {
+        if let Some(waker) = self.send_deferred(entry)? {
+            waker.wake();
+        }
+
+        Ok(())
+    }
+
+    /// Like [Sender::send], but return the waker of the receiver
+    /// instead of waking it, so the caller can wake it later.
+    pub fn send_deferred(self, entry: T) -> Result<Option<Waker>, T> {
         let this = unsafe { self.0.as_ref() };
 
         unsafe {
@@ -86,18 +144,16 @@ impl<T> Sender<T> {
             return Err(value);
         }
 
-        // take waker and wake it
+        // take waker
         if state & WAKER_READY == WAKER_READY {
             let state = this.state.fetch_and(!WAKER_READY, Ordering::AcqRel);
 
             if state & WAKER_READY == WAKER_READY {
-                unsafe {
-                    take(&this.waker).wake();
-                }
+                return Ok(Some(unsafe { take(&this.waker) }));
             }
         }
 
-        Ok(())
+        Ok(None)
     }
 
     #[inline]
@@ -106,6 +162,59 @@ impl<T> Sender<T> {
 
         this.state.load(Ordering::Relaxed) & CLOSED == CLOSED
     }
+
+    /// Whether the receiver was dropped, nothing will see a value sent from now on.
+    #[inline]
+    pub fn is_canceled(&self) -> bool {
+        let this = unsafe { self.0.as_ref() };
+
+        this.state.load(Ordering::Relaxed) & CANCELED == CANCELED
+    }
+
+    /// Completes once the receiver is dropped.
+    pub fn poll_canceled(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let this = unsafe { self.0.as_ref() };
+
+        // take waker
+        let state = this.state.fetch_and(!TX_WAKER_READY, Ordering::AcqRel);
+
+        if state & CANCELED == CANCELED {
+            // the receiver leaves the waker to us once it sees the bit cleared
+            if state & TX_WAKER_READY == TX_WAKER_READY {
+                drop(unsafe { take(&this.tx_waker) });
+            }
+
+            return Poll::Ready(());
+        }
+
+        if state & TX_WAKER_READY == TX_WAKER_READY {
+            let waker_ref = unsafe {
+                let waker_ptr = this.tx_waker
+                    .with_mut(|ptr| (&mut *ptr).as_mut_ptr());
+                &mut *waker_ptr
+            };
+
+            if !waker_ref.will_wake(cx.waker()) {
+                let _ = mem::replace(waker_ref, cx.waker().clone());
+            }
+        } else {
+            // never race with the receiver, it only takes the waker with the bit set.
+            unsafe {
+                this.tx_waker.with_mut(|ptr| &mut *ptr)
+                    .as_mut_ptr()
+                    .write(cx.waker().clone());
+            }
+        }
+
+        let state = this.state.fetch_or(TX_WAKER_READY, Ordering::AcqRel);
+
+        // check canceled again
+        if state & CANCELED == CANCELED {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
 }
 
 impl<T> Future for Receiver<T> {
@@ -171,6 +280,12 @@ impl<T> Future for Receiver<T> {
 }
 
 impl<T> Receiver<T> {
+    /// The same pointer as [Sender::into_raw] of its sender.
+    #[inline]
+    pub fn as_ptr(&self) -> *const () {
+        (self.0).0.as_ptr() as *const ()
+    }
+
     #[inline]
     pub fn is_closed(&self) -> bool {
         let this = unsafe { self.0.as_ref() };
@@ -179,23 +294,46 @@ impl<T> Receiver<T> {
     }
 }
 
-impl<T> Drop for InlineRc<T> {
+impl<T> Drop for Receiver<T> {
     fn drop(&mut self) {
         let this = unsafe { self.0.as_ref() };
 
-        let state = this.state.fetch_or(CLOSED, Ordering::AcqRel);
+        // cancel before closing, the sender can free the channel once it's closed.
+        let state = this.state.fetch_or(CANCELED, Ordering::AcqRel);
 
-        // check reference count
-        if state & CLOSED == CLOSED {
-            unsafe {
-                Box::from_raw(self.0.as_ptr());
+        let waker = if state & TX_WAKER_READY == TX_WAKER_READY {
+            // the sender may be taking it too
+            let state = this.state.fetch_and(!TX_WAKER_READY, Ordering::AcqRel);
+
+            if state & TX_WAKER_READY == TX_WAKER_READY {
+                Some(unsafe { take(&this.tx_waker) })
+            } else {
+                None
             }
+        } else {
+            None
+        };
+
+        let state = this.state.fetch_or(CLOSED, Ordering::AcqRel);
+        self.0.release(state);
+
+        if let Some(waker) = waker {
+            waker.wake();
         }
     }
 }
 
-impl<T> Drop for Inner<T> {
+impl<T> Drop for Sender<T> {
     fn drop(&mut self) {
+        let this = unsafe { self.0.as_ref() };
+
+        let state = this.state.fetch_or(CLOSED, Ordering::AcqRel);
+        self.0.release(state);
+    }
+}
+
+impl<T> Inner<T> {
+    fn clear(&mut self) {
         // we can get state safely because we hold its ownership.
         let state = load_u8(&mut self.state);
 
@@ -203,9 +341,29 @@ impl<T> Drop for Inner<T> {
             unsafe { take(&self.waker) };
         }
 
+        if state & TX_WAKER_READY == TX_WAKER_READY {
+            unsafe { take(&self.tx_waker) };
+        }
+
         if state & VALUE_READY == VALUE_READY {
             unsafe { take(&self.value) };
         }
+
+        self.state = AtomicU8::new(0);
+    }
+}
+
+impl<T> Drop for Inner<T> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<T> Drop for Slot<T> {
+    fn drop(&mut self) {
+        unsafe {
+            drop(Box::from_raw(self.0.as_ptr()));
+        }
     }
 }
 
@@ -228,3 +386,40 @@ pub fn load_u8(t: &mut AtomicU8) -> u8 {
 pub fn load_u8(t: &mut AtomicU8) -> u8 {
     *t.get_mut()
 }
+
+
+#[test]
+fn test_canceled() {
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicUsize;
+    use futures_util::task::{ waker, ArcWake };
+
+    struct Count(AtomicUsize);
+
+    impl ArcWake for Count {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    let count = Arc::new(Count(AtomicUsize::new(0)));
+    let waker = waker(count.clone());
+    let mut cx = Context::from_waker(&waker);
+
+    let (mut tx, rx) = channel::<u32>();
+    assert!(!tx.is_canceled());
+    assert!(tx.poll_canceled(&mut cx).is_pending());
+    assert!(tx.poll_canceled(&mut cx).is_pending());
+
+    drop(rx);
+    assert_eq!(count.0.load(Ordering::Relaxed), 1);
+    assert!(tx.is_canceled());
+    assert!(tx.poll_canceled(&mut cx).is_ready());
+
+    // a waker registered after the receiver is gone
+    let (mut tx, rx) = channel::<u32>();
+    drop(rx);
+    assert!(tx.poll_canceled(&mut cx).is_ready());
+    assert_eq!(tx.send(1), Err(1));
+    assert_eq!(count.0.load(Ordering::Relaxed), 1);
+}
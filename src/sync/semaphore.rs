@@ -0,0 +1,206 @@
+use std::rc::Rc;
+use std::pin::Pin;
+use std::future::Future;
+use std::cell::{ Cell, RefCell };
+use std::collections::VecDeque;
+use std::task::{ Context, Poll, Waker };
+
+
+/// Limits how many tasks of this thread hold a permit at a time.
+///
+/// Waiters are served in order, a task waiting for many permits
+/// holds back the ones queued after it.
+pub struct Semaphore {
+    permits: Cell<usize>,
+    waiters: RefCell<VecDeque<Rc<Waiter>>>
+}
+
+struct Waiter {
+    n: usize,
+    waker: Cell<Option<Waker>>,
+    granted: Cell<bool>
+}
+
+/// Permits of a [Semaphore], given back on drop.
+#[must_use]
+pub struct SemaphorePermit<'a> {
+    sem: &'a Semaphore,
+    n: usize
+}
+
+/// Waits for permits, see [Semaphore::acquire_many].
+pub struct Acquire<'a> {
+    sem: &'a Semaphore,
+    n: usize,
+    waiter: Option<Rc<Waiter>>
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            permits: Cell::new(permits),
+            waiters: RefCell::new(VecDeque::new())
+        }
+    }
+
+    #[inline]
+    pub fn available_permits(&self) -> usize {
+        self.permits.get()
+    }
+
+    pub fn add_permits(&self, n: usize) {
+        self.permits.set(self.permits.get() + n);
+        self.grant();
+    }
+
+    #[inline]
+    pub fn acquire(&self) -> Acquire<'_> {
+        self.acquire_many(1)
+    }
+
+    pub fn acquire_many(&self, n: usize) -> Acquire<'_> {
+        Acquire { sem: self, n, waiter: None }
+    }
+
+    #[inline]
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        self.try_acquire_many(1)
+    }
+
+    /// Take `n` permits if they are available and nobody is waiting.
+    pub fn try_acquire_many(&self, n: usize) -> Option<SemaphorePermit<'_>> {
+        if self.waiters.borrow().is_empty() && self.permits.get() >= n {
+            self.permits.set(self.permits.get() - n);
+            Some(SemaphorePermit { sem: self, n })
+        } else {
+            None
+        }
+    }
+
+    // hand the permits to the waiters in order.
+    fn grant(&self) {
+        let mut waiters = self.waiters.borrow_mut();
+
+        while let Some(waiter) = waiters.front() {
+            let permits = self.permits.get();
+
+            if waiter.n > permits {
+                break
+            }
+
+            self.permits.set(permits - waiter.n);
+            waiter.granted.set(true);
+
+            if let Some(waker) = waiter.waker.take() {
+                waker.wake();
+            }
+
+            waiters.pop_front();
+        }
+    }
+}
+
+impl SemaphorePermit<'_> {
+    /// Keep the permits out of the semaphore for good.
+    pub fn forget(mut self) {
+        self.n = 0;
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        if self.n != 0 {
+            self.sem.add_permits(self.n);
+        }
+    }
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = SemaphorePermit<'a>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let sem = self.sem;
+        let n = self.n;
+
+        match self.waiter.as_ref() {
+            Some(waiter) if waiter.granted.get() => {
+                self.waiter = None;
+                Poll::Ready(SemaphorePermit { sem, n })
+            },
+            Some(waiter) => {
+                waiter.waker.set(Some(cx.waker().clone()));
+                Poll::Pending
+            },
+            None => match sem.try_acquire_many(n) {
+                Some(permit) => {
+                    permit.forget();
+                    Poll::Ready(SemaphorePermit { sem, n })
+                },
+                None => {
+                    let waiter = Rc::new(Waiter {
+                        n,
+                        waker: Cell::new(Some(cx.waker().clone())),
+                        granted: Cell::new(false)
+                    });
+                    sem.waiters.borrow_mut().push_back(waiter.clone());
+                    self.waiter = Some(waiter);
+                    Poll::Pending
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let waiter = match self.waiter.take() {
+            Some(waiter) => waiter,
+            None => return
+        };
+
+        if waiter.granted.get() {
+            self.sem.add_permits(self.n);
+        } else {
+            self.sem.waiters.borrow_mut().retain(|w| !Rc::ptr_eq(w, &waiter));
+
+            // the waiters behind it may fit now
+            self.sem.grant();
+        }
+    }
+}
+
+
+#[test]
+fn test_semaphore() {
+    use futures_util::future::{ self, FutureExt };
+    use crate::executor::block_on;
+
+    let sem = Semaphore::new(2);
+
+    block_on(async {
+        let a = sem.acquire().await;
+        let many = sem.acquire_many(2);
+        futures_util::pin_mut!(many);
+        assert!(many.as_mut().now_or_never().is_none());
+
+        // queued behind `many`
+        assert!(sem.try_acquire().is_none());
+        assert_eq!(sem.available_permits(), 1);
+
+        drop(a);
+        let b = many.await;
+        assert_eq!(sem.available_permits(), 0);
+        drop(b);
+
+        // a dropped waiter doesn't hold back the others
+        let a = sem.acquire().await;
+        let mut many = Box::pin(sem.acquire_many(2));
+        assert!(future::poll_immediate(&mut many).await.is_none());
+        let mut one = Box::pin(sem.acquire());
+        assert!(future::poll_immediate(&mut one).await.is_none());
+        drop(many);
+        one.await.forget();
+        drop(a);
+        assert_eq!(sem.available_permits(), 1);
+    });
+}
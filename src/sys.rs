@@ -0,0 +1,348 @@
+//! Kernel ABI that `io-uring` 0.3 does not expose yet.
+//!
+//! `squeue::Entry` and `cqueue::Entry` are `repr(transparent)` wrappers around
+//! the kernel structures, so we can view them through our own layouts.
+
+#![allow(dead_code)]
+
+use std::{ io, mem, ptr };
+use std::os::unix::io::RawFd;
+use static_assertions::const_assert_eq;
+use io_uring::opcode;
+use crate::{ SubmissionEntry, CompletionEntry };
+
+
+pub const IORING_OP_TEE: u8 = 33;
+pub const IORING_OP_RENAMEAT: u8 = 35;
+pub const IORING_OP_UNLINKAT: u8 = 36;
+pub const IORING_OP_MKDIRAT: u8 = 37;
+pub const IORING_OP_SYMLINKAT: u8 = 38;
+pub const IORING_OP_LINKAT: u8 = 39;
+pub const IORING_OP_MSG_RING: u8 = 40;
+pub const IORING_OP_FSETXATTR: u8 = 41;
+pub const IORING_OP_SETXATTR: u8 = 42;
+pub const IORING_OP_FGETXATTR: u8 = 43;
+pub const IORING_OP_GETXATTR: u8 = 44;
+pub const IORING_OP_URING_CMD: u8 = 46;
+pub const IORING_OP_FUTEX_WAIT: u8 = 51;
+pub const IORING_OP_FUTEX_WAKE: u8 = 52;
+pub const IORING_OP_FTRUNCATE: u8 = 55;
+pub const IORING_OP_WAITID: u8 = 50;
+pub const IORING_OP_RECV_ZC: u8 = 58;
+
+// not a kernel opcode, it's always run on the blocking pool with `getdents64(2)`.
+pub const RITSU_OP_GETDENTS: u8 = u8::MAX;
+// `getaddrinfo(3)` on the blocking pool, `addr` points to an `action::net::Lookup`.
+pub const RITSU_OP_GETADDRINFO: u8 = u8::MAX - 1;
+// `flock(2)` on the blocking pool, see `action::fs::lock`.
+pub const RITSU_OP_FLOCK: u8 = u8::MAX - 2;
+
+pub const IOSQE_CQE_SKIP_SUCCESS: u8 = 1 << 6;
+
+pub const IORING_SETUP_CQSIZE: u32 = 1 << 3;
+pub const IORING_SETUP_CLAMP: u32 = 1 << 4;
+pub const IORING_SETUP_SQE128: u32 = 1 << 10;
+pub const IORING_SETUP_CQE32: u32 = 1 << 11;
+pub const IORING_SETUP_SINGLE_ISSUER: u32 = 1 << 12;
+pub const IORING_SETUP_DEFER_TASKRUN: u32 = 1 << 13;
+
+pub const IORING_OFF_SQ_RING: i64 = 0;
+pub const IORING_OFF_CQ_RING: i64 = 0x800_0000;
+pub const IORING_OFF_SQES: i64 = 0x1000_0000;
+
+pub const IORING_ENTER_GETEVENTS: u32 = 1 << 0;
+pub const IORING_ENTER_REGISTERED_RING: u32 = 1 << 4;
+
+pub const IORING_FEAT_CQE_SKIP: u32 = 1 << 11;
+
+pub const IORING_POLL_ADD_MULTI: u32 = 1 << 0;
+
+pub const IORING_TIMEOUT_MULTISHOT: u32 = 1 << 6;
+
+pub const IORING_CQE_F_BUFFER: u32 = 1 << 0;
+pub const IORING_CQE_F_MORE: u32 = 1 << 1;
+pub const IORING_CQE_F_SOCK_NONEMPTY: u32 = 1 << 2;
+pub const IORING_CQE_F_NOTIF: u32 = 1 << 3;
+pub const IORING_CQE_BUFFER_SHIFT: u32 = 16;
+
+pub const IORING_RECV_MULTISHOT: u16 = 1 << 1;
+
+pub const IORING_ASYNC_CANCEL_ALL: u32 = 1 << 0;
+pub const IORING_ASYNC_CANCEL_ANY: u32 = 1 << 2;
+
+pub const IORING_REGISTER_EVENTFD: u32 = 4;
+pub const IORING_REGISTER_PERSONALITY: u32 = 9;
+pub const IORING_UNREGISTER_PERSONALITY: u32 = 10;
+pub const IORING_REGISTER_RING_FDS: u32 = 20;
+pub const IORING_UNREGISTER_RING_FDS: u32 = 21;
+pub const IORING_REGISTER_NAPI: u32 = 27;
+pub const IORING_UNREGISTER_NAPI: u32 = 28;
+pub const IORING_REGISTER_ZCRX_IFQ: u32 = 32;
+
+pub const IORING_MEM_REGION_TYPE_USER: u32 = 1;
+
+pub const IORING_ZCRX_AREA_SHIFT: u32 = 48;
+pub const IORING_ZCRX_AREA_MASK: u64 = !((1 << IORING_ZCRX_AREA_SHIFT) - 1);
+
+/// `struct io_uring_sqe` without the unions.
+#[repr(C)]
+pub struct Sqe {
+    pub opcode: u8,
+    pub flags: u8,
+    pub ioprio: u16,
+    pub fd: i32,
+    /// `off` / `addr2` / `cmd_op`
+    pub off: u64,
+    /// `addr` / `splice_off_in`
+    pub addr: u64,
+    pub len: u32,
+    /// `rw_flags` / `poll_events` / `timeout_flags` / ...
+    pub op_flags: u32,
+    pub user_data: u64,
+    /// `buf_index` / `buf_group`
+    pub buf_index: u16,
+    pub personality: u16,
+    /// `splice_fd_in` / `file_index`
+    pub file_index: u32,
+    /// `addr3` and the start of `cmd`
+    pub addr3: u64,
+    pub pad: u64
+}
+
+/// `struct io_uring_cqe`
+#[repr(C)]
+pub struct Cqe {
+    pub user_data: u64,
+    pub res: i32,
+    pub flags: u32
+}
+
+/// `struct io_uring_cqe` of a ring set up with `IORING_SETUP_CQE32`.
+#[repr(C)]
+pub struct Cqe32 {
+    pub user_data: u64,
+    pub res: i32,
+    pub flags: u32,
+    /// `struct io_uring_zcrx_cqe` for `IORING_OP_RECV_ZC`,
+    /// the extra results of a passthrough command
+    pub big_cqe: [u64; 2]
+}
+
+/// `struct io_sqring_offsets`
+#[repr(C)]
+#[derive(Default)]
+pub struct SqringOffsets {
+    pub head: u32,
+    pub tail: u32,
+    pub ring_mask: u32,
+    pub ring_entries: u32,
+    pub flags: u32,
+    pub dropped: u32,
+    pub array: u32,
+    pub resv1: u32,
+    pub user_addr: u64
+}
+
+/// `struct io_cqring_offsets`
+#[repr(C)]
+#[derive(Default)]
+pub struct CqringOffsets {
+    pub head: u32,
+    pub tail: u32,
+    pub ring_mask: u32,
+    pub ring_entries: u32,
+    pub overflow: u32,
+    pub cqes: u32,
+    pub flags: u32,
+    pub resv1: u32,
+    pub user_addr: u64
+}
+
+/// `struct io_uring_params`, for rings `io-uring` 0.3 can't set up.
+#[repr(C)]
+#[derive(Default)]
+pub struct Params {
+    pub sq_entries: u32,
+    pub cq_entries: u32,
+    pub flags: u32,
+    pub sq_thread_cpu: u32,
+    pub sq_thread_idle: u32,
+    pub features: u32,
+    pub wq_fd: u32,
+    pub resv: [u32; 3],
+    pub sq_off: SqringOffsets,
+    pub cq_off: CqringOffsets
+}
+
+/// `struct io_uring_region_desc`
+#[repr(C)]
+#[derive(Default)]
+pub struct RegionDesc {
+    pub user_addr: u64,
+    pub size: u64,
+    pub flags: u32,
+    pub id: u32,
+    pub mmap_offset: u64,
+    pub resv: [u64; 4]
+}
+
+/// `struct io_uring_zcrx_rqe`
+#[repr(C)]
+pub struct ZcrxRqe {
+    pub off: u64,
+    pub len: u32,
+    pub pad: u32
+}
+
+/// `struct io_uring_zcrx_offsets`
+#[repr(C)]
+#[derive(Default)]
+pub struct ZcrxOffsets {
+    pub head: u32,
+    pub tail: u32,
+    pub rqes: u32,
+    pub resv2: u32,
+    pub resv: [u64; 2]
+}
+
+/// `struct io_uring_zcrx_area_reg`
+#[repr(C)]
+#[derive(Default)]
+pub struct ZcrxAreaReg {
+    pub addr: u64,
+    pub len: u64,
+    pub rq_area_token: u64,
+    pub flags: u32,
+    pub dmabuf_fd: u32,
+    pub resv2: [u64; 2]
+}
+
+/// `struct io_uring_zcrx_ifq_reg`
+#[repr(C)]
+#[derive(Default)]
+pub struct ZcrxIfqReg {
+    pub if_idx: u32,
+    pub if_rxq: u32,
+    pub rq_entries: u32,
+    pub flags: u32,
+    /// `struct io_uring_zcrx_area_reg *`
+    pub area_ptr: u64,
+    /// `struct io_uring_region_desc *`
+    pub region_ptr: u64,
+    pub offsets: ZcrxOffsets,
+    pub zcrx_id: u32,
+    pub resv2: u32,
+    pub resv: [u64; 3]
+}
+
+/// `struct io_uring_rsrc_update`
+#[repr(C)]
+pub struct RsrcUpdate {
+    pub offset: u32,
+    pub resv: u32,
+    pub data: u64
+}
+
+/// `struct io_uring_napi`
+#[repr(C)]
+#[derive(Default)]
+pub struct Napi {
+    pub busy_poll_to: u32,
+    pub prefer_busy_poll: u8,
+    pub opcode: u8,
+    pub pad: [u8; 2],
+    pub op_param: u32,
+    pub resv: u32
+}
+
+/// `struct open_how`
+#[repr(C)]
+pub struct OpenHow {
+    pub flags: u64,
+    pub mode: u64,
+    pub resolve: u64
+}
+
+const_assert_eq!(mem::size_of::<Sqe>(), 64);
+const_assert_eq!(mem::size_of::<io_uring::Parameters>(), 120);
+const_assert_eq!(mem::size_of::<Params>(), 120);
+const_assert_eq!(mem::size_of::<Cqe32>(), 32);
+const_assert_eq!(mem::size_of::<ZcrxRqe>(), 16);
+const_assert_eq!(mem::size_of::<RegionDesc>(), 64);
+const_assert_eq!(mem::size_of::<ZcrxAreaReg>(), 48);
+const_assert_eq!(mem::size_of::<ZcrxIfqReg>(), 96);
+const_assert_eq!(mem::size_of::<SubmissionEntry>(), mem::size_of::<Sqe>());
+const_assert_eq!(mem::align_of::<SubmissionEntry>(), mem::align_of::<Sqe>());
+const_assert_eq!(mem::size_of::<CompletionEntry>(), mem::size_of::<Cqe>());
+
+/// Build an empty entry for `opcode`.
+#[inline]
+pub fn entry(opcode: u8) -> SubmissionEntry {
+    let mut entry = opcode::Nop::new().build();
+    sqe_mut(&mut entry).opcode = opcode;
+    entry
+}
+
+#[inline]
+pub fn sqe(entry: &SubmissionEntry) -> &Sqe {
+    unsafe { &*(entry as *const SubmissionEntry as *const Sqe) }
+}
+
+#[inline]
+pub fn sqe_mut(entry: &mut SubmissionEntry) -> &mut Sqe {
+    unsafe { &mut *(entry as *mut SubmissionEntry as *mut Sqe) }
+}
+
+#[inline]
+pub fn completion(user_data: u64, res: i32, flags: u32) -> CompletionEntry {
+    unsafe { mem::transmute(Cqe { user_data, res, flags }) }
+}
+
+#[inline]
+pub fn cqe(entry: &CompletionEntry) -> &Cqe {
+    unsafe { &*(entry as *const CompletionEntry as *const Cqe) }
+}
+
+/// `io_uring_params.features`, `Parameters` is a newtype around it.
+#[inline]
+pub fn features(params: &io_uring::Parameters) -> u32 {
+    unsafe { *(params as *const io_uring::Parameters as *const u32).add(5) }
+}
+
+pub unsafe fn io_uring_setup(entries: u32, params: &mut Params) -> io::Result<RawFd> {
+    let ret = libc::syscall(libc::SYS_io_uring_setup, entries, params as *mut Params);
+
+    if ret >= 0 {
+        Ok(ret as _)
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+pub unsafe fn io_uring_enter(fd: RawFd, to_submit: u32, min_complete: u32, flags: u32)
+    -> io::Result<usize>
+{
+    let ret = libc::syscall(
+        libc::SYS_io_uring_enter,
+        fd, to_submit, min_complete, flags,
+        ptr::null::<libc::sigset_t>(), 0usize
+    );
+
+    if ret >= 0 {
+        Ok(ret as _)
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+pub unsafe fn io_uring_register(fd: RawFd, opcode: u32, arg: *const libc::c_void, nr_args: u32)
+    -> io::Result<i32>
+{
+    let ret = libc::syscall(libc::SYS_io_uring_register, fd, opcode, arg, nr_args);
+
+    if ret >= 0 {
+        Ok(ret as _)
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
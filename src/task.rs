@@ -0,0 +1,113 @@
+//! Cooperative scheduling.
+//!
+//! A task that keeps finding its ops complete never returns `Pending`,
+//! so nothing else on the thread runs until it does.
+//! [Runtime::set_budget](crate::executor::Runtime::set_budget) bounds how many
+//! completions a task takes in one poll, after that its op futures return `Pending`
+//! and wake the task again, so it's put back at the end of the queue.
+
+use std::pin::Pin;
+use std::cell::Cell;
+use std::future::Future;
+use std::task::{ Context, Poll };
+
+
+thread_local!{
+    // the budget of every task polled on this thread
+    static LIMIT: Cell<Option<u32>> = const { Cell::new(None) };
+    // what's left of it for the task being polled
+    static REMAINING: Cell<Option<u32>> = const { Cell::new(None) };
+}
+
+/// Returns `Pending` once, so the executor polls other tasks before this one again.
+///
+/// Use it in a long CPU-bound loop, which never returns `Pending` otherwise.
+pub fn yield_now() -> YieldNow {
+    YieldNow(false)
+}
+
+pub struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            Poll::Ready(())
+        } else {
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+/// Set the budget of the tasks polled on this thread, `None` is unlimited.
+pub(crate) fn set_limit(limit: Option<u32>) -> Option<u32> {
+    LIMIT.with(|l| l.replace(limit))
+}
+
+/// Poll a task with a fresh budget.
+pub(crate) fn budget<R>(f: impl FnOnce() -> R) -> R {
+    let limit = LIMIT.with(Cell::get);
+    let prev = REMAINING.with(|r| r.replace(limit));
+    let ret = f();
+    REMAINING.with(|r| r.set(prev));
+    ret
+}
+
+/// Whether the task being polled may take another completion.
+///
+/// Once the budget is spent, this wakes the task and returns `Pending`.
+pub(crate) fn poll_proceed(cx: &mut Context<'_>) -> Poll<()> {
+    match REMAINING.with(Cell::get) {
+        Some(0) => {
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        },
+        _ => Poll::Ready(())
+    }
+}
+
+/// Take one unit of the budget of the task being polled.
+pub(crate) fn consume() {
+    REMAINING.with(|r| {
+        if let Some(n) = r.get() {
+            r.set(Some(n.saturating_sub(1)));
+        }
+    });
+}
+
+
+#[test]
+fn test_budget() {
+    use futures_util::task::noop_waker_ref;
+    use futures_util::future::FutureExt;
+    use crate::{ sys, Ticket };
+
+    let mut cx = Context::from_waker(noop_waker_ref());
+
+    let mut futs = (0..3)
+        .map(|_| {
+            let (ticket, fut) = Ticket::new();
+            ticket.send(sys::completion(fut.user_data(), 0, 0));
+            fut
+        })
+        .collect::<Vec<_>>();
+
+    let prev = set_limit(Some(2));
+
+    budget(|| {
+        assert!(futs[0].poll_unpin(&mut cx).is_ready());
+        assert!(futs[1].poll_unpin(&mut cx).is_ready());
+        assert!(futs[2].poll_unpin(&mut cx).is_pending());
+    });
+
+    budget(|| assert!(futs[2].poll_unpin(&mut cx).is_ready()));
+
+    set_limit(prev);
+
+    let mut yield_now = yield_now();
+    assert!(yield_now.poll_unpin(&mut cx).is_pending());
+    assert!(yield_now.poll_unpin(&mut cx).is_ready());
+}
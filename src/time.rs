@@ -0,0 +1,413 @@
+//! Timers on `IORING_OP_TIMEOUT`.
+//!
+//! The kernel wakes the ring when a timeout expires,
+//! so sleeping costs no thread and no busy wakeup of the executor.
+//!
+//! Timers go through the timer wheel of the Proactor, which only arms the nearest
+//! deadline when it parks, so many timers cost a single kernel timeout.
+//! [sleep] falls back to a timeout of its own on a thread without a Proactor,
+//! like a worker of another runtime, [delay] is bound to the Proactor of its thread.
+
+use std::io;
+use std::rc::Rc;
+use std::pin::Pin;
+use std::cell::RefCell;
+use std::future::Future;
+use std::time::{ Duration, Instant };
+use std::task::{ Context, Poll };
+use io_uring::opcode::{ self, types };
+use crate::action::op::{ self, Op };
+use crate::RawHandle;
+
+mod interval;
+pub(crate) mod wheel;
+
+pub use interval::{ Interval, MissedTickBehavior, interval, interval_at };
+
+
+/// When a timer expires, either relative to when it starts or at a fixed point in time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Deadline {
+    After(Duration),
+    At(Instant)
+}
+
+impl Deadline {
+    /// The point in time, relative deadlines start now.
+    pub fn instant(self) -> Instant {
+        match self {
+            Deadline::After(dur) => Instant::now() + dur,
+            Deadline::At(instant) => instant
+        }
+    }
+
+    /// The timespec and flags of a timeout SQE.
+    ///
+    /// [Instant] is `CLOCK_MONOTONIC`, which is what `IORING_TIMEOUT_ABS` measures,
+    /// so an absolute deadline is converted once and never drifts however often it's armed.
+    pub(crate) fn timeout(self) -> (types::Timespec, types::TimeoutFlags) {
+        match self {
+            Deadline::After(dur) => (timespec(dur), types::TimeoutFlags::empty()),
+            Deadline::At(instant) => {
+                let mut now = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+
+                unsafe {
+                    libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now);
+                }
+
+                let now = Duration::new(now.tv_sec as u64, now.tv_nsec as u32);
+                let abs = now + instant.saturating_duration_since(Instant::now());
+
+                (timespec(abs), types::TimeoutFlags::ABS)
+            }
+        }
+    }
+}
+
+impl From<Duration> for Deadline {
+    #[inline]
+    fn from(dur: Duration) -> Deadline {
+        Deadline::After(dur)
+    }
+}
+
+impl From<Instant> for Deadline {
+    #[inline]
+    fn from(instant: Instant) -> Deadline {
+        Deadline::At(instant)
+    }
+}
+
+/// Completes once its deadline has passed, see [sleep].
+///
+/// It's on the wheel of the thread that polled it last. Polled on another thread,
+/// it starts over on the wheel there, and its entry on the old wheel can't be removed
+/// from this thread, so it stays until the deadline and wakes the task once for nothing.
+pub struct Sleep {
+    deadline: Deadline,
+    state: SleepState
+}
+
+enum SleepState {
+    Idle,
+    // on the wheel of the Proactor `ring`, only this thread can touch it.
+    Wheel {
+        ring: usize,
+        key: wheel::Key
+    },
+    // the timespec is kept alive until the completion, even if the future is dropped
+    Kernel(op::Submit<types::Timespec>),
+    Done
+}
+
+/// Wait until `dur` has elapsed.
+///
+/// The timer starts on the first poll, on the timer wheel of this thread,
+/// and is removed if the future is dropped before it expires.
+#[inline]
+pub fn sleep(dur: Duration) -> Sleep {
+    Sleep::new(dur)
+}
+
+/// Wait until `deadline`.
+///
+/// A deadline that has already passed completes right away.
+#[inline]
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep::new(deadline)
+}
+
+impl Sleep {
+    pub fn new(deadline: impl Into<Deadline>) -> Sleep {
+        Sleep { deadline: deadline.into(), state: SleepState::Idle }
+    }
+
+    /// Start the timer, on the wheel if this thread has one.
+    fn start(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let handle = match current() {
+            Some(handle) => handle,
+            None => return self.submit()
+        };
+
+        // a sleep that moves to another thread starts over from the same point in time.
+        let deadline = self.deadline.instant();
+        self.deadline = Deadline::At(deadline);
+
+        let key = handle.ring.timers.borrow_mut().insert(deadline, cx.waker());
+
+        match key {
+            Some(key) => {
+                self.state = SleepState::Wheel { ring: ring_id(&handle), key };
+                Poll::Pending
+            },
+            None => {
+                self.state = SleepState::Done;
+                Poll::Ready(Ok(()))
+            }
+        }
+    }
+
+    /// Submit a timeout of its own, on the next poll.
+    fn submit(&mut self) -> Poll<io::Result<()>> {
+        let (timespec, flags) = self.deadline.timeout();
+        let op = unsafe {
+            Op::new(timespec, |timespec| opcode::Timeout::new(&*timespec).flags(flags).build())
+        };
+
+        self.state = SleepState::Kernel(op.submit());
+        Poll::Pending
+    }
+}
+
+fn ring_id(handle: &RawHandle) -> usize {
+    Rc::as_ptr(&handle.ring) as usize
+}
+
+pub(crate) fn timespec(dur: Duration) -> types::Timespec {
+    types::Timespec {
+        tv_sec: dur.as_secs() as _,
+        tv_nsec: dur.subsec_nanos() as _
+    }
+}
+
+/// The result of a timeout completion, expiring is not an error.
+pub(crate) fn timeout_result(ret: i32) -> io::Result<()> {
+    if ret >= 0 || ret == -libc::ETIME {
+        Ok(())
+    } else {
+        Err(io::Error::from_raw_os_error(-ret))
+    }
+}
+
+impl Future for Sleep {
+    type Output = io::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+
+        loop {
+            match &mut this.state {
+                // then poll the timer once, so a kernel timeout registers the waker
+                SleepState::Idle => if let Poll::Ready(ret) = this.start(cx) {
+                    return Poll::Ready(ret);
+                },
+                SleepState::Wheel { ring, key } => {
+                    let (ring, key) = (*ring, *key);
+
+                    let handle = match current() {
+                        Some(handle) if ring_id(&handle) == ring => handle,
+                        // the old timer expires on its own, see `Sleep`
+                        _ => {
+                            this.state = SleepState::Idle;
+                            continue
+                        }
+                    };
+
+                    let mut timers = handle.ring.timers.borrow_mut();
+
+                    if !timers.poll(key, cx.waker()) {
+                        return Poll::Pending;
+                    }
+
+                    timers.remove(key);
+                    this.state = SleepState::Done;
+                },
+                SleepState::Kernel(fut) => {
+                    let ret = futures_util::ready!(Pin::new(fut).poll(cx));
+                    this.state = SleepState::Done;
+
+                    return Poll::Ready(ret.and_then(|(cqe, _)| timeout_result(cqe.result())));
+                },
+                SleepState::Done => return Poll::Ready(Ok(()))
+            }
+        }
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let SleepState::Wheel { ring, key } = self.state {
+            match current() {
+                Some(handle) if ring_id(&handle) == ring => handle.ring.timers.borrow_mut().remove(key),
+                _ => ()
+            }
+        }
+    }
+}
+
+thread_local!{
+    static WHEEL: RefCell<Option<RawHandle>> = const { RefCell::new(None) };
+}
+
+/// Use the timer wheel of `handle` for [delay] on this thread.
+///
+/// The executors do this for their Proactor.
+pub fn set_wheel(handle: RawHandle) {
+    WHEEL.with(|w| {
+        w.borrow_mut().replace(handle);
+    });
+}
+
+/// The Proactor of this thread, see [set_wheel].
+pub(crate) fn current() -> Option<RawHandle> {
+    WHEEL.with(|w| w.borrow().clone())
+}
+
+/// Completes at a deadline on the timer wheel of a Proactor.
+///
+/// It's only as precise as a millisecond, and never completes early.
+/// Unlike [Sleep] it's bound to its Proactor, so it isn't `Send`.
+pub struct Delay {
+    // the wheel of this thread is looked up on the first poll
+    handle: Option<RawHandle>,
+    deadline: Instant,
+    key: Option<wheel::Key>,
+    done: bool
+}
+
+/// Wait until `dur` has elapsed, on the timer wheel of this thread.
+pub fn delay(dur: Duration) -> Delay {
+    delay_until(Instant::now() + dur)
+}
+
+/// Wait until `deadline`, on the timer wheel of this thread.
+pub fn delay_until(deadline: Instant) -> Delay {
+    Delay { handle: None, deadline, key: None, done: false }
+}
+
+impl Delay {
+    pub(crate) fn new(handle: RawHandle, deadline: Instant) -> Delay {
+        Delay { handle: Some(handle), deadline, key: None, done: false }
+    }
+
+    #[inline]
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Move the deadline, the delay can be awaited again afterwards.
+    pub fn reset(&mut self, deadline: impl Into<Deadline>) {
+        self.clear();
+        self.deadline = deadline.into().instant();
+        self.done = false;
+    }
+
+    fn clear(&mut self) {
+        if let (Some(key), Some(handle)) = (self.key.take(), self.handle.as_ref()) {
+            handle.ring.timers.borrow_mut().remove(key);
+        }
+    }
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = &mut *self;
+
+        if this.done {
+            return Poll::Ready(());
+        }
+
+        let handle = this.handle.get_or_insert_with(|| {
+            current().expect("not found ritsu runtime")
+        });
+
+        let fired = {
+            let mut timers = handle.ring.timers.borrow_mut();
+
+            match this.key {
+                Some(key) => timers.poll(key, cx.waker()),
+                None => {
+                    this.key = timers.insert(this.deadline, cx.waker());
+                    this.key.is_none()
+                }
+            }
+        };
+
+        if fired {
+            this.clear();
+            this.done = true;
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Delay {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+
+#[test]
+fn test_sleep() {
+    use std::time::Instant;
+    use crate::executor::block_on;
+
+    let now = Instant::now();
+    block_on(sleep(Duration::from_millis(10))).unwrap();
+    assert!(now.elapsed() >= Duration::from_millis(10));
+
+    let deadline = Instant::now() + Duration::from_millis(10);
+    block_on(sleep_until(deadline)).unwrap();
+    assert!(Instant::now() >= deadline);
+
+    block_on(sleep_until(now)).unwrap();
+
+    // it's on the wheel, and leaves it when dropped
+    fn is_send<T: Send>(_: &T) {}
+    let fut = sleep(Duration::from_secs(60));
+    is_send(&fut);
+
+    let ret = block_on(futures_util::future::select(sleep(Duration::from_millis(1)), fut));
+    assert!(matches!(ret, futures_util::future::Either::Left(_)));
+    drop(ret);
+    WHEEL.with(|w| assert_eq!(w.borrow().as_ref().unwrap().ring.timers.borrow().len(), 0));
+}
+
+#[test]
+fn test_sleep_dropped() {
+    use futures_util::future::FutureExt;
+    use crate::{ handle, Proactor };
+
+    let mut proactor = Proactor::new().unwrap();
+
+    unsafe {
+        handle::set(handle::default_handle(proactor.raw_handle()));
+    }
+
+    // no wheel on this thread, it arms a timeout of its own
+    let mut fut = sleep(Duration::from_secs(60));
+    assert!((&mut fut).now_or_never().is_none());
+    assert!(matches!(fut.state, SleepState::Kernel(_)));
+
+    // the timespec lives until the cancelled timeout completes
+    drop(fut);
+    assert_eq!(op::orphans(), 1);
+
+    while op::orphans() != 0 {
+        proactor.park(Some(Duration::from_millis(10))).unwrap();
+    }
+}
+
+#[test]
+fn test_delay() {
+    use futures_util::future::{ self, Either };
+    use crate::executor::block_on;
+
+    let now = Instant::now();
+    block_on(delay(Duration::from_millis(10)));
+    assert!(now.elapsed() >= Duration::from_millis(10));
+
+    // the losing timer is removed from the wheel.
+    let ret = block_on(future::select(
+        delay(Duration::from_millis(1)),
+        delay(Duration::from_secs(60))
+    ));
+    assert!(matches!(ret, Either::Left(_)));
+    drop(ret);
+    WHEEL.with(|w| assert_eq!(w.borrow().as_ref().unwrap().ring.timers.borrow().len(), 0));
+}
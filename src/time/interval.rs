@@ -0,0 +1,248 @@
+use std::io;
+use std::pin::Pin;
+use std::future::Future;
+use std::time::{ Duration, Instant };
+use std::task::{ Context, Poll };
+use io_uring::opcode;
+use crate::sys;
+use crate::sync::multishot::Multishot;
+use super::{ sleep_until, timespec, Sleep };
+
+
+/// What an [Interval] does when ticks were missed,
+/// because the task was busy or the interval was not polled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MissedTickBehavior {
+    /// Fire the missed ticks back to back until caught up,
+    /// keeping the original schedule.
+    Burst,
+    /// Fire once, then restart the schedule a full period from now.
+    Delay,
+    /// Fire once, then drop the missed ticks and keep the original schedule.
+    Skip
+}
+
+/// Ticks every `period`, see [interval].
+///
+/// Deadlines are computed from the schedule rather than from when the last
+/// tick was observed, so unlike sleeping in a loop it does not drift.
+///
+/// Once it's on schedule, the ticks come from a single multishot timeout
+/// on the Proactor of this thread. Kernels without it wait for every tick on the
+/// timer wheel instead, and runtimes without a Proactor on this thread arm a timeout for every tick.
+pub struct Interval {
+    period: Duration,
+    next: Instant,
+    behavior: MissedTickBehavior,
+    sleep: Option<Sleep>,
+    // the multishot timeout, it holds its timespec
+    periodic: Option<Multishot>
+}
+
+/// Create an [Interval] whose first tick completes immediately.
+///
+/// ```
+/// use std::time::Duration;
+/// use futures_util::stream::StreamExt;
+/// use ritsu::executor::block_on;
+/// use ritsu::time::interval;
+///
+/// block_on(async {
+///     let ticks = interval(Duration::from_millis(1)).take(3);
+///     let ticks = ticks.collect::<Vec<_>>().await;
+///     assert!(ticks.into_iter().all(|tick| tick.is_ok()));
+/// });
+/// ```
+///
+/// # Panics
+///
+/// If `period` is zero.
+pub fn interval(period: Duration) -> Interval {
+    interval_at(Instant::now(), period)
+}
+
+/// Create an [Interval] whose first tick completes at `start`.
+///
+/// # Panics
+///
+/// If `period` is zero.
+pub fn interval_at(start: Instant, period: Duration) -> Interval {
+    assert!(period > Duration::from_secs(0), "interval period must be non-zero");
+
+    Interval {
+        period,
+        next: start,
+        behavior: MissedTickBehavior::Burst,
+        sleep: None,
+        periodic: None
+    }
+}
+
+impl Interval {
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    pub fn missed_tick_behavior(&self) -> MissedTickBehavior {
+        self.behavior
+    }
+
+    pub fn set_missed_tick_behavior(&mut self, behavior: MissedTickBehavior) {
+        self.behavior = behavior;
+    }
+
+    /// Restart the schedule so the next tick is a full period from now.
+    pub fn reset(&mut self) {
+        self.next = Instant::now() + self.period;
+        self.sleep = None;
+        self.periodic = None;
+    }
+
+    /// Wait for the next tick, returning its scheduled deadline.
+    pub async fn tick(&mut self) -> io::Result<Instant> {
+        futures_util::future::poll_fn(|cx| self.poll_tick(cx)).await
+    }
+
+    pub fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Instant>> {
+        if let Some(periodic) = self.periodic.as_mut() {
+            match periodic.poll_next(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(cqe)) if cqe.result() == -libc::ETIME => {
+                    let deadline = self.next;
+
+                    match self.behavior {
+                        // every completion is a tick
+                        MissedTickBehavior::Burst => self.next += self.period,
+                        MissedTickBehavior::Skip => {
+                            let missed = periodic.clear();
+                            self.next += self.period * (missed as u32 + 1);
+                        },
+                        MissedTickBehavior::Delay => if periodic.clear() == 0 {
+                            self.next += self.period;
+                        } else {
+                            // the kernel keeps the old schedule, start over.
+                            self.next = Instant::now() + self.period;
+                            self.periodic = None;
+                        }
+                    }
+
+                    return Poll::Ready(Ok(deadline));
+                },
+                Poll::Ready(Some(cqe)) if cqe.result() == -libc::EINVAL => {
+                    if let Some(handle) = super::current() {
+                        handle.timeout_multishot().set(false);
+                    }
+
+                    self.periodic = None;
+                },
+                Poll::Ready(Some(cqe)) => {
+                    self.periodic = None;
+                    return Poll::Ready(Err(io::Error::from_raw_os_error(-cqe.result())));
+                },
+                Poll::Ready(None) => self.periodic = None
+            }
+        }
+
+        if self.sleep.is_none() {
+            let now = Instant::now();
+
+            if self.next > now {
+                self.sleep = Some(sleep_until(self.next));
+            }
+        }
+
+        if let Some(fut) = self.sleep.as_mut() {
+            let ret = futures_util::ready!(Pin::new(fut).poll(cx));
+            self.sleep = None;
+            ret?;
+        }
+
+        let deadline = self.next;
+        let now = Instant::now();
+        self.next = next_deadline(self.behavior, deadline, self.period, now);
+
+        // on schedule, the kernel can take over from here.
+        if self.next == deadline + self.period && self.next > now {
+            self.arm();
+        }
+
+        Poll::Ready(Ok(deadline))
+    }
+
+    fn arm(&mut self) {
+        let handle = match super::current() {
+            Some(handle) if handle.timeout_multishot().get() => handle,
+            _ => return
+        };
+
+        let timespec = Box::new(timespec(self.period));
+        let mut entry = opcode::Timeout::new(&*timespec).build();
+        sys::sqe_mut(&mut entry).op_flags |= sys::IORING_TIMEOUT_MULTISHOT;
+
+        if let Ok(completions) = unsafe { Multishot::push(handle, entry) } {
+            completions.hold(timespec);
+            self.periodic = Some(completions);
+        }
+    }
+}
+
+fn next_deadline(behavior: MissedTickBehavior, deadline: Instant, period: Duration, now: Instant)
+    -> Instant
+{
+    let next = deadline + period;
+
+    if now < next {
+        return next;
+    }
+
+    match behavior {
+        MissedTickBehavior::Burst => next,
+        MissedTickBehavior::Delay => now + period,
+        MissedTickBehavior::Skip => {
+            let missed = (now - deadline).as_nanos() / period.as_nanos();
+            deadline + period * (missed as u32 + 1)
+        }
+    }
+}
+
+impl futures_util::stream::Stream for Interval {
+    type Item = io::Result<Instant>;
+
+    #[inline]
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_tick(cx).map(Some)
+    }
+}
+
+
+#[test]
+fn test_missed_ticks() {
+    let start = Instant::now();
+    let period = Duration::from_millis(10);
+    let now = start + Duration::from_millis(35);
+
+    assert_eq!(next_deadline(MissedTickBehavior::Burst, start, period, start), start + period);
+    assert_eq!(next_deadline(MissedTickBehavior::Burst, start, period, now), start + period);
+    assert_eq!(next_deadline(MissedTickBehavior::Delay, start, period, now), now + period);
+    assert_eq!(next_deadline(MissedTickBehavior::Skip, start, period, now), start + period * 4);
+}
+
+#[test]
+fn test_interval() {
+    use crate::executor::block_on;
+
+    let start = Instant::now();
+
+    block_on(async {
+        let mut interval = interval(Duration::from_millis(5));
+
+        for _ in 0..4 {
+            interval.tick().await.unwrap();
+        }
+
+        let multishot = super::current().unwrap().timeout_multishot().get();
+        assert!(interval.periodic.is_some() || !multishot);
+    });
+
+    assert!(start.elapsed() >= Duration::from_millis(15));
+}
@@ -0,0 +1,317 @@
+//! A hierarchical timer wheel.
+//!
+//! Deadlines are tracked in userspace with a resolution of one millisecond,
+//! the Proactor only arms a kernel timeout for the nearest one when it parks.
+//! Each of the levels has 64 slots, a slot of level `n` covers `64^n` ms,
+//! so 6 levels reach about two years, later deadlines are re-inserted as they come closer.
+//!
+//! Timers are removed lazily, a slot only counts its live entries,
+//! the stale ones are skipped once the slot expires.
+
+use std::mem;
+use std::task::Waker;
+use std::time::{ Duration, Instant };
+
+
+const LEVELS: usize = 6;
+const BITS: u32 = 6;
+const SLOTS: usize = 1 << BITS;
+const SLOT_MASK: u64 = SLOTS as u64 - 1;
+const MAX_DURATION: u64 = 1 << (BITS * LEVELS as u32);
+
+pub(crate) struct Wheel {
+    start: Instant,
+    // milliseconds since `start` that were processed
+    elapsed: u64,
+    entries: Vec<Entry>,
+    free: Vec<usize>,
+    levels: Vec<Level>,
+    len: usize
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Key {
+    index: usize,
+    gen: u64
+}
+
+struct Entry {
+    gen: u64,
+    when: u64,
+    level: usize,
+    slot: usize,
+    state: State
+}
+
+enum State {
+    Vacant,
+    Pending(Option<Waker>),
+    Fired
+}
+
+struct Level {
+    occupied: u64,
+    live: [u32; SLOTS],
+    slots: Vec<Vec<Key>>
+}
+
+impl Wheel {
+    pub(crate) fn new() -> Wheel {
+        Wheel::with_start(Instant::now())
+    }
+
+    fn with_start(start: Instant) -> Wheel {
+        let levels = (0..LEVELS)
+            .map(|_| Level {
+                occupied: 0,
+                live: [0; SLOTS],
+                slots: (0..SLOTS).map(|_| Vec::new()).collect()
+            })
+            .collect();
+
+        Wheel {
+            start,
+            elapsed: 0,
+            entries: Vec::new(),
+            free: Vec::new(),
+            levels,
+            len: 0
+        }
+    }
+
+    /// The number of pending timers.
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Start a timer, returns `None` if `deadline` has already passed.
+    pub(crate) fn insert(&mut self, deadline: Instant, waker: &Waker) -> Option<Key> {
+        // round up, a timer never fires early.
+        let when = deadline.saturating_duration_since(self.start)
+            .as_nanos()
+            .div_ceil(1_000_000)
+            .min(u64::MAX as u128) as u64;
+
+        if when <= self.elapsed {
+            return None;
+        }
+
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                self.entries.push(Entry { gen: 0, when: 0, level: 0, slot: 0, state: State::Vacant });
+                self.entries.len() - 1
+            }
+        };
+
+        let entry = &mut self.entries[index];
+        entry.when = when;
+        entry.state = State::Pending(Some(waker.clone()));
+        let key = Key { index, gen: entry.gen };
+
+        self.len += 1;
+        self.place(key);
+
+        Some(key)
+    }
+
+    /// Whether the timer has fired, otherwise it wakes `waker` when it does.
+    pub(crate) fn poll(&mut self, key: Key, waker: &Waker) -> bool {
+        let entry = match self.entries.get_mut(key.index) {
+            Some(entry) if entry.gen == key.gen => entry,
+            _ => return true
+        };
+
+        match &mut entry.state {
+            State::Pending(Some(old)) if old.will_wake(waker) => false,
+            State::Pending(old) => {
+                *old = Some(waker.clone());
+                false
+            },
+            State::Fired | State::Vacant => true
+        }
+    }
+
+    /// Stop a timer, or release a fired one.
+    pub(crate) fn remove(&mut self, key: Key) {
+        let entry = match self.entries.get_mut(key.index) {
+            Some(entry) if entry.gen == key.gen => entry,
+            _ => return
+        };
+
+        if let State::Pending(_) = entry.state {
+            let level = &mut self.levels[entry.level];
+            level.live[entry.slot] -= 1;
+
+            if level.live[entry.slot] == 0 {
+                level.occupied &= !(1 << entry.slot);
+                level.slots[entry.slot].clear();
+            }
+
+            self.len -= 1;
+        }
+
+        entry.gen = entry.gen.wrapping_add(1);
+        entry.state = State::Vacant;
+        self.free.push(key.index);
+    }
+
+    /// The deadline of the nearest slot that holds a pending timer.
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.next_expiration()
+            .map(|(_, _, deadline)| self.start + Duration::from_millis(deadline))
+    }
+
+    /// Fire every timer up to `now`, returns their wakers.
+    pub(crate) fn fire(&mut self, now: Instant) -> Vec<Waker> {
+        let now = now.saturating_duration_since(self.start).as_millis().min(u64::MAX as u128) as u64;
+        let mut wakers = Vec::new();
+
+        while let Some((level, slot, deadline)) = self.next_expiration() {
+            if deadline > now {
+                break
+            }
+
+            self.process(level, slot, deadline, &mut wakers);
+        }
+
+        self.elapsed = self.elapsed.max(now);
+
+        wakers
+    }
+
+    fn next_expiration(&self) -> Option<(usize, usize, u64)> {
+        // a lower level only holds deadlines before any slot of the higher ones.
+        self.levels.iter()
+            .enumerate()
+            .find(|(_, level)| level.occupied != 0)
+            .map(|(n, level)| {
+                let slot_range = 1u64 << (BITS * n as u32);
+                let level_range = slot_range << BITS;
+                let now_slot = (self.elapsed / slot_range) & SLOT_MASK;
+
+                let rotated = level.occupied.rotate_right(now_slot as u32);
+                let slot = (u64::from(rotated.trailing_zeros()) + now_slot) & SLOT_MASK;
+
+                let level_start = self.elapsed & !(level_range - 1);
+                let mut deadline = level_start + slot * slot_range;
+
+                // only beyond the last level, the slot is in the next round.
+                if deadline <= self.elapsed {
+                    deadline += level_range;
+                }
+
+                (n, slot as usize, deadline)
+            })
+    }
+
+    fn process(&mut self, level: usize, slot: usize, deadline: u64, wakers: &mut Vec<Waker>) {
+        let level = &mut self.levels[level];
+        let keys = mem::take(&mut level.slots[slot]);
+        level.occupied &= !(1 << slot);
+        level.live[slot] = 0;
+
+        self.elapsed = self.elapsed.max(deadline);
+
+        for key in keys {
+            let entry = &mut self.entries[key.index];
+
+            if entry.gen != key.gen || !matches!(entry.state, State::Pending(_)) {
+                continue
+            }
+
+            if entry.when <= self.elapsed {
+                if let State::Pending(Some(waker)) = mem::replace(&mut entry.state, State::Fired) {
+                    wakers.push(waker);
+                }
+
+                self.len -= 1;
+            } else {
+                self.place(key);
+            }
+        }
+    }
+
+    fn place(&mut self, key: Key) {
+        let entry = &mut self.entries[key.index];
+        let when = entry.when;
+
+        let masked = ((self.elapsed ^ when) | SLOT_MASK).min(MAX_DURATION - 1);
+        let significant = 63 - masked.leading_zeros();
+        let level = (significant / BITS) as usize;
+        let slot = ((when >> (level as u32 * BITS)) & SLOT_MASK) as usize;
+
+        entry.level = level;
+        entry.slot = slot;
+
+        let level = &mut self.levels[level];
+        level.slots[slot].push(key);
+        level.live[slot] += 1;
+        level.occupied |= 1 << slot;
+    }
+}
+
+
+#[test]
+fn test_wheel() {
+    use std::sync::Arc;
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+    use futures_task::ArcWake;
+
+    struct Counter(AtomicUsize);
+
+    impl ArcWake for Counter {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    let start = Instant::now();
+    let ms = Duration::from_millis;
+    let counter = Arc::new(Counter(AtomicUsize::new(0)));
+    let waker = futures_task::waker(counter.clone());
+    let mut wheel = Wheel::with_start(start);
+
+    let a = wheel.insert(start + ms(10), &waker).unwrap();
+    let b = wheel.insert(start + ms(100), &waker).unwrap();
+    let c = wheel.insert(start + ms(5000), &waker).unwrap();
+    let d = wheel.insert(start + Duration::from_secs(3 * 365 * 24 * 3600), &waker).unwrap();
+    let e = wheel.insert(start + ms(100), &waker).unwrap();
+    assert_eq!(wheel.len(), 5);
+    assert_eq!(wheel.next_deadline(), Some(start + ms(10)));
+
+    wheel.remove(e);
+    assert_eq!(wheel.len(), 4);
+
+    assert!(wheel.fire(start + ms(9)).is_empty());
+    assert_eq!(wheel.fire(start + ms(10)).len(), 1);
+    assert!(wheel.poll(a, &waker));
+    assert!(!wheel.poll(b, &waker));
+
+    // a higher level only knows its slot, the timer moves down when that expires.
+    assert_eq!(wheel.next_deadline(), Some(start + ms(64)));
+    assert!(wheel.fire(start + ms(99)).is_empty());
+    assert_eq!(wheel.next_deadline(), Some(start + ms(100)));
+
+    assert_eq!(wheel.fire(start + ms(4999)).len(), 1);
+    assert!(!wheel.poll(c, &waker));
+    assert!(wheel.next_deadline().unwrap() <= start + ms(5000));
+    assert_eq!(wheel.fire(start + ms(5000)).len(), 1);
+    assert!(wheel.poll(c, &waker));
+
+    // cascades through every level, and wraps past the last one.
+    assert!(wheel.fire(start + Duration::from_secs(3 * 365 * 24 * 3600) - ms(1)).is_empty());
+    assert!(!wheel.poll(d, &waker));
+    assert_eq!(wheel.fire(start + Duration::from_secs(3 * 365 * 24 * 3600)).len(), 1);
+    assert_eq!(wheel.len(), 0);
+    assert_eq!(wheel.next_deadline(), None);
+
+    // already expired
+    assert!(wheel.insert(start, &waker).is_none());
+
+    for key in [a, b, c, d] {
+        wheel.remove(key);
+    }
+    assert_eq!(wheel.free.len(), wheel.entries.len());
+}
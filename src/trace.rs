@@ -0,0 +1,86 @@
+//! Instrumentation with `tracing`, behind the `tracing` feature.
+//!
+//! Ops are identified by their `user_data`, which is the same on
+//! the submission and the completion event.
+
+/// `tracing::trace!` if the feature is enabled, nothing otherwise.
+macro_rules! trace_event {
+    ( $( $arg:tt )* ) => {
+        #[cfg(feature = "tracing")]
+        tracing::trace!( $( $arg )* );
+    }
+}
+
+
+#[cfg(feature = "tracing")]
+#[test]
+fn test_events() {
+    use std::fmt;
+    use std::sync::{ Arc, Mutex };
+    use std::collections::HashMap;
+    use tracing::{ span, Event, Metadata, Subscriber };
+    use tracing::field::{ Field, Visit };
+    use io_uring::opcode;
+    use crate::executor::block_on;
+    use crate::handle;
+
+    type Fields = HashMap<&'static str, String>;
+
+    #[derive(Default)]
+    struct Record(Arc<Mutex<Vec<Fields>>>);
+
+    struct Visitor<'a>(&'a mut Fields);
+
+    impl Visit for Visitor<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0.insert(field.name(), format!("{:?}", value));
+        }
+    }
+
+    impl Subscriber for Record {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+            span::Id::from_u64(1)
+        }
+
+        fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut fields = Fields::new();
+            event.record(&mut Visitor(&mut fields));
+            self.0.lock().unwrap().push(fields);
+        }
+
+        fn enter(&self, _: &span::Id) {}
+
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    let record = Record::default();
+    let events = record.0.clone();
+
+    let ret = tracing::subscriber::with_default(record, || block_on(async {
+        let entry = opcode::Nop::new().build();
+        unsafe { handle::push(entry).unwrap().await.result() }
+    }));
+    assert_eq!(ret, 0);
+
+    let events = events.lock().unwrap();
+    let message = |fields: &Fields, message: &str| fields.get("message").map(String::as_str) == Some(message);
+
+    let submit = events.iter()
+        .find(|fields| message(fields, "submit") && fields["opcode"] == opcode::Nop::CODE.to_string())
+        .expect("no submit event");
+    let user_data = &submit["user_data"];
+
+    let complete = events.iter()
+        .find(|fields| message(fields, "complete") && &fields["user_data"] == user_data)
+        .expect("no complete event");
+    assert_eq!(complete["res"], "0");
+    assert!(complete.contains_key("flags"));
+}
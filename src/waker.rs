@@ -1,8 +1,10 @@
 use std::fs::File;
-use std::sync::{ atomic, Arc };
-use std::io::{ self, Write };
+use std::sync::{ atomic, Arc, Mutex };
+use std::io::{ self, Read, Write };
 use std::os::unix::io::{ FromRawFd, AsRawFd, RawFd };
 use futures_task::ArcWake;
+use io_uring::IoUring;
+use crate::{ sys, WAKE_TOKEN };
 
 
 #[derive(Debug)]
@@ -37,6 +39,15 @@ impl EventFd {
         State(state)
     }
 
+    /// Clear the eventfd counter.
+    ///
+    /// Only call this when the eventfd is known to be readable,
+    /// otherwise it blocks.
+    pub fn consume(&self) {
+        let mut buf = [0; 8];
+        let _ = (&self.fd as &File).read(&mut buf);
+    }
+
     #[inline]
     pub fn reset(&self) {
         self.flag.fetch_and(!READY, atomic::Ordering::Release);
@@ -59,12 +70,19 @@ impl ArcWake for EventFd {
     fn wake_by_ref(arc_self: &Arc<Self>) {
         let EventFd { flag, fd } = &**arc_self;
 
-        let state = State(flag.fetch_and(!READY, atomic::Ordering::AcqRel));
+        // clear `PARKING` in the same step, clearing it after the write
+        // could drop the flag of the next park and lose its wakeup.
+        let state = flag.fetch_update(atomic::Ordering::AcqRel, atomic::Ordering::Acquire, |state| {
+            if state == PARKING {
+                Some(READY)
+            } else {
+                Some(state | READY)
+            }
+        });
+        let state = State(state.unwrap_or_else(|state| state));
 
         if !state.is_ready() && state.is_park() {
             let _ = (fd as &File).write(&0x1u64.to_le_bytes());
-
-            flag.fetch_and(!PARKING, atomic::Ordering::Release);
         }
     }
 }
@@ -75,3 +93,73 @@ impl AsRawFd for EventFd {
         self.fd.as_raw_fd()
     }
 }
+
+
+/// Wake a Proactor by posting a completion directly into its ring
+/// with `IORING_OP_MSG_RING` (5.18), instead of writing its eventfd.
+///
+/// Messages are sent from a small ring owned by the waker,
+/// so it can be used from any thread.
+pub struct MsgRingWaker {
+    ring: Mutex<IoUring>,
+    target: RawFd
+}
+
+impl MsgRingWaker {
+    /// `target` is the fd of the Proactor ring to wake,
+    /// it must stay open as long as the waker is used.
+    pub fn new(target: RawFd) -> io::Result<MsgRingWaker> {
+        let ring = IoUring::new(8)?;
+
+        let mut probe = io_uring::Probe::new();
+        ring.submitter().register_probe(&mut probe)?;
+
+        if !probe.is_supported(sys::IORING_OP_MSG_RING) {
+            return Err(crate::probe::unsupported(sys::IORING_OP_MSG_RING));
+        }
+
+        Ok(MsgRingWaker { ring: Mutex::new(ring), target })
+    }
+
+    /// Post a completion with `user_data` and `res` into the target ring.
+    ///
+    /// # Safety
+    ///
+    /// `user_data` belongs to ritsu on the target ring,
+    /// it must be `0` or come from [crate::Ticket::register].
+    pub unsafe fn post(&self, user_data: u64, res: i32) -> io::Result<()> {
+        let mut entry = sys::entry(sys::IORING_OP_MSG_RING);
+
+        {
+            let sqe = sys::sqe_mut(&mut entry);
+            sqe.flags = sys::IOSQE_CQE_SKIP_SUCCESS;
+            sqe.fd = self.target;
+            sqe.len = res as u32;
+            sqe.off = user_data;
+        }
+
+        let mut ring = self.ring.lock().unwrap();
+
+        // failed messages are the only completions, nobody waits for them.
+        ring.completion().available().for_each(drop);
+
+        let ret = ring.submission().available().push(entry);
+        if let Err(entry) = ret {
+            ring.submit()?;
+            ring.submission().available().push(entry)
+                .map_err(|_| io::Error::from_raw_os_error(libc::EBUSY))?;
+        }
+
+        ring.submit()?;
+
+        Ok(())
+    }
+}
+
+impl ArcWake for MsgRingWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        unsafe {
+            let _ = arc_self.post(WAKE_TOKEN, 0);
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use std::io;
+use std::thread;
+use std::fs::File;
+use ritsu::executor::Runtime;
+use tokio::io::{ AsyncReadExt, AsyncWriteExt };
+use tokio_ritsu::Handle;
+use tokio_ritsu::compat::Compat;
+
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let tokio_handle = tokio::runtime::Handle::current();
+    let (driver, handle) = Handle::new(tokio_handle);
+
+    thread::spawn(move || {
+        let mut pool = Runtime::new().unwrap();
+        let raw_handle = pool.raw_handle();
+        pool.run_until(driver.register(raw_handle))
+            .unwrap();
+    });
+
+    let fut = async move {
+        let mut input = Compat::new(File::open("./Cargo.toml")?);
+        let mut stdout = Compat::with_capacity(64, File::create("/dev/stdout")?);
+
+        // tokio's own helpers, the ops go through the ring
+        let mut header = [0; 9];
+        input.read_exact(&mut header).await?;
+        stdout.write_all(&header).await?;
+
+        let n = tokio::io::copy(&mut input, &mut stdout).await?;
+        stdout.shutdown().await?;
+
+        eprintln!("copied {} bytes", n + header.len() as u64);
+
+        Ok(()) as io::Result<()>
+    };
+
+    handle.spawn(fut).await??;
+
+    Ok(())
+}
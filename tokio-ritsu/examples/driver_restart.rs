@@ -0,0 +1,63 @@
+use std::io;
+use std::thread;
+use std::fs::File as StdFile;
+use bytes::BytesMut;
+use io_uring::opcode;
+use io_uring::squeue::Flags;
+use ritsu::executor::Runtime;
+use ritsu::action::fs;
+use ritsu::restrict::Restrictions;
+use tokio_ritsu::{ Driver, Handle };
+
+
+fn drive(driver: Driver) -> thread::JoinHandle<io::Result<()>> {
+    thread::spawn(move || {
+        let mut pool = Runtime::new()?;
+        let raw_handle = pool.raw_handle();
+
+        // everything but fsync
+        let mut restrictions = Restrictions::new();
+        for op in (0..=u8::MAX).filter(|&op| op != opcode::Fsync::CODE) {
+            restrictions.allow_op(op);
+        }
+        restrictions.allow_sqe_flags(Flags::all());
+        raw_handle.restrict(restrictions)?;
+
+        pool.run_until(driver.register(raw_handle))
+    })
+}
+
+async fn sync(handle: &Handle) -> io::Result<()> {
+    let fd = fs::File::from_std(StdFile::open("./Cargo.toml")?);
+    handle.spawn(async move { fd.sync_all().await }).await?
+}
+
+async fn read(handle: &Handle) -> io::Result<usize> {
+    let fd = fs::File::from_std(StdFile::open("./Cargo.toml")?);
+
+    handle.spawn(async move {
+        let buf = fd.read_at(0, BytesMut::with_capacity(64)).await?;
+        Ok(buf.len())
+    }).await?
+}
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let (driver, handle) = Handle::new(tokio::runtime::Handle::current());
+
+    // the thread of the Proactor is gone, ops fail instead of waiting for it
+    drop(driver);
+    let err = read(&handle).await.unwrap_err();
+    println!("without a driver: {}", err);
+
+    // and another one takes over
+    let _thread = drive(handle.driver().expect("the driver was dropped"));
+    println!("read {} bytes", read(&handle).await?);
+
+    // an op the ring refuses fails on its own, the driver keeps going
+    let err = sync(&handle).await.unwrap_err();
+    println!("restricted: {}", err);
+    println!("read {} bytes", read(&handle).await?);
+
+    Ok(())
+}
@@ -0,0 +1,44 @@
+use std::convert::Infallible;
+use hyper::{ Body, Client, Request, Response, Server };
+use hyper::service::{ make_service_fn, service_fn };
+use ritsu::Proactor;
+use tokio::task::LocalSet;
+use tokio_ritsu::Handle;
+use tokio_ritsu::net::{ Connector, Incoming };
+
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let (driver, handle) = Handle::new(tokio::runtime::Handle::current());
+
+    let local = LocalSet::new();
+    local.spawn_local(driver.drive(Proactor::new()?));
+
+    let incoming = Incoming::bind("127.0.0.1:0".parse()?)?;
+    let addr = incoming.local_addr()?;
+
+    let make_service = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+            let body = format!("hello from {}", req.uri().path());
+            Ok::<_, Infallible>(Response::new(Body::from(body)))
+        }))
+    });
+    let server = Server::builder(incoming)
+        .executor(handle.clone())
+        .serve(make_service);
+    handle.spawn(server);
+
+    let client = Client::builder()
+        .executor(handle.clone())
+        .build::<_, Body>(Connector::new());
+    let uri = format!("http://{}/ritsu", addr).parse()?;
+
+    let body = local.run_until(handle.spawn(async move {
+        let resp = client.get(uri).await?;
+        hyper::body::to_bytes(resp.into_body()).await
+    })).await??;
+
+    println!("{}", String::from_utf8_lossy(&body));
+
+    Ok(())
+}
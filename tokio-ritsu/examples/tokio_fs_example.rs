@@ -1,9 +1,9 @@
 use std::io;
-use std::thread;
 use std::fs::File as StdFile;
 use bytes::BytesMut;
-use ritsu::executor::Runtime;
+use ritsu::Proactor;
 use ritsu::action::fs;
+use tokio::task::LocalSet;
 use tokio_ritsu::Handle;
 
 
@@ -12,17 +12,14 @@ async fn main() -> io::Result<()> {
     let tokio_handle = tokio::runtime::Handle::current();
     let (driver, handle) = Handle::new(tokio_handle);
 
-    thread::spawn(move || {
-        let mut pool = Runtime::new().unwrap();
-        let raw_handle = pool.raw_handle();
-        pool.run_until(driver.register(raw_handle))
-            .unwrap();
-    });
+    // the driver runs on this thread, next to the tasks of tokio
+    let local = LocalSet::new();
+    local.spawn_local(driver.drive(Proactor::new()?));
 
     let fd = StdFile::open("./Cargo.toml")?;
     let stdout = StdFile::create("/dev/stdout")?;
-    let mut fd = fs::File::from_std(fd);
-    let mut stdout = fs::File::from_std(stdout);
+    let fd = fs::File::from_std(fd);
+    let stdout = fs::File::from_std(stdout);
 
     let fut = async move {
         let mut pos = 0;
@@ -41,7 +38,7 @@ async fn main() -> io::Result<()> {
         Ok(()) as io::Result<()>
     };
 
-    handle.spawn(fut).await??;
+    local.run_until(handle.spawn(fut)).await??;
 
     Ok(())
 }
@@ -0,0 +1,187 @@
+//! `tokio::io::{AsyncRead, AsyncWrite}` over the ring.
+//!
+//! The kernel owns the buffer of an op until it completes, so [Compat]
+//! reads into a buffer of its own and copies out of it,
+//! and copies writes into one that's written in the background.
+//! Both are [Op]s, a dropped read leaves its buffer to the ring rather than freeing it.
+
+use std::{ io, cmp };
+use std::pin::Pin;
+use std::sync::Arc;
+use std::future::Future;
+use std::task::{ ready, Context, Poll };
+use std::os::unix::io::AsRawFd;
+use bytes::{ Buf, BufMut, Bytes, BytesMut };
+use io_uring::opcode::{ self, types };
+use tokio::io::{ AsyncRead, AsyncWrite };
+use ritsu::action::op::{ Op, Submit };
+
+
+const DEFAULT_CAPACITY: usize = 8 * 1024;
+
+/// Implements the tokio io traits for any fd, like a ritsu `File` or `TcpStream`.
+///
+/// Reads and writes go at the current position of the fd.
+/// A write completes once it's copied, [AsyncWrite::poll_flush] waits for the ring,
+/// and reports the error of a background write.
+pub struct Compat<T: 'static> {
+    io: Arc<T>,
+    capacity: usize,
+    rbuf: BytesMut,
+    read: Option<Submit<(Arc<T>, BytesMut)>>,
+    write: Option<Submit<(Arc<T>, Bytes)>>
+}
+
+impl<T: AsRawFd + Send + Sync + 'static> Compat<T> {
+    #[inline]
+    pub fn new(io: T) -> Compat<T> {
+        Compat::with_capacity(DEFAULT_CAPACITY, io)
+    }
+
+    /// Read and write at most `capacity` bytes per op.
+    pub fn with_capacity(capacity: usize, io: T) -> Compat<T> {
+        Compat {
+            io: Arc::new(io),
+            capacity,
+            rbuf: BytesMut::new(),
+            read: None,
+            write: None
+        }
+    }
+
+    #[inline]
+    pub fn get_ref(&self) -> &T {
+        &self.io
+    }
+
+    /// Take `T` back, `None` while an op still holds it.
+    pub fn into_inner(self) -> Option<T> {
+        let Compat { io, read, write, .. } = self;
+
+        // a dropped op that's done releases its reference
+        drop((read, write));
+        Arc::try_unwrap(io).ok()
+    }
+
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let io = &self.io;
+        let capacity = self.capacity;
+        let read = self.read.get_or_insert_with(|| {
+            let buf = BytesMut::with_capacity(capacity);
+
+            unsafe {
+                Op::new((io.clone(), buf), |(io, buf)| {
+                    let bytes = buf.bytes_mut();
+                    opcode::Read::new(fd(io), bytes.as_mut_ptr() as *mut _, bytes.len() as _)
+                        .offset(-1)
+                        .build()
+                })
+            }.submit()
+        });
+
+        let ret = ready!(Pin::new(read).poll(cx));
+        self.read = None;
+
+        let (cqe, (_, mut buf)) = ret?;
+        let n = cqe.bytes()?;
+
+        unsafe {
+            buf.advance_mut(n);
+        }
+
+        self.rbuf = buf;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_written(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while let Some(write) = self.write.as_mut() {
+            let ret = ready!(Pin::new(write).poll(cx));
+            self.write = None;
+
+            let (cqe, (_, mut buf)) = ret?;
+            let n = cqe.bytes()?;
+
+            if n == 0 && !buf.is_empty() {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+
+            buf.advance(n);
+
+            // the rest of a short write
+            if !buf.is_empty() {
+                self.write = Some(self.submit_write(buf));
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn submit_write(&self, buf: Bytes) -> Submit<(Arc<T>, Bytes)> {
+        unsafe {
+            Op::new((self.io.clone(), buf), |(io, buf)| {
+                opcode::Write::new(fd(io), buf.as_ptr(), buf.len() as _)
+                    .offset(-1)
+                    .build()
+            }).submit()
+        }
+    }
+}
+
+impl<T: AsRawFd + Send + Sync + 'static> AsyncRead for Compat<T> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        if this.rbuf.is_empty() {
+            // an empty fill is the end of the stream
+            ready!(this.poll_fill(cx))?;
+        }
+
+        let n = cmp::min(buf.len(), this.rbuf.len());
+        buf[..n].copy_from_slice(&this.rbuf[..n]);
+        this.rbuf.advance(n);
+
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<T: AsRawFd + Send + Sync + 'static> AsyncWrite for Compat<T> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+
+        ready!(this.poll_written(cx))?;
+
+        let n = cmp::min(buf.len(), this.capacity);
+        this.write = Some(this.submit_write(Bytes::copy_from_slice(&buf[..n])));
+
+        Poll::Ready(Ok(n))
+    }
+
+    #[inline]
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_written(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_written(cx))?;
+
+        // half-close a socket, there's nothing to do for other fds
+        if unsafe { libc::shutdown(self.io.as_raw_fd(), libc::SHUT_WR) } < 0 {
+            let err = io::Error::last_os_error();
+
+            if err.raw_os_error() != Some(libc::ENOTSOCK) {
+                return Poll::Ready(Err(err));
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[inline]
+fn fd<T: AsRawFd>(io: &Arc<T>) -> types::Target {
+    types::Target::Fd(io.as_raw_fd())
+}
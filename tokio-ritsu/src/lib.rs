@@ -1,14 +1,23 @@
+pub mod compat;
+#[cfg(feature = "hyper")]
+pub mod net;
+
 use std::{ io, mem };
 use std::pin::Pin;
+use std::sync::{ Arc, Mutex, Weak };
 use std::task::{ Context, Poll };
-use std::future::Future;
-use tokio::runtime;
+use std::future::{ self as future, Future };
+use std::os::unix::io::{ AsRawFd, RawFd };
+use tokio::{ runtime, time };
+use tokio::io::PollEvented;
 use tokio::task::JoinHandle;
 use tokio::sync::mpsc;
 use pin_project_lite::pin_project;
+use io_uring::opcode;
 use ritsu::action::{ Handle as TaskHandle, HandleVTable };
+use ritsu::error::errno;
 use ritsu::{
-    RawHandle,
+    Proactor, RawHandle,
     Ticket, TicketFuture,
     SubmissionEntry
 };
@@ -21,14 +30,68 @@ pub struct Handle {
 }
 
 #[derive(Clone)]
-struct InnerHandle(mpsc::UnboundedSender<SubmissionEntry>);
+struct InnerHandle(Arc<Shared>);
+
+struct Shared {
+    tx: mpsc::UnboundedSender<Submission>,
+    // the queue while no Driver holds it, submissions fail then
+    idle: Mutex<Option<mpsc::UnboundedReceiver<Submission>>>
+}
 
-pub struct Driver(mpsc::UnboundedReceiver<SubmissionEntry>);
+/// Pushes the submissions of a [Handle] to a Proactor.
+///
+/// Dropping it, like when the thread driving it panics, fails the ops that were queued
+/// and the ones submitted after, until [Handle::driver] registers a new one.
+pub struct Driver {
+    rx: Option<mpsc::UnboundedReceiver<Submission>>,
+    shared: Weak<Shared>
+}
+
+// the tickets are registered by the Driver, so the ones it never pushes can be completed.
+enum Submission {
+    One(Ticket, SubmissionEntry),
+    Detached(SubmissionEntry),
+    Linked(Vec<(Ticket, SubmissionEntry)>)
+}
 
 impl Handle {
     pub fn new(tokio: runtime::Handle) -> (Driver, Handle) {
         let (tx, rx) = mpsc::unbounded_channel();
-        (Driver(rx), Handle { inner: InnerHandle(tx), tokio })
+        let shared = Arc::new(Shared { tx, idle: Mutex::new(None) });
+        let driver = Driver { rx: Some(rx), shared: Arc::downgrade(&shared) };
+
+        (driver, Handle { inner: InnerHandle(shared), tokio })
+    }
+
+    /// A handle that spawns on another runtime, with the same Driver.
+    pub fn with_runtime(&self, tokio: runtime::Handle) -> Handle {
+        Handle { inner: self.inner.clone(), tokio }
+    }
+
+    /// A new Driver once the previous one is gone, `None` while it's still there.
+    ///
+    /// ```no_run
+    /// # use std::thread;
+    /// # use ritsu::executor::Runtime;
+    /// # fn f(handle: tokio_ritsu::Handle) {
+    /// // restart the thread of the Proactor if it dies
+    /// loop {
+    ///     let driver = handle.driver().expect("one thread drives it");
+    ///     let thread = thread::spawn(move || {
+    ///         let mut pool = Runtime::new().unwrap();
+    ///         let raw_handle = pool.raw_handle();
+    ///         pool.run_until(driver.register(raw_handle))
+    ///     });
+    ///
+    ///     if let Ok(Ok(())) = thread.join() {
+    ///         break
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub fn driver(&self) -> Option<Driver> {
+        let rx = self.inner.0.idle.lock().unwrap().take()?;
+        Some(Driver { rx: Some(rx), shared: Arc::downgrade(&self.inner.0) })
     }
 
     pub fn enter<R, F: FnOnce() -> R>(&self, f: F) -> R {
@@ -41,12 +104,14 @@ impl Handle {
         f()
     }
 
+    /// Spawn `fut` on the runtime, its ops go to the Driver.
+    ///
+    /// Without a Driver they fail with [ritsu::Error::Closed] rather than wait for one.
     pub fn spawn<F>(&self, fut: F) -> JoinHandle<F::Output>
     where
         F: Future + Send + 'static,
         F::Output: Send + 'static
     {
-
         self.tokio.spawn(WithFuture {
             handle: self.inner.clone(),
             fut
@@ -55,20 +120,194 @@ impl Handle {
 }
 
 impl Driver {
+    /// Push the submissions to the Proactor of `handle`, it returns once every [Handle] is dropped.
+    ///
+    /// An op that fails to push completes with the error, only a closed Proactor stops the Driver.
     pub async fn register(mut self, handle: RawHandle) -> io::Result<()> {
-        while let Some(sqe) = self.0.recv().await {
+        while let Some(submission) = self.rx().recv().await {
             unsafe {
-                handle.raw_push(sqe)?;
+                submission.push(&handle)?;
             }
         }
 
         Ok(())
     }
+
+    #[inline]
+    fn rx(&mut self) -> &mut mpsc::UnboundedReceiver<Submission> {
+        self.rx.as_mut().expect("the queue is only taken on drop")
+    }
+
+    /// Drive `proactor` from the tokio reactor, without a thread of its own.
+    ///
+    /// The ring fd is registered with tokio, and its completions are dispatched
+    /// when it's readable. The Proactor isn't `Send`,
+    /// so this must run on a `LocalSet`. It returns once every [Handle] is dropped,
+    /// the worker threads that polled a spawned task keep one though.
+    ///
+    /// Like [Driver::register], an op that fails to push completes with the error.
+    pub async fn drive(mut self, mut proactor: Proactor) -> io::Result<()> {
+        let handle = proactor.raw_handle();
+        let ring = PollEvented::new(RingFd(proactor.as_raw_fd()))?;
+        let mut delay: Option<time::Delay> = None;
+        let mut closed = false;
+
+        future::poll_fn(|cx| loop {
+            while !closed {
+                match self.rx().poll_recv(cx) {
+                    Poll::Ready(Some(submission)) => unsafe { submission.push(&handle)? },
+                    Poll::Ready(None) => closed = true,
+                    Poll::Pending => break
+                }
+            }
+
+            if let Poll::Ready(ret) = ring.poll_read_ready(cx, mio::Ready::readable()) {
+                ret?;
+                ring.clear_read_ready(cx, mio::Ready::readable())?;
+            }
+
+            proactor.try_park()?;
+
+            if closed {
+                return Poll::Ready(Ok(()));
+            }
+
+            // the timers of the wheel don't wake the ring
+            match proactor.next_deadline() {
+                Some(deadline) => {
+                    let deadline = time::Instant::from_std(deadline);
+                    let delay = delay.get_or_insert_with(|| time::delay_until(deadline));
+
+                    if delay.deadline() != deadline {
+                        delay.reset(deadline);
+                    }
+
+                    if Pin::new(delay).poll(cx).is_pending() {
+                        return Poll::Pending;
+                    }
+                },
+                None => return Poll::Pending
+            }
+        }).await
+    }
+}
+
+impl Drop for Driver {
+    fn drop(&mut self) {
+        if let (Some(mut rx), Some(shared)) = (self.rx.take(), self.shared.upgrade()) {
+            let mut idle = shared.idle.lock().unwrap();
+
+            while let Ok(submission) = rx.try_recv() {
+                submission.cancel();
+            }
+
+            *idle = Some(rx);
+        }
+    }
+}
+
+impl Submission {
+    /// Push the entries, the tickets of the ones that fail complete with the error.
+    ///
+    /// Only fails once the Proactor is closed.
+    unsafe fn push(self, handle: &RawHandle) -> io::Result<()> {
+        let ret = match self {
+            Submission::One(ticket, sqe) => {
+                let sqe = ticket.register(sqe);
+                let ret = handle.raw_push(sqe.clone());
+
+                if let Err(err) = &ret {
+                    Ticket::unregister(&sqe).complete(-errno(err));
+                }
+
+                ret
+            },
+            Submission::Detached(sqe) => handle.raw_push_detached(sqe),
+            Submission::Linked(sqes) => {
+                let sqes = sqes.into_iter()
+                    .map(|(ticket, sqe)| ticket.register(sqe))
+                    .collect::<Vec<_>>();
+                let ret = handle.raw_push_linked(&sqes);
+
+                if let Err(err) = &ret {
+                    for sqe in &sqes {
+                        Ticket::unregister(sqe).complete(-errno(err));
+                    }
+                }
+
+                ret
+            }
+        };
+
+        match ret {
+            Err(err) if is_closed(&err) => Err(err),
+            _ => Ok(())
+        }
+    }
+
+    /// Complete the tickets, the entries never reach a ring.
+    fn cancel(self) {
+        match self {
+            Submission::One(ticket, _) => ticket.complete(-libc::ECANCELED),
+            Submission::Detached(_) => (),
+            Submission::Linked(sqes) => for (ticket, _) in sqes {
+                ticket.complete(-libc::ECANCELED);
+            }
+        }
+    }
+}
+
+impl InnerHandle {
+    fn send(&self, submission: Submission) -> io::Result<()> {
+        let idle = self.0.idle.lock().unwrap();
+
+        if idle.is_none() && self.0.tx.send(submission).is_ok() {
+            Ok(())
+        } else {
+            Err(ritsu::Error::Closed.into())
+        }
+    }
+
+    /// Like [InnerHandle::send], but the tickets complete with `ECANCELED`
+    /// if the driver is gone.
+    fn send_or_cancel(&self, submission: Submission) {
+        let idle = self.0.idle.lock().unwrap();
+
+        if idle.is_some() {
+            submission.cancel();
+        } else if let Err(mpsc::error::SendError(submission)) = self.0.tx.send(submission) {
+            submission.cancel();
+        }
+    }
+}
+
+fn is_closed(err: &io::Error) -> bool {
+    matches!(ritsu::Error::from_io(err), Some(ritsu::Error::Closed))
+}
+
+struct RingFd(RawFd);
+
+impl mio::Evented for RingFd {
+    fn register(&self, poll: &mio::Poll, token: mio::Token, interest: mio::Ready, opts: mio::PollOpt)
+        -> io::Result<()>
+    {
+        mio::unix::EventedFd(&self.0).register(poll, token, interest, opts)
+    }
+
+    fn reregister(&self, poll: &mio::Poll, token: mio::Token, interest: mio::Ready, opts: mio::PollOpt)
+        -> io::Result<()>
+    {
+        mio::unix::EventedFd(&self.0).reregister(poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
+        mio::unix::EventedFd(&self.0).deregister(poll)
+    }
 }
 
 fn create_handle(handle: InnerHandle) -> TaskHandle {
     static VTABLE: HandleVTable = HandleVTable {
-        push, clone, drop
+        push, push_linked, push_detached, poll_ready, push_registered, cancel, clone, drop
     };
 
     unsafe fn push(ptr: *const (), entry: SubmissionEntry) -> io::Result<TicketFuture> {
@@ -76,15 +315,60 @@ fn create_handle(handle: InnerHandle) -> TaskHandle {
 
         let (ticket, fut) = Ticket::new();
 
-        let reg = handle.0.send(ticket.register(entry));
+        let ret = handle.send(Submission::One(ticket, entry));
         mem::forget(handle);
 
-        ret.map_err(|_| io::Error::new(
-            io::ErrorKind::Other,
-            "tokio-ritsu driver closed"
-        ))?;
+        ret.map(|_| fut)
+    }
+
+    unsafe fn push_linked(ptr: *const (), entries: &[SubmissionEntry]) -> io::Result<Vec<TicketFuture>> {
+        let handle = Box::from_raw(ptr as *mut InnerHandle);
 
-        Ok(fut)
+        let mut futs = Vec::with_capacity(entries.len());
+        let entries = entries.iter()
+            .map(|entry| {
+                let (ticket, fut) = Ticket::new();
+                futs.push(fut);
+                (ticket, entry.clone())
+            })
+            .collect();
+
+        let ret = handle.send(Submission::Linked(entries));
+        mem::forget(handle);
+
+        ret.map(|_| futs)
+    }
+
+    unsafe fn push_detached(ptr: *const (), entry: SubmissionEntry) -> io::Result<()> {
+        let handle = Box::from_raw(ptr as *mut InnerHandle);
+
+        let ret = handle.send(Submission::Detached(entry));
+        mem::forget(handle);
+
+        ret
+    }
+
+    // the driver pushes everything it receives, nothing is held back.
+    unsafe fn poll_ready(_: *const (), _: &mut Context<'_>) -> Poll<()> {
+        Poll::Ready(())
+    }
+
+    unsafe fn push_registered(ptr: *const (), entry: SubmissionEntry) {
+        let handle = Box::from_raw(ptr as *mut InnerHandle);
+
+        let ticket = Ticket::unregister(&entry);
+        handle.send_or_cancel(Submission::One(ticket, entry));
+
+        mem::forget(handle);
+    }
+
+    unsafe fn cancel(ptr: *const (), user_data: u64) {
+        let handle = Box::from_raw(ptr as *mut InnerHandle);
+
+        let entry = opcode::AsyncCancel::new(user_data).build();
+        let _ = handle.send(Submission::Detached(entry));
+
+        mem::forget(handle);
     }
 
     unsafe fn clone(ptr: *const ()) -> TaskHandle {
@@ -96,7 +380,7 @@ fn create_handle(handle: InnerHandle) -> TaskHandle {
     }
 
     unsafe fn drop(ptr: *const ()) {
-        Box::from_raw(ptr as *mut InnerHandle);
+        let _ = Box::from_raw(ptr as *mut InnerHandle);
     }
 
     let handle = Box::new(handle);
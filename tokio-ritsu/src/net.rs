@@ -0,0 +1,148 @@
+//! Run hyper on sockets whose I/O goes through the ring.
+//!
+//! [Incoming] accepts the connections of a hyper `Server`, and [Connector] connects
+//! the ones of a hyper `Client`, both hand out a [Compat] over a ritsu `TcpStream`.
+//! Their ops go to the Driver of a [Handle], so the server or client
+//! must use it as its executor, and run in a task spawned by it.
+//!
+//! ```no_run
+//! use std::convert::Infallible;
+//! use hyper::{ Body, Request, Response, Server };
+//! use hyper::service::{ make_service_fn, service_fn };
+//! use tokio_ritsu::Handle;
+//! use tokio_ritsu::net::Incoming;
+//!
+//! # async fn f(handle: Handle) -> anyhow::Result<()> {
+//! let incoming = Incoming::bind("127.0.0.1:3000".parse()?)?;
+//! let make_service = make_service_fn(|_| async {
+//!     Ok::<_, Infallible>(service_fn(|_: Request<Body>| async {
+//!         Ok::<_, Infallible>(Response::new(Body::from("hello")))
+//!     }))
+//! });
+//! let server = Server::builder(incoming)
+//!     .executor(handle.clone())
+//!     .serve(make_service);
+//!
+//! handle.spawn(server).await??;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{ io, net, ptr };
+use std::pin::Pin;
+use std::sync::Arc;
+use std::future::Future;
+use std::task::{ ready, Context, Poll };
+use std::os::unix::io::{ AsRawFd, FromRawFd };
+use io_uring::opcode::{ self, types };
+use hyper::Uri;
+use hyper::client::connect::{ Connected, Connection };
+use hyper::server::accept::Accept;
+use hyper::service::Service;
+use ritsu::action::op::{ Op, Submit };
+use ritsu::action::tcp::TcpStream;
+use crate::Handle;
+use crate::compat::Compat;
+
+
+/// The connections of a listener, for `hyper::Server::builder`.
+pub struct Incoming {
+    listener: Arc<net::TcpListener>,
+    accept: Option<Submit<Arc<net::TcpListener>>>
+}
+
+/// Connects the `http` URIs of a `hyper::Client`.
+#[derive(Clone, Debug, Default)]
+pub struct Connector(());
+
+impl Incoming {
+    pub fn from_std(listener: net::TcpListener) -> Incoming {
+        Incoming { listener: Arc::new(listener), accept: None }
+    }
+
+    pub fn bind(addr: net::SocketAddr) -> io::Result<Incoming> {
+        net::TcpListener::bind(addr).map(Incoming::from_std)
+    }
+
+    #[inline]
+    pub fn local_addr(&self) -> io::Result<net::SocketAddr> {
+        self.listener.local_addr()
+    }
+}
+
+impl Accept for Incoming {
+    type Conn = Compat<TcpStream>;
+    type Error = io::Error;
+
+    fn poll_accept(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<io::Result<Self::Conn>>> {
+        let this = self.get_mut();
+        let listener = &this.listener;
+
+        let accept = this.accept.get_or_insert_with(|| unsafe {
+            Op::new(listener.clone(), |listener| {
+                opcode::Accept::new(types::Target::Fd(listener.as_raw_fd()), ptr::null_mut(), ptr::null_mut())
+                    .flags(libc::SOCK_CLOEXEC as _)
+                    .build()
+            }).submit()
+        });
+
+        let ret = ready!(Pin::new(accept).poll(cx));
+        this.accept = None;
+
+        let fd = ret?.0.ok()? as _;
+        let stream = unsafe { net::TcpStream::from_raw_fd(fd) };
+
+        Poll::Ready(Some(Ok(Compat::new(TcpStream::from_std(stream)))))
+    }
+}
+
+impl Connector {
+    #[inline]
+    pub fn new() -> Connector {
+        Connector(())
+    }
+}
+
+impl Service<Uri> for Connector {
+    type Response = Compat<TcpStream>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<Compat<TcpStream>>> + Send>>;
+
+    #[inline]
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        Box::pin(async move {
+            if uri.scheme_str() != Some("http") {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "only http URIs are supported"));
+            }
+
+            let host = uri.host()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "URI without a host"))?;
+            let port = uri.port_u16().unwrap_or(80);
+
+            let stream = TcpStream::connect_host(&format!("{}:{}", host, port)).await?;
+            Ok(Compat::new(stream))
+        })
+    }
+}
+
+impl Connection for Compat<TcpStream> {
+    #[inline]
+    fn connected(&self) -> Connected {
+        Connected::new()
+    }
+}
+
+/// Spawns the connection tasks of hyper with [Handle::spawn].
+impl<F> hyper::rt::Executor<F> for Handle
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static
+{
+    fn execute(&self, fut: F) {
+        self.spawn(fut);
+    }
+}